@@ -0,0 +1,159 @@
+use crate::elevation::Dem;
+use osmpbfreader::{OsmId, OsmObj};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Terrain statistics within an area (closed way or multipolygon)
+#[derive(Debug, Serialize)]
+pub struct AreaInfo {
+    // Number of DEM pixels whose centre is inside the area
+    pixel_count: usize,
+    // Mean and maximum terrain slope, in degrees
+    mean_slope: f64,
+    max_slope: f64,
+    // Mean direction (circular mean, in degrees clockwise from north) faced by the slopes
+    mean_aspect: Option<f64>,
+}
+
+// Even-odd rule over the segments of all the rings, so that the holes
+// of multipolygons are handled without assembling the rings
+fn contains(rings: &[Vec<[f64; 2]>], (lon, lat): (f64, f64)) -> bool {
+    let mut inside = false;
+    for ring in rings {
+        for segment in ring.windows(2) {
+            let (a, b) = (segment[0], segment[1]);
+            if (a[1] > lat) != (b[1] > lat)
+                && lon < a[0] + (lat - a[1]) / (b[1] - a[1]) * (b[0] - a[0])
+            {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+fn compute(rings: &[Vec<[f64; 2]>], dem: &Dem) -> Option<AreaInfo> {
+    let points = rings.iter().flatten();
+    let min = points.clone().fold((f64::INFINITY, f64::INFINITY), |m, p| {
+        (m.0.min(p[0]), m.1.min(p[1]))
+    });
+    let max = points.fold((f64::NEG_INFINITY, f64::NEG_INFINITY), |m, p| {
+        (m.0.max(p[0]), m.1.max(p[1]))
+    });
+    if !min.0.is_finite() {
+        return None;
+    }
+
+    let terrain: Vec<(f64, f64)> = dem
+        .pixel_centres(min, max)
+        .into_iter()
+        .filter(|centre| contains(rings, *centre))
+        .filter_map(|(lon, lat)| dem.terrain(lon, lat))
+        .collect();
+    if terrain.is_empty() {
+        return None;
+    }
+    let (sin, cos) = terrain.iter().filter(|(slope, _)| *slope > 0.).fold(
+        (0., 0.),
+        |(sin, cos), (_, aspect)| {
+            (
+                sin + aspect.to_radians().sin(),
+                cos + aspect.to_radians().cos(),
+            )
+        },
+    );
+    Some(AreaInfo {
+        pixel_count: terrain.len(),
+        mean_slope: terrain.iter().map(|(slope, _)| slope).sum::<f64>() / terrain.len() as f64,
+        max_slope: terrain.iter().map(|(slope, _)| *slope).fold(0., f64::max),
+        mean_aspect: (sin != 0. || cos != 0.)
+            .then(|| f64::atan2(sin, cos).to_degrees().rem_euclid(360.)),
+    })
+}
+
+// Coordinates of the nodes of a way, if they are all available
+fn way_coordinates(
+    objs: &BTreeMap<OsmId, OsmObj>,
+    way: &osmpbfreader::Way,
+) -> Option<Vec<[f64; 2]>> {
+    way.nodes
+        .iter()
+        .map(|node_id| {
+            let node = objs.get(&OsmId::Node(*node_id))?.node()?;
+            Some([node.lon(), node.lat()])
+        })
+        .collect()
+}
+
+/// Compute the terrain statistics of the closed ways and multipolygons validating
+/// the predicate, and write them as a JSON map of "way/ID" or "relation/ID" -> statistics
+pub fn run<P>(osm_file: &str, elevation_file: &str, output_file: &str, pred: P)
+where
+    P: Fn(&OsmObj) -> bool,
+{
+    let dem = Dem::open(elevation_file).unwrap_or_else(|e| {
+        log::error!(target: "setup", fields:serde = serde_json::json!({ "elevation_file": elevation_file }); "{}", e);
+        std::process::exit(1);
+    });
+    let r = std::fs::File::open(osm_file).unwrap_or_else(|_| {
+        log::error!(
+            target: "setup",
+            fields:serde = serde_json::json!({ "osm_file": osm_file });
+            "Unable to open OSM file {}", osm_file
+        );
+        std::process::exit(1);
+    });
+    let mut pbf = osmpbfreader::OsmPbfReader::new(r);
+    let objs = pbf
+        .get_objs_and_deps(|obj| match obj {
+            OsmObj::Way(way) => way.is_closed() && pred(obj),
+            OsmObj::Relation(relation) => {
+                relation.tags.contains("type", "multipolygon") && pred(obj)
+            }
+            OsmObj::Node(_) => false,
+        })
+        .unwrap_or_else(|e| {
+            log::error!(
+                target: "read",
+                fields:serde = serde_json::json!({ "osm_file": osm_file });
+                "Unable to read OSM file {}: {}", osm_file, e
+            );
+            std::process::exit(1);
+        });
+
+    let mut result = BTreeMap::new();
+    for (id, obj) in &objs {
+        let rings: Vec<Vec<[f64; 2]>> = match obj {
+            OsmObj::Way(way) if way.is_closed() && pred(obj) => {
+                way_coordinates(&objs, way).into_iter().collect()
+            }
+            OsmObj::Relation(relation) if relation.tags.contains("type", "multipolygon") => {
+                relation
+                    .refs
+                    .iter()
+                    .filter_map(|member| match objs.get(&member.member)? {
+                        OsmObj::Way(way) => way_coordinates(&objs, way),
+                        _ => None,
+                    })
+                    .collect()
+            }
+            _ => continue,
+        };
+        if let Some(info) = compute(&rings, &dem) {
+            let id = match id {
+                OsmId::Way(way_id) => format!("way/{}", way_id.0),
+                OsmId::Relation(relation_id) => format!("relation/{}", relation_id.0),
+                OsmId::Node(node_id) => format!("node/{}", node_id.0),
+            };
+            result.insert(id, info);
+        }
+    }
+
+    log::info!(
+        target: "compute",
+        fields:serde = serde_json::json!({ "area_count": result.len() });
+        "{} areas computed", result.len()
+    );
+    let json_str = serde_json::to_string(&result).expect("Unable to serialize result to string");
+    std::fs::write(output_file, json_str).expect("Unable to write file");
+}
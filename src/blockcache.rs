@@ -0,0 +1,58 @@
+use fxhash::FxHashMap;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Number of decoded blocks kept in memory by each raster
+const CAPACITY: usize = 256;
+
+// Decoded blocks, and the last time each of them was used
+struct Blocks {
+    blocks: FxHashMap<usize, (Rc<Vec<f64>>, u64)>,
+    clock: u64,
+}
+
+/// Least recently used cache of the decoded blocks (tiles or strips) of a raster,
+/// keyed by block index, so that the neighbouring nodes of the ways, which are
+/// mostly read one after the other, are read from memory
+pub struct BlockCache {
+    blocks: RefCell<Blocks>,
+}
+
+impl BlockCache {
+    pub fn new() -> BlockCache {
+        BlockCache {
+            blocks: RefCell::new(Blocks {
+                blocks: FxHashMap::default(),
+                clock: 0,
+            }),
+        }
+    }
+
+    /// Values of a block, decoded (and cached, in place of the least recently used
+    /// block if the cache is full) if it isn't in the cache
+    pub fn get<F>(&self, index: usize, decode: F) -> Result<Rc<Vec<f64>>, String>
+    where
+        F: FnOnce() -> Result<Vec<f64>, String>,
+    {
+        let mut blocks = self.blocks.borrow_mut();
+        blocks.clock += 1;
+        let clock = blocks.clock;
+        if let Some((block, last_use)) = blocks.blocks.get_mut(&index) {
+            *last_use = clock;
+            return Ok(block.clone());
+        }
+        let block = Rc::new(decode()?);
+        if blocks.blocks.len() >= CAPACITY {
+            let oldest = blocks
+                .blocks
+                .iter()
+                .min_by_key(|(_, (_, last_use))| *last_use)
+                .map(|(index, _)| *index);
+            if let Some(oldest) = oldest {
+                blocks.blocks.remove(&oldest);
+            }
+        }
+        blocks.blocks.insert(index, (block.clone(), clock));
+        Ok(block)
+    }
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+const GRAVITY: f64 = 9.81;
+const AIR_DENSITY: f64 = 1.225;
+const ROLLING_RESISTANCE: f64 = 0.012;
+const DRAG_AREA: f64 = 0.7;
+
+// Default speed (in km/h) of each highway class, when the way has no usable maxspeed tag
+const DEFAULT_SPEEDS: [(&str, f64); 13] = [
+    ("motorway", 110.),
+    ("trunk", 90.),
+    ("primary", 80.),
+    ("secondary", 70.),
+    ("tertiary", 60.),
+    ("unclassified", 50.),
+    ("residential", 40.),
+    ("motorway_link", 60.),
+    ("trunk_link", 50.),
+    ("primary_link", 50.),
+    ("secondary_link", 40.),
+    ("tertiary_link", 40.),
+    ("living_street", 10.),
+];
+const FALLBACK_SPEED: f64 = 30.;
+
+/// Vehicle used to estimate the slowdown caused by climbs
+#[derive(Debug, Clone, Copy)]
+pub struct Vehicle {
+    // Mass in kg
+    pub mass: f64,
+    // Available power in W
+    pub power: f64,
+}
+
+/// Motor vehicle travel time estimates of a way
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CarInfo {
+    // Travel time (in seconds) on flat ground at the base speed of the way
+    base_time: f64,
+    // Time (in seconds) to add to the base travel time, in each direction
+    time_penalty_forward: f64,
+    time_penalty_backward: f64,
+}
+
+// Base speed of a way in m/s, from its maxspeed tag or its highway class
+fn base_speed(tags: &osmpbfreader::Tags) -> f64 {
+    let tagged = tags.get("maxspeed").and_then(|value| {
+        let value = value.trim();
+        match value.strip_suffix("mph") {
+            Some(mph) => mph.trim().parse::<f64>().ok().map(|mph| mph * 1.609_344),
+            None => value.parse::<f64>().ok(),
+        }
+    });
+    let kmh = tagged.unwrap_or_else(|| {
+        tags.get("highway")
+            .and_then(|highway| {
+                DEFAULT_SPEEDS
+                    .iter()
+                    .find(|(class, _)| *class == highway.as_str())
+            })
+            .map(|(_, speed)| *speed)
+            .unwrap_or(FALLBACK_SPEED)
+    });
+    kmh / 3.6
+}
+
+// Highest speed (capped at the base speed) the vehicle can sustain on the given grade
+fn speed_on_grade(vehicle: &Vehicle, base_speed: f64, grade: f64) -> f64 {
+    let angle = grade.atan();
+    let required_power = |v: f64| {
+        vehicle.mass * GRAVITY * (angle.sin() + ROLLING_RESISTANCE * angle.cos()) * v
+            + 0.5 * AIR_DENSITY * DRAG_AREA * v.powi(3)
+    };
+    if required_power(base_speed) <= vehicle.power {
+        return base_speed;
+    }
+    // The required power increases with the speed on a climb, solve by bisection
+    let (mut low, mut high) = (0., base_speed);
+    for _ in 0..50 {
+        let mid = (low + high) / 2.;
+        if required_power(mid) > vehicle.power {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+    low.max(0.1)
+}
+
+/// Estimate the time penalty caused by the grade of each segment of a way
+pub fn compute(
+    tags: &osmpbfreader::Tags,
+    distances: &[f64],
+    elevations: &[Option<f64>],
+    vehicle: &Vehicle,
+) -> CarInfo {
+    let base_speed = base_speed(tags);
+    let mut time_penalty_forward = 0.;
+    let mut time_penalty_backward = 0.;
+    for i in 1..elevations.len() {
+        let length = distances[i] - distances[i - 1];
+        let (ele_a, ele_b) = match (elevations[i - 1], elevations[i]) {
+            (Some(ele_a), Some(ele_b)) if length > 0. => (ele_a, ele_b),
+            _ => continue,
+        };
+        let grade = (ele_b - ele_a) / length;
+        let base_time = length / base_speed;
+        time_penalty_forward += length / speed_on_grade(vehicle, base_speed, grade) - base_time;
+        time_penalty_backward += length / speed_on_grade(vehicle, base_speed, -grade) - base_time;
+    }
+
+    CarInfo {
+        base_time: distances.last().copied().unwrap_or(0.) / base_speed,
+        time_penalty_forward,
+        time_penalty_backward,
+    }
+}
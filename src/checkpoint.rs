@@ -0,0 +1,94 @@
+use crate::output::Record;
+use crate::WayInfo;
+use serde::Deserialize;
+use std::io::{BufRead, Write};
+
+// The checkpoint file is flushed (and synced to the disk) every this number of ways
+const SYNC_EVERY: usize = 10_000;
+
+/// Line of the checkpoint file: a computed way, with what its record is written with
+/// (the user-defined metrics not being read back, as they aren't deserialized)
+#[derive(Deserialize)]
+pub struct CheckpointRecord {
+    pub way_id: i64,
+    pub geometry: Vec<[f64; 2]>,
+    pub partition: Option<String>,
+    pub info: WayInfo,
+}
+
+/// NDJSON file to which the ways are appended as they are computed, so that a run
+/// stopped before its end (killed, out of memory, power loss) can be resumed
+/// without computing them again
+pub struct Checkpoint {
+    out: std::io::BufWriter<std::fs::File>,
+    // Ways written since the last sync
+    pending: usize,
+}
+
+impl Checkpoint {
+    /// Start a new checkpoint file, or continue an existing one after its last
+    /// complete line (the following ones having been cut by the end of the run)
+    pub fn open(path: &str, resume_after: Option<u64>) -> Result<Checkpoint, String> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(resume_after.is_none())
+            .open(path)
+            .map_err(|e| format!("Unable to open {}: {}", path, e))?;
+        if let Some(length) = resume_after {
+            file.set_len(length)
+                .map_err(|e| format!("Unable to truncate {}: {}", path, e))?;
+        }
+        let mut out = std::io::BufWriter::new(file);
+        std::io::Seek::seek(&mut out, std::io::SeekFrom::End(0))
+            .map_err(|e| format!("Unable to open {}: {}", path, e))?;
+        Ok(Checkpoint { out, pending: 0 })
+    }
+
+    pub fn write(&mut self, record: &Record) {
+        let line = serde_json::json!({
+            "way_id": record.way_id,
+            "geometry": record.geometry,
+            "partition": record.partition,
+            "info": record.info,
+        });
+        writeln!(self.out, "{}", line).expect("Unable to write the checkpoint file");
+        self.pending += 1;
+        if self.pending >= SYNC_EVERY {
+            self.sync();
+        }
+    }
+
+    /// Write the buffered ways to the disk
+    pub fn sync(&mut self) {
+        self.out
+            .flush()
+            .and_then(|_| self.out.get_ref().sync_data())
+            .expect("Unable to write the checkpoint file");
+        self.pending = 0;
+    }
+}
+
+/// Call `f` with each way of a checkpoint file, and return the length of its
+/// complete lines (a last line cut by the end of the run being skipped)
+pub fn read<F: FnMut(CheckpointRecord)>(path: &str, mut f: F) -> Result<u64, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("Unable to open {}: {}", path, e))?;
+    let mut reader = std::io::BufReader::new(file);
+    let mut length = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_line(&mut line)
+            .map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        if read == 0 || !line.ends_with('\n') {
+            return Ok(length);
+        }
+        let checkpointed: CheckpointRecord = match serde_json::from_str(&line) {
+            Ok(checkpointed) => checkpointed,
+            Err(_) => return Ok(length),
+        };
+        f(checkpointed);
+        length += read as u64;
+    }
+}
@@ -0,0 +1,98 @@
+use std::io::{BufWriter, Write};
+
+/// Compression of the output files
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// Path of a compressed file, with the extension of the compression
+    pub fn path(&self, path: &str) -> String {
+        if path.ends_with(self.extension()) {
+            path.to_string()
+        } else {
+            format!("{}{}", path, self.extension())
+        }
+    }
+}
+
+// Output file, or stdout
+type Target = BufWriter<Box<dyn Write + Send>>;
+
+/// Output file (or stdout, for the `-` path), written through the encoder
+/// of its compression if any
+pub enum FileSink {
+    Plain(Target),
+    Gzip(flate2::write::GzEncoder<Target>),
+    Zstd(zstd::stream::write::Encoder<'static, Target>),
+}
+
+impl FileSink {
+    /// Create the file, with the extension of the compression added to its path
+    /// (the final path being returned)
+    pub fn create(
+        path: &str,
+        compression: Option<Compression>,
+    ) -> std::io::Result<(String, FileSink)> {
+        let to_stdout = crate::stream::is_standard_stream(path);
+        let path = match compression {
+            Some(compression) if !to_stdout => compression.path(path),
+            _ => path.to_string(),
+        };
+        let target = || -> std::io::Result<Target> {
+            Ok(BufWriter::new(if to_stdout {
+                Box::new(std::io::stdout())
+            } else {
+                Box::new(std::fs::File::create(&path)?)
+            }))
+        };
+        let sink = match compression {
+            None => FileSink::Plain(target()?),
+            Some(Compression::Gzip) => FileSink::Gzip(flate2::write::GzEncoder::new(
+                target()?,
+                flate2::Compression::default(),
+            )),
+            Some(Compression::Zstd) => FileSink::Zstd(zstd::stream::write::Encoder::new(
+                target()?,
+                zstd::DEFAULT_COMPRESSION_LEVEL,
+            )?),
+        };
+        Ok((path, sink))
+    }
+
+    /// Complete the file: flush it, and write the end of the compressed stream
+    pub fn finish(self) -> std::io::Result<()> {
+        match self {
+            FileSink::Plain(mut out) => out.flush(),
+            FileSink::Gzip(encoder) => encoder.finish()?.flush(),
+            FileSink::Zstd(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for FileSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            FileSink::Plain(out) => out.write(buf),
+            FileSink::Gzip(out) => out.write(buf),
+            FileSink::Zstd(out) => out.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            FileSink::Plain(out) => out.flush(),
+            FileSink::Gzip(out) => out.flush(),
+            FileSink::Zstd(out) => out.flush(),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use crate::elevation::Sample;
+use std::fmt;
+
+/// Number of nodes, among those used by the matched ways, covered by the DEM
+#[derive(Debug, Default)]
+pub struct CoverageReport {
+    pub covered: usize,
+    pub nodata: usize,
+    pub out_of_extent: usize,
+    // Covered nodes whose elevation was read from a fallback DEM
+    pub from_fallback: usize,
+    // Coverage of the nodes read from each tile of a mosaic DEM, by path of the tile
+    // (empty for a single raster)
+    pub tiles: Vec<(String, CoverageReport)>,
+}
+
+impl CoverageReport {
+    /// Report broken down by tile of a mosaic DEM made of the given tiles
+    pub fn for_tiles(paths: &[&str]) -> CoverageReport {
+        CoverageReport {
+            tiles: match paths {
+                [_, _, ..] => paths
+                    .iter()
+                    .map(|path| (path.to_string(), CoverageReport::default()))
+                    .collect(),
+                _ => Vec::new(),
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Count a node, read from the given tile of the mosaic (if any)
+    pub fn record(&mut self, sample: &Sample, from_fallback: bool, tile: Option<usize>) {
+        if let Some((_, report)) = tile.and_then(|tile| self.tiles.get_mut(tile)) {
+            report.record(sample, from_fallback, None);
+        }
+        if from_fallback {
+            self.from_fallback += 1;
+        }
+        match sample {
+            Sample::Value(_) => self.covered += 1,
+            Sample::NoData(_) => self.nodata += 1,
+            Sample::OutOfExtent => self.out_of_extent += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.covered + self.nodata + self.out_of_extent
+    }
+
+    /// Percentage of nodes with a valid elevation value
+    pub fn percentage(&self) -> f64 {
+        if self.total() == 0 {
+            return 100.;
+        }
+        self.covered as f64 / self.total() as f64 * 100.
+    }
+}
+
+impl fmt::Display for CoverageReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "DEM coverage: {:.2}% of {} nodes ({} nodata, {} outside of the raster extent)",
+            self.percentage(),
+            self.total(),
+            self.nodata,
+            self.out_of_extent,
+        )?;
+        if self.from_fallback > 0 {
+            write!(f, ", {} read from the fallback DEMs", self.from_fallback)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+const GRAVITY: f64 = 9.81;
+const AIR_DENSITY: f64 = 1.225;
+
+/// Rider and bike used to estimate the speed and energy on each grade
+#[derive(Debug, Clone, Copy)]
+pub struct Cyclist {
+    // Mass of the rider and the bike, in kg
+    pub mass: f64,
+    pub rolling_resistance: f64,
+    // Drag area (drag coefficient times frontal area), in m²
+    pub drag_area: f64,
+    // Power sustained by the rider, in W
+    pub power: f64,
+    // Highest speed (in m/s), e.g. when braking on the descents
+    pub max_speed: f64,
+}
+
+/// Cycling travel time and energy estimates of a way
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CyclingInfo {
+    // Travel time (in seconds) in each direction
+    cycling_time_forward: f64,
+    cycling_time_backward: f64,
+    // Energy (in kJ) spent by the rider in each direction
+    cycling_energy_forward: f64,
+    cycling_energy_backward: f64,
+}
+
+impl Cyclist {
+    // Force (in N) resisting the motion at the given speed on the given grade
+    fn resistance(&self, grade: f64, speed: f64) -> f64 {
+        let angle = grade.atan();
+        self.mass * GRAVITY * (angle.sin() + self.rolling_resistance * angle.cos())
+            + 0.5 * AIR_DENSITY * self.drag_area * speed.powi(2)
+    }
+
+    // Speed (capped at the highest speed) sustained with the power of the rider
+    fn speed_on_grade(&self, grade: f64) -> f64 {
+        if self.resistance(grade, self.max_speed) * self.max_speed <= self.power {
+            return self.max_speed;
+        }
+        // The required power increases with the speed, solve by bisection
+        let (mut low, mut high) = (0., self.max_speed);
+        for _ in 0..50 {
+            let mid = (low + high) / 2.;
+            if self.resistance(grade, mid) * mid > self.power {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+        low.max(0.1)
+    }
+}
+
+/// Estimate the travel time and energy of a cyclist from the grade of each segment of a
+/// way, the segments without a known elevation at both ends being ridden as on flat ground
+pub fn compute(distances: &[f64], elevations: &[Option<f64>], cyclist: &Cyclist) -> CyclingInfo {
+    let mut info = CyclingInfo {
+        cycling_time_forward: 0.,
+        cycling_time_backward: 0.,
+        cycling_energy_forward: 0.,
+        cycling_energy_backward: 0.,
+    };
+    for i in 1..elevations.len() {
+        let length = distances[i] - distances[i - 1];
+        if length <= 0. {
+            continue;
+        }
+        let grade = match (elevations[i - 1], elevations[i]) {
+            (Some(ele_a), Some(ele_b)) => (ele_b - ele_a) / length,
+            _ => 0.,
+        };
+        for (grade, time, energy) in [
+            (
+                grade,
+                &mut info.cycling_time_forward,
+                &mut info.cycling_energy_forward,
+            ),
+            (
+                -grade,
+                &mut info.cycling_time_backward,
+                &mut info.cycling_energy_backward,
+            ),
+        ] {
+            let speed = cyclist.speed_on_grade(grade);
+            *time += length / speed;
+            // No energy is spent when coasting or braking
+            *energy += cyclist.resistance(grade, speed).max(0.) * length / 1000.;
+        }
+    }
+    info
+}
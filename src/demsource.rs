@@ -0,0 +1,511 @@
+use crate::InputFormat;
+#[cfg(feature = "gdal")]
+use gdal::{raster::Buffer, spatial_ref::SpatialRef, DriverManager};
+use serde_json::json;
+use std::io::Read;
+use std::path::Path;
+
+/// Global DEM whose tiles are downloaded for the extent of the OSM input
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DemSource {
+    // SRTM 1 arc-second tiles (.hgt.gz) of the AWS terrain tiles dataset
+    Srtm,
+    // Copernicus GLO-30 Cloud Optimized GeoTIFF tiles of the AWS open data registry
+    #[value(name = "copernicus-glo30")]
+    CopernicusGlo30,
+    // Terrarium PNG tiles (Web Mercator, at the zoom level of --dem-zoom) of the
+    // AWS terrain tiles dataset
+    Terrarium,
+    // Mapbox Terrain-RGB PNG tiles (Web Mercator, at the zoom level of --dem-zoom),
+    // with the access token of --mapbox-token
+    #[value(name = "terrain-rgb")]
+    TerrainRgb,
+}
+
+const SRTM_URL: &str = "https://s3.amazonaws.com/elevation-tiles-prod/skadi";
+const COPERNICUS_URL: &str = "https://copernicus-dem-30m.s3.amazonaws.com";
+const TERRARIUM_URL: &str = "https://s3.amazonaws.com/elevation-tiles-prod/terrarium";
+const TERRAIN_RGB_URL: &str = "https://api.mapbox.com/v4/mapbox.terrain-rgb";
+
+/// Default zoom level of the Terrarium and Terrain-RGB tiles (about 30 m pixels
+/// at mid-latitudes)
+pub const DEFAULT_ZOOM: u8 = 12;
+
+// Half of the extent of the Web Mercator tiles, in meters
+const MERCATOR_EXTENT: f64 = 20_037_508.342_789_244;
+
+// Latitude and longitude prefixes of the name of a 1° tile (by its south-west corner)
+fn prefixes(lon: i32, lat: i32) -> (char, char) {
+    (
+        if lat < 0 { 'S' } else { 'N' },
+        if lon < 0 { 'W' } else { 'E' },
+    )
+}
+
+impl DemSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DemSource::Srtm => "srtm",
+            DemSource::CopernicusGlo30 => "copernicus-glo30",
+            DemSource::Terrarium => "terrarium",
+            DemSource::TerrainRgb => "terrain-rgb",
+        }
+    }
+
+    // Whether the DEM is made of Web Mercator PNG tiles rather than of 1° tiles
+    fn is_web_tiles(&self) -> bool {
+        matches!(self, DemSource::Terrarium | DemSource::TerrainRgb)
+    }
+
+    // URL of a Web Mercator tile (without the access token of Mapbox, added to the
+    // requests only)
+    fn web_tile_url(&self, (zoom, x, y): (u8, u32, u32)) -> String {
+        match self {
+            DemSource::TerrainRgb => {
+                format!("{}/{}/{}/{}.pngraw", TERRAIN_RGB_URL, zoom, x, y)
+            }
+            _ => format!("{}/{}/{}/{}.png", TERRARIUM_URL, zoom, x, y),
+        }
+    }
+
+    // Elevation (in meters) encoded in the color of a pixel of a PNG tile
+    fn decode_elevation(&self, [r, g, b]: [u8; 3]) -> f32 {
+        let (r, g, b) = (r as f64, g as f64, b as f64);
+        match self {
+            DemSource::TerrainRgb => (-10000. + (r * 65536. + g * 256. + b) * 0.1) as f32,
+            _ => (r * 256. + g + b / 256. - 32768.) as f32,
+        }
+    }
+
+    // URL of the 1° tile whose south-west corner is (lon, lat)
+    fn tile_url(&self, lon: i32, lat: i32) -> String {
+        let (ns, ew) = prefixes(lon, lat);
+        let (lat, lon) = (lat.abs(), lon.abs());
+        match self {
+            DemSource::Srtm => format!(
+                "{}/{}{:02}/{}{:02}{}{:03}.hgt.gz",
+                SRTM_URL, ns, lat, ns, lat, ew, lon
+            ),
+            DemSource::CopernicusGlo30 => {
+                let name = format!(
+                    "Copernicus_DSM_COG_10_{}{:02}_00_{}{:03}_00_DEM",
+                    ns, lat, ew, lon
+                );
+                format!("{}/{}/{}.tif", COPERNICUS_URL, name, name)
+            }
+            DemSource::Terrarium | DemSource::TerrainRgb => {
+                unreachable!("The Web Mercator tiles are not 1° tiles")
+            }
+        }
+    }
+}
+
+// Longitudes covered by the inputs, as the range (west, east) of each 1° column
+// of the globe, and their latitude range
+struct Extent {
+    columns: Vec<Option<(f64, f64)>>,
+    south: f64,
+    north: f64,
+}
+
+impl Extent {
+    fn new() -> Self {
+        Extent {
+            columns: vec![None; 360],
+            south: f64::INFINITY,
+            north: f64::NEG_INFINITY,
+        }
+    }
+
+    // Extend to the longitudes from west to east (crossing the antimeridian when
+    // west > east) and to the latitudes from south to north
+    fn add(&mut self, [west, south, east, north]: [f64; 4]) {
+        if west > east {
+            self.add([west, south, 180., north]);
+            self.add([-180., south, east, north]);
+            return;
+        }
+        self.south = self.south.min(south);
+        self.north = self.north.max(north);
+        let column = |lon: f64| ((lon + 180.).floor() as usize).min(359);
+        for index in column(west)..=column(east) {
+            let column_west = index as f64 - 180.;
+            let (west, east) = (west.max(column_west), east.min(column_west + 1.));
+            let range = self.columns[index].get_or_insert((west, east));
+            *range = (range.0.min(west), range.1.max(east));
+        }
+    }
+
+    // Bounding box leaving out the largest gap between the covered longitudes,
+    // with west > east when it crosses the antimeridian
+    fn bbox(&self) -> Option<[f64; 4]> {
+        let ranges: Vec<(f64, f64)> = self.columns.iter().flatten().copied().collect();
+        let (first, last) = (ranges.first()?, ranges.last()?);
+        // The gap across the antimeridian gives a bounding box that doesn't cross it
+        let (mut west, mut east, mut gap) = (first.0, last.1, first.0 + 360. - last.1);
+        for pair in ranges.windows(2) {
+            if pair[1].0 - pair[0].1 > gap {
+                (west, east, gap) = (pair[1].0, pair[0].1, pair[1].0 - pair[0].1);
+            }
+        }
+        Some([west, self.south, east, self.north])
+    }
+}
+
+/// Bounding box (west, south, east, north) of the nodes of the inputs: the one
+/// recorded in the header of the PBF files, or else read from their nodes. West
+/// is greater than east when the inputs cross the antimeridian (e.g. Fiji).
+pub fn extent(inputs: &[(&str, InputFormat)]) -> Result<[f64; 4], String> {
+    let mut extent = Extent::new();
+    for (path, format) in inputs {
+        if *format == InputFormat::Pbf {
+            let header = crate::pbf::try_read_header(path)?;
+            if header.has_bbox() {
+                // In nanodegrees, with left > right across the antimeridian
+                let header_bbox = header.get_bbox();
+                let degrees = |nanodegrees: i64| nanodegrees as f64 * 1e-9;
+                extent.add([
+                    degrees(header_bbox.get_left()),
+                    degrees(header_bbox.get_bottom()),
+                    degrees(header_bbox.get_right()),
+                    degrees(header_bbox.get_top()),
+                ]);
+                continue;
+            }
+        }
+        let add_node = |obj: osmpbfreader::OsmObj| {
+            if let osmpbfreader::OsmObj::Node(node) = obj {
+                let (lon, lat) = (node.lon(), node.lat());
+                extent.add([lon, lat, lon, lat]);
+            }
+        };
+        match format {
+            InputFormat::Stdin => {
+                return Err("The extent of the OSM input can't be read from stdin".into())
+            }
+            InputFormat::Xml => crate::osmxml::for_each_object(path, add_node),
+            InputFormat::O5m => crate::o5m::for_each_object(path, add_node),
+            InputFormat::Pbf => {
+                let file = std::fs::File::open(path)
+                    .map_err(|e| format!("Unable to open OSM file {}: {}", path, e))?;
+                crate::pbf::read_objects(std::io::BufReader::new(file), add_node);
+            }
+        }
+    }
+    extent
+        .bbox()
+        .ok_or_else(|| "The OSM input has no node".into())
+}
+
+// Longitude ranges (west, east) of a bounding box, split in two at the
+// antimeridian when it crosses it
+fn longitude_ranges([west, _, east, _]: [f64; 4]) -> Vec<(f64, f64)> {
+    if west > east {
+        vec![(west, 180.), (-180., east)]
+    } else {
+        vec![(west, east)]
+    }
+}
+
+// South-west corners (lon, lat) of the 1° tiles covering a bounding box
+fn tiles(bbox: [f64; 4]) -> Vec<(i32, i32)> {
+    // A bounding box ending on a whole degree doesn't reach the next tile
+    let range = |min: f64, max: f64| {
+        let first = min.floor() as i32;
+        first..=(max.ceil() as i32 - 1).max(first)
+    };
+    let lons: Vec<i32> = longitude_ranges(bbox)
+        .into_iter()
+        .flat_map(|(west, east)| range(west, east))
+        // A range starting on the antimeridian starts on the tile of 180°W
+        .map(|lon| if lon == 180 { -180 } else { lon })
+        .collect();
+    range(bbox[1], bbox[3])
+        .flat_map(|lat| lons.iter().map(move |&lon| (lon, lat)))
+        .collect()
+}
+
+// Web Mercator tiles (zoom, x, y) covering a bounding box
+fn web_tiles(zoom: u8, bbox: [f64; 4]) -> Vec<(u8, u32, u32)> {
+    let count = 1u32 << zoom;
+    let column = |lon: f64| (((lon + 180.) / 360. * count as f64) as u32).min(count - 1);
+    let row = |lat: f64| {
+        let lat = lat.clamp(-85.0511, 85.0511).to_radians();
+        let y = (1. - (lat.tan() + 1. / lat.cos()).ln() / std::f64::consts::PI) / 2.;
+        ((y * count as f64) as u32).min(count - 1)
+    };
+    let columns: Vec<u32> = longitude_ranges(bbox)
+        .into_iter()
+        .flat_map(|(west, east)| column(west)..=column(east))
+        .collect();
+    let (south, north) = (bbox[1], bbox[3]);
+    (row(north)..=row(south))
+        .flat_map(|y| columns.iter().map(move |&x| (zoom, x, y)))
+        .collect()
+}
+
+// Geo transform (in EPSG:3857) of a Web Mercator tile of the given size in pixels
+fn web_tile_transform((zoom, x, y): (u8, u32, u32), size: usize) -> [f64; 6] {
+    let tile_size = 2. * MERCATOR_EXTENT / (1u32 << zoom) as f64;
+    let resolution = tile_size / size as f64;
+    [
+        -MERCATOR_EXTENT + x as f64 * tile_size,
+        resolution,
+        0.,
+        MERCATOR_EXTENT - y as f64 * tile_size,
+        0.,
+        -resolution,
+    ]
+}
+
+// Elevations (row by row) of a PNG tile
+fn decode_web_tile(source: DemSource, png: &Path) -> Result<(usize, usize, Vec<f32>), String> {
+    let data = std::fs::read(png).map_err(|e| e.to_string())?;
+    let (width, height, pixels) = crate::png::decode_rgb(&data)?;
+    let elevations = pixels
+        .into_iter()
+        .map(|pixel| source.decode_elevation(pixel))
+        .collect();
+    Ok((width, height, elevations))
+}
+
+// GeoTIFF copy (in EPSG:3857) of a PNG tile
+#[cfg(feature = "gdal")]
+fn convert_web_tile(
+    source: DemSource,
+    png: &Path,
+    path: &Path,
+    tile: (u8, u32, u32),
+) -> Result<(), String> {
+    let (width, height, elevations) = decode_web_tile(source, png)?;
+    let driver = DriverManager::get_driver_by_name("GTiff").map_err(|e| e.to_string())?;
+    let mut dataset = driver
+        .create_with_band_type::<f32, _>(path, width as isize, height as isize, 1)
+        .map_err(|e| e.to_string())?;
+    dataset
+        .set_geo_transform(&web_tile_transform(tile, width))
+        .and_then(|_| dataset.set_spatial_ref(&SpatialRef::from_epsg(3857)?))
+        .and_then(|_| {
+            dataset.rasterband(1).and_then(|mut band| {
+                band.write(
+                    (0, 0),
+                    (width, height),
+                    &Buffer::new((width, height), elevations),
+                )
+            })
+        })
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "gdal"))]
+fn convert_web_tile(
+    source: DemSource,
+    png: &Path,
+    path: &Path,
+    tile: (u8, u32, u32),
+) -> Result<(), String> {
+    let (width, height, elevations) = decode_web_tile(source, png)?;
+    crate::tiff::write_f32(
+        path,
+        (width, height),
+        web_tile_transform(tile, width),
+        &elevations,
+        None,
+        3857,
+    )
+    .map_err(|e| e.to_string())
+}
+
+// Whether the converted copy of a downloaded tile is missing or older than it
+fn is_outdated(path: &Path, downloaded: &str) -> bool {
+    let modified = |path: &Path| {
+        path.metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    modified(path) < modified(Path::new(downloaded))
+}
+
+// Download (or reuse the cached copies of) the Web Mercator tiles covering
+// the bounding box, converted to GeoTIFF files
+fn download_web_tiles(
+    source: DemSource,
+    bbox: [f64; 4],
+    zoom: u8,
+    mapbox_token: Option<&str>,
+    cache: &crate::fetch::Cache,
+) -> Result<Vec<String>, String> {
+    if source == DemSource::TerrainRgb && mapbox_token.is_none() {
+        return Err("The Terrain-RGB tiles require a Mapbox access token (--mapbox-token or the MAPBOX_ACCESS_TOKEN environment variable)".into());
+    }
+    let tiles = web_tiles(zoom, bbox);
+    log::info!(
+        target: "fetch",
+        fields:serde = json!({
+            "dem_source": source.name(),
+            "bbox": bbox,
+            "zoom": zoom,
+            "tile_count": tiles.len(),
+        });
+        "Using {} {} tiles (zoom {}) covering the OSM input", tiles.len(), source.name(), zoom
+    );
+    let mut paths = Vec::new();
+    for tile in tiles {
+        let url = source.web_tile_url(tile);
+        let token = mapbox_token.map(|token| ("access_token", token));
+        let downloaded = match cache.resolve_with_query(&url, token) {
+            Ok(path) => path,
+            Err(e) => {
+                log::warn!(
+                    target: "fetch",
+                    fields:serde = json!({ "tile": [tile.0, tile.1, tile.2] });
+                    "{}, the tile is skipped", e
+                );
+                continue;
+            }
+        };
+        let (zoom, x, y) = tile;
+        let path = cache
+            .dir
+            .join(format!("{}-{}-{}-{}.tif", source.name(), zoom, x, y));
+        if is_outdated(&path, &downloaded) {
+            convert_web_tile(source, Path::new(&downloaded), &path, tile).map_err(|e| {
+                format!(
+                    "Unable to convert {} tile {}/{}/{}: {}",
+                    source.name(),
+                    zoom,
+                    x,
+                    y,
+                    e
+                )
+            })?;
+        }
+        paths.push(path.to_string_lossy().to_string());
+    }
+    if paths.is_empty() {
+        return Err(format!("No {} tile covers the OSM input", source.name()));
+    }
+    Ok(paths)
+}
+
+// Decompressed copy of a .hgt.gz tile (whose location is given by its name)
+fn convert_hgt(compressed: &Path, path: &Path) -> Result<(), String> {
+    let mut data = Vec::new();
+    flate2::read::GzDecoder::new(std::fs::File::open(compressed).map_err(|e| e.to_string())?)
+        .read_to_end(&mut data)
+        .map_err(|e| e.to_string())?;
+    std::fs::write(path, data).map_err(|e| e.to_string())
+}
+
+/// Download (or reuse the cached copies of) the tiles of the DEM covering the
+/// bounding box, the missing tiles (e.g. at sea) being skipped with a warning,
+/// and the Web Mercator ones (at the given zoom level) being converted to GeoTIFF
+pub fn download(
+    source: DemSource,
+    bbox: [f64; 4],
+    zoom: u8,
+    mapbox_token: Option<&str>,
+    cache: &crate::fetch::Cache,
+) -> Result<Vec<String>, String> {
+    if source.is_web_tiles() {
+        return download_web_tiles(source, bbox, zoom, mapbox_token, cache);
+    }
+    let corners = tiles(bbox);
+    log::info!(
+        target: "fetch",
+        fields:serde = json!({
+            "dem_source": source.name(),
+            "bbox": bbox,
+            "tile_count": corners.len(),
+        });
+        "Using {} {} tiles covering the OSM input", corners.len(), source.name()
+    );
+    let mut paths = Vec::new();
+    for corner in corners {
+        let url = source.tile_url(corner.0, corner.1);
+        let downloaded = match cache.resolve(&url) {
+            Ok(path) => path,
+            Err(e) => {
+                log::warn!(
+                    target: "fetch",
+                    fields:serde = json!({ "url": url });
+                    "{}, the tile is skipped", e
+                );
+                continue;
+            }
+        };
+        if source == DemSource::Srtm {
+            let (ns, ew) = prefixes(corner.0, corner.1);
+            let name = format!("{}{:02}{}{:03}", ns, corner.1.abs(), ew, corner.0.abs());
+            let path = cache.dir.join(format!("{}.hgt", name));
+            // The tile is converted again when its downloaded copy has changed
+            if is_outdated(&path, &downloaded) {
+                convert_hgt(Path::new(&downloaded), &path)
+                    .map_err(|e| format!("Unable to convert SRTM tile {}: {}", downloaded, e))?;
+            }
+            paths.push(path.to_string_lossy().to_string());
+        } else {
+            paths.push(downloaded);
+        }
+    }
+    if paths.is_empty() {
+        return Err(format!("No {} tile covers the OSM input", source.name()));
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extent_across_the_antimeridian() {
+        // Nodes of Fiji, on both sides of the antimeridian
+        let mut extent = Extent::new();
+        for (lon, lat) in [
+            (177.2, -17.8),
+            (178.4, -18.1),
+            (-179.9, -16.2),
+            (-178.7, -17.0),
+        ] {
+            extent.add([lon, lat, lon, lat]);
+        }
+        assert_eq!(extent.bbox(), Some([177.2, -18.1, -178.7, -16.2]));
+        assert_eq!(
+            tiles([177.2, -18.1, -178.7, -16.2]),
+            [
+                (177, -19),
+                (178, -19),
+                (179, -19),
+                (-180, -19),
+                (-179, -19),
+                (177, -18),
+                (178, -18),
+                (179, -18),
+                (-180, -18),
+                (-179, -18),
+                (177, -17),
+                (178, -17),
+                (179, -17),
+                (-180, -17),
+                (-179, -17),
+            ]
+        );
+        assert_eq!(
+            web_tiles(3, [177.2, -18.1, -178.7, -16.2]),
+            [(3, 7, 4), (3, 0, 4)]
+        );
+    }
+
+    #[test]
+    fn extent_of_a_header_bbox() {
+        let mut extent = Extent::new();
+        extent.add([170., -20., -175., -10.]);
+        assert_eq!(extent.bbox(), Some([170., -20., -175., -10.]));
+        let mut extent = Extent::new();
+        extent.add([5.5, 45., 10.5, 48.]);
+        extent.add([-1., 44., 0.5, 45.]);
+        assert_eq!(extent.bbox(), Some([-1., 44., 10.5, 48.]));
+        assert_eq!(Extent::new().bbox(), None);
+    }
+}
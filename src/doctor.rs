@@ -0,0 +1,129 @@
+use crate::elevation::{Dem, Sample};
+use crate::osmxml::{attribute, Element, XmlReader};
+use crate::InputFormat;
+#[cfg(feature = "gdal")]
+use gdal::spatial_ref::{CoordTransform, SpatialRef};
+#[cfg(feature = "gdal")]
+use gdal::{DriverManager, Metadata};
+
+// Print the result of a check and whether it succeeded
+fn report(ok: bool, message: &str) {
+    eprintln!("[{}] {}", if ok { " OK " } else { "FAIL" }, message);
+}
+
+#[cfg(feature = "gdal")]
+fn check_proj() -> Result<(), String> {
+    let wgs84 = SpatialRef::from_epsg(4326).map_err(|e| e.to_string())?;
+    let web_mercator = SpatialRef::from_epsg(3857).map_err(|e| e.to_string())?;
+    CoordTransform::new(&wgs84, &web_mercator).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn check_raster(path: &str) -> Result<String, String> {
+    let dem = Dem::try_open(path)?;
+    let (width, height) = dem.size();
+    let transform = dem.geo_transform();
+    let crs = dem.crs().unwrap_or_else(|| "unknown CRS".to_string());
+
+    // Sample the centre of the raster, as the computation does
+    let x = transform[0] + transform[1] * width as f64 / 2.;
+    let y = transform[3] + transform[5] * height as f64 / 2.;
+    let sample = match dem.sample_at(x, y) {
+        Sample::Value(elevation) => format!("elevation {:.1} at the centre", elevation),
+        Sample::NoData(_) => "nodata at the centre".to_string(),
+        Sample::OutOfExtent => return Err("Unable to sample the centre of the raster".into()),
+    };
+    Ok(format!("{}x{} pixels, {}, {}", width, height, crs, sample))
+}
+
+fn check_pbf(path: &str) -> Result<String, String> {
+    let header = crate::pbf::try_read_header(path)?;
+    Ok(format!(
+        "written by {}, features: {}",
+        header.get_writingprogram(),
+        header
+            .get_required_features()
+            .iter()
+            .chain(header.get_optional_features())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ")
+    ))
+}
+
+fn check_xml(path: &str) -> Result<String, String> {
+    let input = crate::osmxml::open(path).map_err(|e| e.to_string())?;
+    match XmlReader::new(input).next_element() {
+        Ok(Some(Element::Start(name, attributes) | Element::Empty(name, attributes)))
+            if name == "osm" =>
+        {
+            Ok(format!(
+                "OSM XML version {}, written by {}",
+                attribute(&attributes, "version").unwrap_or("unknown"),
+                attribute(&attributes, "generator").unwrap_or("unknown")
+            ))
+        }
+        Ok(_) => Err("Not an OSM XML file (no <osm> root element)".into()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(feature = "gdal")]
+fn report_gdal() {
+    eprintln!("GDAL {}", gdal::version::version_info("RELEASE_NAME"));
+
+    let raster_drivers: Vec<String> = (0..DriverManager::count())
+        .filter_map(|i| DriverManager::get_driver(i).ok())
+        .filter(|driver| driver.metadata_item("DCAP_RASTER", "").as_deref() == Some("YES"))
+        .map(|driver| driver.short_name())
+        .collect();
+    report(
+        raster_drivers.iter().any(|driver| driver == "GTiff"),
+        &format!(
+            "{} raster drivers: {}",
+            raster_drivers.len(),
+            raster_drivers.join(", ")
+        ),
+    );
+
+    for variable in ["PROJ_DATA", "PROJ_LIB", "GDAL_DATA"] {
+        if let Ok(value) = std::env::var(variable) {
+            eprintln!("{}={}", variable, value);
+        }
+    }
+    match check_proj() {
+        Ok(()) => report(true, "PROJ data available (EPSG:4326 -> EPSG:3857)"),
+        Err(e) => report(false, &format!("PROJ data unavailable: {}", e)),
+    }
+}
+
+/// Report the GDAL environment and check that the given files can be read
+pub fn run(files: &[String]) {
+    eprintln!("osm-ways-slope {}", env!("CARGO_PKG_VERSION"));
+    #[cfg(feature = "gdal")]
+    report_gdal();
+    #[cfg(not(feature = "gdal"))]
+    eprintln!("Built without GDAL, only GeoTIFF elevation files can be read");
+
+    let mut failed = false;
+    for file in files {
+        // The files read as PBF by default are only checked as such with their extension
+        let result = match InputFormat::detect(file) {
+            InputFormat::Stdin => Err("The standard input can't be checked".into()),
+            InputFormat::Xml => check_xml(file),
+            InputFormat::O5m => Ok("o5m file".to_string()),
+            InputFormat::Pbf if file.to_lowercase().ends_with(".pbf") => check_pbf(file),
+            InputFormat::Pbf => check_raster(file),
+        };
+        match result {
+            Ok(message) => report(true, &format!("{}: {}", file, message)),
+            Err(e) => {
+                report(false, &format!("{}: {}", file, e));
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
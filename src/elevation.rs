@@ -0,0 +1,1028 @@
+#[cfg(feature = "gdal")]
+use crate::blockcache::BlockCache;
+use crate::reproject::Reprojection;
+#[cfg(feature = "gdal")]
+use gdal::raster::ResampleAlg;
+#[cfg(feature = "gdal")]
+use gdal::Dataset;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+/// Outcome of reading the elevation raster at a given location
+#[derive(Debug, Clone, Copy)]
+pub enum Sample {
+    // A valid elevation value
+    Value(f64),
+    // The pixel holds the nodata value of the band
+    NoData(f64),
+    // The location falls outside of the raster
+    OutOfExtent,
+}
+
+/// Source of the elevations of the locations (given in WGS84): a DEM, an elevation
+/// API, or anything else able to tell the elevation at a location
+pub trait ElevationProvider {
+    /// Elevation at the given location, and whether it was read from a fallback source
+    fn sample_with_source(&self, lon: f64, lat: f64) -> (Sample, bool);
+
+    /// Elevation at the given location
+    fn sample(&self, lon: f64, lat: f64) -> Sample {
+        self.sample_with_source(lon, lat).0
+    }
+
+    /// Valid elevation at the given location, if any
+    fn elevation(&self, lon: f64, lat: f64) -> Option<f64> {
+        match self.sample(lon, lat) {
+            Sample::Value(elevation) => Some(elevation),
+            Sample::NoData(_) | Sample::OutOfExtent => None,
+        }
+    }
+
+    /// Key by which the locations of a batch are sorted before being read, so that
+    /// the neighbouring locations are read one after the other
+    fn batch_key(&self, _lon: f64, _lat: f64) -> usize {
+        0
+    }
+
+    /// Elevations at several locations, and whether they were read from a fallback
+    /// source, in the order of the locations
+    fn sample_batch(&self, locations: &[(f64, f64)]) -> Result<Vec<(Sample, bool)>, String> {
+        Ok(locations
+            .iter()
+            .map(|(lon, lat)| self.sample_with_source(*lon, *lat))
+            .collect())
+    }
+}
+
+/// Interpolation of the elevation between the pixels around a location
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    // Value of the pixel containing the location
+    Nearest,
+    // Bilinear interpolation between the centres of the 2x2 closest pixels
+    Bilinear,
+    // Cubic convolution (Catmull-Rom) over the 4x4 closest pixels
+    Cubic,
+}
+
+impl Interpolation {
+    // Size of the window of pixels read around a location
+    fn window_size(&self) -> usize {
+        match self {
+            Interpolation::Nearest => 1,
+            Interpolation::Bilinear => 2,
+            Interpolation::Cubic => 4,
+        }
+    }
+
+    // Weights of the pixels of the window along an axis, for a location at the
+    // given fraction of the way between the centres of the two middle ones
+    fn weights(&self, t: f64) -> Vec<f64> {
+        match self {
+            Interpolation::Nearest => vec![1.],
+            Interpolation::Bilinear => vec![1. - t, t],
+            Interpolation::Cubic => {
+                let (t2, t3) = (t * t, t * t * t);
+                vec![
+                    (-t3 + 2. * t2 - t) / 2.,
+                    (3. * t3 - 5. * t2 + 2.) / 2.,
+                    (-3. * t3 + 4. * t2 + t) / 2.,
+                    (t3 - t2) / 2.,
+                ]
+            }
+        }
+    }
+}
+
+/// Conversion of the elevations of a DEM with the undulations of a geoid model
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeoidConversion {
+    // From ellipsoidal heights (e.g. of a GNSS-based DEM) to orthometric heights
+    ToOrthometric,
+    // From orthometric heights to ellipsoidal heights
+    ToEllipsoidal,
+}
+
+impl GeoidConversion {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GeoidConversion::ToOrthometric => "to-orthometric",
+            GeoidConversion::ToEllipsoidal => "to-ellipsoidal",
+        }
+    }
+}
+
+// Apply a geo transform (as in GDAL) to pixel coordinates
+fn apply(transform: &[f64; 6], x: f64, y: f64) -> (f64, f64) {
+    (
+        transform[0] + x * transform[1] + y * transform[2],
+        transform[3] + x * transform[4] + y * transform[5],
+    )
+}
+
+// Inverse of a geo transform
+fn invert(transform: &[f64; 6]) -> Option<[f64; 6]> {
+    let det = transform[1] * transform[5] - transform[2] * transform[4];
+    if det == 0. {
+        return None;
+    }
+    let (a, b, d, e) = (
+        transform[5] / det,
+        -transform[2] / det,
+        -transform[4] / det,
+        transform[1] / det,
+    );
+    Some([
+        -(a * transform[0] + b * transform[3]),
+        a,
+        b,
+        -(d * transform[0] + e * transform[3]),
+        d,
+        e,
+    ])
+}
+
+// Extensions of the DEM tiles read from a directory
+#[cfg(feature = "gdal")]
+const TILE_EXTENSIONS: [&str; 5] = ["tif", "tiff", "vrt", "hgt", "img"];
+#[cfg(not(feature = "gdal"))]
+const TILE_EXTENSIONS: [&str; 3] = ["tif", "tiff", "hgt"];
+
+// Default GDAL configuration of the remote rasters (unless set by the user): no
+// listing of their directory, and caching of the blocks read (with merged range
+// requests) in memory
+#[cfg(feature = "gdal")]
+const REMOTE_CONFIG: [(&str, &str); 4] = [
+    ("GDAL_DISABLE_READDIR_ON_OPEN", "EMPTY_DIR"),
+    ("GDAL_HTTP_MERGE_CONSECUTIVE_RANGES", "YES"),
+    ("VSI_CACHE", "TRUE"),
+    ("VSI_CACHE_SIZE", "268435456"),
+];
+
+/// Whether an elevation file is a remote raster (an http(s) or s3 URL)
+pub fn is_remote(path: &str) -> bool {
+    crate::fetch::is_url(path) || path.starts_with("s3://")
+}
+
+// GDAL path of a raster, the remote ones (e.g. Cloud Optimized GeoTIFFs) being
+// read in place with range requests
+#[cfg(feature = "gdal")]
+fn gdal_path(path: &str) -> String {
+    if !is_remote(path) {
+        return path.to_string();
+    }
+    for (key, value) in REMOTE_CONFIG {
+        if gdal::config::get_config_option(key, "")
+            .unwrap_or_default()
+            .is_empty()
+        {
+            let _ = gdal::config::set_config_option(key, value);
+        }
+    }
+    match path.strip_prefix("s3://") {
+        Some(key) => format!("/vsis3/{}", key),
+        None => format!("/vsicurl/{}", path),
+    }
+}
+
+// First band of a GDAL dataset, or of a GeoTIFF file when built without
+// the `gdal` feature
+struct Raster {
+    #[cfg(feature = "gdal")]
+    dataset: Dataset,
+    // Size of the blocks of the band, and the cache of the blocks read
+    #[cfg(feature = "gdal")]
+    block_size: (usize, usize),
+    #[cfg(feature = "gdal")]
+    blocks: BlockCache,
+    #[cfg(not(feature = "gdal"))]
+    raster: crate::tiff::GeoTiff,
+    size: (usize, usize),
+    transform: [f64; 6],
+    invert_transform: [f64; 6],
+    nodata: Option<f64>,
+    path: String,
+}
+
+impl Raster {
+    #[cfg(feature = "gdal")]
+    fn open(path: &str) -> Result<Raster, String> {
+        let dataset = Dataset::open(gdal_path(path)).map_err(|e| e.to_string())?;
+        let transform = dataset.geo_transform().map_err(|e| e.to_string())?;
+        let invert_transform = invert(&transform).ok_or("Invalid geo transform")?;
+        let band = dataset.rasterband(1).map_err(|e| e.to_string())?;
+        let (nodata, block_size) = (band.no_data_value(), band.block_size());
+        Ok(Raster {
+            size: dataset.raster_size(),
+            block_size,
+            blocks: BlockCache::new(),
+            dataset,
+            transform,
+            invert_transform,
+            nodata,
+            path: path.to_string(),
+        })
+    }
+
+    #[cfg(not(feature = "gdal"))]
+    fn open(path: &str) -> Result<Raster, String> {
+        if is_remote(path) {
+            return Err("Remote elevation files can only be read with the gdal feature".into());
+        }
+        let raster = if path.to_ascii_lowercase().ends_with(".hgt") {
+            crate::tiff::GeoTiff::open_hgt(path)?
+        } else {
+            crate::tiff::GeoTiff::open(path)?
+        };
+        let invert_transform = invert(&raster.transform).ok_or("Invalid geo transform")?;
+        Ok(Raster {
+            size: (raster.width, raster.height),
+            transform: raster.transform,
+            invert_transform,
+            nodata: raster.nodata,
+            raster,
+            path: path.to_string(),
+        })
+    }
+
+    // Values of a block of the band (those of the partial blocks at the right and
+    // bottom edges being packed on rows of the width of the partial block)
+    #[cfg(feature = "gdal")]
+    fn block(&self, (block_x, block_y): (usize, usize)) -> Result<Vec<f64>, String> {
+        let (block_width, block_height) = self.block_size;
+        let (x, y) = (block_x * block_width, block_y * block_height);
+        let size = (
+            block_width.min(self.size.0 - x),
+            block_height.min(self.size.1 - y),
+        );
+        self.dataset
+            .rasterband(1)
+            .and_then(|band| {
+                band.read_as::<f64>(
+                    (x as isize, y as isize),
+                    size,
+                    size,
+                    Some(ResampleAlg::NearestNeighbour),
+                )
+            })
+            .map(|buffer| buffer.data)
+            .map_err(|e| e.to_string())
+    }
+
+    // Values of a window of pixels (row by row), which must be inside the raster,
+    // read from the cached blocks
+    #[cfg(feature = "gdal")]
+    fn read(&self, (x, y): (isize, isize), (width, height): (usize, usize)) -> Option<Vec<f64>> {
+        let (block_width, block_height) = self.block_size;
+        let blocks_across = self.size.0.div_ceil(block_width);
+        let mut values = Vec::with_capacity(width * height);
+        for row in y as usize..y as usize + height {
+            for col in x as usize..x as usize + width {
+                let (block_x, block_y) = (col / block_width, row / block_height);
+                let block = self
+                    .blocks
+                    .get(block_y * blocks_across + block_x, || {
+                        self.block((block_x, block_y))
+                    })
+                    .ok()?;
+                let row_width = block_width.min(self.size.0 - block_x * block_width);
+                values.push(block[(row % block_height) * row_width + col % block_width]);
+            }
+        }
+        Some(values)
+    }
+
+    #[cfg(not(feature = "gdal"))]
+    fn read(&self, (x, y): (isize, isize), (width, height): (usize, usize)) -> Option<Vec<f64>> {
+        self.raster
+            .read((x as usize, y as usize), (width, height))
+            .ok()
+    }
+
+    // Size of the blocks (tiles or strips) of the raster
+    fn block_size(&self) -> (usize, usize) {
+        #[cfg(feature = "gdal")]
+        return self.block_size;
+        #[cfg(not(feature = "gdal"))]
+        return self.raster.chunk_size();
+    }
+
+    fn crs(&self) -> Option<String> {
+        #[cfg(feature = "gdal")]
+        let code = self
+            .dataset
+            .spatial_ref()
+            .and_then(|srs| srs.auth_code())
+            .ok();
+        #[cfg(not(feature = "gdal"))]
+        let code = self.raster.epsg;
+        code.map(|code| format!("EPSG:{}", code))
+    }
+
+    // Definition of the CRS of a projected raster (its authority code, or else its WKT),
+    // None for a geographic one
+    #[cfg(feature = "gdal")]
+    fn projected_crs(&self) -> Result<Option<String>, String> {
+        match self.dataset.spatial_ref() {
+            Ok(srs) if srs.is_projected() => match srs.auth_code() {
+                Ok(code) => Ok(Some(format!("EPSG:{}", code))),
+                Err(_) => srs.to_wkt().map(Some).map_err(|e| e.to_string()),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    #[cfg(not(feature = "gdal"))]
+    fn projected_crs(&self) -> Result<Option<String>, String> {
+        match (self.raster.projected, self.raster.epsg) {
+            (false, _) => Ok(None),
+            (true, Some(code)) => Ok(Some(format!("EPSG:{}", code))),
+            (true, None) => Err("Unknown projected CRS".into()),
+        }
+    }
+
+    // Corners (min x, min y, max x, max y) of a north-up raster, in its CRS
+    fn bounds(&self) -> [f64; 4] {
+        let (width, height) = self.size;
+        let (x0, y0) = apply(&self.transform, 0., 0.);
+        let (x1, y1) = apply(&self.transform, width as f64, height as f64);
+        [x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)]
+    }
+
+    // Elevation at the given location, if it falls inside the raster
+    fn sample(&self, location: (f64, f64), geographic: bool) -> Option<Sample> {
+        let (x, y) = pixel(&self.invert_transform, self.size, location, geographic)?;
+        let Some(window) = self.read((x as isize, y as isize), (1, 1)) else {
+            log::error!(
+                target: "sample",
+                fields:serde = serde_json::json!({ "path": self.path });
+                "Unable to read the elevation file {}", self.path
+            );
+            std::process::exit(1);
+        };
+        let value = window[0];
+        Some(match self.nodata {
+            Some(nodata) if value == nodata || (nodata.is_nan() && value.is_nan()) => {
+                Sample::NoData(value)
+            }
+            _ => Sample::Value(value),
+        })
+    }
+}
+
+// Pixel coordinates of the given location, if it falls inside a raster of the given
+// size. In a geographic CRS, longitudes are also tried shifted by ±360° so that
+// rasters whose extent goes past the antimeridian (or uses the 0..360 convention)
+// are handled.
+fn pixel(
+    invert_transform: &[f64; 6],
+    (width, height): (usize, usize),
+    (lon, lat): (f64, f64),
+    geographic: bool,
+) -> Option<(f64, f64)> {
+    let shifts: &[f64] = if geographic {
+        &[0., 360., -360.]
+    } else {
+        &[0.]
+    };
+    shifts
+        .iter()
+        .map(|shift| apply(invert_transform, lon + shift, lat))
+        .find(|(x, y)| *x >= 0. && *y >= 0. && *x < width as f64 && *y < height as f64)
+}
+
+// Paths of the tiles of a DEM: the files of a directory with a raster extension
+// (sorted by name), or the path itself
+fn tile_paths(path: &str) -> Result<Vec<String>, String> {
+    if !std::path::Path::new(path).is_dir() {
+        return Ok(vec![path.to_string()]);
+    }
+    let mut paths: Vec<String> = std::fs::read_dir(path)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    TILE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+                })
+        })
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    if paths.is_empty() {
+        return Err(format!(
+            "No DEM tile (with a {} extension) in the directory",
+            TILE_EXTENSIONS.join(", ")
+        ));
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Digital elevation model: a single raster, or a mosaic of tiles (north-up,
+/// in the same CRS) in which each location is read from the tile covering it.
+/// The grid of a mosaic covers all its tiles, with the resolution of the first one.
+/// Fallback DEMs, with their own grids, are read where it has no valid value.
+/// The locations are given in WGS84, and reprojected for a DEM in a projected CRS.
+pub struct Dem {
+    tiles: Vec<Raster>,
+    size: (usize, usize),
+    transform: [f64; 6],
+    invert_transform: [f64; 6],
+    // From WGS84 to the CRS of a projected DEM, and back
+    projection: Option<(Reprojection, Reprojection)>,
+    interpolation: Interpolation,
+    // Geoid model grid whose undulations convert the elevations
+    geoid: Option<(Box<Dem>, GeoidConversion)>,
+    fallbacks: Vec<Dem>,
+}
+
+impl Dem {
+    /// Open a raster, or the mosaic of the tiles of a directory, with an error
+    /// message naming the file
+    pub fn open(path: &str) -> Result<Dem, String> {
+        Dem::try_open(path).map_err(|e| format!("Unable to open elevation file {}: {}", path, e))
+    }
+
+    /// Open a raster, or the mosaic of the tiles of a directory
+    pub fn try_open(path: &str) -> Result<Dem, String> {
+        Dem::try_open_mosaic(&tile_paths(path)?)
+    }
+
+    /// Open the mosaic of several rasters (or directories of tiles)
+    pub fn open_mosaic(paths: &[String]) -> Result<Dem, String> {
+        if let [path] = paths {
+            return Dem::open(path);
+        }
+        Dem::try_open_mosaic(paths)
+            .map_err(|e| format!("Unable to open elevation files {}: {}", paths.join(", "), e))
+    }
+
+    pub fn try_open_mosaic(paths: &[String]) -> Result<Dem, String> {
+        let mut tiles = Vec::new();
+        for path in paths {
+            for tile_path in tile_paths(path)? {
+                let tile = Raster::open(&tile_path).map_err(|e| match paths.len() {
+                    1 if tile_path == *path => e,
+                    _ => format!("{}: {}", tile_path, e),
+                })?;
+                tiles.push(tile);
+            }
+        }
+        let first = tiles.first().ok_or("No elevation file")?;
+        let projection = match first.projected_crs()? {
+            Some(crs) => Some((Reprojection::new(&crs)?, Reprojection::inverse(&crs)?)),
+            None => None,
+        };
+        if tiles.len() == 1 {
+            return Ok(Dem {
+                size: first.size,
+                transform: first.transform,
+                invert_transform: first.invert_transform,
+                tiles,
+                projection,
+                interpolation: Interpolation::Nearest,
+                geoid: None,
+                fallbacks: Vec::new(),
+            });
+        }
+        if tiles
+            .iter()
+            .any(|tile| tile.transform[2] != 0. || tile.transform[4] != 0.)
+        {
+            return Err("The tiles of a mosaic must be north-up".into());
+        }
+        if tiles.iter().any(|tile| tile.crs() != first.crs()) {
+            return Err("The tiles of a mosaic must be in the same CRS".into());
+        }
+        let mut bounds = first.bounds();
+        for tile in &tiles[1..] {
+            let [min_x, min_y, max_x, max_y] = tile.bounds();
+            bounds = [
+                bounds[0].min(min_x),
+                bounds[1].min(min_y),
+                bounds[2].max(max_x),
+                bounds[3].max(max_y),
+            ];
+        }
+        let (x_resolution, y_resolution) = (first.transform[1], first.transform[5]);
+        let transform = [
+            if x_resolution > 0. {
+                bounds[0]
+            } else {
+                bounds[2]
+            },
+            x_resolution,
+            0.,
+            if y_resolution < 0. {
+                bounds[3]
+            } else {
+                bounds[1]
+            },
+            0.,
+            y_resolution,
+        ];
+        let size = (
+            ((bounds[2] - bounds[0]) / x_resolution.abs()).round() as usize,
+            ((bounds[3] - bounds[1]) / y_resolution.abs()).round() as usize,
+        );
+        Ok(Dem {
+            size,
+            invert_transform: invert(&transform).ok_or("Invalid geo transform")?,
+            transform,
+            tiles,
+            projection,
+            interpolation: Interpolation::Nearest,
+            geoid: None,
+            fallbacks: Vec::new(),
+        })
+    }
+
+    /// Read the given DEMs, in order, where this one has no valid value (e.g. SRTM
+    /// behind a patchy high-resolution lidar DEM)
+    pub fn with_fallbacks(mut self, fallbacks: Vec<Dem>) -> Dem {
+        self.fallbacks = fallbacks;
+        self
+    }
+
+    /// Interpolate the elevations between the pixels around the locations (also
+    /// in the fallback DEMs)
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Dem {
+        self.interpolation = interpolation;
+        self.fallbacks = self
+            .fallbacks
+            .into_iter()
+            .map(|fallback| fallback.with_interpolation(interpolation))
+            .collect();
+        self
+    }
+
+    /// Convert the elevations of this DEM and of its fallbacks with the undulations
+    /// of a geoid model grid (e.g. EGM96 or EGM2008), interpolated bilinearly, the
+    /// locations outside of the grid being read as outside of the DEM
+    pub fn with_geoid(mut self, geoid: Dem, conversion: GeoidConversion) -> Dem {
+        self.geoid = Some((
+            Box::new(geoid.with_interpolation(Interpolation::Bilinear)),
+            conversion,
+        ));
+        self
+    }
+
+    /// Size of the raster, in pixels
+    pub fn size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    /// Geo transform of the raster (as in GDAL)
+    pub fn geo_transform(&self) -> [f64; 6] {
+        self.transform
+    }
+
+    /// Authority code of the CRS of the raster (e.g. "EPSG:4326"), if known
+    pub fn crs(&self) -> Option<String> {
+        self.tiles[0].crs()
+    }
+
+    // Location in the CRS of the raster
+    fn project(&self, lon: f64, lat: f64) -> (f64, f64) {
+        match &self.projection {
+            // A location that can't be projected is outside of the raster
+            Some((to_raster, _)) => match to_raster.reproject(&[[lon, lat]]) {
+                Ok(coordinates) => (coordinates[0][0], coordinates[0][1]),
+                Err(_) => (f64::NAN, f64::NAN),
+            },
+            None => (lon, lat),
+        }
+    }
+
+    // Index of the block (with the block size of the first tile) of the grid holding
+    // the location, usize::MAX outside of the grid
+    fn block_index(&self, lon: f64, lat: f64) -> usize {
+        let (block_width, block_height) = self.tiles[0].block_size();
+        match pixel(
+            &self.invert_transform,
+            self.size,
+            self.project(lon, lat),
+            self.projection.is_none(),
+        ) {
+            Some((x, y)) => {
+                let blocks_across = self.size.0.div_ceil(block_width);
+                (y as usize / block_height) * blocks_across + x as usize / block_width
+            }
+            None => usize::MAX,
+        }
+    }
+
+    /// Elevation at the given location in the CRS of the raster, from the first tile
+    /// with a valid value there (the fallback DEMs being ignored), interpolated
+    /// between the pixels around it unless some of them are nodata or outside
+    /// of the raster
+    pub fn sample_at(&self, x: f64, y: f64) -> Sample {
+        match self.nearest_at(x, y) {
+            Sample::Value(value) => Sample::Value(self.interpolate_at(x, y).unwrap_or(value)),
+            sample => sample,
+        }
+    }
+
+    /// Paths of the tiles of the mosaic (the raster itself for a single raster)
+    pub fn tile_files(&self) -> Vec<&str> {
+        self.tiles.iter().map(|tile| tile.path.as_str()).collect()
+    }
+
+    /// Index of the tile the location (in WGS84) is read from: the first one with
+    /// a valid value there, or else the first one covering it
+    pub fn tile_at(&self, lon: f64, lat: f64) -> Option<usize> {
+        let location = self.project(lon, lat);
+        let mut covering = None;
+        for (index, tile) in self.tiles.iter().enumerate() {
+            match tile.sample(location, self.projection.is_none()) {
+                Some(Sample::Value(_)) => return Some(index),
+                Some(_) if covering.is_none() => covering = Some(index),
+                _ => {}
+            }
+        }
+        covering
+    }
+
+    // Value of the pixel containing the location, from the first tile with a valid
+    // value there
+    fn nearest_at(&self, x: f64, y: f64) -> Sample {
+        let mut sample = Sample::OutOfExtent;
+        for tile in &self.tiles {
+            match tile.sample((x, y), self.projection.is_none()) {
+                Some(Sample::Value(value)) => return Sample::Value(value),
+                Some(nodata) if matches!(sample, Sample::OutOfExtent) => sample = nodata,
+                _ => {}
+            }
+        }
+        sample
+    }
+
+    // Elevation interpolated between the pixels around the location, None with
+    // nearest-neighbour sampling, or if some of the pixels are nodata or outside
+    // of the raster
+    fn interpolate_at(&self, x: f64, y: f64) -> Option<f64> {
+        let size = self.interpolation.window_size();
+        if size == 1 {
+            return None;
+        }
+        let (x, y) = pixel(
+            &self.invert_transform,
+            self.size,
+            (x, y),
+            self.projection.is_none(),
+        )?;
+        // Position relative to the centres of the pixels
+        let (x, y) = (x - 0.5, y - 0.5);
+        let offset = (size / 2 - 1) as isize;
+        let (x0, y0) = (x.floor() as isize - offset, y.floor() as isize - offset);
+        let (width, height) = self.size;
+        if x0 < 0 || y0 < 0 || x0 as usize + size > width || y0 as usize + size > height {
+            return None;
+        }
+        let window = self.window((x0, y0), (size, size))?;
+        if window.iter().any(|value| value.is_nan()) {
+            return None;
+        }
+        let (x_weights, y_weights) = (
+            self.interpolation.weights(x - x.floor()),
+            self.interpolation.weights(y - y.floor()),
+        );
+        Some(
+            window
+                .iter()
+                .enumerate()
+                .map(|(i, value)| value * x_weights[i % size] * y_weights[i / size])
+                .sum(),
+        )
+    }
+
+    // Values of a window of pixels of the grid (row by row), which must be inside it,
+    // read at once from a single raster, or pixel by pixel from the tiles of a mosaic
+    // (nodata values being returned as NaN)
+    fn window(&self, (x, y): (isize, isize), (width, height): (usize, usize)) -> Option<Vec<f64>> {
+        if let [tile] = &self.tiles[..] {
+            let window = tile.read((x, y), (width, height))?;
+            return Some(match tile.nodata {
+                Some(nodata) => window
+                    .into_iter()
+                    .map(|value| if value == nodata { f64::NAN } else { value })
+                    .collect(),
+                None => window,
+            });
+        }
+        let mut window = Vec::with_capacity(width * height);
+        for dy in 0..height as isize {
+            for dx in 0..width as isize {
+                let (centre_x, centre_y) = apply(
+                    &self.transform,
+                    (x + dx) as f64 + 0.5,
+                    (y + dy) as f64 + 0.5,
+                );
+                match self.nearest_at(centre_x, centre_y) {
+                    Sample::Value(value) => window.push(value),
+                    _ => window.push(f64::NAN),
+                }
+            }
+        }
+        Some(window)
+    }
+
+    /// Terrain slope (in degrees) and aspect (in degrees clockwise from the north,
+    /// i.e. the direction the slope faces) at the given location, computed
+    /// with Horn's method over the 3x3 pixels window around it (on the grid of the
+    /// first fallback DEM with valid values there, if this one has none)
+    pub fn terrain(&self, lon: f64, lat: f64) -> Option<(f64, f64)> {
+        self.grid_terrain(lon, lat).or_else(|| {
+            self.fallbacks
+                .iter()
+                .find_map(|fallback| fallback.terrain(lon, lat))
+        })
+    }
+
+    fn grid_terrain(&self, lon: f64, lat: f64) -> Option<(f64, f64)> {
+        let location = self.project(lon, lat);
+        let (x, y) = pixel(
+            &self.invert_transform,
+            self.size,
+            location,
+            self.projection.is_none(),
+        )?;
+        let (x, y) = (x as isize, y as isize);
+        let (width, height) = self.size;
+        if x < 1 || y < 1 || x + 1 >= width as isize || y + 1 >= height as isize {
+            return None;
+        }
+        let window = self.window((x - 1, y - 1), (3, 3))?;
+        if window.iter().any(|value| value.is_nan()) {
+            return None;
+        }
+        let [a, b, c, d, _, f, g, h, i]: [f64; 9] = window.try_into().ok()?;
+        // Pixel size in meters on the ground (measured between the locations of the
+        // edges of the pixel for a projected raster, whose scale varies, e.g. in Mercator)
+        let (dx, dy) = match &self.projection {
+            Some((_, to_wgs84)) => {
+                let (px, py) = (self.transform[1] / 2., self.transform[5] / 2.);
+                let edges = to_wgs84
+                    .reproject(&[
+                        [location.0 - px, location.1],
+                        [location.0 + px, location.1],
+                        [location.0, location.1 - py],
+                        [location.0, location.1 + py],
+                    ])
+                    .ok()?;
+                let distance = |[lon0, lat0]: [f64; 2], [lon1, lat1]: [f64; 2]| {
+                    crate::geodesy::haversine_distance(
+                        crate::geodesy::Location {
+                            latitude: lat0,
+                            longitude: lon0,
+                        },
+                        crate::geodesy::Location {
+                            latitude: lat1,
+                            longitude: lon1,
+                        },
+                    ) * 1000.
+                };
+                (distance(edges[0], edges[1]), distance(edges[2], edges[3]))
+            }
+            None => (
+                self.transform[1].abs() * 111_320. * lat.to_radians().cos(),
+                self.transform[5].abs() * 110_540.,
+            ),
+        };
+        // Rate of change towards the east and towards the north
+        let gx = ((c + 2. * f + i) - (a + 2. * d + g)) / (8. * dx);
+        let gy = ((a + 2. * b + c) - (g + 2. * h + i)) / (8. * dy);
+        let slope = (gx * gx + gy * gy).sqrt().atan().to_degrees();
+        let aspect = (-gx).atan2(-gy).to_degrees().rem_euclid(360.);
+        Some((slope, aspect))
+    }
+
+    /// Locations (lon, lat) of the centres of the pixels within the given bounding box
+    /// (min and max (lon, lat))
+    pub fn pixel_centres(&self, min: (f64, f64), max: (f64, f64)) -> Vec<(f64, f64)> {
+        let (width, height) = self.size;
+        // The bounding box of the corners, in the CRS of a projected raster
+        let corners = [
+            (min.0, min.1),
+            (min.0, max.1),
+            (max.0, min.1),
+            (max.0, max.1),
+        ]
+        .map(|(lon, lat)| self.project(lon, lat));
+        let (min, max) = corners.iter().fold(
+            (
+                (f64::INFINITY, f64::INFINITY),
+                (f64::NEG_INFINITY, f64::NEG_INFINITY),
+            ),
+            |(min, max), (x, y)| {
+                (
+                    (min.0.min(*x), min.1.min(*y)),
+                    (max.0.max(*x), max.1.max(*y)),
+                )
+            },
+        );
+        let (x0, y0) = apply(&self.invert_transform, min.0, min.1);
+        let (x1, y1) = apply(&self.invert_transform, max.0, max.1);
+        let range = |a: f64, b: f64, size: usize| {
+            let start = a.min(b).floor().max(0.) as usize;
+            let end = (a.max(b).ceil().max(0.) as usize).min(size);
+            start..end
+        };
+        let mut centres = Vec::new();
+        for y in range(y0, y1, height) {
+            for x in range(x0, x1, width) {
+                centres.push(apply(&self.transform, x as f64 + 0.5, y as f64 + 0.5));
+            }
+        }
+        if let Some((_, to_wgs84)) = &self.projection {
+            let coordinates: Vec<[f64; 2]> = centres.iter().map(|(x, y)| [*x, *y]).collect();
+            // No centre is given when they can't be located in WGS84
+            centres = to_wgs84
+                .reproject(&coordinates)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|[lon, lat]| (lon, lat))
+                .collect();
+        }
+        centres
+    }
+}
+
+impl ElevationProvider for Dem {
+    /// Elevation at the given location, read from the first tile (or fallback DEM)
+    /// with a valid value there, and whether it was read from a fallback DEM
+    fn sample_with_source(&self, lon: f64, lat: f64) -> (Sample, bool) {
+        let (x, y) = self.project(lon, lat);
+        let mut sample = self.sample_at(x, y);
+        let mut from_fallback = false;
+        for fallback in &self.fallbacks {
+            match (sample, fallback.sample(lon, lat)) {
+                (Sample::Value(_), _) => break,
+                (_, Sample::Value(value)) => {
+                    sample = Sample::Value(value);
+                    from_fallback = true;
+                    break;
+                }
+                (Sample::OutOfExtent, nodata) => sample = nodata,
+                _ => {}
+            }
+        }
+        // The geoid conversion applies to the value, whichever DEM it was read from
+        let sample = match (sample, &self.geoid) {
+            (Sample::Value(elevation), Some((geoid, conversion))) => {
+                match (geoid.sample(lon, lat), conversion) {
+                    (Sample::Value(undulation), GeoidConversion::ToOrthometric) => {
+                        Sample::Value(elevation - undulation)
+                    }
+                    (Sample::Value(undulation), GeoidConversion::ToEllipsoidal) => {
+                        Sample::Value(elevation + undulation)
+                    }
+                    _ => Sample::OutOfExtent,
+                }
+            }
+            _ => sample,
+        };
+        (sample, from_fallback)
+    }
+
+    fn batch_key(&self, lon: f64, lat: f64) -> usize {
+        self.block_index(lon, lat)
+    }
+}
+
+// Number of nodes of a sample batch
+const BATCH_SIZE: usize = 1 << 20;
+
+/// Node (id and location) of a sample batch, with its elevation and whether it was
+/// read from a fallback DEM
+pub type SampledNode = (i64, (f64, f64), (Sample, bool));
+
+/// Nodes whose elevations are read at once, sorted by the batch key of the source:
+/// by block of a DEM, so that the nodes of a block are read one after the other
+/// from the block decoded once (rather than in the order of the OSM file, which
+/// reads the same blocks again and again), or by batches of locations from an
+/// elevation API
+pub struct SampleBatch<'a> {
+    source: &'a dyn ElevationProvider,
+    // Batch key, id and location of the nodes
+    nodes: Vec<(usize, i64, (f64, f64))>,
+}
+
+impl<'a> SampleBatch<'a> {
+    pub fn new(source: &'a dyn ElevationProvider) -> SampleBatch<'a> {
+        SampleBatch {
+            source,
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Add a node to the batch, and tell whether the batch is full
+    pub fn push(&mut self, id: i64, lon: f64, lat: f64) -> bool {
+        self.nodes
+            .push((self.source.batch_key(lon, lat), id, (lon, lat)));
+        self.nodes.len() >= BATCH_SIZE
+    }
+
+    /// Elevation of the nodes of the batch, which is then emptied
+    pub fn sample(&mut self) -> Result<Vec<SampledNode>, String> {
+        self.nodes.sort_unstable_by_key(|(key, _, _)| *key);
+        let locations: Vec<(f64, f64)> = self
+            .nodes
+            .iter()
+            .map(|(_, _, location)| *location)
+            .collect();
+        let samples = self.source.sample_batch(&locations)?;
+        Ok(self
+            .nodes
+            .drain(..)
+            .zip(samples)
+            .map(|((_, id, location), sample)| (id, location, sample))
+            .collect())
+    }
+}
+
+/// Worker threads sampling the nodes of a batch in parallel, each with its own DEM
+/// (and block caches), opened by the worker as the rasters can't be shared between
+/// threads. The sorted nodes of a batch are split into a contiguous run per worker,
+/// so that each worker reads its own blocks.
+pub struct SamplePool {
+    // DEM of the calling thread, for the batch keys and the single locations
+    dem: Dem,
+    workers: Vec<SampleWorker>,
+}
+
+// Channels of a worker thread: the locations sent to it, and their elevations sent back
+struct SampleWorker {
+    locations: Sender<Vec<(f64, f64)>>,
+    samples: Receiver<Vec<(Sample, bool)>>,
+}
+
+impl SamplePool {
+    pub fn new<F>(threads: usize, open: F) -> SamplePool
+    where
+        F: Fn() -> Dem + Send + Sync + 'static,
+    {
+        let open = Arc::new(open);
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let (locations_tx, locations_rx) = channel::<Vec<(f64, f64)>>();
+                let (samples_tx, samples_rx) = channel();
+                let open = open.clone();
+                std::thread::spawn(move || {
+                    let dem = open();
+                    // The worker stops once the pool is dropped
+                    for locations in locations_rx {
+                        let samples = locations
+                            .iter()
+                            .map(|(lon, lat)| dem.sample_with_source(*lon, *lat))
+                            .collect();
+                        if samples_tx.send(samples).is_err() {
+                            break;
+                        }
+                    }
+                });
+                SampleWorker {
+                    locations: locations_tx,
+                    samples: samples_rx,
+                }
+            })
+            .collect();
+        SamplePool {
+            dem: open(),
+            workers,
+        }
+    }
+}
+
+impl ElevationProvider for SamplePool {
+    fn sample_with_source(&self, lon: f64, lat: f64) -> (Sample, bool) {
+        self.dem.sample_with_source(lon, lat)
+    }
+
+    fn batch_key(&self, lon: f64, lat: f64) -> usize {
+        self.dem.block_index(lon, lat)
+    }
+
+    fn sample_batch(&self, locations: &[(f64, f64)]) -> Result<Vec<(Sample, bool)>, String> {
+        let chunks: Vec<&[(f64, f64)]> = locations
+            .chunks(locations.len().div_ceil(self.workers.len()).max(1))
+            .collect();
+        for (chunk, worker) in chunks.iter().zip(&self.workers) {
+            worker
+                .locations
+                .send(chunk.to_vec())
+                .map_err(|_| "Elevation sampling thread stopped".to_string())?;
+        }
+        let mut samples = Vec::with_capacity(locations.len());
+        for worker in self.workers.iter().take(chunks.len()) {
+            samples.extend(
+                worker
+                    .samples
+                    .recv()
+                    .map_err(|_| "Elevation sampling thread panicked".to_string())?,
+            );
+        }
+        Ok(samples)
+    }
+}
@@ -0,0 +1,101 @@
+use crate::elevation::{ElevationProvider, Sample};
+use serde_json::{json, Value};
+
+/// Default number of locations of a request
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+
+/// Default number of requests running at once
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// JSON elevation API compatible with Open-Elevation: the locations are posted
+/// (`{"locations": [{"latitude": ..., "longitude": ...}, ...]}`) and their
+/// elevations are read from the results of the response
+pub struct ElevationApi {
+    pub url: String,
+    // Number of locations of a request
+    pub batch_size: usize,
+    // Number of requests running at once
+    pub concurrency: usize,
+}
+
+impl ElevationApi {
+    // Elevations of some locations, in their order, posted in a request
+    fn post(&self, locations: &[(f64, f64)]) -> Result<Vec<Option<f64>>, String> {
+        let body = json!({
+            "locations": locations
+                .iter()
+                .map(|(lon, lat)| json!({ "latitude": lat, "longitude": lon }))
+                .collect::<Vec<_>>(),
+        });
+        let response = ureq::post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_string(&body.to_string())
+            .map_err(|e| {
+                format!(
+                    "The request to the elevation API {} failed: {}",
+                    self.url,
+                    crate::fetch::request_error(e)
+                )
+            })?;
+        let response: Value = serde_json::from_reader(response.into_reader())
+            .map_err(|e| format!("Invalid response of the elevation API {}: {}", self.url, e))?;
+        let results = response["results"]
+            .as_array()
+            .filter(|results| results.len() == locations.len())
+            .ok_or_else(|| {
+                format!(
+                    "The response of the elevation API {} doesn't have the results of the {} locations",
+                    self.url,
+                    locations.len()
+                )
+            })?;
+        Ok(results
+            .iter()
+            .map(|result| result["elevation"].as_f64())
+            .collect())
+    }
+
+    /// Elevations of the locations (lon, lat), None where the API has none, looked
+    /// up by batches of locations with several requests running at once
+    pub fn lookup(&self, locations: &[(f64, f64)]) -> Result<Vec<Option<f64>>, String> {
+        let mut elevations = Vec::with_capacity(locations.len());
+        let batches: Vec<&[(f64, f64)]> = locations.chunks(self.batch_size.max(1)).collect();
+        for group in batches.chunks(self.concurrency.max(1)) {
+            let results: Vec<Result<Vec<Option<f64>>, String>> = std::thread::scope(|scope| {
+                let requests: Vec<_> = group
+                    .iter()
+                    .map(|batch| scope.spawn(|| self.post(batch)))
+                    .collect();
+                requests
+                    .into_iter()
+                    .map(|request| request.join().unwrap())
+                    .collect()
+            });
+            for result in results {
+                elevations.extend(result?);
+            }
+        }
+        Ok(elevations)
+    }
+}
+
+impl ElevationProvider for ElevationApi {
+    // A failed request aborts the run, as for a batch, rather than the location
+    // being taken as outside of the DEM
+    fn sample_with_source(&self, lon: f64, lat: f64) -> (Sample, bool) {
+        let elevation = self.lookup(&[(lon, lat)]).unwrap_or_else(|e| {
+            log::error!(target: "sample", fields:serde = json!({ "elevation_api": self.url }); "{}", e);
+            std::process::exit(1);
+        })[0];
+        (elevation.map_or(Sample::OutOfExtent, Sample::Value), false)
+    }
+
+    // The locations for which the API has no elevation are outside of the DEM
+    fn sample_batch(&self, locations: &[(f64, f64)]) -> Result<Vec<(Sample, bool)>, String> {
+        Ok(self
+            .lookup(locations)?
+            .into_iter()
+            .map(|elevation| (elevation.map_or(Sample::OutOfExtent, Sample::Value), false))
+            .collect())
+    }
+}
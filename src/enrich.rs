@@ -0,0 +1,111 @@
+use crate::elevation::{Dem, ElevationProvider};
+use crate::gpx::LineMetrics;
+use serde_json::{json, Value};
+use std::io::Write;
+
+// [lon, lat] points of a GeoJSON LineString (the other coordinates being ignored)
+fn points(coordinates: &Value) -> Option<Vec<[f64; 2]>> {
+    coordinates
+        .as_array()?
+        .iter()
+        .map(|position| Some([position[0].as_f64()?, position[1].as_f64()?]))
+        .collect()
+}
+
+// Metrics of the LineString (or MultiLineString) geometry of a feature
+fn metrics(geometry: &Value, dem: &dyn ElevationProvider) -> Option<LineMetrics> {
+    let coordinates = &geometry["coordinates"];
+    match geometry["type"].as_str()? {
+        "LineString" => Some(LineMetrics::compute(&points(coordinates)?, dem)),
+        "MultiLineString" => {
+            let parts: Option<Vec<LineMetrics>> = coordinates
+                .as_array()?
+                .iter()
+                .map(|line| Some(LineMetrics::compute(&points(line)?, dem)))
+                .collect();
+            Some(LineMetrics::sum(&parts?))
+        }
+        _ => None,
+    }
+}
+
+/// Add the metrics of the LineString (or MultiLineString) geometries of the features
+/// of a GeoJSON FeatureCollection (in WGS84) to their properties, from the elevations
+/// sampled on the DEM, the other features being kept as is
+pub fn enrich(
+    collection: &mut Value,
+    dem: &dyn ElevationProvider,
+) -> Result<(usize, usize), String> {
+    let crs = collection["crs"]["properties"]["name"]
+        .as_str()
+        .unwrap_or("");
+    if !collection["crs"].is_null() && !crs.ends_with("CRS84") && !crs.ends_with("4326") {
+        return Err(format!("The features must be in WGS84 (not {})", crs));
+    }
+    let features = collection
+        .get_mut("features")
+        .and_then(Value::as_array_mut)
+        .ok_or("The input is not a GeoJSON FeatureCollection")?;
+    let (mut enriched, mut skipped) = (0, 0);
+    for feature in features {
+        let metrics = match metrics(&feature["geometry"], dem) {
+            Some(metrics) => metrics,
+            None => {
+                skipped += 1;
+                continue;
+            }
+        };
+        if !feature["properties"].is_object() {
+            feature["properties"] = json!({});
+        }
+        let properties = feature["properties"].as_object_mut().unwrap();
+        if let Value::Object(metrics) =
+            serde_json::to_value(metrics).expect("Unable to serialize the metrics")
+        {
+            properties.extend(metrics);
+        }
+        enriched += 1;
+    }
+    Ok((enriched, skipped))
+}
+
+/// Enrich the features of a GeoJSON file with their slope metrics
+pub fn run(input_file: &str, elevation_file: &str, output_file: &str) {
+    let mut collection: Value = std::fs::read_to_string(input_file)
+        .map_err(|e| format!("Unable to read {}: {}", input_file, e))
+        .and_then(|content| {
+            serde_json::from_str(&content)
+                .map_err(|e| format!("Unable to parse {}: {}", input_file, e))
+        })
+        .unwrap_or_else(|e| {
+            log::error!(target: "setup", fields:serde = json!({ "path": input_file }); "{}", e);
+            std::process::exit(1);
+        });
+    let dem = Dem::open(elevation_file).unwrap_or_else(|e| {
+        log::error!(target: "setup", fields:serde = json!({ "elevation_file": elevation_file }); "{}", e);
+        std::process::exit(1);
+    });
+    let (enriched, skipped) = enrich(&mut collection, &dem).unwrap_or_else(|e| {
+        log::error!(target: "setup", fields:serde = json!({ "path": input_file }); "{}", e);
+        std::process::exit(1);
+    });
+    if skipped > 0 {
+        log::warn!(
+            target: "compute",
+            fields:serde = json!({ "skipped_count": skipped });
+            "{} features without a LineString geometry were kept as is", skipped
+        );
+    }
+    log::info!(
+        target: "compute",
+        fields:serde = json!({ "feature_count": enriched });
+        "{} features enriched", enriched
+    );
+    let file = std::fs::File::create(output_file)
+        .unwrap_or_else(|_| panic!("Unable to write output file {}", output_file));
+    let mut out = std::io::BufWriter::new(file);
+    serde_json::to_writer(&mut out, &collection)
+        .map_err(std::io::Error::from)
+        .and_then(|_| out.flush())
+        .unwrap_or_else(|_| panic!("Unable to write output file {}", output_file));
+}
@@ -0,0 +1,188 @@
+use serde_json::{Map, Value};
+
+/// Arithmetic expression over the fields of the result of a way,
+/// e.g. `climb * 10 + distance / 1000`
+#[derive(Debug)]
+pub enum Expr {
+    Number(f64),
+    // Field of the result of the way (booleans are 1 or 0)
+    Field(String),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    // min, max, abs or sqrt
+    Call(String, Vec<Expr>),
+}
+
+const FUNCTIONS: [(&str, usize); 4] = [("min", 2), ("max", 2), ("abs", 1), ("sqrt", 1)];
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().find(|c| !c.is_whitespace())
+    }
+
+    // Consume the next non-whitespace character
+    fn next(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        let c = self.input[self.pos..].chars().next()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{} at position {} of \"{}\"", message, self.pos, self.input)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(self.error(&format!("expected '{}'", expected))),
+        }
+    }
+
+    // Span of the characters matching the predicate, from the current position
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> &'a str {
+        self.skip_whitespace();
+        let rest = &self.input[self.pos..];
+        let len = rest.find(|c| !pred(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    // sum := product (('+' | '-') product)*
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.next();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    // product := unary (('*' | '/') unary)*
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.next();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    // unary := '-' unary | power
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some('-') {
+            self.next();
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    // power := atom ('^' unary)?
+    fn power(&mut self) -> Result<Expr, String> {
+        let expr = self.atom()?;
+        if self.peek() == Some('^') {
+            self.next();
+            return Ok(Expr::Binary('^', Box::new(expr), Box::new(self.unary()?)));
+        }
+        Ok(expr)
+    }
+
+    // atom := number | field | function '(' sum (',' sum)* ')' | '(' sum ')'
+    fn atom(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some('(') => {
+                self.next();
+                let expr = self.sum()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let number = self.take_while(|c| c.is_ascii_digit() || c == '.');
+                number
+                    .parse()
+                    .map(Expr::Number)
+                    .map_err(|_| self.error("invalid number"))
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let name = self.take_while(|c| c.is_alphanumeric() || c == '_' || c == ':');
+                if self.peek() != Some('(') {
+                    return Ok(Expr::Field(name.to_string()));
+                }
+                let arity = match FUNCTIONS.iter().find(|(function, _)| *function == name) {
+                    Some((_, arity)) => *arity,
+                    None => return Err(self.error(&format!("unknown function {}", name))),
+                };
+                self.next();
+                let mut args = vec![self.sum()?];
+                while self.peek() == Some(',') {
+                    self.next();
+                    args.push(self.sum()?);
+                }
+                self.expect(')')?;
+                if args.len() != arity {
+                    return Err(self.error(&format!("{} takes {} arguments", name, arity)));
+                }
+                Ok(Expr::Call(name.to_string(), args))
+            }
+            _ => Err(self.error("expected a number, a field or '('")),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Expr, String> {
+        let mut parser = Parser { input, pos: 0 };
+        let expr = parser.sum()?;
+        match parser.next() {
+            None => Ok(expr),
+            Some(_) => Err(parser.error("unexpected character")),
+        }
+    }
+
+    /// Evaluate the expression, None if a field is missing or isn't a number
+    pub fn eval(&self, fields: &Map<String, Value>) -> Option<f64> {
+        Some(match self {
+            Expr::Number(value) => *value,
+            Expr::Field(name) => match fields.get(name)? {
+                Value::Number(value) => value.as_f64()?,
+                Value::Bool(value) => *value as u8 as f64,
+                _ => return None,
+            },
+            Expr::Neg(expr) => -expr.eval(fields)?,
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(fields)?, b.eval(fields)?);
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    _ => a.powf(b),
+                }
+            }
+            Expr::Call(function, args) => {
+                let args: Vec<f64> = args
+                    .iter()
+                    .map(|arg| arg.eval(fields))
+                    .collect::<Option<_>>()?;
+                match function.as_str() {
+                    "min" => args[0].min(args[1]),
+                    "max" => args[0].max(args[1]),
+                    "abs" => args[0].abs(),
+                    _ => args[0].sqrt(),
+                }
+            }
+        })
+        // Divisions by zero and the like are reported as null rather than inf / NaN
+        .filter(|value| value.is_finite())
+    }
+}
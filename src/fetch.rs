@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+/// Cache of the inputs fetched over HTTP(S)
+pub struct Cache {
+    pub dir: PathBuf,
+    // Download the inputs again, even when they are unchanged
+    pub refresh: bool,
+}
+
+// Validators of a cached file, stored next to it
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Validators {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// Whether an input is a URL rather than a local path
+pub fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// Default cache directory ($XDG_CACHE_HOME/osm-ways-slope or ~/.cache/osm-ways-slope)
+pub fn default_dir() -> PathBuf {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("osm-ways-slope")
+}
+
+/// Description of a failed request, without its URL (whose query may hold a secret)
+pub fn request_error(error: ureq::Error) -> String {
+    match error {
+        ureq::Error::Status(code, _) => format!("HTTP status {}", code),
+        ureq::Error::Transport(transport) => match transport.message() {
+            Some(message) => format!("{} ({})", transport.kind(), message),
+            None => transport.kind().to_string(),
+        },
+    }
+}
+
+impl Cache {
+    /// Local path of an input: the input itself for local paths, or the cached copy
+    /// of a URL, downloaded when it is missing or has changed
+    pub fn resolve(&self, input: &str) -> Result<String, String> {
+        self.resolve_with_query(input, None)
+    }
+
+    /// Same as `resolve`, with a secret query parameter (e.g. an access token) added to
+    /// the request only: it isn't logged, stored with the cached file nor part of its key
+    pub fn resolve_with_query(
+        &self,
+        input: &str,
+        secret_query: Option<(&str, &str)>,
+    ) -> Result<String, String> {
+        if !is_url(input) {
+            return Ok(input.to_string());
+        }
+        std::fs::create_dir_all(&self.dir).map_err(|e| {
+            format!(
+                "Unable to create cache directory {}: {}",
+                self.dir.display(),
+                e
+            )
+        })?;
+        // The name of the cached file keeps the extension(s) of the URL,
+        // from which the formats may be inferred
+        let name = input
+            .split(['?', '#'])
+            .next()
+            .and_then(|url| url.rsplit('/').next())
+            .filter(|name| !name.is_empty())
+            .unwrap_or("input");
+        let key = format!("{:016x}-{}", fxhash::hash64(input), name);
+        let path = self.dir.join(&key);
+        let validators_path = self.dir.join(format!("{}.json", key));
+        let validators: Validators = std::fs::read_to_string(&validators_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+        let mut request = ureq::get(input);
+        if let Some((name, value)) = secret_query {
+            request = request.query(name, value);
+        }
+        if path.exists() && !self.refresh {
+            if let Some(etag) = &validators.etag {
+                request = request.set("If-None-Match", etag);
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                request = request.set("If-Modified-Since", last_modified);
+            }
+        }
+        log::info!(
+            target: "fetch",
+            fields:serde = json!({ "url": input, "cached": path.exists() });
+            "Fetching {}", input
+        );
+        let response = request
+            .call()
+            .map_err(|e| format!("Unable to fetch {}: {}", input, request_error(e)))?;
+
+        if response.status() == 304 {
+            log::info!(
+                target: "fetch",
+                fields:serde = json!({ "url": input, "path": path.display().to_string() });
+                "{} is unchanged, using the cached copy", input
+            );
+        } else {
+            let validators = Validators {
+                url: input.to_string(),
+                etag: response.header("etag").map(str::to_string),
+                last_modified: response.header("last-modified").map(str::to_string),
+            };
+            // Downloaded to a partial file, renamed once complete
+            let partial = self.dir.join(format!("{}.part", key));
+            std::fs::File::create(&partial)
+                .and_then(|mut file| std::io::copy(&mut response.into_reader(), &mut file))
+                .map_err(|e| {
+                    let _ = std::fs::remove_file(&partial);
+                    format!("Unable to fetch {}: {}", input, e)
+                })?;
+            std::fs::rename(&partial, &path)
+                .map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+            let json_str = serde_json::to_string_pretty(&validators)
+                .expect("Unable to serialize cache validators");
+            std::fs::write(&validators_path, json_str)
+                .map_err(|e| format!("Unable to write {}: {}", validators_path.display(), e))?;
+        }
+        Ok(path.to_string_lossy().to_string())
+    }
+}
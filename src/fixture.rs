@@ -0,0 +1,243 @@
+use crate::{haversine_distance, Location};
+#[cfg(feature = "gdal")]
+use gdal::raster::Buffer;
+#[cfg(feature = "gdal")]
+use gdal::spatial_ref::SpatialRef;
+#[cfg(feature = "gdal")]
+use gdal::DriverManager;
+use serde_json::json;
+use std::path::Path;
+
+// Size (in pixels) and resolution (in degrees) of the synthetic DEM
+const DEM_SIZE: usize = 100;
+const DEM_RESOLUTION: f64 = 0.001;
+// Top-left corner of the synthetic DEM
+const DEM_ORIGIN: (f64, f64) = (6.0, 46.1);
+
+// Analytic surface of the DEM: a plane rising by 10 m per column towards the east
+fn surface(col: usize) -> f64 {
+    500. + 10. * col as f64
+}
+
+// (id, tags, (col, row) of the nodes) of the synthetic ways
+type FixtureWay = (
+    i64,
+    [(&'static str, &'static str); 1],
+    &'static [(usize, usize)],
+);
+
+const WAYS: [FixtureWay; 4] = [
+    // Climbing eastward
+    (1, [("highway", "primary")], &[(10, 50), (20, 50), (30, 50)]),
+    // The same way in the other direction, descending
+    (
+        2,
+        [("highway", "secondary")],
+        &[(30, 50), (20, 50), (10, 50)],
+    ),
+    // Following a contour line, flat
+    (
+        3,
+        [("highway", "residential")],
+        &[(40, 10), (40, 30), (40, 50)],
+    ),
+    // Climbing then descending back to its start
+    (
+        4,
+        [("highway", "path")],
+        &[(50, 70), (60, 70), (70, 70), (60, 70), (50, 70)],
+    ),
+];
+
+// Id of the node at the centre of the given pixel
+fn node_id(col: usize, row: usize) -> i64 {
+    (row * DEM_SIZE + col + 1) as i64
+}
+
+// (lon, lat) as decimicro degrees of the centre of the given pixel
+fn pixel_centre(col: usize, row: usize) -> (i32, i32) {
+    let lon = DEM_ORIGIN.0 + (col as f64 + 0.5) * DEM_RESOLUTION;
+    let lat = DEM_ORIGIN.1 - (row as f64 + 0.5) * DEM_RESOLUTION;
+    ((lon * 1e7).round() as i32, (lat * 1e7).round() as i32)
+}
+
+#[cfg(feature = "gdal")]
+fn write_dem(path: &Path) {
+    let driver = DriverManager::get_driver_by_name("GTiff").expect("GTiff driver not available");
+    let mut dataset = driver
+        .create_with_band_type::<f32, _>(path, DEM_SIZE as isize, DEM_SIZE as isize, 1)
+        .expect("Unable to create fixture DEM");
+    dataset
+        .set_geo_transform(&[
+            DEM_ORIGIN.0,
+            DEM_RESOLUTION,
+            0.,
+            DEM_ORIGIN.1,
+            0.,
+            -DEM_RESOLUTION,
+        ])
+        .expect("Unable to set fixture DEM geo transform");
+    dataset
+        .set_spatial_ref(&SpatialRef::from_epsg(4326).unwrap())
+        .expect("Unable to set fixture DEM spatial reference");
+    let data: Vec<f32> = (0..DEM_SIZE * DEM_SIZE)
+        .map(|i| surface(i % DEM_SIZE) as f32)
+        .collect();
+    dataset
+        .rasterband(1)
+        .and_then(|mut band| {
+            band.write(
+                (0, 0),
+                (DEM_SIZE, DEM_SIZE),
+                &Buffer::new((DEM_SIZE, DEM_SIZE), data),
+            )
+        })
+        .expect("Unable to write fixture DEM");
+}
+
+#[cfg(not(feature = "gdal"))]
+fn write_dem(path: &Path) {
+    let data: Vec<f32> = (0..DEM_SIZE * DEM_SIZE)
+        .map(|i| surface(i % DEM_SIZE) as f32)
+        .collect();
+    crate::tiff::write_f32(
+        path,
+        (DEM_SIZE, DEM_SIZE),
+        [
+            DEM_ORIGIN.0,
+            DEM_RESOLUTION,
+            0.,
+            DEM_ORIGIN.1,
+            0.,
+            -DEM_RESOLUTION,
+        ],
+        &data,
+        None,
+        4326,
+    )
+    .expect("Unable to write fixture DEM");
+}
+
+fn write_pbf(path: &Path) {
+    let mut pixels: Vec<(usize, usize)> = WAYS
+        .iter()
+        .flat_map(|(_, _, pixels)| pixels.iter().copied())
+        .collect();
+    pixels.sort_by_key(|(col, row)| node_id(*col, *row));
+    pixels.dedup();
+    let nodes: Vec<osmpbfreader::Node> = pixels
+        .iter()
+        .map(|(col, row)| {
+            let (decimicro_lon, decimicro_lat) = pixel_centre(*col, *row);
+            osmpbfreader::Node {
+                id: osmpbfreader::NodeId(node_id(*col, *row)),
+                tags: osmpbfreader::Tags::new(),
+                decimicro_lat,
+                decimicro_lon,
+            }
+        })
+        .collect();
+    let ways: Vec<osmpbfreader::Way> = WAYS
+        .iter()
+        .map(|(id, tags, pixels)| osmpbfreader::Way {
+            id: osmpbfreader::WayId(*id),
+            tags: tags
+                .iter()
+                .map(|(k, v)| ((*k).into(), (*v).into()))
+                .collect(),
+            nodes: pixels
+                .iter()
+                .map(|(col, row)| osmpbfreader::NodeId(node_id(*col, *row)))
+                .collect(),
+        })
+        .collect();
+
+    let file = std::fs::File::create(path).expect("Unable to create fixture PBF file");
+    let mut writer = crate::pbf::PbfWriter::new(std::io::BufWriter::new(file));
+    writer.write_nodes(&nodes);
+    writer.write_ways(&ways);
+}
+
+// Output expected for the synthetic ways, with the default profile,
+// computed from the analytic surface rather than by sampling the DEM
+fn expected_output() -> serde_json::Value {
+    let mut result = serde_json::Map::new();
+    for (id, _, pixels) in WAYS.iter() {
+        let (mut distance, mut climb_distance, mut descent_distance) = (0., 0., 0.);
+        let (mut climb, mut descent) = (0., 0.);
+        let (mut min_grade, mut max_grade) = (f64::INFINITY, f64::NEG_INFINITY);
+        for pair in pixels.windows(2) {
+            let location = |(col, row): (usize, usize)| {
+                let (lon, lat) = pixel_centre(col, row);
+                Location {
+                    latitude: lat as f64 * 1e-7,
+                    longitude: lon as f64 * 1e-7,
+                }
+            };
+            let segment = haversine_distance(location(pair[0]), location(pair[1])) * 1000.;
+            let change = surface(pair[1].0) - surface(pair[0].0);
+            distance += segment;
+            min_grade = min_grade.min(change / segment * 100.);
+            max_grade = max_grade.max(change / segment * 100.);
+            if change > 0. {
+                climb_distance += segment;
+                climb += change;
+            } else {
+                descent_distance += segment;
+                descent -= change;
+            }
+        }
+        let endpoint = |(col, row): (usize, usize)| {
+            let (lon, lat) = pixel_centre(col, row);
+            (node_id(col, row), [lon as f64 * 1e-7, lat as f64 * 1e-7])
+        };
+        let average = |value: f64, over: f64| if over > 0. { value / over * 100. } else { 0. };
+        let (first_node, first_node_location) = endpoint(pixels[0]);
+        let (last_node, last_node_location) = endpoint(pixels[pixels.len() - 1]);
+        result.insert(
+            id.to_string(),
+            json!({
+                "distance": distance,
+                "climb_distance": climb_distance,
+                "descent_distance": descent_distance,
+                "climb": climb,
+                "descent": descent,
+                "climb_per_km": climb / distance * 1000.,
+                "descent_per_km": descent / distance * 1000.,
+                "climb_forward": climb,
+                "descent_forward": descent,
+                "climb_backward": descent,
+                "descent_backward": climb,
+                "avg_climb_grade": average(climb, climb_distance),
+                "avg_descent_grade": average(descent, descent_distance),
+                "max_grade": max_grade,
+                "min_grade": min_grade,
+                "is_closed": pixels.first() == pixels.last(),
+                "first_node": first_node,
+                "last_node": last_node,
+                "first_node_location": first_node_location,
+                "last_node_location": last_node_location,
+            }),
+        );
+    }
+    serde_json::Value::Object(result)
+}
+
+/// Write a tiny synthetic PBF file, a DEM built from an analytic surface
+/// and the expected output of the default profile to the given directory
+pub fn generate(dir: &str) {
+    let dir = Path::new(dir);
+    std::fs::create_dir_all(dir)
+        .unwrap_or_else(|_| panic!("Unable to create directory {}", dir.display()));
+    write_dem(&dir.join("fixture.tif"));
+    write_pbf(&dir.join("fixture.osm.pbf"));
+    let json_str = serde_json::to_string_pretty(&expected_output())
+        .expect("Unable to serialize expected output");
+    std::fs::write(dir.join("expected.json"), json_str).expect("Unable to write expected output");
+    eprintln!(
+        "Fixture written to {}, run: osm-ways-slope {} {} out.json",
+        dir.display(),
+        dir.join("fixture.osm.pbf").display(),
+        dir.join("fixture.tif").display()
+    );
+}
@@ -0,0 +1,458 @@
+use crate::output::{OutputWriter, Record};
+use crate::reproject::Reprojection;
+use std::io::{BufWriter, Write};
+
+const MAGIC: [u8; 8] = [0x66, 0x67, 0x62, 0x03, 0x66, 0x67, 0x62, 0x00];
+
+// Number of children of the nodes of the spatial index
+const INDEX_NODE_SIZE: u16 = 16;
+
+// GeometryType and ColumnType values of the FlatGeobuf schema
+const GEOMETRY_LINESTRING: u8 = 2;
+const COLUMN_BOOL: u8 = 2;
+const COLUMN_LONG: u8 = 7;
+const COLUMN_DOUBLE: u8 = 10;
+
+// Attributes of the features, after way_id, followed by is_closed
+const DOUBLE_COLUMNS: [&str; 7] = [
+    "distance",
+    "climb_distance",
+    "descent_distance",
+    "climb",
+    "descent",
+    "climb_per_km",
+    "descent_per_km",
+];
+
+// Value of a field of a FlatBuffers table
+enum Field<'a> {
+    U8(u8),
+    Bool(bool),
+    U16(u16),
+    I32(i32),
+    U64(u64),
+    Str(&'a str),
+    Bytes(&'a [u8]),
+    Doubles(&'a [f64]),
+    Table(Table<'a>),
+    Tables(Vec<Table<'a>>),
+}
+
+impl Field<'_> {
+    // Size (and alignment) of the field in its table, the other objects being
+    // referenced by a 32 bits offset
+    fn inline_size(&self) -> usize {
+        match self {
+            Field::U8(_) | Field::Bool(_) => 1,
+            Field::U16(_) => 2,
+            Field::U64(_) => 8,
+            _ => 4,
+        }
+    }
+}
+
+// FlatBuffers table, as (field id, value) pairs
+struct Table<'a>(Vec<(u16, Field<'a>)>);
+
+// Minimal FlatBuffers serializer, writing the objects front to back
+// (each object being followed by the objects it references)
+struct Builder {
+    buf: Vec<u8>,
+}
+
+impl Builder {
+    fn pad(&mut self, alignment: usize) {
+        while !self.buf.len().is_multiple_of(alignment) {
+            self.buf.push(0);
+        }
+    }
+
+    fn patch_offset(&mut self, slot: usize, target: usize) {
+        let offset = (target - slot) as u32;
+        self.buf[slot..slot + 4].copy_from_slice(&offset.to_le_bytes());
+    }
+
+    // Write a table and the objects it references, returning its position
+    fn table(&mut self, table: &Table) -> usize {
+        // Layout of the fields, the largest first
+        let mut fields: Vec<&(u16, Field)> = table.0.iter().collect();
+        fields.sort_by_key(|(_, field)| std::cmp::Reverse(field.inline_size()));
+        let mut positions = Vec::with_capacity(fields.len());
+        let mut size: usize = 4;
+        for (_, field) in &fields {
+            let field_size = field.inline_size();
+            size = size.div_ceil(field_size) * field_size;
+            positions.push(size);
+            size += field_size;
+        }
+        let alignment = fields
+            .first()
+            .map_or(4, |(_, field)| field.inline_size().max(4));
+
+        // The vtable, followed by the table
+        let slots = table
+            .0
+            .iter()
+            .map(|(id, _)| *id as usize + 1)
+            .max()
+            .unwrap_or(0);
+        let mut vtable = vec![0u16; 2 + slots];
+        vtable[0] = (vtable.len() * 2) as u16;
+        vtable[1] = size as u16;
+        for ((id, _), position) in fields.iter().zip(&positions) {
+            vtable[2 + *id as usize] = *position as u16;
+        }
+        self.pad(2);
+        let vtable_position = self.buf.len();
+        self.buf
+            .extend(vtable.iter().flat_map(|value| value.to_le_bytes()));
+        self.pad(alignment);
+        let start = self.buf.len();
+        self.buf.resize(start + size, 0);
+        let soffset = (start - vtable_position) as i32;
+        self.buf[start..start + 4].copy_from_slice(&soffset.to_le_bytes());
+
+        for ((_, field), position) in fields.iter().zip(&positions) {
+            let slot = start + position;
+            let mut inline = |bytes: &[u8]| {
+                self.buf[slot..slot + bytes.len()].copy_from_slice(bytes);
+            };
+            match field {
+                Field::U8(value) => inline(&[*value]),
+                Field::Bool(value) => inline(&[*value as u8]),
+                Field::U16(value) => inline(&value.to_le_bytes()),
+                Field::I32(value) => inline(&value.to_le_bytes()),
+                Field::U64(value) => inline(&value.to_le_bytes()),
+                Field::Str(value) => {
+                    let target = self.vector(value.len(), 1, value.as_bytes());
+                    self.buf.push(0);
+                    self.patch_offset(slot, target);
+                }
+                Field::Bytes(value) => {
+                    let target = self.vector(value.len(), 1, value);
+                    self.patch_offset(slot, target);
+                }
+                Field::Doubles(values) => {
+                    let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+                    let target = self.vector(values.len(), 8, &bytes);
+                    self.patch_offset(slot, target);
+                }
+                Field::Table(child) => {
+                    let target = self.table(child);
+                    self.patch_offset(slot, target);
+                }
+                Field::Tables(children) => {
+                    let target = self.vector(children.len(), 4, &vec![0; children.len() * 4]);
+                    for (i, child) in children.iter().enumerate() {
+                        let child_position = self.table(child);
+                        self.patch_offset(target + 4 + i * 4, child_position);
+                    }
+                    self.patch_offset(slot, target);
+                }
+            }
+        }
+        start
+    }
+
+    // Write a vector (its length, then its elements aligned to `alignment`),
+    // returning its position
+    fn vector(&mut self, len: usize, alignment: usize, bytes: &[u8]) -> usize {
+        self.pad(4);
+        while !(self.buf.len() + 4).is_multiple_of(alignment) {
+            self.buf.push(0);
+        }
+        let position = self.buf.len();
+        self.buf.extend((len as u32).to_le_bytes());
+        self.buf.extend_from_slice(bytes);
+        position
+    }
+}
+
+// Size-prefixed FlatBuffers buffer of a root table
+fn finish(table: &Table) -> Vec<u8> {
+    let mut builder = Builder { buf: vec![0; 8] };
+    let root = builder.table(table);
+    builder.patch_offset(4, root);
+    let size = (builder.buf.len() - 4) as u32;
+    builder.buf[..4].copy_from_slice(&size.to_le_bytes());
+    builder.buf
+}
+
+// Position of (x, y), scaled to 0..65535, along a Hilbert curve
+fn hilbert(x: u32, y: u32) -> u32 {
+    let mut a = x ^ y;
+    let mut b = 0xFFFF ^ a;
+    let mut c = 0xFFFF ^ (x | y);
+    let mut d = x & (y ^ 0xFFFF);
+    let mut big_a = a | (b >> 1);
+    let mut big_b = (a >> 1) ^ a;
+    let mut big_c = ((c >> 1) ^ (b & (d >> 1))) ^ c;
+    let mut big_d = ((a & (c >> 1)) ^ (d >> 1)) ^ d;
+
+    a = big_a;
+    b = big_b;
+    c = big_c;
+    d = big_d;
+    big_a = (a & (a >> 2)) ^ (b & (b >> 2));
+    big_b = (a & (b >> 2)) ^ (b & ((a ^ b) >> 2));
+    big_c ^= (a & (c >> 2)) ^ (b & (d >> 2));
+    big_d ^= (b & (c >> 2)) ^ ((a ^ b) & (d >> 2));
+
+    a = big_a;
+    b = big_b;
+    c = big_c;
+    d = big_d;
+    big_a = (a & (a >> 4)) ^ (b & (b >> 4));
+    big_b = (a & (b >> 4)) ^ (b & ((a ^ b) >> 4));
+    big_c ^= (a & (c >> 4)) ^ (b & (d >> 4));
+    big_d ^= (b & (c >> 4)) ^ ((a ^ b) & (d >> 4));
+
+    a = big_a;
+    b = big_b;
+    c = big_c;
+    d = big_d;
+    big_c ^= (a & (c >> 8)) ^ (b & (d >> 8));
+    big_d ^= (b & (c >> 8)) ^ ((a ^ b) & (d >> 8));
+
+    a = big_c ^ (big_c >> 1);
+    b = big_d ^ (big_d >> 1);
+    let mut i0 = x ^ y;
+    let mut i1 = b | (0xFFFF ^ (i0 | a));
+    for (shift, mask) in [
+        (8, 0x00FF00FF),
+        (4, 0x0F0F0F0F),
+        (2, 0x33333333),
+        (1, 0x55555555),
+    ] {
+        i0 = (i0 | (i0 << shift)) & mask;
+        i1 = (i1 | (i1 << shift)) & mask;
+    }
+    (i1 << 1) | i0
+}
+
+// Bounding box, as [min x, min y, max x, max y]
+type BBox = [f64; 4];
+
+fn expand(bbox: &mut BBox, other: &BBox) {
+    bbox[0] = bbox[0].min(other[0]);
+    bbox[1] = bbox[1].min(other[1]);
+    bbox[2] = bbox[2].max(other[2]);
+    bbox[3] = bbox[3].max(other[3]);
+}
+
+const EMPTY_BBOX: BBox = [
+    f64::INFINITY,
+    f64::INFINITY,
+    f64::NEG_INFINITY,
+    f64::NEG_INFINITY,
+];
+
+// Nodes (bounding box and offset) of a packed Hilbert R-tree over the sorted
+// items, the root first and the leaves (the items) last
+fn packed_rtree(items: &[(BBox, u64)]) -> Vec<(BBox, u64)> {
+    let node_size = INDEX_NODE_SIZE as usize;
+    // Number of nodes of each level, from the leaves to the root
+    let mut level_sizes = vec![items.len()];
+    let mut n = items.len();
+    loop {
+        n = n.div_ceil(node_size);
+        level_sizes.push(n);
+        if n == 1 {
+            break;
+        }
+    }
+    let total: usize = level_sizes.iter().sum();
+    let mut level_starts = Vec::with_capacity(level_sizes.len());
+    let mut end = total;
+    for size in &level_sizes {
+        end -= size;
+        level_starts.push(end);
+    }
+
+    let mut nodes = vec![(EMPTY_BBOX, 0); total];
+    nodes[level_starts[0]..].copy_from_slice(items);
+    for level in 0..level_sizes.len() - 1 {
+        let (start, end) = (
+            level_starts[level],
+            level_starts[level] + level_sizes[level],
+        );
+        for (i, first) in (start..end).step_by(node_size).enumerate() {
+            let mut bbox = EMPTY_BBOX;
+            for child in &nodes[first..(first + node_size).min(end)] {
+                expand(&mut bbox, &child.0);
+            }
+            nodes[level_starts[level + 1] + i] = (bbox, first as u64);
+        }
+    }
+    nodes
+}
+
+/// FlatGeobuf file of the ways (LineString geometries and main metrics), with a
+/// spatial index: the features are first written to a temporary file, then sorted
+/// along a Hilbert curve once all of them are known
+pub struct FlatGeobufWriter<'a> {
+    path: String,
+    temp_path: String,
+    temp: BufWriter<std::fs::File>,
+    reprojection: Option<&'a Reprojection>,
+    // Bounding box, offset in the temporary file and size of each feature
+    features: Vec<(BBox, u64, usize)>,
+    temp_size: u64,
+}
+
+impl<'a> FlatGeobufWriter<'a> {
+    pub fn create(path: &str, reprojection: Option<&'a Reprojection>) -> FlatGeobufWriter<'a> {
+        let temp_path = format!("{}.features", path);
+        let temp = std::fs::File::create(&temp_path)
+            .unwrap_or_else(|_| panic!("Unable to write output file {}", temp_path));
+        FlatGeobufWriter {
+            path: path.to_string(),
+            temp_path,
+            temp: BufWriter::new(temp),
+            reprojection,
+            features: Vec::new(),
+            temp_size: 0,
+        }
+    }
+
+    fn header(&self, envelope: &BBox) -> Vec<u8> {
+        let columns = std::iter::once(("way_id", COLUMN_LONG))
+            .chain(DOUBLE_COLUMNS.iter().map(|name| (*name, COLUMN_DOUBLE)))
+            .chain(std::iter::once(("is_closed", COLUMN_BOOL)))
+            .map(|(name, column_type)| {
+                Table(vec![
+                    (0, Field::Str(name)),
+                    (1, Field::U8(column_type)),
+                    (7, Field::Bool(false)),
+                ])
+            })
+            .collect();
+        // Code of the CRS, when it is an EPSG one
+        let crs = match self.reprojection {
+            None => Table(vec![(1, Field::I32(4326))]),
+            Some(reprojection) => match reprojection.epsg_code() {
+                Some(code) => Table(vec![(1, Field::I32(code))]),
+                None => Table(vec![(5, Field::Str(reprojection.crs()))]),
+            },
+        };
+        let mut fields = vec![
+            (0, Field::Str("ways")),
+            (2, Field::U8(GEOMETRY_LINESTRING)),
+            (7, Field::Tables(columns)),
+            (8, Field::U64(self.features.len() as u64)),
+            (10, Field::Table(crs)),
+        ];
+        if self.features.is_empty() {
+            fields.push((9, Field::U16(0)));
+        } else {
+            fields.push((1, Field::Doubles(envelope)));
+            fields.push((9, Field::U16(INDEX_NODE_SIZE)));
+        }
+        finish(&Table(fields))
+    }
+
+    fn write_file(&mut self) -> std::io::Result<()> {
+        self.temp.flush()?;
+        let temp = std::fs::File::open(&self.temp_path)?;
+        // Safety: the temporary file is only used by this writer
+        let features = unsafe { memmap2::Mmap::map(&temp)? };
+
+        let mut envelope = EMPTY_BBOX;
+        for (bbox, _, _) in &self.features {
+            expand(&mut envelope, bbox);
+        }
+        let (width, height) = (envelope[2] - envelope[0], envelope[3] - envelope[1]);
+        let scale = |value: f64, min: f64, extent: f64| {
+            if extent > 0. {
+                (65535. * (value - min) / extent) as u32
+            } else {
+                0
+            }
+        };
+        let mut order: Vec<usize> = (0..self.features.len()).collect();
+        order.sort_by_cached_key(|i| {
+            let bbox = &self.features[*i].0;
+            hilbert(
+                scale((bbox[0] + bbox[2]) / 2., envelope[0], width),
+                scale((bbox[1] + bbox[3]) / 2., envelope[1], height),
+            )
+        });
+
+        let mut out = BufWriter::new(std::fs::File::create(&self.path)?);
+        out.write_all(&MAGIC)?;
+        out.write_all(&self.header(&envelope))?;
+        if !self.features.is_empty() {
+            // Offsets of the features in the data section, in the sorted order
+            let mut offset = 0;
+            let items: Vec<(BBox, u64)> = order
+                .iter()
+                .map(|i| {
+                    let (bbox, _, size) = self.features[*i];
+                    let item = (bbox, offset);
+                    offset += size as u64;
+                    item
+                })
+                .collect();
+            for (bbox, offset) in packed_rtree(&items) {
+                for value in bbox {
+                    out.write_all(&value.to_le_bytes())?;
+                }
+                out.write_all(&offset.to_le_bytes())?;
+            }
+        }
+        for i in order {
+            let (_, start, size) = self.features[i];
+            out.write_all(&features[start as usize..start as usize + size])?;
+        }
+        out.flush()
+    }
+}
+
+impl OutputWriter for FlatGeobufWriter<'_> {
+    fn write(&mut self, record: &Record) {
+        let coordinates =
+            crate::reproject::output_coordinates(self.reprojection, record.geometry, &self.path);
+        let mut bbox = EMPTY_BBOX;
+        for [x, y] in &coordinates {
+            expand(&mut bbox, &[*x, *y, *x, *y]);
+        }
+        let xy: Vec<f64> = coordinates.iter().flatten().copied().collect();
+
+        // Properties, as the index of each column followed by its value
+        let way = record.info;
+        let mut properties = Vec::with_capacity(2 * 9 + 8 * 8 + 1);
+        properties.extend(0u16.to_le_bytes());
+        properties.extend(record.way_id.to_le_bytes());
+        let doubles = [
+            way.distance,
+            way.climb_distance,
+            way.descent_distance,
+            way.climb,
+            way.descent,
+            way.climb_per_km,
+            way.descent_per_km,
+        ];
+        for (i, value) in doubles.iter().enumerate() {
+            properties.extend((i as u16 + 1).to_le_bytes());
+            properties.extend(value.to_le_bytes());
+        }
+        properties.extend((doubles.len() as u16 + 1).to_le_bytes());
+        properties.push(way.is_closed as u8);
+
+        let feature = finish(&Table(vec![
+            (0, Field::Table(Table(vec![(1, Field::Doubles(&xy))]))),
+            (1, Field::Bytes(&properties)),
+        ]));
+        self.temp
+            .write_all(&feature)
+            .unwrap_or_else(|_| panic!("Unable to write output file {}", self.temp_path));
+        self.features.push((bbox, self.temp_size, feature.len()));
+        self.temp_size += feature.len() as u64;
+    }
+
+    fn finish(mut self: Box<Self>) {
+        self.write_file()
+            .unwrap_or_else(|e| panic!("Unable to write output file {}: {}", self.path, e));
+        let _ = std::fs::remove_file(&self.temp_path);
+    }
+}
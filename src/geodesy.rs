@@ -0,0 +1,93 @@
+//! Distances on the sphere between (lon, lat) locations, in degrees
+
+#[derive(Debug)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Wrap a longitude difference to the [-180, 180] range so that segments
+/// crossing the antimeridian are measured the short way around
+pub fn wrap_longitude_delta(d_lon: f64) -> f64 {
+    (d_lon + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Great-circle distance between two locations, in kilometers
+pub fn haversine_distance(start: Location, end: Location) -> f64 {
+    let d_lat: f64 = (end.latitude - start.latitude).to_radians();
+    let d_lon: f64 = wrap_longitude_delta(end.longitude - start.longitude).to_radians();
+    let lat1: f64 = (start.latitude).to_radians();
+    let lat2: f64 = (end.latitude).to_radians();
+
+    let a: f64 = ((d_lat / 2.0).sin()) * ((d_lat / 2.0).sin())
+        + ((d_lon / 2.0).sin()) * ((d_lon / 2.0).sin()) * (lat1.cos()) * (lat2.cos());
+    let c: f64 = 2.0 * ((a.sqrt()).atan2((1.0 - a).sqrt()));
+
+    6371.0 * c
+}
+
+/// Fill `distances` with the cumulative distance (in meters) of each
+/// [lon, lat] coordinate from the first one
+pub fn cumulative_distances(coordinates: &[[f64; 2]], distances: &mut Vec<f64>) {
+    distances.clear();
+    let mut distance = 0.;
+    for (i, [lon, lat]) in coordinates.iter().enumerate() {
+        if i > 0 {
+            let [previous_lon, previous_lat] = coordinates[i - 1];
+            distance += haversine_distance(
+                Location {
+                    latitude: previous_lat,
+                    longitude: previous_lon,
+                },
+                Location {
+                    latitude: *lat,
+                    longitude: *lon,
+                },
+            ) * 1000.;
+        }
+        distances.push(distance);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Length of a degree of a great circle, in kilometers
+    const DEGREE: f64 = 111.19492664455873;
+
+    #[test]
+    fn longitude_deltas_across_the_antimeridian() {
+        assert_eq!(wrap_longitude_delta(10.), 10.);
+        assert_eq!(wrap_longitude_delta(359.), -1.);
+        assert_eq!(wrap_longitude_delta(-359.), 1.);
+        assert_eq!(wrap_longitude_delta(190.), -170.);
+        let distance = haversine_distance(
+            Location {
+                latitude: 0.,
+                longitude: 179.5,
+            },
+            Location {
+                latitude: 0.,
+                longitude: -179.5,
+            },
+        );
+        assert!((distance - DEGREE).abs() < 1e-9);
+    }
+
+    #[test]
+    fn cumulative_distances_of_the_nodes() {
+        let mut distances = vec![42.];
+        cumulative_distances(&[[0., 0.], [1., 0.], [1., 1.], [1., 1.]], &mut distances);
+        let expected = [0., DEGREE * 1000., DEGREE * 2000., DEGREE * 2000.];
+        assert_eq!(distances.len(), expected.len());
+        for (distance, expected) in distances.iter().zip(expected) {
+            assert!(
+                (distance - expected).abs() < 1e-6,
+                "{} != {}",
+                distance,
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,80 @@
+/// Portion of a line between two distances along it (in meters), given the
+/// cumulative distances of its vertices; the endpoints are linearly interpolated
+pub fn sub_line(
+    coordinates: &[[f64; 2]],
+    distances: &[f64],
+    start: f64,
+    end: f64,
+) -> Vec<[f64; 2]> {
+    let interpolate = |d: f64| -> [f64; 2] {
+        let i = distances
+            .partition_point(|x| *x < d)
+            .clamp(1, distances.len() - 1);
+        let length = distances[i] - distances[i - 1];
+        let t = if length > 0. {
+            ((d - distances[i - 1]) / length).clamp(0., 1.)
+        } else {
+            0.
+        };
+        let (a, b) = (coordinates[i - 1], coordinates[i]);
+        [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t]
+    };
+
+    let mut line = vec![interpolate(start)];
+    line.extend(
+        distances
+            .iter()
+            .zip(coordinates.iter())
+            .filter(|(d, _)| **d > start && **d < end)
+            .map(|(_, c)| *c),
+    );
+    line.push(interpolate(end));
+    line
+}
+
+// Distance (in meters) from p to the segment [a, b], the coordinates being
+// projected on a plane tangent at a
+fn segment_distance(p: [f64; 2], a: [f64; 2], b: [f64; 2]) -> f64 {
+    const EARTH_RADIUS: f64 = 6371000.;
+    let scale_x = a[1].to_radians().cos() * EARTH_RADIUS;
+    let project = |c: [f64; 2]| {
+        [
+            crate::geodesy::wrap_longitude_delta(c[0] - a[0]).to_radians() * scale_x,
+            (c[1] - a[1]).to_radians() * EARTH_RADIUS,
+        ]
+    };
+    let (p, b) = (project(p), project(b));
+    let length2 = b[0] * b[0] + b[1] * b[1];
+    let t = if length2 > 0. {
+        ((p[0] * b[0] + p[1] * b[1]) / length2).clamp(0., 1.)
+    } else {
+        0.
+    };
+    (p[0] - t * b[0]).hypot(p[1] - t * b[1])
+}
+
+/// Indices of the vertices kept by a Douglas-Peucker simplification of the line,
+/// with a tolerance in meters (the first and last vertices are always kept)
+pub fn simplify(coordinates: &[[f64; 2]], tolerance: f64) -> Vec<usize> {
+    if coordinates.len() < 3 {
+        return (0..coordinates.len()).collect();
+    }
+    let mut keep = vec![false; coordinates.len()];
+    keep[0] = true;
+    keep[coordinates.len() - 1] = true;
+    let mut stack = vec![(0, coordinates.len() - 1)];
+    while let Some((start, end)) = stack.pop() {
+        let (a, b) = (coordinates[start], coordinates[end]);
+        let farthest = (start + 1..end)
+            .map(|i| (i, segment_distance(coordinates[i], a, b)))
+            .max_by(|x, y| x.1.total_cmp(&y.1));
+        if let Some((i, distance)) = farthest {
+            if distance > tolerance {
+                keep[i] = true;
+                stack.push((start, i));
+                stack.push((i, end));
+            }
+        }
+    }
+    (0..coordinates.len()).filter(|i| keep[*i]).collect()
+}
@@ -0,0 +1,136 @@
+use crate::output::{OutputWriter, Record};
+use crate::reproject::Reprojection;
+use gdal::spatial_ref::SpatialRef;
+use gdal::vector::{FieldValue, Geometry, LayerAccess, OGRFieldType, OGRwkbGeometryType};
+use gdal::{Dataset, DriverManager, LayerOptions};
+
+// Name of the layer of the ways
+const LAYER_NAME: &str = "ways";
+
+// Number of features written in each transaction
+const BATCH_SIZE: usize = 10_000;
+
+// Attributes of the features, the real ones being followed by is_closed
+const REAL_FIELDS: [&str; 7] = [
+    "distance",
+    "climb_distance",
+    "descent_distance",
+    "climb",
+    "descent",
+    "climb_per_km",
+    "descent_per_km",
+];
+
+// Feature waiting to be written
+struct Pending {
+    way_id: i64,
+    coordinates: Vec<[f64; 2]>,
+    reals: [f64; 7],
+    is_closed: bool,
+}
+
+/// GeoPackage layer of the ways, with their LineString geometries and main metrics
+pub struct GpkgWriter<'a> {
+    path: String,
+    dataset: Dataset,
+    reprojection: Option<&'a Reprojection>,
+    batch: Vec<Pending>,
+}
+
+impl<'a> GpkgWriter<'a> {
+    /// Create the GeoPackage (replacing any existing file) and its layer
+    pub fn create(
+        path: &str,
+        reprojection: Option<&'a Reprojection>,
+    ) -> Result<GpkgWriter<'a>, String> {
+        let error = |e: gdal::errors::GdalError| format!("Unable to create {}: {}", path, e);
+        let driver = DriverManager::get_driver_by_name("GPKG").map_err(error)?;
+        if std::path::Path::new(path).exists() {
+            std::fs::remove_file(path).map_err(|e| format!("Unable to replace {}: {}", path, e))?;
+        }
+        let mut dataset = driver.create_vector_only(path).map_err(error)?;
+        let srs = match reprojection {
+            Some(reprojection) => SpatialRef::from_definition(reprojection.crs()),
+            None => SpatialRef::from_epsg(4326),
+        }
+        .map_err(error)?;
+        let layer = dataset
+            .create_layer(LayerOptions {
+                name: LAYER_NAME,
+                srs: Some(&srs),
+                ty: OGRwkbGeometryType::wkbLineString,
+                options: None,
+            })
+            .map_err(error)?;
+        let mut fields = vec![("way_id", OGRFieldType::OFTInteger64)];
+        fields.extend(
+            REAL_FIELDS
+                .iter()
+                .map(|name| (*name, OGRFieldType::OFTReal)),
+        );
+        fields.push(("is_closed", OGRFieldType::OFTInteger));
+        layer.create_defn_fields(&fields).map_err(error)?;
+        Ok(GpkgWriter {
+            path: path.to_string(),
+            dataset,
+            reprojection,
+            batch: Vec::with_capacity(BATCH_SIZE),
+        })
+    }
+
+    fn write_batch(&mut self) -> gdal::errors::Result<()> {
+        let transaction = self.dataset.start_transaction()?;
+        let mut layer = transaction.layer_by_name(LAYER_NAME)?;
+        let mut names = vec!["way_id"];
+        names.extend(REAL_FIELDS);
+        names.push("is_closed");
+        for feature in self.batch.drain(..) {
+            let mut geometry = Geometry::empty(OGRwkbGeometryType::wkbLineString)?;
+            let coordinates = crate::reproject::output_coordinates(
+                self.reprojection,
+                &feature.coordinates,
+                &self.path,
+            );
+            for [x, y] in coordinates {
+                geometry.add_point_2d((x, y));
+            }
+            let mut values = vec![FieldValue::Integer64Value(feature.way_id)];
+            values.extend(feature.reals.map(FieldValue::RealValue));
+            values.push(FieldValue::IntegerValue(feature.is_closed as i32));
+            layer.create_feature_fields(geometry, &names, &values)?;
+        }
+        transaction.commit()
+    }
+}
+
+impl OutputWriter for GpkgWriter<'_> {
+    fn write(&mut self, record: &Record) {
+        let way = record.info;
+        let reals = [
+            way.distance,
+            way.climb_distance,
+            way.descent_distance,
+            way.climb,
+            way.descent,
+            way.climb_per_km,
+            way.descent_per_km,
+        ];
+        self.batch.push(Pending {
+            way_id: record.way_id,
+            coordinates: record.geometry.to_vec(),
+            reals,
+            is_closed: way.is_closed,
+        });
+        if self.batch.len() == BATCH_SIZE {
+            let path = self.path.clone();
+            self.write_batch()
+                .unwrap_or_else(|e| panic!("Unable to write output file {}: {}", path, e));
+        }
+    }
+
+    fn finish(mut self: Box<Self>) {
+        let path = self.path.clone();
+        self.write_batch()
+            .unwrap_or_else(|e| panic!("Unable to write output file {}: {}", path, e));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::elevation::{Dem, ElevationProvider};
+use crate::osmxml::{attribute, Element, XmlReader};
+use crate::{geodesy, gradient};
+use serde::Serialize;
+
+/// Climb and descent metrics of a line (a GPX track or one of its segments,
+/// or a GeoJSON LineString)
+#[derive(Debug, Default, Serialize)]
+pub struct LineMetrics {
+    distance: f64,
+    climb_distance: f64,
+    descent_distance: f64,
+    climb: f64,
+    descent: f64,
+    // Climb and descent (in meters) per kilometer
+    climb_per_km: f64,
+    descent_per_km: f64,
+}
+
+impl LineMetrics {
+    /// Metrics of the [lon, lat] points of a line, from the elevations sampled on the DEM
+    pub fn compute(points: &[[f64; 2]], dem: &dyn ElevationProvider) -> LineMetrics {
+        let elevations: Vec<Option<f64>> = points
+            .iter()
+            // The nodata pixels and the locations outside of the DEM have no elevation
+            .map(|[lon, lat]| dem.elevation(*lon, *lat))
+            .collect();
+        let mut distances = Vec::with_capacity(points.len());
+        geodesy::cumulative_distances(points, &mut distances);
+        let totals = gradient::ClimbDescent::compute(&distances, &elevations);
+        LineMetrics {
+            distance: distances.last().copied().unwrap_or(0.),
+            climb_distance: totals.climb_distance,
+            descent_distance: totals.descent_distance,
+            climb: totals.climb,
+            descent: totals.descent,
+            ..Default::default()
+        }
+        .with_per_km()
+    }
+
+    /// Totals of the metrics of several lines (the gaps between them not being counted)
+    pub fn sum(parts: &[LineMetrics]) -> LineMetrics {
+        let sum = |metric: fn(&LineMetrics) -> f64| parts.iter().map(metric).sum();
+        LineMetrics {
+            distance: sum(|part| part.distance),
+            climb_distance: sum(|part| part.climb_distance),
+            descent_distance: sum(|part| part.descent_distance),
+            climb: sum(|part| part.climb),
+            descent: sum(|part| part.descent),
+            ..Default::default()
+        }
+        .with_per_km()
+    }
+
+    fn with_per_km(mut self) -> LineMetrics {
+        if self.distance > 0. {
+            self.climb_per_km = self.climb / self.distance * 1000.;
+            self.descent_per_km = self.descent / self.distance * 1000.;
+        }
+        self
+    }
+}
+
+/// Metrics of a track (or route) of a GPX file, the totals of its segments
+#[derive(Debug, Serialize)]
+pub struct TrackInfo {
+    name: Option<String>,
+    #[serde(flatten)]
+    totals: LineMetrics,
+    segments: Vec<LineMetrics>,
+}
+
+// Name and [lon, lat] points of the segments of a track
+#[derive(Default)]
+struct Track {
+    name: Option<String>,
+    segments: Vec<Vec<[f64; 2]>>,
+}
+
+// Tracks of the file, the routes being read as tracks of a single segment
+fn read_tracks(gpx_file: &str) -> Vec<Track> {
+    let file = std::fs::File::open(gpx_file)
+        .unwrap_or_else(|e| panic!("Unable to open GPX file {}: {}", gpx_file, e));
+    let mut reader = XmlReader::new(std::io::BufReader::new(file));
+    let mut tracks: Vec<Track> = Vec::new();
+    // The names of the file, of its waypoints and of the points of the tracks are skipped
+    let mut in_track = false;
+    let mut in_point = false;
+    loop {
+        let element = reader
+            .next_element()
+            .unwrap_or_else(|e| panic!("Unable to read GPX file {}: {}", gpx_file, e));
+        let (name, attributes, is_start) = match element {
+            None => break,
+            Some(Element::End(name)) => {
+                match name.as_str() {
+                    "trk" | "rte" => in_track = false,
+                    "trkpt" | "rtept" => in_point = false,
+                    "name" if in_track && !in_point => {
+                        if let Some(track) = tracks.last_mut() {
+                            track.name.get_or_insert_with(|| reader.text.clone());
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            Some(Element::Start(name, attributes)) => (name, attributes, true),
+            Some(Element::Empty(name, attributes)) => (name, attributes, false),
+        };
+        match name.as_str() {
+            "trk" | "rte" => {
+                in_track = is_start;
+                let mut track = Track::default();
+                if name == "rte" {
+                    track.segments.push(Vec::new());
+                }
+                tracks.push(track);
+            }
+            "trkseg" => {
+                if let Some(track) = tracks.last_mut() {
+                    track.segments.push(Vec::new());
+                }
+            }
+            "trkpt" | "rtept" => {
+                in_point = is_start;
+                let coordinate = |name| {
+                    attribute(&attributes, name)
+                        .and_then(|value| value.trim().parse::<f64>().ok())
+                        .unwrap_or_else(|| panic!("Invalid {} of a point in {}", name, gpx_file))
+                };
+                let point = [coordinate("lon"), coordinate("lat")];
+                if let Some(segment) = tracks
+                    .last_mut()
+                    .and_then(|track| track.segments.last_mut())
+                {
+                    segment.push(point);
+                }
+            }
+            _ => {}
+        }
+    }
+    tracks
+}
+
+/// Compute the metrics of every track (and route) of the GPX file, from the
+/// elevations sampled on the DEM along them (their own elevations being ignored)
+pub fn compute(gpx_file: &str, dem: &dyn ElevationProvider) -> Vec<TrackInfo> {
+    read_tracks(gpx_file)
+        .into_iter()
+        .map(|track| {
+            let segments: Vec<LineMetrics> = track
+                .segments
+                .iter()
+                .map(|points| LineMetrics::compute(points, dem))
+                .collect();
+            TrackInfo {
+                name: track.name,
+                totals: LineMetrics::sum(&segments),
+                segments,
+            }
+        })
+        .collect()
+}
+
+/// Compute the metrics of the tracks of a GPX file and write them as JSON
+pub fn run(gpx_file: &str, elevation_file: &str, output_file: &str) {
+    let dem = Dem::open(elevation_file).unwrap_or_else(|e| {
+        log::error!(target: "setup", fields:serde = serde_json::json!({ "elevation_file": elevation_file }); "{}", e);
+        std::process::exit(1);
+    });
+    let result = compute(gpx_file, &dem);
+    log::info!(
+        target: "compute",
+        fields:serde = serde_json::json!({ "track_count": result.len() });
+        "{} tracks computed", result.len()
+    );
+    let json_str = serde_json::to_string(&result).expect("Unable to serialize result to string");
+    std::fs::write(output_file, json_str).expect("Unable to write file");
+}
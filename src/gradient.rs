@@ -0,0 +1,398 @@
+/// Portion of a way steeper than a given gradient
+#[derive(Debug)]
+pub struct Section {
+    // Distances from the start of the way, in meters
+    pub start: f64,
+    pub end: f64,
+    // Net gradient over the section, in per-mille (signed, in the direction of the way)
+    pub gradient: f64,
+}
+
+/// Net gradient (rise over run, signed) between the first and last nodes
+/// with a known elevation, given the cumulative distances of the nodes
+pub fn net_gradient(distances: &[f64], elevations: &[Option<f64>]) -> Option<f64> {
+    let mut known = distances
+        .iter()
+        .zip(elevations.iter())
+        .filter_map(|(d, e)| e.map(|e| (*d, e)));
+    match (known.next(), known.next_back()) {
+        (Some((d_first, first)), Some((d_last, last))) if d_last > d_first => {
+            Some((last - first) / (d_last - d_first))
+        }
+        _ => None,
+    }
+}
+
+/// Gradient (in per-mille, signed) of the steepest segment of a way
+pub fn max_segment_gradient(distances: &[f64], elevations: &[Option<f64>]) -> f64 {
+    let mut max_gradient: f64 = 0.;
+    for i in 1..elevations.len() {
+        let length = distances[i] - distances[i - 1];
+        if let (Some(ele_a), Some(ele_b)) = (elevations[i - 1], elevations[i]) {
+            let gradient = (ele_b - ele_a) / length * 1000.;
+            if length > 0. && gradient.abs() > max_gradient.abs() {
+                max_gradient = gradient;
+            }
+        }
+    }
+    max_gradient
+}
+
+/// Steepest descent and climb (grades in %, signed) over the segments of a way
+/// with a known elevation at both ends, None when there are none
+pub fn grade_range(distances: &[f64], elevations: &[Option<f64>]) -> Option<(f64, f64)> {
+    let mut range: Option<(f64, f64)> = None;
+    for i in 1..elevations.len() {
+        let length = distances[i] - distances[i - 1];
+        if let (Some(ele_a), Some(ele_b)) = (elevations[i - 1], elevations[i]) {
+            if length > 0. {
+                let grade = (ele_b - ele_a) / length * 100.;
+                range = Some(match range {
+                    Some((min, max)) => (min.min(grade), max.max(grade)),
+                    None => (grade, grade),
+                });
+            }
+        }
+    }
+    range
+}
+
+/// Length of the segments of a way in each class of absolute grade, given the
+/// increasing upper bounds (in %) of the classes, the last class being unbounded
+/// (the segments without a known elevation at both ends are not counted)
+pub fn grade_histogram(distances: &[f64], elevations: &[Option<f64>], bounds: &[f64]) -> Vec<f64> {
+    let mut histogram = vec![0.; bounds.len() + 1];
+    for i in 1..elevations.len() {
+        let length = distances[i] - distances[i - 1];
+        if let (Some(ele_a), Some(ele_b)) = (elevations[i - 1], elevations[i]) {
+            if length > 0. {
+                let grade = (ele_b - ele_a).abs() / length * 100.;
+                let class = bounds
+                    .iter()
+                    .position(|bound| grade < *bound)
+                    .unwrap_or(bounds.len());
+                histogram[class] += length;
+            }
+        }
+    }
+    histogram
+}
+
+/// Walking time (in seconds) along a way, forward or backward, with Tobler's hiking
+/// function: 6 * exp(-3.5 * |grade + 0.05|) km/h on each segment, the segments
+/// without a known elevation at both ends being walked as on flat ground
+pub fn walk_time(distances: &[f64], elevations: &[Option<f64>], backward: bool) -> f64 {
+    let mut time = 0.;
+    for i in 1..elevations.len() {
+        let length = distances[i] - distances[i - 1];
+        if length <= 0. {
+            continue;
+        }
+        let grade = match (elevations[i - 1], elevations[i]) {
+            (Some(ele_a), Some(ele_b)) if backward => (ele_a - ele_b) / length,
+            (Some(ele_a), Some(ele_b)) => (ele_b - ele_a) / length,
+            _ => 0.,
+        };
+        let speed = 6. * (-3.5 * (grade + 0.05).abs()).exp() / 3.6;
+        time += length / speed;
+    }
+    time
+}
+
+/// Walking time (in seconds) along a way, forward or backward, with Naismith's rule
+/// (5 km/h, plus 1 hour per 600 m of climb) and Langmuir's corrections for the
+/// descents: 10 minutes less per 300 m of descent between 5 and 12 degrees, and 10
+/// minutes more per 300 m steeper than 12 degrees. The segments without a known
+/// elevation at both ends are walked as on flat ground.
+pub fn naismith_walk_time(distances: &[f64], elevations: &[Option<f64>], backward: bool) -> f64 {
+    let mut time = 0.;
+    for i in 1..elevations.len() {
+        let length = distances[i] - distances[i - 1];
+        if length <= 0. {
+            continue;
+        }
+        time += length / 5000. * 3600.;
+        let change = match (elevations[i - 1], elevations[i]) {
+            (Some(ele_a), Some(ele_b)) if backward => ele_a - ele_b,
+            (Some(ele_a), Some(ele_b)) => ele_b - ele_a,
+            _ => 0.,
+        };
+        let angle = (change.abs() / length).atan().to_degrees();
+        if change > 0. {
+            time += change / 600. * 3600.;
+        } else if angle > 12. {
+            time -= change / 300. * 600.;
+        } else if angle > 5. {
+            time += change / 300. * 600.;
+        }
+    }
+    time
+}
+
+/// Sections of consecutive segments whose gradient, in the same direction,
+/// is steeper than the threshold (in per-mille)
+pub fn steep_sections(
+    distances: &[f64],
+    elevations: &[Option<f64>],
+    threshold: f64,
+) -> Vec<Section> {
+    let mut sections: Vec<Section> = Vec::new();
+    // Whether the previous segment was part of the last section
+    let mut in_section = false;
+
+    for i in 1..elevations.len() {
+        let length = distances[i] - distances[i - 1];
+        let (ele_a, ele_b) = match (elevations[i - 1], elevations[i]) {
+            (Some(ele_a), Some(ele_b)) if length > 0. => (ele_a, ele_b),
+            _ => {
+                in_section = false;
+                continue;
+            }
+        };
+        let gradient = (ele_b - ele_a) / length * 1000.;
+        if gradient.abs() <= threshold {
+            in_section = false;
+            continue;
+        }
+        match sections.last_mut() {
+            Some(section) if in_section && section.gradient.signum() == gradient.signum() => {
+                // Extend the current section, its gradient being the net gradient over its length
+                let rise =
+                    section.gradient * (section.end - section.start) / 1000. + (ele_b - ele_a);
+                section.end = distances[i];
+                section.gradient = rise / (section.end - section.start) * 1000.;
+            }
+            _ => sections.push(Section {
+                start: distances[i - 1],
+                end: distances[i],
+                gradient,
+            }),
+        }
+        in_section = true;
+    }
+    sections
+}
+
+/// Steepest net gradient (rise over run, absolute) over any portion of the way
+/// at least `window` meters long (or over the whole way if it is shorter)
+pub fn max_sustained_gradient(distances: &[f64], elevations: &[Option<f64>], window: f64) -> f64 {
+    let total = distances.last().copied().unwrap_or(0.);
+    let window = window.min(total);
+    let mut max_gradient: f64 = 0.;
+    let mut j = 0;
+    for i in 0..elevations.len() {
+        let ele_i = match elevations[i] {
+            Some(ele_i) => ele_i,
+            None => continue,
+        };
+        // First node at least `window` meters further along the way
+        j = j.max(i + 1);
+        while j < elevations.len() && distances[j] - distances[i] < window {
+            j += 1;
+        }
+        if j == elevations.len() {
+            break;
+        }
+        if let Some(ele_j) = elevations[j] {
+            let length = distances[j] - distances[i];
+            if length > 0. {
+                max_gradient = max_gradient.max((ele_j - ele_i).abs() / length);
+            }
+        }
+    }
+    max_gradient
+}
+
+/// Total climb and descent of a way, and the distances over which they occur
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ClimbDescent {
+    pub climb_distance: f64,
+    pub descent_distance: f64,
+    pub climb: f64,
+    pub descent: f64,
+}
+
+impl ClimbDescent {
+    pub fn compute(distances: &[f64], elevations: &[Option<f64>]) -> ClimbDescent {
+        let mut totals = ClimbDescent::default();
+        for i in 1..elevations.len() {
+            let segment_distance = distances[i] - distances[i - 1];
+
+            // Segments with a node outside of the DEM don't contribute to climb / descent
+            let (ele_a, ele_b) = match (elevations[i - 1], elevations[i]) {
+                (Some(ele_a), Some(ele_b)) => (ele_a, ele_b),
+                _ => continue,
+            };
+
+            if ele_a < ele_b {
+                totals.climb_distance += segment_distance;
+                totals.climb += ele_b - ele_a;
+            } else {
+                totals.descent_distance += segment_distance;
+                totals.descent += ele_a - ele_b;
+            }
+        }
+        totals
+    }
+
+    /// Climb and descent ignoring the elevation changes smaller than the threshold,
+    /// which are considered as noise of the DEM
+    pub fn compute_with_hysteresis(
+        distances: &[f64],
+        elevations: &[Option<f64>],
+        threshold: f64,
+    ) -> ClimbDescent {
+        let mut totals = ClimbDescent::default();
+        // Elevation and distance of the last point where a change was committed
+        let mut reference: Option<(f64, f64)> = None;
+        let mut last_distance = 0.;
+        for (distance, elevation) in distances.iter().zip(elevations) {
+            let elevation = match elevation {
+                Some(elevation) => *elevation,
+                None => continue,
+            };
+            last_distance = *distance;
+            let (ref_elevation, ref_distance) = match reference {
+                Some(reference) => reference,
+                None => {
+                    reference = Some((elevation, *distance));
+                    continue;
+                }
+            };
+            let change = elevation - ref_elevation;
+            if change.abs() < threshold {
+                continue;
+            }
+            if change > 0. {
+                totals.climb_distance += distance - ref_distance;
+                totals.climb += change;
+            } else {
+                totals.descent_distance += distance - ref_distance;
+                totals.descent -= change;
+            }
+            reference = Some((elevation, *distance));
+        }
+        // As with the segments, the remaining (flat) part counts as descent
+        if let Some((_, ref_distance)) = reference {
+            totals.descent_distance += last_distance - ref_distance;
+        }
+        totals
+    }
+
+    /// Standard deviations of the climb and descent propagated (to the first order)
+    /// from the standard deviations of the elevations, the errors of the nodes being
+    /// considered independent. The segments are classified as in `compute`.
+    pub fn sigma(elevations: &[Option<f64>], sigmas: &[f64]) -> (f64, f64) {
+        // Derivatives of the climb and descent with respect to the elevation of each node
+        let mut climb_derivatives = vec![0.; elevations.len()];
+        let mut descent_derivatives = vec![0.; elevations.len()];
+        for i in 1..elevations.len() {
+            if let (Some(ele_a), Some(ele_b)) = (elevations[i - 1], elevations[i]) {
+                if ele_a < ele_b {
+                    climb_derivatives[i] += 1.;
+                    climb_derivatives[i - 1] -= 1.;
+                } else {
+                    descent_derivatives[i - 1] += 1.;
+                    descent_derivatives[i] -= 1.;
+                }
+            }
+        }
+        let propagate = |derivatives: Vec<f64>| {
+            derivatives
+                .iter()
+                .zip(sigmas)
+                .map(|(derivative, sigma)| (derivative * sigma).powi(2))
+                .sum::<f64>()
+                .sqrt()
+        };
+        (propagate(climb_derivatives), propagate(descent_derivatives))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn climb_and_descent() {
+        let distances = [0., 100., 200., 300., 400., 500.];
+        let elevations = [Some(10.), Some(20.), Some(15.), Some(15.), None, Some(30.)];
+        let totals = ClimbDescent::compute(&distances, &elevations);
+        assert_close(totals.climb, 10.);
+        assert_close(totals.climb_distance, 100.);
+        // The flat segment counts as descent, the segments without elevation don't count
+        assert_close(totals.descent, 5.);
+        assert_close(totals.descent_distance, 200.);
+    }
+
+    #[test]
+    fn climb_and_descent_with_hysteresis() {
+        let distances = [0., 100., 200., 300., 400.];
+        let elevations = [Some(100.), Some(101.), Some(99.), Some(105.), Some(104.)];
+        let totals = ClimbDescent::compute(&distances, &elevations);
+        assert_close(totals.climb, 7.);
+        assert_close(totals.descent, 3.);
+        // The changes below 3 m are ignored
+        let totals = ClimbDescent::compute_with_hysteresis(&distances, &elevations, 3.);
+        assert_close(totals.climb, 5.);
+        assert_close(totals.climb_distance, 300.);
+        assert_close(totals.descent, 0.);
+        assert_close(totals.descent_distance, 100.);
+    }
+
+    #[test]
+    fn grade_classes() {
+        let distances = [0., 100., 200., 300., 400., 500.];
+        let elevations = [Some(0.), Some(1.), Some(5.), Some(-5.), Some(-3.), None];
+        // Grades of 1%, 4%, 10% (descending) and 2% (on the bound of the second class)
+        let histogram = grade_histogram(&distances, &elevations, &[2., 5.]);
+        assert_eq!(histogram, vec![100., 200., 100.]);
+    }
+
+    #[test]
+    fn tobler_walk_time() {
+        let distances = [0., 1000.];
+        assert_close(
+            walk_time(&distances, &[Some(0.), Some(0.)], false),
+            714.7477299674148,
+        );
+        assert_close(
+            walk_time(&distances, &[None, Some(0.)], false),
+            714.7477299674148,
+        );
+        // 6 km/h on a 5% descent, the fastest grade
+        assert_close(walk_time(&distances, &[Some(50.), Some(0.)], false), 600.);
+        assert_close(
+            walk_time(&distances, &[Some(50.), Some(0.)], true),
+            851.4405291559543,
+        );
+    }
+
+    #[test]
+    fn naismith_walk_times() {
+        // 5 km/h, plus 1 hour per 600 m of climb
+        let climb = [Some(0.), Some(60.)];
+        assert_close(naismith_walk_time(&[0., 1000.], &climb, false), 1080.);
+        // A gentle descent (3.4 degrees) takes as long as the flat
+        assert_close(naismith_walk_time(&[0., 1000.], &climb, true), 720.);
+        // 10 minutes less per 300 m of descent between 5 and 12 degrees (8.5 degrees)
+        assert_close(
+            naismith_walk_time(&[0., 100.], &[Some(15.), Some(0.)], false),
+            42.,
+        );
+        // 10 minutes more per 300 m of descent steeper than 12 degrees (16.7 degrees)
+        assert_close(
+            naismith_walk_time(&[0., 100.], &[Some(30.), Some(0.)], false),
+            132.,
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use crate::elevation::{Dem, ElevationProvider};
+use crate::{geodesy, gradient};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+
+/// Climb and grade statistics of a GTFS shape
+#[derive(Debug, Serialize)]
+pub struct ShapeInfo {
+    distance: f64,
+    climb_distance: f64,
+    descent_distance: f64,
+    climb: f64,
+    descent: f64,
+    // Net grade between the first and last points of the shape, in %
+    net_grade: Option<f64>,
+    // Steepest grade sustained over the grade window, in %
+    max_grade: f64,
+    // Routes of the trips following the shape
+    route_ids: BTreeSet<String>,
+}
+
+// Content of a file of the feed, which is either a directory or a zip archive
+// (None if the feed has no such file)
+fn read_file(feed: &str, name: &str) -> Result<Option<String>, String> {
+    let path = std::path::Path::new(feed);
+    if path.is_dir() {
+        return Ok(std::fs::read_to_string(path.join(name)).ok());
+    }
+    let file =
+        std::fs::File::open(path).map_err(|_| format!("Unable to open GTFS feed {}", feed))?;
+    let mut archive = zip::ZipArchive::new(file)
+        .map_err(|_| format!("The GTFS feed {} is not a zip archive", feed))?;
+    let Ok(mut file) = archive.by_name(name) else {
+        return Ok(None);
+    };
+    let mut content = String::new();
+    file.read_to_string(&mut content)
+        .map_err(|_| format!("Unable to read {} from the GTFS feed", name))?;
+    Ok(Some(content))
+}
+
+// Split a CSV record, handling quoted fields
+fn csv_record(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+// Records of a CSV table, as maps of column name -> value
+fn csv_table(content: &str) -> Vec<BTreeMap<String, String>> {
+    let mut lines = content
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.is_empty());
+    let header: Vec<String> = match lines.next() {
+        Some(header) => csv_record(header)
+            .into_iter()
+            .map(|name| name.trim().to_string())
+            .collect(),
+        None => return Vec::new(),
+    };
+    lines
+        .map(|line| header.iter().cloned().zip(csv_record(line)).collect())
+        .collect()
+}
+
+/// Compute the statistics of every shape of the feed
+pub fn compute(
+    feed: &str,
+    dem: &dyn ElevationProvider,
+    grade_window: f64,
+) -> Result<BTreeMap<String, ShapeInfo>, String> {
+    let shapes = read_file(feed, "shapes.txt")?
+        .ok_or_else(|| format!("The GTFS feed {} has no shapes.txt", feed))?;
+
+    // Points of each shape: (sequence, lon, lat)
+    let mut points: BTreeMap<String, Vec<(u32, f64, f64)>> = BTreeMap::new();
+    for record in csv_table(&shapes) {
+        let field = |name: &str| {
+            record
+                .get(name)
+                .ok_or_else(|| format!("Missing {} in shapes.txt", name))
+        };
+        let number = |name: &str| -> Result<f64, String> {
+            field(name)?
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid {} in shapes.txt", name))
+        };
+        points
+            .entry(field("shape_id")?.to_string())
+            .or_default()
+            .push((
+                number("shape_pt_sequence")? as u32,
+                number("shape_pt_lon")?,
+                number("shape_pt_lat")?,
+            ));
+    }
+
+    // Routes following each shape, from the trips
+    let mut routes: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for trip in csv_table(&read_file(feed, "trips.txt")?.unwrap_or_default()) {
+        if let (Some(shape_id), Some(route_id)) = (trip.get("shape_id"), trip.get("route_id")) {
+            routes
+                .entry(shape_id.to_string())
+                .or_default()
+                .insert(route_id.to_string());
+        }
+    }
+
+    let mut result = BTreeMap::new();
+    for (shape_id, mut points) in points {
+        points.sort_by_key(|(sequence, _, _)| *sequence);
+        let elevations: Vec<Option<f64>> = points
+            .iter()
+            // The nodata pixels and the locations outside of the DEM have no elevation
+            .map(|(_, lon, lat)| dem.elevation(*lon, *lat))
+            .collect();
+        let coordinates: Vec<[f64; 2]> = points.iter().map(|(_, lon, lat)| [*lon, *lat]).collect();
+        let mut distances = Vec::with_capacity(points.len());
+        geodesy::cumulative_distances(&coordinates, &mut distances);
+        let distance = distances.last().copied().unwrap_or(0.);
+        let totals = gradient::ClimbDescent::compute(&distances, &elevations);
+        result.insert(
+            shape_id.clone(),
+            ShapeInfo {
+                distance,
+                climb_distance: totals.climb_distance,
+                descent_distance: totals.descent_distance,
+                climb: totals.climb,
+                descent: totals.descent,
+                net_grade: gradient::net_gradient(&distances, &elevations)
+                    .map(|gradient| gradient * 100.),
+                max_grade: gradient::max_sustained_gradient(&distances, &elevations, grade_window)
+                    * 100.,
+                route_ids: routes.remove(&shape_id).unwrap_or_default(),
+            },
+        );
+    }
+    Ok(result)
+}
+
+/// Compute the statistics of the shapes of a GTFS feed and write them as JSON
+pub fn run(feed: &str, elevation_file: &str, output_file: &str, grade_window: f64) {
+    let dem = Dem::open(elevation_file).unwrap_or_else(|e| {
+        log::error!(target: "setup", fields:serde = serde_json::json!({ "elevation_file": elevation_file }); "{}", e);
+        std::process::exit(1);
+    });
+    let result = compute(feed, &dem, grade_window).unwrap_or_else(|e| {
+        log::error!(target: "compute", fields:serde = serde_json::json!({ "feed": feed }); "{}", e);
+        std::process::exit(1);
+    });
+    log::info!(
+        target: "compute",
+        fields:serde = serde_json::json!({ "shape_count": result.len() });
+        "{} shapes computed", result.len()
+    );
+    let json_str = serde_json::to_string(&result).expect("Unable to serialize result to string");
+    std::fs::write(output_file, json_str).expect("Unable to write file");
+}
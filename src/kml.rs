@@ -0,0 +1,131 @@
+use crate::compress::FileSink;
+use crate::output::{OutputWriter, Record};
+use crate::summary::{average_grade, class_label, grade_class};
+use std::io::{BufWriter, Write};
+
+// Colors of the ramp, from the flattest to the steepest class
+const RAMP: [[f64; 3]; 3] = [[26., 152., 80.], [254., 224., 60.], [215., 48., 39.]];
+
+// Color of the ramp at t (0 to 1), as a KML aabbggrr color
+fn ramp_color(t: f64) -> String {
+    let position = t.clamp(0., 1.) * (RAMP.len() - 1) as f64;
+    let i = (position.floor() as usize).min(RAMP.len() - 2);
+    let f = position - i as f64;
+    let [r, g, b] =
+        [0, 1, 2].map(|c| (RAMP[i][c] + (RAMP[i + 1][c] - RAMP[i][c]) * f).round() as u8);
+    format!("ff{:02x}{:02x}{:02x}", b, g, r)
+}
+
+// Output file, zipped as doc.kml in KMZ files
+enum KmlOut {
+    Kml(FileSink),
+    Kmz(zip::ZipWriter<BufWriter<std::fs::File>>),
+}
+
+impl Write for KmlOut {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            KmlOut::Kml(out) => out.write(buf),
+            KmlOut::Kmz(out) => out.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            KmlOut::Kml(out) => out.flush(),
+            KmlOut::Kmz(out) => out.flush(),
+        }
+    }
+}
+
+/// KML (or KMZ) document of the ways, as LineString placemarks styled by the
+/// class of their average grade, from green to red
+pub struct KmlWriter<'a> {
+    path: String,
+    out: KmlOut,
+    breaks: &'a [f64],
+}
+
+impl<'a> KmlWriter<'a> {
+    /// Writer of a KML document to the (possibly compressed) file
+    pub fn create(path: String, file: FileSink, breaks: &'a [f64]) -> KmlWriter<'a> {
+        KmlWriter::start(path, KmlOut::Kml(file), breaks)
+    }
+
+    pub fn create_kmz(path: &str, breaks: &'a [f64]) -> std::io::Result<KmlWriter<'a>> {
+        let file = BufWriter::new(std::fs::File::create(path)?);
+        let mut zip = zip::ZipWriter::new(file);
+        zip.start_file("doc.kml", zip::write::FileOptions::default())?;
+        Ok(KmlWriter::start(path.to_string(), KmlOut::Kmz(zip), breaks))
+    }
+
+    // Write the header of the document, with a style per grade class
+    fn start(path: String, out: KmlOut, breaks: &'a [f64]) -> KmlWriter<'a> {
+        let mut writer = KmlWriter { path, out, breaks };
+        let mut header = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n<name>Ways slope</name>\n",
+        );
+        for class in 0..=breaks.len() {
+            let t = if breaks.is_empty() {
+                0.
+            } else {
+                class as f64 / breaks.len() as f64
+            };
+            header.push_str(&format!(
+                "<Style id=\"grade-{}\"><LineStyle><color>{}</color><width>3</width></LineStyle></Style>\n",
+                class,
+                ramp_color(t)
+            ));
+        }
+        writer.write_str(&header);
+        writer
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.out
+            .write_all(s.as_bytes())
+            .unwrap_or_else(|_| panic!("Unable to write output file {}", self.path));
+    }
+}
+
+impl OutputWriter for KmlWriter<'_> {
+    fn write(&mut self, record: &Record) {
+        let way = record.info;
+        let grade = average_grade(way.distance, way.climb, way.descent);
+        let class = grade_class(grade, self.breaks);
+        let coordinates: Vec<String> = record
+            .geometry
+            .iter()
+            .map(|[lon, lat]| format!("{},{}", lon, lat))
+            .collect();
+        let placemark = format!(
+            "<Placemark><name>Way {}</name>\
+             <description>Length: {:.0} m, climb: {:.0} m, descent: {:.0} m, average grade: {:.1}% ({})</description>\
+             <styleUrl>#grade-{}</styleUrl>\
+             <LineString><tessellate>1</tessellate><coordinates>{}</coordinates></LineString></Placemark>\n",
+            record.way_id,
+            way.distance,
+            way.climb,
+            way.descent,
+            grade,
+            class_label(class, self.breaks).replace('<', "&lt;"),
+            class,
+            coordinates.join(" ")
+        );
+        self.write_str(&placemark);
+    }
+
+    fn finish(mut self: Box<Self>) {
+        self.write_str("</Document>\n</kml>\n");
+        let path = self.path.clone();
+        let result = match self.out {
+            KmlOut::Kml(out) => out.finish(),
+            KmlOut::Kmz(mut zip) => zip
+                .finish()
+                .map_err(std::io::Error::from)
+                .and_then(|mut out| out.flush()),
+        };
+        result.unwrap_or_else(|_| panic!("Unable to write output file {}", path));
+    }
+}
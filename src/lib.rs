@@ -0,0 +1,7 @@
+//! Computations of osm-ways-slope on elevation profiles, operating on plain slices
+//! of distances and elevations and without dependencies, so that they can be
+//! reused (and tested) independently of the command-line tool
+
+pub mod geodesy;
+pub mod gradient;
+pub mod smoothing;
@@ -0,0 +1,102 @@
+use log::kv::{Key, Value as KvValue, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{json, Value};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Format of the messages printed on stderr
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    // Free-form text
+    #[default]
+    Text,
+    // One JSON object per line, with the phase of the run and the related counts / ids
+    Json,
+}
+
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+/// Logger printing the events of the `log` macros on stderr: their message as text,
+/// or a JSON object merging the level, the phase (the target of the event), the
+/// message and the object given as the `fields` key-value
+struct StderrLogger;
+
+// Object of the `fields` key-value of an event
+struct Fields(Option<Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: KvValue<'kvs>) -> Result<(), log::kv::Error> {
+        if key.as_str() == "fields" {
+            self.0 = serde_json::to_value(value).ok();
+        }
+        Ok(())
+    }
+}
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if !is_json() {
+            eprintln!("{}", record.args());
+            return;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64())
+            .unwrap_or_default();
+        let level = match record.level() {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+        let mut event = json!({
+            "timestamp": timestamp,
+            "level": level,
+            "phase": record.target(),
+            "message": record.args().to_string(),
+        });
+        let mut fields = Fields(None);
+        record.key_values().visit(&mut fields).ok();
+        if let (Some(event), Some(Value::Object(fields))) = (event.as_object_mut(), fields.0) {
+            event.extend(fields);
+        }
+        eprintln!("{}", event);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the logger: the debug events (the parsed options, the DEM metadata, the
+/// timings of the phases) are printed from one -v, and the trace events (the details
+/// of each step of a phase, e.g. each sample batch) from two
+pub fn init(format: LogFormat, verbosity: u8) {
+    FORMAT.set(format).ok();
+    log::set_logger(&StderrLogger).ok();
+    log::set_max_level(match verbosity {
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    });
+}
+
+pub fn is_json() -> bool {
+    FORMAT.get().copied().unwrap_or_default() == LogFormat::Json
+}
+
+/// Debug event of the time taken by a phase (or a part of it) since `start`
+pub fn timing(phase: &str, what: &str, start: std::time::Instant) {
+    let seconds = start.elapsed().as_secs_f64();
+    log::debug!(
+        target: phase,
+        fields:serde = json!({ "elapsed_seconds": seconds });
+        "{} in {:.3} s", what, seconds
+    );
+}
@@ -1,22 +1,517 @@
-use clap::Parser;
+mod area;
+mod blockcache;
+mod car;
+mod checkpoint;
+mod compress;
+mod coverage;
+mod cycling;
+mod demsource;
+mod doctor;
+mod elevation;
+mod elevationapi;
+mod enrich;
+mod expression;
+mod fetch;
+mod fixture;
+mod flatgeobuf;
+mod geometry;
+#[cfg(feature = "gdal")]
+mod gpkg;
+mod gpx;
+mod gtfs;
+mod kml;
+mod logger;
+mod maproulette;
+mod metadata;
+mod mtb;
+mod mvt;
+mod o5m;
+mod osmxml;
+mod output;
+mod overpass;
+mod overrides;
+mod pbf;
+mod piste;
+mod png;
+mod postgis;
+mod profile;
+mod progress;
+mod rail;
+mod reproject;
+mod sampling;
+mod server;
+mod shutdown;
+mod store;
+mod stream;
+mod structures;
+mod summary;
+mod template;
+#[cfg(not(feature = "gdal"))]
+mod tiff;
+mod transform;
+mod truck;
+mod update;
+mod verify;
+mod warnings;
+mod waterway;
+mod wheelchair;
+mod winter;
+
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use coverage::CoverageReport;
+use elevation::{Dem, ElevationProvider, Sample};
 use fxhash::FxHashMap;
-use gdal::raster::{RasterBand, ResampleAlg};
-use gdal::{Dataset, GeoTransformEx};
+use osm_ways_slope::geodesy::{self, haversine_distance, Location};
+use osm_ways_slope::gradient;
+use osm_ways_slope::smoothing;
+use profile::Profile;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::path::Path;
+use store::{new_node_store, FlatNodeStore, NodeIdSet, NodeInfo, NodeStore};
+use warnings::{Reason, WarningLog};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    args: Args,
+    // Format of the messages printed on stderr
+    #[arg(long, global = true, value_enum, default_value_t = logger::LogFormat::Text)]
+    log_format: logger::LogFormat,
+    // Don't colorize the summary table (it is only colorized on terminals)
+    #[arg(long, global = true)]
+    no_color: bool,
+    // Don't draw the progress of the phases of the run
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    // Print debug messages (-v: options, DEM metadata and timings, -vv: each step)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write a synthetic PBF file, DEM and the expected output to a directory
+    GenFixture {
+        // The directory to write the fixture to
+        output_dir: String,
+    },
+    /// Report the GDAL environment and check that the given OSM / elevation files can be read
+    Doctor {
+        // The paths to the files to check
+        files: Vec<String>,
+    },
+    /// Compute the climb and grade statistics of the shapes of a GTFS feed
+    Gtfs {
+        // The path to the GTFS feed (zip archive or directory)
+        feed: String,
+        // The path to the elevation file to process
+        elevation_file: String,
+        // The path to the output file
+        output_file: String,
+        // Length (in meters) over which the maximum grade of a shape is measured
+        #[arg(long, value_name = "METERS", default_value_t = 100.)]
+        grade_window: f64,
+    },
+    /// Update a previous JSON output with an OsmChange diff, recomputing only the ways
+    /// affected by the changes (from the updated OSM file)
+    Update {
+        // The previous JSON output
+        #[arg(long, value_name = "FILE")]
+        previous: String,
+        // The OsmChange diff (.osc, .osc.gz or .osc.bz2) applied to the OSM file since
+        #[arg(long, value_name = "OSC_FILE")]
+        diff: String,
+        #[command(flatten)]
+        args: Box<Args>,
+    },
+    /// Add the slope metrics of the LineStrings of a GeoJSON file to their properties
+    Enrich {
+        // The path to the GeoJSON file (FeatureCollection, in WGS84)
+        input_file: String,
+        // The path to the elevation file to process
+        elevation_file: String,
+        // The path to the output file
+        output_file: String,
+    },
+    /// Compute the climb and descent statistics of the tracks (and their segments) of a GPX file
+    Gpx {
+        // The path to the GPX file
+        gpx_file: String,
+        // The path to the elevation file to process
+        elevation_file: String,
+        // The path to the output file
+        output_file: String,
+    },
+    /// Serve the elevation profile and slope metrics of posted lines (POST /profile)
+    Serve {
+        // The path to the elevation file to process
+        elevation_file: String,
+        // Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// Compute the terrain slope and aspect within closed ways and multipolygons
+    Areas {
+        // The path to the OSM file to process
+        osm_file: String,
+        // The path to the elevation file to process
+        elevation_file: String,
+        // The path to the output file
+        output_file: String,
+        // Filter the areas by tag, as for the ways (repeatable,
+        // the areas matching any of the filters are kept)
+        #[arg(short, long, default_value = "landuse,leisure")]
+        filter: Vec<String>,
+    },
+    /// Print a summary table of the ways of a result file
+    Stats {
+        // The path to the result file (JSON) of a run
+        results_file: String,
+    },
+    /// Write Mapbox Vector Tiles of the ways of a GeoJSON result file, with their slope class
+    Tiles {
+        // The path to the GeoJSON result file of a run (not reprojected)
+        results_file: String,
+        // The directory of z/x/y.pbf tiles, or the MBTiles file (.mbtiles) to write
+        output: String,
+        #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(0..=20))]
+        min_zoom: u8,
+        #[arg(long, default_value_t = 14, value_parser = clap::value_parser!(u8).range(0..=20))]
+        max_zoom: u8,
+    },
+}
+
+#[derive(clap::Args, Debug)]
 struct Args {
     // The path to the OSM file to process
-    osm_file: String,
-    // The path to the elevation file to process
-    elevation_file: String,
+    #[arg(required = true)]
+    osm_file: Option<String>,
+    // Additional OSM file (e.g. an adjacent extract) merged with the input, the ways
+    // and nodes present in several files being read once (repeatable)
+    #[arg(long, value_name = "OSM_FILE")]
+    merge: Vec<String>,
+    // The path to the elevation file to process (or to a directory of DEM tiles),
+    // omitted with --dem-source and --elevation-api
+    #[arg(required_unless_present_any = ["dem_source", "elevation_api"])]
+    elevation_file: Option<String>,
+    // Additional DEM tile (or directory of tiles) of the mosaic read with the
+    // elevation file, each node being read from the tile covering it (repeatable)
+    #[arg(long, value_name = "ELEVATION_FILE")]
+    dem: Vec<String>,
+    // DEM (or directory of tiles) read where the elevation file and the previous
+    // fallbacks have no valid value, e.g. SRTM behind a patchy lidar DEM
+    // (repeatable, in order of priority)
+    #[arg(long, value_name = "ELEVATION_FILE")]
+    fallback_dem: Vec<String>,
+    // Global DEM whose tiles covering the OSM input are downloaded (and cached),
+    // instead of an elevation file
+    #[arg(long, value_enum)]
+    dem_source: Option<demsource::DemSource>,
+    // Directory where the tiles of --dem-source are cached (by default the dem
+    // subdirectory of the cache directory)
+    #[arg(long, value_name = "DIR", requires = "dem_source")]
+    dem_cache_dir: Option<String>,
+    // Zoom level of the Terrarium and Terrain-RGB tiles of --dem-source
+    #[arg(long, value_name = "ZOOM", default_value_t = demsource::DEFAULT_ZOOM, value_parser = clap::value_parser!(u8).range(0..=15), requires = "dem_source")]
+    dem_zoom: u8,
+    // Mapbox access token of the Terrain-RGB tiles of --dem-source (by default
+    // the MAPBOX_ACCESS_TOKEN environment variable)
+    #[arg(long, value_name = "TOKEN", requires = "dem_source")]
+    mapbox_token: Option<String>,
+    // URL of an Open-Elevation compatible JSON API (e.g.
+    // https://api.open-elevation.com/api/v1/lookup) queried for the elevations of
+    // the nodes, instead of an elevation file
+    #[arg(long, value_name = "URL", conflicts_with_all = ["dem_source", "dem", "fallback_dem", "geoid"])]
+    elevation_api: Option<String>,
+    // Number of locations of a request to --elevation-api
+    #[arg(long, value_name = "COUNT", default_value_t = elevationapi::DEFAULT_BATCH_SIZE, requires = "elevation_api")]
+    elevation_api_batch_size: usize,
+    // Number of requests to --elevation-api running at once
+    #[arg(long, value_name = "COUNT", default_value_t = elevationapi::DEFAULT_CONCURRENCY, requires = "elevation_api")]
+    elevation_api_concurrency: usize,
     // The path to the output file
-    output_file: String,
-    // The key or key-value pair to filter the ways by
+    #[arg(required_unless_present_any = ["output", "dem_source", "elevation_api"])]
+    output_file: Option<String>,
+    // Format of the output file
+    #[arg(long, value_enum, default_value_t = output::Format::Json)]
+    format: output::Format,
+    // Additional output, as [FORMAT:]PATH (repeatable, the format being inferred
+    // from the extension when it isn't given)
+    #[arg(long, value_name = "[FORMAT:]PATH")]
+    output: Vec<String>,
+    // Compress the output files (adding the extension of the compression to their paths)
+    #[arg(long, value_enum)]
+    compress: Option<compress::Compression>,
+    // Write one record per segment (pair of consecutive nodes) of the ways, rather
+    // than per way, to the ndjson, csv and geojson outputs
+    #[arg(long, conflicts_with_all = ["partition_by", "checkpoint"])]
+    per_segment: bool,
+    // Write the outputs as Hive-style partitioned datasets (directories), by the value
+    // of a tag (e.g. highway) or by Web Mercator tile (tile:ZOOM)
+    #[arg(long, value_name = "TAG|tile:ZOOM")]
+    partition_by: Option<String>,
+    // The key or key-value pair to filter the ways by (repeatable, or comma-separated,
+    // with "\," for a literal comma)
     #[arg(short, long)]
-    filter: Option<String>,
+    filter: Vec<String>,
+    // The path to a file of filters, one expression per line (lines starting with "#"
+    // are comments)
+    #[arg(long, value_name = "FILE")]
+    filter_file: Option<String>,
+    // The path to a TOML file of tag transformations, applied before filtering the ways
+    #[arg(long, value_name = "FILE")]
+    transform: Option<String>,
+    // Whether the ways must match any or all of the filters
+    #[arg(long, value_enum, default_value_t = FilterMode::Any)]
+    filter_mode: FilterMode,
+    // The profile to use, which sets the default filter and the additional metrics
+    #[arg(long, value_enum, default_value_t = Profile::Default)]
+    profile: Profile,
+    // Ruling gradient (in per-mille) above which sections are flagged, with the rail profile
+    #[arg(long, value_name = "PERMILLE", default_value_t = 20.)]
+    ruling_gradient: f64,
+    // Drop (in m/km) above which reaches are flagged as steep, with the waterway profile
+    #[arg(long, value_name = "M_PER_KM", default_value_t = 20.)]
+    steep_reach: f64,
+    // Length (in meters) over which the maximum grade of a piste is measured, with the piste profile
+    #[arg(long, value_name = "METERS", default_value_t = 50.)]
+    piste_window: f64,
+    // Length (in meters) over which the maximum climbing grade is measured, with the mtb profile
+    #[arg(long, value_name = "METERS", default_value_t = 20.)]
+    mtb_window: f64,
+    // Maximum running slope (in %) of a compliant way, with the wheelchair profile
+    #[arg(long, value_name = "PERCENT", default_value_t = 5.)]
+    wheelchair_max_slope: f64,
+    // Running slope (in %) above which a way fails, with the wheelchair profile
+    #[arg(long, value_name = "PERCENT", default_value_t = 8.)]
+    wheelchair_limit_slope: f64,
+    // Grade thresholds (in %) checked with the truck profile
+    #[arg(long, value_name = "PERCENT", value_delimiter = ',', default_values_t = [6., 8., 10.])]
+    truck_thresholds: Vec<f64>,
+    // Minimum length (in meters) over which a threshold must be exceeded, with the truck profile
+    #[arg(long, value_name = "METERS", default_value_t = 100.)]
+    truck_min_length: f64,
+    // The path to an optional GeoJSON layer of the sections exceeding the truck thresholds
+    #[arg(long, value_name = "FILE")]
+    truck_layer: Option<String>,
+    // Mass (in kg) of the vehicle, with the car profile
+    #[arg(long, value_name = "KG", default_value_t = 1500.)]
+    vehicle_mass: f64,
+    // Power (in kW) of the vehicle, with the car profile
+    #[arg(long, value_name = "KW", default_value_t = 75.)]
+    vehicle_power: f64,
+    // Mass (in kg) of the rider and the bike, with the cycling profile
+    #[arg(long, value_name = "KG", default_value_t = 85.)]
+    cycling_mass: f64,
+    // Rolling resistance coefficient of the bike, with the cycling profile
+    #[arg(long, value_name = "COEFFICIENT", default_value_t = 0.005)]
+    cycling_rolling_resistance: f64,
+    // Drag area (CdA, in m²) of the rider, with the cycling profile
+    #[arg(long, value_name = "M2", default_value_t = 0.4)]
+    cycling_cda: f64,
+    // Power (in W) sustained by the rider, with the cycling profile
+    #[arg(long, value_name = "W", default_value_t = 150.)]
+    cycling_power: f64,
+    // Highest speed (in km/h) of the rider, e.g. on the descents, with the cycling profile
+    #[arg(long, value_name = "KMH", default_value_t = 50.)]
+    cycling_max_speed: f64,
+    // CRS of the geometries of the GeoJSON layers (e.g. EPSG:3857), instead of WGS84
+    #[arg(long, value_name = "CRS")]
+    output_crs: Option<String>,
+    // Upper bounds (in %) of the grade classes colored from green to red in the KML outputs
+    #[arg(long, value_name = "PERCENT", value_delimiter = ',', default_values_t = [2., 5., 8., 12.])]
+    kml_breaks: Vec<f64>,
+    // Add the length of each way in each grade class to its metrics
+    #[arg(long)]
+    grade_histogram: bool,
+    // Upper bounds (in %) of the grade classes of --grade-histogram
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        value_delimiter = ',',
+        default_values_t = [2., 5., 8., 12.],
+        requires = "grade_histogram"
+    )]
+    grade_histogram_breaks: Vec<f64>,
+    // Add the walking time of each way in both directions, with Tobler's hiking function,
+    // to its metrics
+    #[arg(long)]
+    walk_time: bool,
+    // Formula of the walking times of --walk-time
+    #[arg(long, value_enum, default_value_t = CostModel::Tobler, requires = "walk_time")]
+    cost_model: CostModel,
+    // Add the elevation profile of each way, as [distance, elevation] pairs, to its metrics
+    #[arg(long)]
+    include_profile: bool,
+    // The path to an optional CSV file of the roads ranked by winter-maintenance priority
+    #[arg(long, value_name = "FILE")]
+    winter_ranking: Option<String>,
+    // The path to an optional GeoJSON layer of the roads styled by winter-maintenance priority
+    #[arg(long, value_name = "FILE")]
+    winter_layer: Option<String>,
+    // Elevation change (in meters) below which variations along closed ways
+    // (e.g. roundabouts) are ignored as DEM noise
+    #[arg(long, value_name = "METERS")]
+    closed_way_threshold: Option<f64>,
+    // Leave out the metrics of the direction in which a way can't be traveled
+    // according to its oneway tag
+    #[arg(long)]
+    oneway: bool,
+    // Interpolate the elevation of the bridges and tunnels between their endpoints
+    // instead of sampling the DEM, with all the profiles
+    #[arg(long)]
+    flatten_structures: bool,
+    // Filter applied to the elevations along the ways before accumulating their
+    // climb and descent, as the DEM noise inflates them
+    #[arg(long, value_enum)]
+    smoothing: Option<Smoothing>,
+    // Width (in meters, along the way) of the window of --smoothing
+    #[arg(
+        long,
+        value_name = "METERS",
+        default_value_t = 50.,
+        requires = "smoothing"
+    )]
+    smoothing_window: f64,
+    // Simplify the ways with the Douglas-Peucker algorithm and this tolerance (in meters)
+    // before sampling their nodes
+    #[arg(long, value_name = "METERS")]
+    simplify: Option<f64>,
+    // Sample the DEM only at every Nth node of the ways (and at their endpoints),
+    // interpolating the elevation in between, for faster approximate runs
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    sample_every: Option<usize>,
+    // Shorthand for --sample-every 5
+    #[arg(long, conflicts_with = "sample_every")]
+    approx: bool,
+    // Sample the DEM along the segments longer than this spacing too, at evenly
+    // spaced points, to accumulate the climb and descent over the terrain they cross
+    #[arg(long, value_name = "METERS", conflicts_with_all = ["sample_every", "approx"])]
+    max_sample_spacing: Option<f64>,
+    // Global vertical error (RMSE, in meters) of the DEM, to estimate the
+    // uncertainty of the climb and descent
+    #[arg(long, value_name = "METERS")]
+    dem_rmse: Option<f64>,
+    // The path to a raster of the vertical error (standard deviation, in meters)
+    // of the DEM, taking precedence over --dem-rmse where it has a value
+    #[arg(long, value_name = "FILE")]
+    dem_error_file: Option<String>,
+    // The path to a CSV (node_id,elevation) or JSON file of node elevations
+    // used instead of those of the DEM, e.g. surveyed benchmarks or bridge decks
+    #[arg(long, value_name = "FILE")]
+    elevation_overrides: Option<String>,
+    // Use the ele tags of the nodes instead of the DEM, or where the DEM has no value
+    #[arg(long, value_enum)]
+    ele_tags: Option<EleTags>,
+    // The path to a geoid model grid (e.g. EGM96 or EGM2008 undulations, in meters)
+    // converting the elevations of the DEM between ellipsoidal and orthometric heights
+    #[arg(long, value_name = "FILE")]
+    geoid: Option<String>,
+    // Direction of the conversion of the elevations of the DEM (and fallback DEMs) with --geoid
+    #[arg(long, value_enum, default_value_t = elevation::GeoidConversion::ToOrthometric, requires = "geoid")]
+    geoid_conversion: elevation::GeoidConversion,
+    // Interpolation of the elevation of the nodes between the pixels around them
+    #[arg(long, value_enum, default_value_t = elevation::Interpolation::Nearest)]
+    interpolation: elevation::Interpolation,
+    // What happens to the ways with a node on a nodata pixel of the DEM
+    #[arg(long, value_enum, default_value_t = NodataPolicy::SkipSegment)]
+    nodata_policy: NodataPolicy,
+    // What happens to the nodes outside of the extent of the DEM
+    #[arg(long, value_enum, default_value_t = OutOfExtentPolicy::Skip)]
+    out_of_extent: OutOfExtentPolicy,
+    // Abort if less than this percentage of the nodes are covered by the DEM
+    #[arg(long, value_name = "PERCENT")]
+    min_coverage: Option<f64>,
+    // The path to an optional JSON file of the parameters of the run
+    #[arg(long, value_name = "FILE")]
+    metadata: Option<String>,
+    // Append the computed ways to this NDJSON file as they are computed, so that
+    // a run stopped before its end can be resumed with --resume
+    #[arg(long, value_name = "FILE")]
+    checkpoint: Option<String>,
+    // Resume a run from its --checkpoint file, the ways in it being not computed again
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+    // The path to an optional JSON file listing the ways computed with a fallback
+    #[arg(long)]
+    warnings_file: Option<String>,
+    // Store node elevations as f32 to reduce memory usage
+    #[arg(long)]
+    f32_elevations: bool,
+    // Store node coordinates as f32 to reduce memory usage
+    #[arg(long)]
+    f32_coordinates: bool,
+    // Store nodes in a memory-mapped file indexed by node id, for very large extracts
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["f32_elevations", "f32_coordinates"])]
+    flat_nodes: Option<String>,
+    // Keep the nodes in memory until they use more than --node-cache-threshold,
+    // then move them to this memory-mapped file indexed by node id
+    #[arg(long, value_name = "FILE", conflicts_with = "flat_nodes")]
+    node_cache: Option<String>,
+    // Memory (in MB) used by the nodes above which they are moved to --node-cache
+    #[arg(
+        long,
+        value_name = "MB",
+        default_value_t = 4096,
+        requires = "node_cache"
+    )]
+    node_cache_threshold: usize,
+    // Directory where the inputs given as URLs are cached
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<String>,
+    // Download the inputs given as URLs again, even if the cached copies are up to date
+    #[arg(long)]
+    refresh: bool,
+    // Read the elevation files given as http(s) URLs in place (e.g. Cloud Optimized
+    // GeoTIFFs, with range requests) instead of downloading them, as the s3 ones are
+    #[arg(long)]
+    remote_dem: bool,
+    // Read the OSM input as an Overpass QL query (or a file containing it, or a
+    // west,south,east,north bounding box in which the ways matching the filters are
+    // queried), run on the Overpass API
+    #[arg(long)]
+    overpass: bool,
+    // Endpoint of the Overpass API
+    #[arg(long, value_name = "URL", default_value = overpass::DEFAULT_URL)]
+    overpass_url: String,
+    // Reader used to get the ways and their nodes from the OSM file
+    #[arg(long, value_enum, default_value_t = PbfReader::Standard)]
+    pbf_reader: PbfReader,
+    // Read a full-history OSM file as it was at this instant (e.g. 2020-01-01T00:00:00Z),
+    // using the versions of the ways and nodes valid at that time
+    #[arg(long, value_name = "TIMESTAMP")]
+    at: Option<String>,
+    // The path to an optional MapRoulette challenge (GeoJSON) of ways whose
+    // slope contradicts or is missing their incline tag
+    #[arg(long, value_name = "FILE")]
+    maproulette: Option<String>,
+    // Minimum grade (in %) for a way without incline tag to be reported in the challenge
+    #[arg(long, value_name = "PERCENT", default_value_t = 10.)]
+    maproulette_min_grade: f64,
+    // Maximum difference (in % points) between the incline tag and the measured grade
+    #[arg(long, value_name = "PERCENT", default_value_t = 5.)]
+    maproulette_tolerance: f64,
+    // The path to a reference output to compare the results against,
+    // exiting with an error if they differ
+    #[arg(long, value_name = "FILE")]
+    verify: Option<String>,
+    // Absolute difference tolerated between numeric values, when verifying
+    #[arg(long, value_name = "VALUE", default_value_t = 1e-6)]
+    verify_tolerance: f64,
+    // Relative difference tolerated between numeric values, when verifying
+    #[arg(long, value_name = "RATIO", default_value_t = 0.)]
+    verify_relative_tolerance: f64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,13 +521,160 @@ struct WayInfo {
     descent_distance: f64,
     climb: f64,
     descent: f64,
+    // Climb and descent (in meters) per kilometer, to compare ways of different lengths
+    #[serde(default)]
+    climb_per_km: f64,
+    #[serde(default)]
+    descent_per_km: f64,
+    // Climb and descent when traveling the way in each direction, the backward ones
+    // being the descent and climb (null in a direction closed with --oneway)
+    #[serde(default)]
+    climb_forward: Option<f64>,
+    #[serde(default)]
+    descent_forward: Option<f64>,
+    #[serde(default)]
+    climb_backward: Option<f64>,
+    #[serde(default)]
+    descent_backward: Option<f64>,
+    // Average grades (in %) of the climbing and descending parts of the way
+    #[serde(default)]
+    avg_climb_grade: f64,
+    #[serde(default)]
+    avg_descent_grade: f64,
+    // Grades (in %, signed) of the steepest climbing and descending segments
+    #[serde(default)]
+    max_grade: Option<f64>,
+    #[serde(default)]
+    min_grade: Option<f64>,
+    // Length (in meters) of the way in each class of --grade-histogram-breaks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    grade_histogram: Option<Vec<f64>>,
+    // Walking times (in seconds) in each direction, with --walk-time and --cost-model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    walk_time_forward_s: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    walk_time_backward_s: Option<f64>,
+    // Cumulative distance (in meters) and elevation of each node, with --include-profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<Vec<(f64, Option<f64>)>>,
+    // Standard deviations of the climb and descent, from the DEM error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    climb_sigma: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    descent_sigma: Option<f64>,
+    // Whether the first and last nodes of the way are the same
+    #[serde(default)]
+    is_closed: bool,
+    // Ids and [lon, lat] locations of the endpoints of the way, to rebuild the
+    // connectivity of the network (the locations are missing for missing nodes)
+    #[serde(default)]
+    first_node: Option<i64>,
+    #[serde(default)]
+    last_node: Option<i64>,
+    #[serde(default)]
+    first_node_location: Option<[f64; 2]>,
+    #[serde(default)]
+    last_node_location: Option<[f64; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tags: Option<std::collections::BTreeMap<String, String>>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    rail: Option<rail::RailInfo>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    waterway: Option<waterway::WaterwayInfo>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    piste: Option<piste::PisteInfo>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    mtb: Option<mtb::MtbInfo>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    wheelchair: Option<wheelchair::WheelchairInfo>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    truck: Option<truck::TruckInfo>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    car: Option<car::CarInfo>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    winter: Option<winter::WinterInfo>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    cycling: Option<cycling::CyclingInfo>,
+    // User-defined metrics
+    #[serde(flatten, skip_deserializing)]
+    metrics: std::collections::BTreeMap<String, Option<f64>>,
 }
 
-#[derive(Debug)]
-struct Location {
-    latitude: f64,
-    longitude: f64,
-}
+// Fields of the records of the ways (including those of the profiles), which the
+// user-defined metrics, written in the same objects, can't be named after
+const WAY_FIELDS: &[&str] = &[
+    "way_id",
+    "distance",
+    "climb_distance",
+    "descent_distance",
+    "climb",
+    "descent",
+    "climb_per_km",
+    "descent_per_km",
+    "climb_forward",
+    "descent_forward",
+    "climb_backward",
+    "descent_backward",
+    "avg_climb_grade",
+    "avg_descent_grade",
+    "max_grade",
+    "min_grade",
+    "grade_histogram",
+    "walk_time_forward_s",
+    "walk_time_backward_s",
+    "profile",
+    "climb_sigma",
+    "descent_sigma",
+    "is_closed",
+    "first_node",
+    "last_node",
+    "first_node_location",
+    "last_node_location",
+    "tags",
+    // rail profile
+    "gradient_permille",
+    "max_gradient_permille",
+    "steep_sections",
+    // waterway profile
+    "flows_forward",
+    "drop_per_km",
+    "steep_reaches",
+    // piste profile
+    "avg_grade",
+    "piste_max_grade",
+    "estimated_difficulty",
+    "tagged_difficulty",
+    "difficulty_mismatch",
+    // mtb profile
+    "mtb_avg_climb_grade",
+    "max_climb_grade",
+    "estimated_uphill_scale",
+    "tagged_uphill_scale",
+    "suggested_uphill_scale",
+    "uphill_scale_mismatch",
+    // wheelchair profile
+    "max_running_slope",
+    "classification",
+    "non_compliant_length",
+    "over_limit_length",
+    "priority",
+    // truck profile
+    "max_exceeded_threshold",
+    "exceedances",
+    // car profile
+    "base_time",
+    "time_penalty_forward",
+    "time_penalty_backward",
+    // winter profile
+    "winter_score",
+    "winter_max_grade",
+    "northness",
+    // cycling profile
+    "cycling_time_forward",
+    "cycling_time_backward",
+    "cycling_energy_forward",
+    "cycling_energy_backward",
+];
 
 #[derive(Debug)]
 enum Filter {
@@ -40,186 +682,1815 @@ enum Filter {
     KeyValue(String, String),
 }
 
-fn haversine_distance(start: Location, end: Location) -> f64 {
-    let d_lat: f64 = (end.latitude - start.latitude).to_radians();
-    let d_lon: f64 = (end.longitude - start.longitude).to_radians();
-    let lat1: f64 = (start.latitude).to_radians();
-    let lat2: f64 = (end.latitude).to_radians();
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Filter::Key(key) => write!(f, "{}", key),
+            Filter::KeyValue(key, value) => write!(f, "{}={}", key, value),
+        }
+    }
+}
+
+// Formula of the walking times
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum CostModel {
+    // Tobler's hiking function, from the grade of each segment
+    Tobler,
+    // Naismith's rule, with Langmuir's corrections for the descents
+    Naismith,
+}
 
-    let a: f64 = ((d_lat / 2.0).sin()) * ((d_lat / 2.0).sin())
-        + ((d_lon / 2.0).sin()) * ((d_lon / 2.0).sin()) * (lat1.cos()) * (lat2.cos());
-    let c: f64 = 2.0 * ((a.sqrt()).atan2((1.0 - a).sqrt()));
+impl CostModel {
+    fn walk_time(self, distances: &[f64], elevations: &[Option<f64>], backward: bool) -> f64 {
+        match self {
+            CostModel::Tobler => gradient::walk_time(distances, elevations, backward),
+            CostModel::Naismith => gradient::naismith_walk_time(distances, elevations, backward),
+        }
+    }
+}
 
-    return 6371.0 * c;
+// How the ele tags of the nodes are used
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum EleTags {
+    // Instead of the DEM (as the elevation overrides, which take precedence)
+    Override,
+    // Where the DEM has no value (outside of its extent or on nodata)
+    Fallback,
 }
 
-// Macro that takes an array of filter and returns a closure that can be used to filter the ways
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum FilterMode {
+    // Keep the ways matching at least one of the filters
+    Any,
+    // Keep the ways matching all the filters
+    All,
+}
+
+// Format of an OSM input, OSM XML and o5m files being detected from their extension
+// and header and the other files being read as PBF files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    // Read in a single pass, whatever its format
+    Stdin,
+    Xml,
+    O5m,
+    Pbf,
+}
+
+impl InputFormat {
+    fn detect(path: &str) -> InputFormat {
+        if stream::is_standard_stream(path) {
+            InputFormat::Stdin
+        } else if osmxml::is_xml(path) {
+            InputFormat::Xml
+        } else if o5m::is_o5m(path) {
+            InputFormat::O5m
+        } else {
+            InputFormat::Pbf
+        }
+    }
+}
+
+// What happens to the ways with a node on a nodata pixel of the DEM
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum NodataPolicy {
+    // The way is not written
+    SkipWay,
+    // The segments of the way touching the node don't contribute to the climb
+    // and descent, as for the nodes outside of the DEM
+    SkipSegment,
+    // The elevation of the node is interpolated between its closest neighbours
+    // with an elevation along the way
+    Interpolate,
+    // The run is aborted, before any output is written
+    Error,
+}
+
+// What happens to the nodes outside of the extent of the DEM
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum OutOfExtentPolicy {
+    // The segments touching the nodes don't contribute to the climb and descent
+    Skip,
+    // The elevation of the nodes is 0, e.g. for DEMs without the sea
+    Zero,
+    // The run is aborted, before any output is written
+    Error,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum PbfReader {
+    // osmpbfreader's dependency resolution
+    Standard,
+    // Two passes decoding the blocks on all the cores, the second one
+    // only reading the locations of the nodes of the matched ways
+    Parallel,
+    // Bounded memory for planet-scale files: a first pass collects the ids of the
+    // nodes of the matched ways, a second one their locations, the ways being
+    // read again one by one when computing them
+    Streaming,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Smoothing {
+    // Average of the elevations within the window
+    MovingAverage,
+    // Quadratic fitted to the elevations within the window, which keeps the
+    // summits and valleys better
+    SavitzkyGolay,
+}
+
+impl Smoothing {
+    fn apply(self, distances: &[f64], elevations: &[Option<f64>], window: f64) -> Vec<Option<f64>> {
+        match self {
+            Smoothing::MovingAverage => smoothing::moving_average(distances, elevations, window),
+            Smoothing::SavitzkyGolay => smoothing::savitzky_golay(distances, elevations, window),
+        }
+    }
+}
+
+// Parse a filter expression: comma-separated keys or key=value pairs,
+// split on the first "=" so that values may contain "="
+fn parse_filters(expression: &str) -> Vec<Filter> {
+    let mut k_or_kvs = vec![String::new()];
+    let mut chars = expression.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.as_str().starts_with(',') => {
+                k_or_kvs.last_mut().unwrap().push(chars.next().unwrap())
+            }
+            ',' => k_or_kvs.push(String::new()),
+            c => k_or_kvs.last_mut().unwrap().push(c),
+        }
+    }
+    k_or_kvs
+        .into_iter()
+        .filter(|k_or_kv| !k_or_kv.is_empty())
+        .map(|k_or_kv| match k_or_kv.split_once('=') {
+            Some((key, value)) => Filter::KeyValue(key.to_string(), value.to_string()),
+            None => Filter::Key(k_or_kv),
+        })
+        .collect()
+}
+
+// Macro that takes an array of filter and the way they are combined
+// and returns a closure that can be used to filter the ways
 macro_rules! filter {
-    ($filters:expr) => {
-        |obj: &osmpbfreader::OsmObj| {
-            let mut ret_val = false;
-            for filter in $filters {
-                match filter {
-                    Filter::Key(key) => {
-                        if obj.tags().contains_key(key.as_str()) {
-                            ret_val = true;
-                        }
-                    }
-                    Filter::KeyValue(key, value) => {
-                        if obj.tags().get(key.as_str())
-                            == Some(&smartstring::alias::String::from(value.as_str()))
-                        {
-                            ret_val = true;
-                        }
-                    }
+    ($filters:expr, $mode:expr) => {
+        |tags: &osmpbfreader::Tags| {
+            let mut matches = $filters.iter().map(|filter| match filter {
+                Filter::Key(key) => tags.contains_key(key.as_str()),
+                Filter::KeyValue(key, value) => {
+                    tags.get(key.as_str()).map(|v| v.as_str()) == Some(value.as_str())
                 }
+            });
+            match $mode {
+                FilterMode::Any => matches.any(|ret_val| ret_val),
+                FilterMode::All => matches.all(|ret_val| ret_val),
             }
-            ret_val
         }
     };
 }
 
+// Simplify the ways whose nodes are all available, and drop the nodes
+// which are no longer used so that they are not sampled
+fn simplify_ways(
+    objs: &mut std::collections::BTreeMap<osmpbfreader::OsmId, osmpbfreader::OsmObj>,
+    tolerance: f64,
+) {
+    let simplified: Vec<(osmpbfreader::OsmId, Vec<osmpbfreader::NodeId>)> = objs
+        .values()
+        .filter_map(|obj| obj.way())
+        .filter_map(|way| {
+            let coordinates = way
+                .nodes
+                .iter()
+                .map(|node_id| {
+                    let node = objs.get(&osmpbfreader::OsmId::Node(*node_id))?.node()?;
+                    Some([node.lon(), node.lat()])
+                })
+                .collect::<Option<Vec<[f64; 2]>>>()?;
+            let kept = geometry::simplify(&coordinates, tolerance);
+            let nodes = kept.iter().map(|i| way.nodes[*i]).collect();
+            Some((osmpbfreader::OsmId::Way(way.id), nodes))
+        })
+        .collect();
+    for (id, nodes) in simplified {
+        if let Some(osmpbfreader::OsmObj::Way(way)) = objs.get_mut(&id) {
+            way.nodes = nodes;
+        }
+    }
+    let used_nodes: fxhash::FxHashSet<osmpbfreader::NodeId> = objs
+        .values()
+        .filter_map(|obj| obj.way())
+        .flat_map(|way| way.nodes.iter().copied())
+        .collect();
+    objs.retain(|id, _| match id {
+        osmpbfreader::OsmId::Node(node_id) => used_nodes.contains(node_id),
+        _ => true,
+    });
+}
+
+// Whether the way can be traveled forward and backward, from its oneway tag
+fn oneway_directions(tags: &osmpbfreader::Tags) -> (bool, bool) {
+    match tags.get("oneway").map(|value| value.as_str()) {
+        Some("yes" | "true" | "1") => (true, false),
+        Some("-1" | "reverse") => (false, true),
+        _ => (true, true),
+    }
+}
+
 fn main() {
-    let args = Args::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    logger::init(cli.log_format, cli.verbose);
+    progress::init(cli.quiet);
+    match cli.command {
+        Some(Command::GenFixture { output_dir }) => fixture::generate(&output_dir),
+        Some(Command::Doctor { files }) => doctor::run(&files),
+        Some(Command::Gtfs {
+            feed,
+            elevation_file,
+            output_file,
+            grade_window,
+        }) => gtfs::run(&feed, &elevation_file, &output_file, grade_window),
+        Some(Command::Enrich {
+            input_file,
+            elevation_file,
+            output_file,
+        }) => enrich::run(&input_file, &elevation_file, &output_file),
+        Some(Command::Gpx {
+            gpx_file,
+            elevation_file,
+            output_file,
+        }) => gpx::run(&gpx_file, &elevation_file, &output_file),
+        Some(Command::Serve {
+            elevation_file,
+            listen,
+        }) => server::run(&elevation_file, &listen),
+        Some(Command::Areas {
+            osm_file,
+            elevation_file,
+            output_file,
+            filter,
+        }) => {
+            let filters: Vec<Filter> = filter.iter().flat_map(|f| parse_filters(f)).collect();
+            let matches = filter!(&filters, FilterMode::Any);
+            area::run(&osm_file, &elevation_file, &output_file, |obj| {
+                matches(obj.tags())
+            })
+        }
+        Some(Command::Stats { results_file }) => summary::run(&results_file, cli.no_color),
+        Some(Command::Tiles {
+            results_file,
+            output,
+            min_zoom,
+            max_zoom,
+        }) => mvt::run(&results_file, &output, min_zoom, max_zoom.max(min_zoom)),
+        Some(Command::Update {
+            previous,
+            diff,
+            args,
+        }) => {
+            let update = update::Update::read(&previous, &diff).unwrap_or_else(|e| {
+                log::error!(
+                    target: "setup",
+                    fields:serde = json!({ "previous": previous, "diff": diff });
+                    "{}", e
+                );
+                std::process::exit(1);
+            });
+            log::info!(
+                target: "read",
+                fields:serde = json!({
+                    "changed_way_count": update.changed_way_count(),
+                    "changed_node_count": update.changed_node_count(),
+                });
+                "{} ways and {} nodes changed by the diff",
+                update.changed_way_count(),
+                update.changed_node_count(),
+            );
+            let matches = matches.subcommand_matches("update").unwrap();
+            run(*args, matches, cli.no_color, Some(update))
+        }
+        None => run(cli.args, &matches, cli.no_color, None),
+    }
+}
+
+fn run(mut args: Args, matches: &clap::ArgMatches, no_color: bool, update: Option<update::Update>) {
+    // The positional arguments are required when no subcommand is given
+    let osm_file = args.osm_file.clone().unwrap();
+    // With --dem-source (or --elevation-api), the second positional argument is
+    // the output file
+    let elevation_source = match (args.dem_source, &args.elevation_api) {
+        (Some(dem_source), _) => Some(("--dem-source", dem_source.name().to_string())),
+        (None, Some(url)) => Some(("--elevation-api", url.clone())),
+        (None, None) => None,
+    };
+    if let Some((option, source)) = &elevation_source {
+        if args.output_file.is_some() {
+            log::error!(
+                target: "setup",
+                fields:serde = json!({ "elevation_source": source });
+                "No elevation file can be given with {}", option
+            );
+            std::process::exit(1);
+        }
+        args.output_file = args.elevation_file.take();
+    }
+    if let Err(e) = args.profile.check_options(matches) {
+        log::error!(target: "setup", fields:serde = json!({ "profile": format!("{:?}", args.profile) }); "{}", e);
+        std::process::exit(1);
+    }
+    if args.elevation_api.is_some() && args.profile == Profile::Winter {
+        log::error!(
+            target: "setup",
+            fields:serde = json!({ "profile": "winter" });
+            "The winter profile reads the terrain slope of a DEM, which isn't available with --elevation-api",
+        );
+        std::process::exit(1);
+    }
+    let elevation_file = match elevation_source {
+        Some((_, source)) => source,
+        None => args.elevation_file.clone().unwrap(),
+    };
+
+    // Inputs given as URLs are read from their cached copies
+    let cache = fetch::Cache {
+        dir: args
+            .cache_dir
+            .as_ref()
+            .map_or_else(fetch::default_dir, std::path::PathBuf::from),
+        refresh: args.refresh,
+    };
+    let resolve = |input: &String| {
+        cache.resolve(input).unwrap_or_else(|e| {
+            log::error!(target: "fetch", fields:serde = json!({ "url": input }); "{}", e);
+            std::process::exit(1);
+        })
+    };
+    let osm_path = if args.overpass {
+        osm_file.clone()
+    } else {
+        resolve(&osm_file)
+    };
+    let merged_paths: Vec<String> = args.merge.iter().map(resolve).collect();
+    // The remote DEMs read in place are not downloaded
+    let resolve_dem = |input: &String| {
+        if input.starts_with("s3://") || (args.remote_dem && elevation::is_remote(input)) {
+            input.clone()
+        } else {
+            resolve(input)
+        }
+    };
+    let mut dem_paths: Vec<String> = args.dem.iter().map(resolve_dem).collect();
+    if args.dem_source.is_none() && args.elevation_api.is_none() {
+        dem_paths.insert(0, resolve_dem(&elevation_file));
+    }
+    let fallback_dem_paths: Vec<String> = args.fallback_dem.iter().map(resolve_dem).collect();
 
     // Read optional arguments if any in order to build an array of filters
-    let filters = match args.filter {
-        Some(filter) => {
-            let split = filter.split(',');
-            let mut result = Vec::new();
-            for k_or_kv in split {
-                let mut split = k_or_kv.split('=');
-                let key = split.next().unwrap();
-                let value = split.next();
-                let ret_val = match value {
-                    Some(value) => Filter::KeyValue(key.to_string(), value.to_string()),
-                    None => Filter::Key(key.to_string()),
-                };
-                result.push(ret_val);
+    let mut filters: Vec<Filter> = args
+        .filter
+        .iter()
+        .flat_map(|expression| parse_filters(expression))
+        .collect();
+    if let Some(filter_file) = &args.filter_file {
+        let content = std::fs::read_to_string(filter_file).unwrap_or_else(|e| {
+            log::error!(
+                target: "setup",
+                fields:serde = json!({ "filter_file": filter_file });
+                "Unable to read filter file {}: {}", filter_file, e
+            );
+            std::process::exit(1);
+        });
+        // Tag values may contain "#", so only whole lines can be commented out
+        for line in content.lines().map(str::trim) {
+            if !line.starts_with('#') {
+                filters.extend(parse_filters(line));
+            }
+        }
+    }
+    let mut filter_mode = args.filter_mode;
+    if filters.is_empty() {
+        // The default filters of the profiles are alternatives
+        filters = args.profile.default_filters();
+        filter_mode = FilterMode::Any;
+    }
+    log::debug!(
+        target: "setup",
+        fields:serde = json!({
+            "filters": filters.iter().map(|filter| filter.to_string()).collect::<Vec<_>>(),
+            "filter_mode": format!("{:?}", filter_mode).to_lowercase(),
+        });
+        "Filters ({}): {}",
+        format!("{:?}", filter_mode).to_lowercase(),
+        filters
+            .iter()
+            .map(|filter| filter.to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+    );
+
+    // The OSM input of an Overpass query is its response, the ways being queried by
+    // tags unless they are matched after a transformation
+    let osm_path = if args.overpass {
+        let selectors: Vec<String> = match &args.transform {
+            Some(_) => Vec::new(),
+            None => filters
+                .iter()
+                .map(|filter| match filter {
+                    Filter::Key(key) => overpass::tag_selector(key, None),
+                    Filter::KeyValue(key, value) => overpass::tag_selector(key, Some(value)),
+                })
+                .collect(),
+        };
+        overpass::query(&osm_file, &selectors, filter_mode == FilterMode::All)
+            .and_then(|query| overpass::fetch(&cache, &args.overpass_url, &query))
+            .unwrap_or_else(|e| {
+                log::error!(
+                    target: "fetch",
+                    fields:serde = json!({ "url": args.overpass_url });
+                    "{}", e
+                );
+                std::process::exit(1);
+            })
+    } else {
+        osm_path
+    };
+
+    let partition = args.partition_by.as_deref().map(|spec| {
+        output::Partition::parse(spec).unwrap_or_else(|e| {
+            log::error!(target: "setup", fields:serde = json!({ "partition_by": spec }); "{}", e);
+            std::process::exit(1);
+        })
+    });
+
+    // Expand the placeholders of the output paths
+    let placeholders = template::Placeholders {
+        region: if args.overpass {
+            "overpass".to_string()
+        } else {
+            template::region(&osm_file)
+        },
+        date: template::today(),
+        filter_hash: format!(
+            "{:08x}",
+            fxhash::hash32(&format!("{:?} {:?}", filters, filter_mode))
+        ),
+        tile: None,
+    };
+    let expand_with = |path: &str, placeholders: &template::Placeholders| {
+        template::expand(path, placeholders).unwrap_or_else(|e| {
+            log::error!(target: "setup", fields:serde = json!({ "path": path }); "{}", e);
+            std::process::exit(1);
+        })
+    };
+    let expand = |path: &str| expand_with(path, &placeholders);
+    let outputs: Vec<output::Output> = args
+        .output_file
+        .iter()
+        .map(|path| output::Output {
+            format: args.format,
+            path: path.to_string(),
+        })
+        .chain(args.output.iter().map(|spec| {
+            output::Output::parse(spec).unwrap_or_else(|e| {
+                log::error!(target: "setup", fields:serde = json!({ "output": spec }); "{}", e);
+                std::process::exit(1);
+            })
+        }))
+        .map(|output| {
+            // The outputs partitioned by tile are written to a file per tile with {tile}
+            let per_tile =
+                matches!(partition, Some(output::Partition::Tile(_))) && output.is_partitionable();
+            let placeholders = template::Placeholders {
+                tile: per_tile.then(|| "{tile}".to_string()),
+                ..placeholders.clone()
+            };
+            output::Output {
+                path: expand_with(&output.path, &placeholders),
+                ..output
             }
-            result
+        })
+        .collect();
+    if args.compress.is_some() {
+        for output in outputs.iter().filter(|output| !output.format.is_stream()) {
+            log::warn!(
+                target: "setup",
+                fields:serde = json!({ "format": format!("{:?}", output.format) });
+                "The {:?} output is not compressed", output.format
+            );
         }
-        None => vec![Filter::Key("highway".to_string())],
+    }
+    for path in [
+        &mut args.metadata,
+        &mut args.warnings_file,
+        &mut args.maproulette,
+        &mut args.truck_layer,
+        &mut args.winter_ranking,
+        &mut args.winter_layer,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        *path = expand(path);
+    }
+
+    let transform = args.transform.as_deref().map(|path| {
+        transform::Transform::read(path, WAY_FIELDS).unwrap_or_else(|e| {
+            log::error!(target: "setup", fields:serde = json!({ "transform": path }); "{}", e);
+            std::process::exit(1);
+        })
+    });
+    // Ways of the checkpoint file, computed before the run was stopped, when resuming
+    let mut resumed = fxhash::FxHashSet::default();
+    let resume_after = match (&args.checkpoint, args.resume) {
+        (Some(path), true) if Path::new(path).exists() => Some(
+            checkpoint::read(path, |record| {
+                resumed.insert(record.way_id);
+            })
+            .unwrap_or_else(|e| {
+                log::error!(target: "setup", fields:serde = json!({ "checkpoint": path }); "{}", e);
+                std::process::exit(1);
+            }),
+        ),
+        _ => None,
+    };
+    // Whether an object is one of the ways to compute
+    let is_matched = |obj: &osmpbfreader::OsmObj| {
+        let matches = match &transform {
+            Some(transform) => filter!(&filters, filter_mode)(&transform.transformed(obj.tags())),
+            None => filter!(&filters, filter_mode)(obj.tags()),
+        };
+        // Only the ways affected by the diff are recomputed when updating,
+        // and the ways of the checkpoint file are not computed again
+        matches
+            && obj.way().is_none_or(|way| !resumed.contains(&way.id.0))
+            && update
+                .as_ref()
+                .is_none_or(|update| obj.way().is_some_and(|way| update.affects(way)))
+    };
+    // Same, counting the ways read for the progress of the reading phase (which the
+    // ways read again to be computed, with --pbf-reader streaming, don't advance)
+    let reading = progress::Progress::new("Reading the ways");
+    let matches = |obj: &osmpbfreader::OsmObj| {
+        if obj.is_way() {
+            reading.inc(1);
+        }
+        is_matched(obj)
     };
 
-    // Open OSM file
-    let r = std::fs::File::open(&Path::new(&args.osm_file))
-        .expect(format!("Unable to open OSM file {}", &args.osm_file).as_str());
-    let mut pbf = osmpbfreader::OsmPbfReader::new(r);
+    // The ways kept from the previous output have no geometry (nor tags)
+    if update.is_some()
+        && (partition.is_some()
+            || outputs.iter().any(|output| {
+                !matches!(
+                    output.format,
+                    output::Format::Json | output::Format::NdJson | output::Format::Summary
+                )
+            }))
+    {
+        log::error!(
+            target: "setup",
+            fields:serde = json!({
+                "outputs": outputs.iter().map(|output| &output.path).collect::<Vec<_>>(),
+            });
+            "Updates can only write json, ndjson and summary outputs, without partitions"
+        );
+        std::process::exit(1);
+    }
+    if args.per_segment
+        && (update.is_some()
+            || outputs
+                .iter()
+                .any(|output| !output::SEGMENT_FORMATS.contains(&output.format)))
+    {
+        log::error!(
+            target: "setup",
+            fields:serde = json!({
+                "outputs": outputs.iter().map(|output| &output.path).collect::<Vec<_>>(),
+            });
+            "--per-segment can only write ndjson, csv and geojson outputs, and not when updating"
+        );
+        std::process::exit(1);
+    }
+    // Only one output, written as a stream without partitions, can go to stdout
+    let stdout_outputs: Vec<&output::Output> = outputs
+        .iter()
+        .filter(|output| stream::is_standard_stream(&output.path))
+        .collect();
+    let stdout_error = if stdout_outputs.len() > 1 {
+        Some("Only one output can be written to stdout".to_string())
+    } else if !stdout_outputs.is_empty() && partition.is_some() {
+        Some("Partitioned outputs can't be written to stdout".to_string())
+    } else {
+        stdout_outputs
+            .iter()
+            .find(|output| !output.format.is_stream())
+            .map(|output| format!("The {:?} output can't be written to stdout", output.format))
+    };
+    if let Some(e) = stdout_error {
+        log::error!(
+            target: "setup",
+            fields:serde = json!({ "output": stream::STANDARD_STREAM });
+            "{}", e
+        );
+        std::process::exit(1);
+    }
 
-    // Open elevation file
-    let dataset = Dataset::open(&args.elevation_file)
-        .expect(format!("Unable to open elevation file {}", &args.elevation_file).as_str());
-    let rasterband: RasterBand = dataset.rasterband(1).unwrap();
-    let transform = dataset.geo_transform().unwrap();
-    let invert_transform = transform.invert().unwrap();
+    let reprojection = args.output_crs.as_deref().map(|crs| {
+        reproject::Reprojection::new(crs).unwrap_or_else(|e| {
+            log::error!(target: "setup", fields:serde = json!({ "output_crs": crs }); "{}", e);
+            std::process::exit(1);
+        })
+    });
 
-    // Get all the ways, according to the user-defined filter, and their dependencies
-    let objs = pbf
-        .get_objs_and_deps(|obj| obj.is_way() && filter!(&filters)(obj))
-        .unwrap();
+    if args.kml_breaks.is_empty() || !args.kml_breaks.windows(2).all(|pair| pair[0] < pair[1]) {
+        log::error!(
+            target: "setup",
+            fields:serde = json!({ "kml_breaks": args.kml_breaks });
+            "The KML grade breaks must be increasing"
+        );
+        std::process::exit(1);
+    }
 
-    let mut node_elevation: FxHashMap<i64, f64> = FxHashMap::default();
+    if args.grade_histogram_breaks.is_empty()
+        || !args
+            .grade_histogram_breaks
+            .windows(2)
+            .all(|pair| pair[0] < pair[1])
+    {
+        log::error!(
+            target: "setup",
+            fields:serde = json!({ "grade_histogram_breaks": args.grade_histogram_breaks });
+            "The grade histogram breaks must be increasing"
+        );
+        std::process::exit(1);
+    }
 
-    // Iterate over all the dependant nodes and get their elevations
-    objs.iter()
-        .filter(|(_id, obj)| {
-            if let osmpbfreader::OsmObj::Node(_node) = obj {
-                true
-            } else {
-                false
-            }
+    if args
+        .max_sample_spacing
+        .is_some_and(|spacing| spacing.is_nan() || spacing <= 0.)
+    {
+        log::error!(
+            target: "setup",
+            fields:serde = json!({ "max_sample_spacing": args.max_sample_spacing });
+            "The maximum sample spacing must be positive"
+        );
+        std::process::exit(1);
+    }
+
+    let at = args.at.as_deref().map(|at| {
+        pbf::parse_timestamp(at).unwrap_or_else(|e| {
+            log::error!(target: "setup", fields:serde = json!({ "at": at }); "{}", e);
+            std::process::exit(1);
         })
-        .for_each(|(id, obj)| {
-            let node = obj.node().unwrap();
-            let (x, y) = invert_transform.apply(node.lon(), node.lat());
-            let elevation = rasterband
-                .read_as::<f64>(
-                    (x as isize, y as isize),
-                    (1, 1),
-                    (1, 1),
-                    Some(ResampleAlg::NearestNeighbour),
+    });
+    let inputs: Vec<(&str, InputFormat)> = std::iter::once(&osm_path)
+        .chain(&merged_paths)
+        .map(|path| (path.as_str(), InputFormat::detect(path)))
+        .collect();
+    let all_pbf = inputs.iter().all(|(_, format)| *format == InputFormat::Pbf);
+    let setup_error = if !all_pbf && at.is_some() {
+        Some("--at requires PBF history files (not read from stdin)")
+    } else if (!all_pbf || inputs.len() > 1)
+        && outputs
+            .iter()
+            .any(|output| output.format == output::Format::Pbf)
+    {
+        Some("The pbf output requires a single PBF input file (not read from stdin)")
+    } else if args.pbf_reader == PbfReader::Streaming && (!all_pbf || at.is_some()) {
+        Some("--pbf-reader streaming requires PBF files (not read from stdin, nor with --at)")
+    } else if args.pbf_reader == PbfReader::Streaming && args.simplify.is_some() {
+        Some("--simplify can't be used with --pbf-reader streaming")
+    } else if args.pbf_reader == PbfReader::Streaming
+        && args.flat_nodes.is_none()
+        && args.node_cache.is_none()
+    {
+        // The nodes would otherwise all be kept in memory
+        Some("--pbf-reader streaming requires --flat-nodes or --node-cache")
+    } else if args.ele_tags.is_some() && (args.pbf_reader != PbfReader::Standard || at.is_some()) {
+        // The other readers don't decode the tags of the nodes
+        Some("--ele-tags requires --pbf-reader standard (and can't be used with --at)")
+    } else if inputs
+        .iter()
+        .filter(|(_, format)| *format == InputFormat::Stdin)
+        .count()
+        > 1
+    {
+        Some("stdin can only be read once")
+    } else {
+        None
+    };
+    if let Some(e) = setup_error {
+        log::error!(
+            target: "setup",
+            fields:serde = json!({ "osm_file": osm_file, "merge": args.merge });
+            "{}", e
+        );
+        std::process::exit(1);
+    }
+    // The tiles of a global DEM are downloaded for the extent of the inputs
+    if let Some(dem_source) = args.dem_source {
+        let dem_cache = fetch::Cache {
+            dir: args
+                .dem_cache_dir
+                .as_ref()
+                .map_or_else(|| cache.dir.join("dem"), std::path::PathBuf::from),
+            refresh: args.refresh,
+        };
+        let mapbox_token = args
+            .mapbox_token
+            .clone()
+            .or_else(|| std::env::var("MAPBOX_ACCESS_TOKEN").ok());
+        let tiles = demsource::extent(&inputs)
+            .and_then(|bbox| {
+                demsource::download(
+                    dem_source,
+                    bbox,
+                    args.dem_zoom,
+                    mapbox_token.as_deref(),
+                    &dem_cache,
                 )
-                .unwrap();
-            node_elevation.insert(id.inner_id(), elevation.data[0]);
-        });
+            })
+            .unwrap_or_else(|e| {
+                log::error!(
+                    target: "fetch",
+                    fields:serde = json!({ "dem_source": dem_source.name() });
+                    "{}", e
+                );
+                std::process::exit(1);
+            });
+        dem_paths.splice(0..0, tiles);
+    }
 
-    // Create an iterator over all our ways
-    let filtered_objs = objs.iter().filter(|(_id, obj)| {
-        if let osmpbfreader::OsmObj::Way(_way) = obj {
-            true
-        } else {
-            false
+    // Open the elevation file, or the mosaic of the DEM tiles, and the fallback DEMs
+    let open_dem = {
+        let (interpolation, geoid, geoid_conversion) = (
+            args.interpolation,
+            args.geoid.clone(),
+            args.geoid_conversion,
+        );
+        let (dem_paths, fallback_dem_paths) = (dem_paths.clone(), fallback_dem_paths.clone());
+        let open = move || -> Result<Dem, String> {
+            let fallback_dems = fallback_dem_paths
+                .iter()
+                .map(|path| Dem::open(path))
+                .collect::<Result<_, _>>()?;
+            let dem = Dem::open_mosaic(&dem_paths)?
+                .with_fallbacks(fallback_dems)
+                .with_interpolation(interpolation);
+            Ok(match &geoid {
+                Some(geoid) => dem.with_geoid(Dem::open(geoid)?, geoid_conversion),
+                None => dem,
+            })
+        };
+        move || {
+            open().unwrap_or_else(|e| {
+                log::error!(target: "setup", "{}", e);
+                std::process::exit(1);
+            })
         }
+    };
+    // (none with --elevation-api)
+    let dem = args.elevation_api.is_none().then(&open_dem);
+    // The nodes are sampled on all the cores, each sampling thread opening the DEM
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let sample_pool = (args.elevation_api.is_none() && threads > 1)
+        .then(move || elevation::SamplePool::new(threads, open_dem));
+    let elevation_api = args
+        .elevation_api
+        .as_ref()
+        .map(|url| elevationapi::ElevationApi {
+            url: url.clone(),
+            batch_size: args.elevation_api_batch_size,
+            concurrency: args.elevation_api_concurrency,
+        });
+    let error_dem = args.dem_error_file.as_deref().map(|path| {
+        Dem::open(path).unwrap_or_else(|e| {
+            log::error!(target: "setup", fields:serde = json!({ "dem_error_file": path }); "{}", e);
+            std::process::exit(1);
+        })
     });
+    if let Some(dem) = &dem {
+        let (width, height) = dem.size();
+        log::debug!(
+            target: "setup",
+            fields:serde = json!({
+                "dem": dem_paths,
+                "fallback_dem": fallback_dem_paths,
+                "size": [width, height],
+                "geo_transform": dem.geo_transform(),
+                "crs": dem.crs(),
+            });
+            "DEM of {}x{} pixels (file(s): {}, CRS {}), geotransform {:?}",
+            width,
+            height,
+            dem_paths.join(", "),
+            dem.crs().as_deref().unwrap_or("unknown"),
+            dem.geo_transform(),
+        );
+    }
+
+    let mut node_store: Box<dyn NodeStore> = match &args.flat_nodes {
+        Some(path) => Box::new(FlatNodeStore::create(path)),
+        None => new_node_store(
+            args.f32_coordinates,
+            args.f32_elevations,
+            args.node_cache
+                .as_deref()
+                .map(|path| (path, args.node_cache_threshold << 20)),
+        ),
+    };
+    let mut elevation_overrides = match &args.elevation_overrides {
+        Some(path) => overrides::read(path).unwrap_or_else(|e| {
+            log::error!(
+                target: "setup",
+                fields:serde = json!({ "elevation_overrides": path });
+                "{}", e
+            );
+            std::process::exit(1);
+        }),
+        None => FxHashMap::default(),
+    };
+    // Elevations of the ele tags of the nodes, with --ele-tags fallback
+    let mut ele_fallbacks: FxHashMap<i64, f64> = FxHashMap::default();
+    let with_fallback = |ele_fallbacks: &FxHashMap<i64, f64>, id: i64, sample: Sample| match (
+        sample,
+        ele_fallbacks.get(&id),
+    ) {
+        (Sample::NoData(_) | Sample::OutOfExtent, Some(elevation)) => Sample::Value(*elevation),
+        _ => sample,
+    };
+    let sample_every = args
+        .sample_every
+        .or(args.approx.then_some(sampling::APPROX_SAMPLE_EVERY));
+    // Nodes sampled on the DEM, when only some of them are
+    let mut sampled_nodes: Option<NodeIdSet>;
+    let mut coverage = CoverageReport::for_tiles(&dem.as_ref().map_or(Vec::new(), Dem::tile_files));
+    // Mosaic DEM whose tile each node is read from is counted in the coverage report
+    let mosaic = dem.as_ref().filter(|_| !coverage.tiles.is_empty());
+    // The nodes are sampled in batches, sorted by block of the DEM
+    let source: &dyn ElevationProvider = match (&sample_pool, &dem, &elevation_api) {
+        (Some(pool), _, _) => pool,
+        (None, Some(dem), _) => dem,
+        (None, None, Some(api)) => api,
+        (None, None, None) => unreachable!(),
+    };
+    let mut batch = elevation::SampleBatch::new(source);
+    // Time spent sampling the batches
+    let sample_time = std::cell::Cell::new(std::time::Duration::ZERO);
+    let sample_batch = |batch: &mut elevation::SampleBatch| {
+        let start = std::time::Instant::now();
+        let nodes = batch.sample().unwrap_or_else(|e| {
+            log::error!(
+                target: "sample",
+                fields:serde = json!({ "elevation_api": args.elevation_api });
+                "{}", e
+            );
+            std::process::exit(1);
+        });
+        let seconds = start.elapsed().as_secs_f64();
+        log::trace!(
+            target: "sample",
+            fields:serde = json!({ "node_count": nodes.len(), "elapsed_seconds": seconds });
+            "Batch of {} nodes sampled in {:.3} s", nodes.len(), seconds
+        );
+        sample_time.set(sample_time.get() + start.elapsed());
+        nodes
+    };
+    let ele_tag = |tags: &osmpbfreader::Tags| {
+        args.ele_tags
+            .and(tags.get("ele"))
+            .and_then(|ele| overrides::parse_ele_tag(ele))
+    };
+    let sampling = progress::Progress::new("Sampling the nodes");
+    let mut add_node = |id: i64, lon: f64, lat: f64, sampled: bool, ele: Option<f64>| {
+        match (args.ele_tags, ele) {
+            (Some(EleTags::Override), Some(ele)) => {
+                elevation_overrides.entry(id).or_insert(ele);
+            }
+            (Some(EleTags::Fallback), Some(ele)) => {
+                ele_fallbacks.insert(id, ele);
+            }
+            _ => {}
+        }
+        let sample = match elevation_overrides.get(&id) {
+            Some(elevation) => Sample::Value(*elevation),
+            None if sampled => {
+                if batch.push(id, lon, lat) {
+                    for (id, (lon, lat), (sample, from_fallback)) in sample_batch(&mut batch) {
+                        let tile = mosaic.and_then(|dem| dem.tile_at(lon, lat));
+                        coverage.record(&sample, from_fallback, tile);
+                        let sample = with_fallback(&ele_fallbacks, id, sample);
+                        node_store.insert(id, NodeInfo { lon, lat, sample });
+                        sampling.inc(1);
+                    }
+                }
+                return;
+            }
+            // The elevation is interpolated from the sampled nodes of the way
+            None => {
+                let sample = Sample::OutOfExtent;
+                node_store.insert(id, NodeInfo { lon, lat, sample });
+                sampling.inc(1);
+                return;
+            }
+        };
+        coverage.record(&sample, false, None);
+        node_store.insert(id, NodeInfo { lon, lat, sample });
+        sampling.inc(1);
+    };
 
-    // Resulting map of way_id -> WayInfo
-    let mut result_map: FxHashMap<i64, WayInfo> = FxHashMap::with_capacity_and_hasher(
-        filtered_objs.size_hint().1.unwrap(),
-        Default::default(),
+    reading.start(None);
+    let read_start = std::time::Instant::now();
+    let mut ways = Vec::new();
+    // Number of ways read again one by one, with --pbf-reader streaming
+    let mut streamed_way_count = None;
+    // Ways recomputed after the diff, when updating
+    let mut recomputed = None;
+    // (the nodes, and so their ele tags, aren't read on that path)
+    if all_pbf
+        && at.is_none()
+        && args.pbf_reader != PbfReader::Streaming
+        && args.ele_tags.is_none()
+        && inputs
+            .iter()
+            .all(|(path, _)| pbf::has_locations_on_ways(path))
+    {
+        // The node locations are embedded in the ways, no need to resolve the node dependencies
+        log::info!(
+            target: "read",
+            fields:serde = json!({ "locations_on_ways": true });
+            "Using the node locations embedded in the ways of the OSM file"
+        );
+        let mut seen_nodes = fxhash::FxHashSet::default();
+        // The ways present in several files are read once
+        let mut seen_ways = fxhash::FxHashSet::default();
+        sampled_nodes = sample_every.map(|_| Default::default());
+        for (path, _) in &inputs {
+            pbf::for_each_way_with_locations(path, &matches, |mut way, mut locations| {
+                if !seen_ways.insert(way.id.0) {
+                    return;
+                }
+                if let Some(tolerance) = args.simplify {
+                    let coordinates: Vec<[f64; 2]> =
+                        locations.iter().map(|(lon, lat)| [*lon, *lat]).collect();
+                    let kept = geometry::simplify(&coordinates, tolerance);
+                    way.nodes = kept.iter().map(|i| way.nodes[*i]).collect();
+                    locations = kept.iter().map(|i| locations[*i]).collect();
+                }
+                let count = way.nodes.len();
+                for (position, (node_id, (lon, lat))) in way.nodes.iter().zip(locations).enumerate()
+                {
+                    let sampled = sample_every
+                        .is_none_or(|every| sampling::is_sampled(position, count, every));
+                    // A node first seen at an unsampled position of a way is sampled
+                    // again when it is at a sampled position of another one
+                    let newly_sampled = sampled
+                        && sampled_nodes
+                            .as_mut()
+                            .is_some_and(|sampled_nodes| sampled_nodes.insert(node_id.0));
+                    if seen_nodes.insert(node_id.0) || newly_sampled {
+                        add_node(node_id.0, lon, lat, sampled, None);
+                    }
+                }
+                ways.push(way);
+            });
+        }
+    } else if args.pbf_reader == PbfReader::Streaming {
+        // The ids of the nodes of the matched ways are collected in a bitmap (deduplicating
+        // them as they are read), the ways themselves not being kept
+        let mut needed = NodeIdSet::default();
+        let mut sampled = sample_every.map(|_| NodeIdSet::default());
+        let mut way_count = 0;
+        let mut way_ids = update.as_ref().map(|_| fxhash::FxHashSet::default());
+        // The ways present in several files are counted once
+        let mut seen_ways = (inputs.len() > 1).then(fxhash::FxHashSet::default);
+        for (path, _) in &inputs {
+            pbf::for_each_way(path, &matches, |way| {
+                if !seen_ways
+                    .as_mut()
+                    .is_none_or(|seen_ways| seen_ways.insert(way.id.0))
+                {
+                    return;
+                }
+                way_count += 1;
+                if let Some(way_ids) = &mut way_ids {
+                    way_ids.insert(way.id.0);
+                }
+                for node_id in &way.nodes {
+                    needed.insert(node_id.0);
+                }
+                if let (Some(sampled), Some(every)) = (&mut sampled, sample_every) {
+                    let count = way.nodes.len();
+                    for (position, node_id) in way.nodes.iter().enumerate() {
+                        if sampling::is_sampled(position, count, every) {
+                            sampled.insert(node_id.0);
+                        }
+                    }
+                }
+            });
+        }
+        reading.finish();
+        sampling.start(Some(needed.len() as u64));
+        sampled_nodes = sampled;
+        // The nodes present in several files are sampled once
+        let mut found = NodeIdSet::default();
+        for (path, _) in &inputs {
+            pbf::for_each_needed_node(
+                path,
+                |id| needed.contains(id),
+                |node| {
+                    if found.insert(node.id.0) {
+                        let sampled = sampled_nodes
+                            .as_ref()
+                            .is_none_or(|sampled_nodes| sampled_nodes.contains(node.id.0));
+                        add_node(node.id.0, node.lon(), node.lat(), sampled, None);
+                    }
+                },
+            );
+        }
+        streamed_way_count = Some(way_count);
+        recomputed = way_ids;
+    } else {
+        // Get all the ways, according to the user-defined filter, and their dependencies
+        let mut objs = std::collections::BTreeMap::new();
+        for (path, format) in &inputs {
+            let file_objs = match (format, at, args.pbf_reader) {
+                // stdin, OSM XML and o5m files are read in the same way whatever the reader
+                (InputFormat::Stdin, _, _) => stream::get_ways_and_deps(|obj| matches(obj)),
+                (InputFormat::Xml, _, _) => osmxml::get_ways_and_deps(path, |obj| matches(obj)),
+                (InputFormat::O5m, _, _) => o5m::get_ways_and_deps(path, |obj| matches(obj)),
+                // The versions valid at the given instant, for full-history files
+                (InputFormat::Pbf, Some(at), _) => pbf::get_ways_and_deps_at(path, at, &matches),
+                (InputFormat::Pbf, None, PbfReader::Standard) => {
+                    // Open OSM file
+                    let r = std::fs::File::open(Path::new(path)).unwrap_or_else(|e| {
+                        log::error!(
+                            target: "setup",
+                            fields:serde = json!({ "osm_file": path });
+                            "Unable to open OSM file {}: {}", path, e
+                        );
+                        std::process::exit(1);
+                    });
+                    let mut pbf = osmpbfreader::OsmPbfReader::new(r);
+                    pbf.get_objs_and_deps(|obj| obj.is_way() && matches(obj))
+                        .unwrap_or_else(|e| {
+                            log::error!(
+                                target: "read",
+                                fields:serde = json!({ "osm_file": path });
+                                "Unable to read OSM file {}: {}", path, e
+                            );
+                            std::process::exit(1);
+                        })
+                }
+                (InputFormat::Pbf, None, PbfReader::Parallel) => {
+                    pbf::get_ways_and_deps_parallel(path, &matches)
+                }
+                // Streamed above
+                (InputFormat::Pbf, None, PbfReader::Streaming) => unreachable!(),
+            };
+            // The ways and nodes present in several files (e.g. along the boundaries
+            // of adjacent extracts) are kept once
+            if objs.is_empty() {
+                objs = file_objs;
+            } else {
+                for (id, obj) in file_objs {
+                    objs.entry(id).or_insert(obj);
+                }
+            }
+        }
+
+        if let Some(tolerance) = args.simplify {
+            simplify_ways(&mut objs, tolerance);
+        }
+
+        reading.finish();
+        sampling.start(Some(
+            objs.values().filter(|obj| obj.node().is_some()).count() as u64,
+        ));
+        sampled_nodes = sample_every.map(|every| {
+            objs.values()
+                .filter_map(|obj| obj.way())
+                .flat_map(|way| {
+                    let count = way.nodes.len();
+                    way.nodes
+                        .iter()
+                        .enumerate()
+                        .filter(move |(position, _)| sampling::is_sampled(*position, count, every))
+                        .map(|(_, node_id)| node_id.0)
+                })
+                .collect()
+        });
+
+        // Iterate over all the dependant nodes and get their elevations,
+        // the node objects are then dropped in favour of the (more compact) node store
+        for (_id, obj) in objs {
+            match obj {
+                osmpbfreader::OsmObj::Node(node) => {
+                    let sampled = sampled_nodes
+                        .as_ref()
+                        .is_none_or(|sampled_nodes| sampled_nodes.contains(node.id.0));
+                    add_node(
+                        node.id.0,
+                        node.lon(),
+                        node.lat(),
+                        sampled,
+                        ele_tag(&node.tags),
+                    )
+                }
+                osmpbfreader::OsmObj::Way(way) => ways.push(way),
+                osmpbfreader::OsmObj::Relation(_) => {}
+            }
+        }
+    }
+
+    for (id, (lon, lat), (sample, from_fallback)) in sample_batch(&mut batch) {
+        let tile = mosaic.and_then(|dem| dem.tile_at(lon, lat));
+        coverage.record(&sample, from_fallback, tile);
+        let sample = with_fallback(&ele_fallbacks, id, sample);
+        node_store.insert(id, NodeInfo { lon, lat, sample });
+        sampling.inc(1);
+    }
+    reading.finish();
+    sampling.finish();
+    logger::timing("read", "OSM file read and nodes sampled", read_start);
+    log::debug!(
+        target: "sample",
+        fields:serde = json!({ "elapsed_seconds": sample_time.get().as_secs_f64() });
+        "Nodes sampled in {:.3} s", sample_time.get().as_secs_f64()
     );
 
+    let total_way_count = streamed_way_count.unwrap_or(ways.len());
+    log::info!(
+        target: "sample",
+        fields:serde = json!({
+            "way_count": total_way_count,
+            "node_count": coverage.total(),
+            "covered": coverage.covered,
+            "nodata": coverage.nodata,
+            "out_of_extent": coverage.out_of_extent,
+            "from_fallback": coverage.from_fallback,
+            "coverage": coverage.percentage(),
+        });
+        "{}", coverage
+    );
+    for (path, tile) in coverage.tiles.iter().filter(|(_, tile)| tile.total() > 0) {
+        log::info!(
+            target: "sample",
+            fields:serde = json!({
+                "tile": path,
+                "node_count": tile.total(),
+                "covered": tile.covered,
+                "nodata": tile.nodata,
+                "out_of_extent": tile.out_of_extent,
+                "from_fallback": tile.from_fallback,
+                "coverage": tile.percentage(),
+            });
+            "DEM coverage of {}: {:.2}% of {} nodes ({} nodata, {} out of extent)",
+            path,
+            tile.percentage(),
+            tile.total(),
+            tile.nodata,
+            tile.out_of_extent,
+        );
+    }
+    if let Some(min_coverage) = args.min_coverage {
+        if coverage.percentage() < min_coverage {
+            log::error!(
+                target: "sample",
+                fields:serde = json!({
+                    "coverage": coverage.percentage(),
+                    "min_coverage": min_coverage,
+                });
+                "DEM coverage is below the requested threshold of {:.2}%, aborting", min_coverage
+            );
+            std::process::exit(1);
+        }
+    }
+    if args.nodata_policy == NodataPolicy::Error && coverage.nodata > 0 {
+        log::error!(
+            target: "sample",
+            fields:serde = json!({ "nodata": coverage.nodata, "nodata_policy": "error" });
+            "{} nodes of the ways are on nodata pixels of the DEM, aborting", coverage.nodata
+        );
+        std::process::exit(1);
+    }
+    if args.out_of_extent == OutOfExtentPolicy::Error && coverage.out_of_extent > 0 {
+        log::error!(
+            target: "sample",
+            fields:serde = json!({
+                "out_of_extent": coverage.out_of_extent,
+                "out_of_extent_policy": "error",
+            });
+            "{} nodes of the ways are outside of the extent of the DEM, aborting",
+            coverage.out_of_extent,
+        );
+        std::process::exit(1);
+    }
+
+    // The computed ways are streamed to the writers of the outputs
+    let mut writers: Vec<Box<dyn output::OutputWriter>> = outputs
+        .iter()
+        .map(|output| {
+            output.writer(
+                partition.as_ref(),
+                output::WriterOptions {
+                    reprojection: reprojection.as_ref(),
+                    osm_path: &osm_path,
+                    kml_breaks: &args.kml_breaks,
+                    compression: args.compress,
+                    per_segment: args.per_segment,
+                },
+            )
+        })
+        .collect();
+    let mut way_count = 0;
+    // Ways with a node on a nodata pixel, with --nodata-policy skip-way
+    let mut skipped_way_count = 0;
+    // Ways on structures whose elevation was interpolated between their endpoints
+    let mut flattened_way_count = 0;
+    let mut summary = summary::Summary::default();
+    // Map of way_id -> WayInfo, to be compared with the reference file
+    let mut verified = args.verify.as_ref().map(|_| serde_json::Map::new());
+
+    // The ways of the checkpoint file are written first, as they were computed
+    if let (Some(path), Some(_)) = (&args.checkpoint, resume_after) {
+        checkpoint::read(path, |mut checkpointed| {
+            // The user-defined metrics aren't read back, they are computed again
+            if let Some(transform) = &transform {
+                checkpointed.info.metrics = transform.metrics(&checkpointed.info);
+            }
+            let record = output::Record {
+                way_id: checkpointed.way_id,
+                info: &checkpointed.info,
+                geometry: &checkpointed.geometry,
+                partition: checkpointed.partition.as_deref(),
+            };
+            for writer in &mut writers {
+                writer.write(&record);
+            }
+            way_count += 1;
+            summary.add(record.info.distance, record.info.climb, record.info.descent);
+            if let Some(verified) = &mut verified {
+                let value = serde_json::to_value(record.info).expect("Unable to serialize result");
+                verified.insert(record.way_id.to_string(), value);
+            }
+        })
+        .unwrap_or_else(|e| {
+            log::error!(target: "setup", fields:serde = json!({ "checkpoint": path }); "{}", e);
+            std::process::exit(1);
+        });
+        log::info!(
+            target: "compute",
+            fields:serde = json!({ "checkpoint": path, "resumed_way_count": resumed.len() });
+            "{} ways resumed from the checkpoint {}", resumed.len(), path
+        );
+    }
+    let mut checkpoint = args.checkpoint.as_ref().map(|path| {
+        checkpoint::Checkpoint::open(path, resume_after).unwrap_or_else(|e| {
+            log::error!(target: "setup", fields:serde = json!({ "checkpoint": path }); "{}", e);
+            std::process::exit(1);
+        })
+    });
+
+    let mut warning_log = WarningLog::default();
+    let mut maproulette_tasks = Vec::new();
+    let mut profile_metrics = profile::ProfileMetrics::new(&args, dem.as_ref());
+
+    // On SIGINT / SIGTERM, the ways computed so far are written as partial results
+    shutdown::install();
+    let mut partial = false;
+
+    // Buffers reused from one way to the next
+    let mut nodes: Vec<(i64, NodeInfo)> = Vec::new();
+    let mut elevations: Vec<Option<f64>> = Vec::new();
+    let mut distances: Vec<f64> = Vec::new();
+    let mut coordinates: Vec<[f64; 2]> = Vec::new();
+
+    if update.is_some() && recomputed.is_none() {
+        recomputed = Some(ways.iter().map(|way| way.id.0).collect());
+    }
+    let ways: Box<dyn Iterator<Item = osmpbfreader::Way>> = if streamed_way_count.is_some() {
+        // The ways present in several files are computed once
+        let mut seen_ways = (inputs.len() > 1).then(fxhash::FxHashSet::default);
+        Box::new(
+            inputs
+                .iter()
+                .flat_map(|(path, _)| pbf::stream_ways(path, &is_matched))
+                .filter(move |way| {
+                    seen_ways
+                        .as_mut()
+                        .is_none_or(|seen_ways| seen_ways.insert(way.id.0))
+                }),
+        )
+    } else {
+        Box::new(ways.into_iter())
+    };
+
     // Compute slope information for all our ways
-    filtered_objs.for_each(|(id, obj)| {
-        let way = obj.way().unwrap();
-        let mut distance: f64 = 0.0;
-        let mut climb_distance: f64 = 0.0;
-        let mut descent_distance: f64 = 0.0;
-        let mut climb: f64 = 0.0;
-        let mut descent: f64 = 0.0;
-
-        way.nodes
-            .iter()
-            .zip(way.nodes.iter().skip(1))
-            .for_each(|(a, b)| {
-                let node_a = objs
-                    .get(&osmpbfreader::OsmId::Node(*a))
-                    .unwrap()
-                    .node()
-                    .unwrap();
-                let node_b = objs
-                    .get(&osmpbfreader::OsmId::Node(*b))
-                    .unwrap()
-                    .node()
-                    .unwrap();
-                let id_a = &a.0;
-                let id_b = &b.0;
-
-                distance += haversine_distance(
-                    Location {
-                        latitude: node_a.lat(),
-                        longitude: node_a.lon(),
-                    },
-                    Location {
-                        latitude: node_b.lat(),
-                        longitude: node_b.lon(),
+    let computing = progress::Progress::new("Computing the ways");
+    computing.start(Some(total_way_count as u64));
+    let compute_start = std::time::Instant::now();
+    for mut way in ways {
+        computing.inc(1);
+        if shutdown::requested() {
+            partial = true;
+            break;
+        }
+        // The profiles and the output use the transformed tags too
+        if let Some(transform) = &transform {
+            transform.apply(&mut way.tags);
+        }
+        let way_id = way.id.0;
+
+        // Nodes missing from the OSM file are skipped
+        nodes.clear();
+        nodes.extend(way.nodes.iter().filter_map(|node_id| {
+            let node = node_store.get(node_id.0);
+            if node.is_none() {
+                warning_log.add(way_id, Reason::MissingNode, node_id.0);
+            }
+            node.map(|node| (node_id.0, node))
+        }));
+        coordinates.clear();
+        coordinates.extend(nodes.iter().map(|(_, node)| [node.lon, node.lat]));
+
+        let is_sampled = |node_id: &i64| {
+            sampled_nodes.as_ref().is_none_or(|sampled_nodes| {
+                sampled_nodes.contains(*node_id) || elevation_overrides.contains_key(node_id)
+            })
+        };
+        let mut on_nodata = false;
+        elevations.clear();
+        elevations.extend(nodes.iter().map(|(node_id, node)| match node.sample {
+            _ if !is_sampled(node_id) => None,
+            Sample::Value(elevation) => Some(elevation),
+            Sample::NoData(_) => {
+                warning_log.add(way_id, Reason::NoData, *node_id);
+                on_nodata = true;
+                None
+            }
+            Sample::OutOfExtent => {
+                warning_log.add(way_id, Reason::OutOfExtent, *node_id);
+                (args.out_of_extent == OutOfExtentPolicy::Zero).then_some(0.)
+            }
+        }));
+
+        if on_nodata && args.nodata_policy == NodataPolicy::SkipWay {
+            skipped_way_count += 1;
+            continue;
+        }
+
+        // Cumulative distance (in meters) from the first node of the way
+        geodesy::cumulative_distances(&coordinates, &mut distances);
+        let distance = distances.last().copied().unwrap_or(0.);
+
+        // The unsampled nodes, and with --nodata-policy interpolate the nodes on
+        // nodata pixels, get an elevation interpolated along the way
+        let interpolate_nodata = on_nodata && args.nodata_policy == NodataPolicy::Interpolate;
+        if sampled_nodes.is_some() || interpolate_nodata {
+            sampling::interpolate(&distances, &mut elevations, |i| {
+                is_sampled(&nodes[i].0)
+                    && !(interpolate_nodata && matches!(nodes[i].1.sample, Sample::NoData(_)))
+            });
+        }
+
+        // The DEM doesn't reflect the elevation of the track on railway structures
+        // (nor with --flatten-structures on bridges and tunnels)
+        let on_structure = ((args.profile == Profile::Rail
+            && structures::is_structure(&way.tags, &structures::RAIL_STRUCTURES))
+            || (args.flatten_structures
+                && structures::is_structure(&way.tags, &structures::STRUCTURES)))
+            && structures::interpolate_between_endpoints(&distances, &mut elevations);
+        if on_structure {
+            warning_log.add_way(way_id, Reason::StructureInterpolation);
+            flattened_way_count += 1;
+        }
+
+        // With --max-sample-spacing, the climb and descent are accumulated over the
+        // points sampled along the long segments too (but not on the structures)
+        let dense = args
+            .max_sample_spacing
+            .filter(|_| !on_structure)
+            .map(|spacing| {
+                sampling::densify(&coordinates, &distances, &elevations, spacing, |points| {
+                    let samples = source.sample_batch(points).unwrap_or_else(|e| {
+                        log::error!(
+                            target: "sample",
+                            fields:serde = json!({ "elevation_api": args.elevation_api });
+                            "{}", e
+                        );
+                        std::process::exit(1);
+                    });
+                    samples
+                        .into_iter()
+                        .map(|(sample, _)| match sample {
+                            Sample::Value(elevation) => Some(elevation),
+                            _ => None,
+                        })
+                        .collect()
+                })
+            });
+        let (climb_distances, climb_elevations) = match &dense {
+            Some((distances, elevations)) => (distances, elevations),
+            None => (&distances, &elevations),
+        };
+        let smoothed = args.smoothing.map(|smoothing| {
+            smoothing.apply(climb_distances, climb_elevations, args.smoothing_window)
+        });
+        let climb_elevations = smoothed.as_ref().unwrap_or(climb_elevations);
+
+        // The net climb of a closed way is zero, the variations along it are mostly
+        // DEM noise which can be filtered out
+        let is_closed = way.is_closed();
+        let totals = match args.closed_way_threshold {
+            Some(threshold) if is_closed => gradient::ClimbDescent::compute_with_hysteresis(
+                climb_distances,
+                climb_elevations,
+                threshold,
+            ),
+            _ => gradient::ClimbDescent::compute(climb_distances, climb_elevations),
+        };
+
+        if args.maproulette.is_some() {
+            // Net grade of the way, between its first and last nodes with an elevation
+            if let Some(gradient) = gradient::net_gradient(&distances, &elevations) {
+                let grade = gradient * 100.;
+                if let Some(task) = maproulette::check_way(
+                    &way,
+                    &coordinates,
+                    grade,
+                    args.maproulette_min_grade,
+                    args.maproulette_tolerance,
+                ) {
+                    maproulette_tasks.push(task);
+                }
+            }
+        }
+
+        // Vertical error of each node, from the error raster or the global RMSE
+        let sigma = (args.dem_rmse.is_some() || error_dem.is_some()).then(|| {
+            let sigmas: Vec<f64> = nodes
+                .iter()
+                .map(
+                    |(_, node)| match error_dem.as_ref().map(|e| e.sample(node.lon, node.lat)) {
+                        Some(Sample::Value(sigma)) => sigma,
+                        _ => args.dem_rmse.unwrap_or(0.),
                     },
-                ) * 1000.;
-
-                if node_elevation.get(id_a).unwrap() < node_elevation.get(id_b).unwrap() {
-                    climb_distance += distance;
-                    climb += node_elevation.get(id_b).unwrap() - node_elevation.get(id_a).unwrap();
-                } else {
-                    descent_distance += distance;
-                    descent +=
-                        node_elevation.get(id_a).unwrap() - node_elevation.get(id_b).unwrap();
+                )
+                .collect();
+            gradient::ClimbDescent::sigma(&elevations, &sigmas)
+        });
+
+        let first_node = way.nodes.first().map(|node_id| node_id.0);
+        let last_node = way.nodes.last().map(|node_id| node_id.0);
+        let location = |endpoint: Option<&(i64, NodeInfo)>, id: Option<i64>| {
+            endpoint
+                .filter(|(node_id, _)| Some(*node_id) == id)
+                .map(|(_, node)| [node.lon, node.lat])
+        };
+
+        let per_km = |value: f64| {
+            if distance > 0. {
+                value / distance * 1000.
+            } else {
+                0.
+            }
+        };
+
+        // Average grade over a distance, 0 on a zero distance
+        let avg_grade = |value: f64, over: f64| if over > 0. { value / over * 100. } else { 0. };
+        let grade_range = gradient::grade_range(&distances, &elevations);
+
+        let (forward, backward) = if args.oneway {
+            oneway_directions(&way.tags)
+        } else {
+            (true, true)
+        };
+
+        let mut way_info = WayInfo {
+            distance,
+            climb_distance: totals.climb_distance,
+            descent_distance: totals.descent_distance,
+            climb: totals.climb,
+            descent: totals.descent,
+            climb_per_km: per_km(totals.climb),
+            descent_per_km: per_km(totals.descent),
+            climb_forward: forward.then_some(totals.climb),
+            descent_forward: forward.then_some(totals.descent),
+            climb_backward: backward.then_some(totals.descent),
+            descent_backward: backward.then_some(totals.climb),
+            avg_climb_grade: avg_grade(totals.climb, totals.climb_distance),
+            avg_descent_grade: avg_grade(totals.descent, totals.descent_distance),
+            max_grade: grade_range.map(|(_, max)| max),
+            min_grade: grade_range.map(|(min, _)| min),
+            grade_histogram: args.grade_histogram.then(|| {
+                gradient::grade_histogram(&distances, &elevations, &args.grade_histogram_breaks)
+            }),
+            walk_time_forward_s: args
+                .walk_time
+                .then(|| args.cost_model.walk_time(&distances, &elevations, false)),
+            walk_time_backward_s: args
+                .walk_time
+                .then(|| args.cost_model.walk_time(&distances, &elevations, true)),
+            profile: args.include_profile.then(|| {
+                distances
+                    .iter()
+                    .copied()
+                    .zip(elevations.iter().copied())
+                    .collect()
+            }),
+            climb_sigma: sigma.map(|(climb_sigma, _)| climb_sigma),
+            descent_sigma: sigma.map(|(_, descent_sigma)| descent_sigma),
+            is_closed,
+            first_node,
+            last_node,
+            first_node_location: location(nodes.first(), first_node),
+            last_node_location: location(nodes.last(), last_node),
+            tags: transform
+                .as_ref()
+                .and_then(|transform| transform.output_tags(&way.tags)),
+            // Set by the profile
+            rail: None,
+            waterway: None,
+            piste: None,
+            mtb: None,
+            wheelchair: None,
+            truck: None,
+            car: None,
+            winter: None,
+            cycling: None,
+            metrics: Default::default(),
+        };
+        profile_metrics.compute(
+            &profile::ProfileWay {
+                id: way_id,
+                tags: &way.tags,
+                coordinates: &coordinates,
+                distances: &distances,
+                elevations: &elevations,
+                totals: &totals,
+            },
+            &mut way_info,
+        );
+        if let Some(transform) = &transform {
+            way_info.metrics = transform.metrics(&way_info);
+        }
+        let partition_value = partition.as_ref().and_then(|partition| {
+            let first = nodes.first().map(|(_, node)| (node.lon, node.lat));
+            partition.value(&way.tags, first)
+        });
+        let record = output::Record {
+            way_id,
+            info: &way_info,
+            geometry: &coordinates,
+            partition: partition_value.as_deref(),
+        };
+        if args.per_segment {
+            for (index, pair) in nodes.windows(2).enumerate() {
+                let length = distances[index + 1] - distances[index];
+                let elevation_delta = elevations[index]
+                    .zip(elevations[index + 1])
+                    .map(|(from, to)| to - from);
+                let segment = output::Segment {
+                    way_id,
+                    index,
+                    from_node: pair[0].0,
+                    to_node: pair[1].0,
+                    length,
+                    elevation_delta,
+                    grade: elevation_delta
+                        .filter(|_| length > 0.)
+                        .map(|delta| delta / length * 100.),
+                    geometry: [coordinates[index], coordinates[index + 1]],
+                };
+                for writer in &mut writers {
+                    writer.write_segment(&segment);
                 }
-            });
+            }
+        } else {
+            for writer in &mut writers {
+                writer.write(&record);
+            }
+        }
+        if let Some(checkpoint) = &mut checkpoint {
+            checkpoint.write(&record);
+        }
+        way_count += 1;
+        summary.add(way_info.distance, way_info.climb, way_info.descent);
+        if let Some(verified) = &mut verified {
+            let value = serde_json::to_value(&way_info).expect("Unable to serialize result");
+            verified.insert(way_id.to_string(), value);
+        }
+    }
+    computing.finish();
+    logger::timing("compute", "Ways computed", compute_start);
+    if let Some(checkpoint) = &mut checkpoint {
+        checkpoint.sync();
+    }
 
-        result_map.insert(
-            id.inner_id(),
-            WayInfo {
-                distance,
-                climb_distance,
-                descent_distance,
-                climb,
-                descent,
+    if partial {
+        log::warn!(
+            target: "compute",
+            fields:serde = json!({ "way_count": way_count, "total_way_count": total_way_count });
+            "Interrupted, only {} of the {} ways were computed", way_count, total_way_count
+        );
+    }
+
+    // The other ways of the previous output are written as they were
+    if let Some(update) = update {
+        let mut recomputed = recomputed.unwrap_or_default();
+        log::info!(
+            target: "compute",
+            fields:serde = json!({ "recomputed_way_count": recomputed.len() });
+            "{} ways recomputed after the diff", recomputed.len()
+        );
+        // (as were the ways resumed from the checkpoint file)
+        recomputed.extend(&resumed);
+        for (way_id, mut way_info) in update.into_unchanged(&recomputed) {
+            if let Some(transform) = &transform {
+                way_info.metrics = transform.metrics(&way_info);
+            }
+            let record = output::Record {
+                way_id,
+                info: &way_info,
+                geometry: &[],
+                partition: None,
+            };
+            for writer in &mut writers {
+                writer.write(&record);
+            }
+            way_count += 1;
+            summary.add(way_info.distance, way_info.climb, way_info.descent);
+            if let Some(verified) = &mut verified {
+                let value = serde_json::to_value(&way_info).expect("Unable to serialize result");
+                verified.insert(way_id.to_string(), value);
+            }
+        }
+    }
+
+    if skipped_way_count > 0 {
+        log::warn!(
+            target: "compute",
+            fields:serde = json!({
+                "skipped_way_count": skipped_way_count,
+                "nodata_policy": "skip-way",
+            });
+            "{} ways with a node on a nodata pixel were skipped", skipped_way_count
+        );
+    }
+    if flattened_way_count > 0 {
+        log::info!(
+            target: "compute",
+            fields:serde = json!({ "flattened_way_count": flattened_way_count });
+            "The elevation of {} ways on structures was interpolated between their endpoints",
+            flattened_way_count,
+        );
+    }
+    let out_of_extent_ways: Vec<i64> = warning_log
+        .iter()
+        .filter(|(_, reason, _)| *reason == Reason::OutOfExtent)
+        .map(|(way_id, _, _)| way_id)
+        .collect();
+    if !out_of_extent_ways.is_empty() {
+        let listed: Vec<String> = out_of_extent_ways
+            .iter()
+            .take(10)
+            .map(|way_id| way_id.to_string())
+            .collect();
+        log::warn!(
+            target: "compute",
+            fields:serde = json!({ "way_ids": out_of_extent_ways });
+            "{} ways have nodes outside of the extent of the DEM: {}{}",
+            out_of_extent_ways.len(),
+            listed.join(", "),
+            if out_of_extent_ways.len() > listed.len() {
+                ", ..."
+            } else {
+                ""
             },
         );
-    });
+    }
+    // The warnings can be queried from the structured logs too
+    if logger::is_json() {
+        for (way_id, reason, nodes) in warning_log.iter() {
+            log::warn!(
+                target: "compute",
+                fields:serde = json!({ "way_id": way_id, "reason": reason, "nodes": nodes });
+                "Way computed with a fallback"
+            );
+        }
+    }
+    log::info!(
+        target: "compute",
+        fields:serde = json!({
+            "way_count": way_count,
+            "fallback_way_count": warning_log.way_count(),
+        });
+        "{} ways computed", way_count
+    );
+
+    if let Some(warnings_file) = &args.warnings_file {
+        log::info!(
+            target: "write",
+            fields:serde = json!({
+                "fallback_way_count": warning_log.way_count(),
+                "path": warnings_file,
+            });
+            "{} ways were computed with a fallback, see {}", warning_log.way_count(), warnings_file
+        );
+        warning_log.write(warnings_file);
+    }
+
+    if let Some(maproulette_file) = &args.maproulette {
+        log::info!(
+            target: "write",
+            fields:serde = json!({
+                "task_count": maproulette_tasks.len(),
+                "path": maproulette_file,
+            });
+            "{} MapRoulette tasks written to {}", maproulette_tasks.len(), maproulette_file
+        );
+        maproulette::write(maproulette_file, &maproulette_tasks);
+    }
+
+    profile_metrics.write_layers(reprojection.as_ref());
 
-    // Serialize result to a JSON string and write it to a file
-    let json_str =
-        serde_json::to_string(&result_map).expect("Unable to serialize result to string");
-    std::fs::write(args.output_file, json_str).expect("Unable to write file");
+    if let Some(metadata_file) = &args.metadata {
+        let metadata = metadata::Metadata {
+            version: env!("CARGO_PKG_VERSION"),
+            osm_file: osm_file.clone(),
+            merged_osm_files: args.merge.clone(),
+            elevation_file: elevation_file.clone(),
+            dem_tiles: args.dem.clone(),
+            fallback_dems: args.fallback_dem.clone(),
+            interpolation: format!("{:?}", args.interpolation).to_lowercase(),
+            geoid: args.geoid.clone(),
+            geoid_conversion: args
+                .geoid
+                .as_ref()
+                .map(|_| args.geoid_conversion.name().to_string()),
+            profile: format!("{:?}", args.profile).to_lowercase(),
+            filters: filters.iter().map(|filter| filter.to_string()).collect(),
+            filter_mode: format!("{:?}", filter_mode).to_lowercase(),
+            simplify_tolerance: args.simplify,
+            sample_every,
+            at: args.at.clone(),
+            partial,
+        };
+        metadata.write(metadata_file);
+    }
+
+    // Complete each of the output files
+    let write_start = std::time::Instant::now();
+    for writer in writers {
+        writer.finish();
+    }
+    logger::timing("write", "Outputs completed", write_start);
+
+    // Headline numbers for the humans watching the run
+    if !logger::is_json() {
+        eprintln!(
+            "{}",
+            summary.render(summary::use_color(no_color, &std::io::stderr()))
+        );
+    }
+
+    if partial {
+        std::process::exit(130);
+    }
+
+    if let (Some(reference), Some(verified)) = (&args.verify, verified) {
+        let actual = serde_json::Value::Object(verified);
+        let tolerance = verify::Tolerance {
+            absolute: args.verify_tolerance,
+            relative: args.verify_relative_tolerance,
+        };
+        let discrepancies = verify::compare(&actual, reference, &tolerance);
+        for discrepancy in &discrepancies {
+            log::warn!(
+                target: "verify",
+                fields:serde = json!({ "way_id": discrepancy.way_id() });
+                "{}", discrepancy
+            );
+        }
+        if !discrepancies.is_empty() {
+            log::error!(
+                target: "verify",
+                fields:serde = json!({
+                    "discrepancy_count": discrepancies.len(),
+                    "reference": reference,
+                });
+                "{} discrepancies with the reference file {}", discrepancies.len(), reference
+            );
+            std::process::exit(1);
+        }
+        log::info!(
+            target: "verify",
+            fields:serde = json!({ "discrepancy_count": 0, "reference": reference });
+            "The results match the reference file {}", reference
+        );
+    }
 }
@@ -0,0 +1,132 @@
+use serde_json::json;
+
+// Values of the highway tag for which an incline tag is expected on steep ways
+const INCLINE_HIGHWAYS: [&str; 7] = [
+    "footway",
+    "path",
+    "cycleway",
+    "pedestrian",
+    "steps",
+    "bridleway",
+    "track",
+];
+
+/// MapRoulette task for a way whose slope doesn't match its tags
+pub struct Task {
+    way_id: i64,
+    coordinates: Vec<[f64; 2]>,
+    highway: String,
+    incline: Option<String>,
+    grade: f64,
+    reason: String,
+}
+
+// Parse the value of an incline tag into a grade in percent
+// ("up" and "down" are returned as +inf / -inf as only their sign matters)
+fn parse_incline(value: &str) -> Option<f64> {
+    let value = value.trim();
+    match value {
+        "up" => return Some(f64::INFINITY),
+        "down" => return Some(f64::NEG_INFINITY),
+        _ => {}
+    }
+    if let Some(percent) = value.strip_suffix('%') {
+        return percent.trim().parse().ok();
+    }
+    if let Some(degrees) = value.strip_suffix('°') {
+        return degrees
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .map(|degrees| degrees.to_radians().tan() * 100.);
+    }
+    value.parse().ok()
+}
+
+/// Check the tags of a way against its measured grade
+/// (net elevation change over its length, in percent)
+/// and return a task if they contradict each other or if the incline tag is missing.
+pub fn check_way(
+    way: &osmpbfreader::Way,
+    coordinates: &[[f64; 2]],
+    grade: f64,
+    min_grade: f64,
+    tolerance: f64,
+) -> Option<Task> {
+    let highway = way.tags.get("highway")?;
+    let incline = way.tags.get("incline").map(|value| value.to_string());
+
+    let reason = match incline.as_deref().map(parse_incline) {
+        None => {
+            if !INCLINE_HIGHWAYS.contains(&highway.as_str()) || grade.abs() < min_grade {
+                return None;
+            }
+            format!(
+                "Steep {} ({:.1}%) without incline tag",
+                highway,
+                grade.abs()
+            )
+        }
+        Some(Some(expected)) if expected.is_infinite() => {
+            if expected.signum() * grade >= -tolerance {
+                return None;
+            }
+            format!(
+                "Tagged incline={} but the measured grade is {:.1}%",
+                incline.as_deref().unwrap(),
+                grade
+            )
+        }
+        Some(Some(expected)) => {
+            if (expected - grade).abs() <= tolerance {
+                return None;
+            }
+            format!(
+                "Tagged incline={} but the measured grade is {:.1}%",
+                incline.as_deref().unwrap(),
+                grade
+            )
+        }
+        // Unparsable values such as incline=yes can't be checked
+        Some(None) => return None,
+    };
+
+    Some(Task {
+        way_id: way.id.0,
+        coordinates: coordinates.to_vec(),
+        highway: highway.to_string(),
+        incline,
+        grade,
+        reason,
+    })
+}
+
+/// Write the tasks as a MapRoulette challenge GeoJSON file
+pub fn write(path: &str, tasks: &[Task]) {
+    let features: Vec<serde_json::Value> = tasks
+        .iter()
+        .map(|task| {
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": task.coordinates,
+                },
+                "properties": {
+                    "@id": format!("way/{}", task.way_id),
+                    "highway": task.highway,
+                    "incline": task.incline,
+                    "measured_grade": (task.grade * 10.).round() / 10.,
+                    "reason": task.reason,
+                },
+            })
+        })
+        .collect();
+    let collection = json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+    let json_str =
+        serde_json::to_string(&collection).expect("Unable to serialize MapRoulette challenge");
+    std::fs::write(path, json_str).expect("Unable to write MapRoulette challenge file");
+}
@@ -0,0 +1,40 @@
+use serde::Serialize;
+
+/// Parameters of a run, written along with the results
+#[derive(Debug, Serialize)]
+pub struct Metadata {
+    pub version: &'static str,
+    pub osm_file: String,
+    // Additional OSM files merged with the input, if any
+    pub merged_osm_files: Vec<String>,
+    pub elevation_file: String,
+    // Additional DEM tiles of the mosaic, if any
+    pub dem_tiles: Vec<String>,
+    // DEMs read where the elevation file has no valid value, in order of priority
+    pub fallback_dems: Vec<String>,
+    // Interpolation of the elevations between the pixels of the DEM
+    pub interpolation: String,
+    // Geoid model grid converting the elevations of the DEM, and the direction of
+    // the conversion, if any
+    pub geoid: Option<String>,
+    pub geoid_conversion: Option<String>,
+    pub profile: String,
+    pub filters: Vec<String>,
+    pub filter_mode: String,
+    // Tolerance (in meters) of the simplification of the ways, if any
+    pub simplify_tolerance: Option<f64>,
+    // Interval of the nodes sampled on the DEM, if the elevation of the others was interpolated
+    pub sample_every: Option<usize>,
+    // Instant at which a full-history file was read, if any
+    pub at: Option<String>,
+    // Whether the run was interrupted, the results only covering some of the ways
+    pub partial: bool,
+}
+
+impl Metadata {
+    pub fn write(&self, path: &str) {
+        let json_str =
+            serde_json::to_string_pretty(self).expect("Unable to serialize metadata to string");
+        std::fs::write(path, json_str).expect("Unable to write metadata file");
+    }
+}
@@ -0,0 +1,69 @@
+use crate::gradient::{self, ClimbDescent};
+use serde::{Deserialize, Serialize};
+
+// Maximum average and sustained grades (in %) of each mtb:scale:uphill value
+const UPHILL_SCALES: [(f64, f64); 5] = [(10., 15.), (15., 20.), (20., 25.), (25., 30.), (30., 40.)];
+
+/// Mountain bike specific metrics of a way, in its uphill direction
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MtbInfo {
+    // Average grade of the climbing segments (in %)
+    mtb_avg_climb_grade: f64,
+    // Steepest climbing grade sustained over the measuring window (in %)
+    max_climb_grade: f64,
+    estimated_uphill_scale: u8,
+    tagged_uphill_scale: Option<String>,
+    // The estimated value, when the way has no mtb:scale:uphill tag
+    suggested_uphill_scale: Option<u8>,
+    // Whether the tagged value doesn't match the estimated one
+    uphill_scale_mismatch: bool,
+}
+
+// mtb:scale:uphill value matching the given average and sustained grades
+fn estimate_uphill_scale(avg_grade: f64, max_grade: f64) -> u8 {
+    UPHILL_SCALES
+        .iter()
+        .position(|(max_avg, max_sustained)| avg_grade < *max_avg && max_grade < *max_sustained)
+        .unwrap_or(UPHILL_SCALES.len()) as u8
+}
+
+/// Estimate the technical climbing difficulty of a trail and compare it with its tags
+pub fn compute(
+    tags: &osmpbfreader::Tags,
+    distances: &[f64],
+    elevations: &[Option<f64>],
+    totals: &ClimbDescent,
+    window: f64,
+) -> MtbInfo {
+    // The uphill direction is the one with the most climbing
+    let (climb, climb_distance) = if totals.climb >= totals.descent {
+        (totals.climb, totals.climb_distance)
+    } else {
+        (totals.descent, totals.descent_distance)
+    };
+    let avg_climb_grade = if climb_distance > 0. {
+        climb / climb_distance * 100.
+    } else {
+        0.
+    };
+    // The measuring window makes short pitches weigh less than long sustained climbs
+    let max_climb_grade = gradient::max_sustained_gradient(distances, elevations, window) * 100.;
+    let estimated_uphill_scale = estimate_uphill_scale(avg_climb_grade, max_climb_grade);
+
+    let tagged_uphill_scale = tags.get("mtb:scale:uphill").map(|value| value.to_string());
+    let uphill_scale_mismatch = match tagged_uphill_scale.as_deref().map(str::parse::<u8>) {
+        Some(Ok(tagged)) => tagged != estimated_uphill_scale,
+        _ => false,
+    };
+
+    MtbInfo {
+        mtb_avg_climb_grade: avg_climb_grade,
+        max_climb_grade,
+        estimated_uphill_scale,
+        suggested_uphill_scale: tagged_uphill_scale
+            .is_none()
+            .then_some(estimated_uphill_scale),
+        tagged_uphill_scale,
+        uphill_scale_mismatch,
+    }
+}
@@ -0,0 +1,578 @@
+use crate::summary::{average_grade, class_label, grade_class, GRADE_CLASSES};
+use protobuf::{CodedOutputStream, ProtobufResult};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+// Size of the tiles, in tile coordinates
+const EXTENT: u32 = 4096;
+// Margin around the tiles in which the lines are kept (in tile coordinates),
+// so that they are rendered without gaps at the edges of the tiles
+const BUFFER: f64 = 64.;
+const LAYER_NAME: &str = "ways";
+
+// Geometry type and commands of the Mapbox Vector Tile specification
+const GEOMETRY_LINESTRING: i32 = 2;
+const COMMAND_MOVE_TO: u32 = 1;
+const COMMAND_LINE_TO: u32 = 2;
+
+// Attributes of the features, after the slope class (uint) and its label (string)
+const NUMERIC_ATTRIBUTES: [&str; 4] = ["avg_grade", "climb", "descent", "distance"];
+
+// Way of the result file
+struct Way {
+    id: u64,
+    // [lon, lat] coordinates
+    coordinates: Vec<[f64; 2]>,
+    grade: f64,
+    climb: f64,
+    descent: f64,
+    distance: f64,
+}
+
+// Part of a way in a tile
+struct TileFeature<'a> {
+    way: &'a Way,
+    // Lines, in tile coordinates
+    parts: Vec<Vec<[i32; 2]>>,
+}
+
+fn encode(f: impl FnOnce(&mut CodedOutputStream) -> ProtobufResult<()>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let mut out = CodedOutputStream::vec(&mut buf);
+    f(&mut out)
+        .and_then(|_| out.flush())
+        .expect("Unable to encode vector tile");
+    drop(out);
+    buf
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+// Web Mercator position of a [lon, lat] location, in tiles of zoom level `zoom`
+fn project([lon, lat]: [f64; 2], zoom: u8) -> [f64; 2] {
+    let n = (1u32 << zoom) as f64;
+    let lat = lat.clamp(-85.0511, 85.0511).to_radians();
+    [
+        (lon + 180.) / 360. * n,
+        (1. - (lat.tan() + 1. / lat.cos()).ln() / std::f64::consts::PI) / 2. * n,
+    ]
+}
+
+// Parameters (t0, t1) of the part of the segment p -> q within the box (Liang-Barsky)
+fn clip_segment(p: [f64; 2], q: [f64; 2], min: [f64; 2], max: [f64; 2]) -> Option<(f64, f64)> {
+    let (mut t0, mut t1) = (0f64, 1f64);
+    for axis in 0..2 {
+        let d = q[axis] - p[axis];
+        for (pk, qk) in [(-d, p[axis] - min[axis]), (d, max[axis] - p[axis])] {
+            if pk == 0. {
+                if qk < 0. {
+                    return None;
+                }
+            } else {
+                let r = qk / pk;
+                if pk < 0. {
+                    if r > t1 {
+                        return None;
+                    }
+                    t0 = t0.max(r);
+                } else {
+                    if r < t0 {
+                        return None;
+                    }
+                    t1 = t1.min(r);
+                }
+            }
+        }
+    }
+    Some((t0, t1))
+}
+
+// Parts of a line within the box
+fn clip_line(points: &[[f64; 2]], min: [f64; 2], max: [f64; 2]) -> Vec<Vec<[f64; 2]>> {
+    let lerp =
+        |p: [f64; 2], q: [f64; 2], t: f64| [p[0] + (q[0] - p[0]) * t, p[1] + (q[1] - p[1]) * t];
+    let mut parts = Vec::new();
+    let mut current = Vec::new();
+    for segment in points.windows(2) {
+        let (p, q) = (segment[0], segment[1]);
+        match clip_segment(p, q, min, max) {
+            Some((t0, t1)) => {
+                if current.is_empty() {
+                    current.push(lerp(p, q, t0));
+                }
+                current.push(lerp(p, q, t1));
+                if t1 < 1. {
+                    parts.push(std::mem::take(&mut current));
+                }
+            }
+            None if !current.is_empty() => parts.push(std::mem::take(&mut current)),
+            None => {}
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+// Layer of the tile, with the slope attributes of the ways
+fn encode_tile(features: &[TileFeature]) -> Vec<u8> {
+    let keys: Vec<&str> = ["slope_class", "slope_label"]
+        .into_iter()
+        .chain(NUMERIC_ATTRIBUTES)
+        .collect();
+    // Encoded values, deduplicated
+    let mut values: Vec<Vec<u8>> = Vec::new();
+    let mut value_ids: BTreeMap<Vec<u8>, u32> = BTreeMap::new();
+    let mut value_id = |value: Vec<u8>| {
+        *value_ids.entry(value.clone()).or_insert_with(|| {
+            values.push(value);
+            (values.len() - 1) as u32
+        })
+    };
+
+    let mut encoded_features = Vec::with_capacity(features.len());
+    for feature in features {
+        let way = feature.way;
+        let class = grade_class(way.grade, &GRADE_CLASSES);
+        let mut tags = vec![
+            value_id(encode(|out| out.write_uint64(5, class as u64))),
+            value_id(encode(|out| {
+                out.write_string(1, &class_label(class, &GRADE_CLASSES))
+            })),
+        ];
+        for value in [way.grade, way.climb, way.descent, way.distance] {
+            let rounded = (value * 10.).round() / 10.;
+            tags.push(value_id(encode(|out| out.write_double(3, rounded))));
+        }
+        let tags = encode(|out| {
+            for (key, value) in tags.iter().enumerate() {
+                out.write_uint32_no_tag(key as u32)?;
+                out.write_uint32_no_tag(*value)?;
+            }
+            Ok(())
+        });
+
+        let mut cursor = [0i32, 0];
+        let geometry = encode(|out| {
+            for part in &feature.parts {
+                out.write_uint32_no_tag(COMMAND_MOVE_TO | (1 << 3))?;
+                for (i, point) in part.iter().enumerate() {
+                    if i == 1 {
+                        out.write_uint32_no_tag(COMMAND_LINE_TO | ((part.len() as u32 - 1) << 3))?;
+                    }
+                    out.write_uint32_no_tag(zigzag(point[0] - cursor[0]))?;
+                    out.write_uint32_no_tag(zigzag(point[1] - cursor[1]))?;
+                    cursor = *point;
+                }
+            }
+            Ok(())
+        });
+
+        encoded_features.push(encode(|out| {
+            out.write_uint64(1, way.id)?;
+            out.write_bytes(2, &tags)?;
+            out.write_enum(3, GEOMETRY_LINESTRING)?;
+            out.write_bytes(4, &geometry)
+        }));
+    }
+
+    let layer = encode(|out| {
+        out.write_uint32(15, 2)?;
+        out.write_string(1, LAYER_NAME)?;
+        for feature in &encoded_features {
+            out.write_bytes(2, feature)?;
+        }
+        for key in &keys {
+            out.write_string(3, key)?;
+        }
+        for value in &values {
+            out.write_bytes(4, value)?;
+        }
+        out.write_uint32(5, EXTENT)
+    });
+    encode(|out| out.write_bytes(3, &layer))
+}
+
+// Destination of the tiles
+enum Sink {
+    // z/x/y.pbf files
+    Directory(std::path::PathBuf),
+    // A file per tile, at the path with {tile} expanded to ZOOM-X-Y
+    Files(String),
+    // MBTiles file, written in a single transaction
+    MbTiles(rusqlite::Connection),
+}
+
+fn mbtiles_error(e: rusqlite::Error) -> String {
+    format!("Unable to write the MBTiles file: {}", e)
+}
+
+impl Sink {
+    fn create(output: &str) -> Result<Sink, String> {
+        if output.contains("{tile}") {
+            return Ok(Sink::Files(output.to_string()));
+        }
+        if !output.ends_with(".mbtiles") {
+            return Ok(Sink::Directory(std::path::PathBuf::from(output)));
+        }
+        if std::path::Path::new(output).exists() {
+            std::fs::remove_file(output)
+                .map_err(|e| format!("Unable to replace {}: {}", output, e))?;
+        }
+        let connection = rusqlite::Connection::open(output)
+            .map_err(|e| format!("Unable to create {}: {}", output, e))?;
+        connection
+            .execute_batch(
+                "PRAGMA journal_mode = OFF;
+                 BEGIN;
+                 CREATE TABLE metadata (name text, value text);
+                 CREATE TABLE tiles (zoom_level integer, tile_column integer, tile_row integer, tile_data blob);
+                 CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);",
+            )
+            .map_err(mbtiles_error)?;
+        Ok(Sink::MbTiles(connection))
+    }
+
+    fn write(&mut self, zoom: u8, x: u32, y: u32, tile: &[u8]) -> Result<(), String> {
+        match self {
+            Sink::Directory(dir) => {
+                let dir = dir.join(zoom.to_string()).join(x.to_string());
+                std::fs::create_dir_all(&dir)
+                    .and_then(|_| std::fs::write(dir.join(format!("{}.pbf", y)), tile))
+                    .map_err(|e| format!("Unable to write tile {}/{}/{}: {}", zoom, x, y, e))
+            }
+            Sink::Files(template) => {
+                let path = crate::template::expand_tile(template, &format!("{}-{}-{}", zoom, x, y));
+                let path = std::path::Path::new(&path);
+                path.parent()
+                    .map_or(Ok(()), std::fs::create_dir_all)
+                    .and_then(|_| std::fs::write(path, tile))
+                    .map_err(|e| format!("Unable to write tile {}: {}", path.display(), e))
+            }
+            Sink::MbTiles(connection) => {
+                // The tiles of MBTiles files are gzipped, and their rows are in the TMS scheme
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(tile).unwrap();
+                let data = encoder.finish().unwrap();
+                let row = (1u32 << zoom) - 1 - y;
+                connection
+                    .prepare_cached("INSERT INTO tiles VALUES (?1, ?2, ?3, ?4)")
+                    .and_then(|mut insert| insert.execute(rusqlite::params![zoom, x, row, data]))
+                    .map(|_| ())
+                    .map_err(mbtiles_error)
+            }
+        }
+    }
+
+    fn finish(self, metadata: &[(&str, String)]) -> Result<(), String> {
+        if let Sink::MbTiles(connection) = self {
+            let mut insert = connection
+                .prepare("INSERT INTO metadata VALUES (?1, ?2)")
+                .map_err(mbtiles_error)?;
+            for (name, value) in metadata {
+                insert
+                    .execute([name, value.as_str()])
+                    .map_err(mbtiles_error)?;
+            }
+            drop(insert);
+            connection.execute_batch("COMMIT;").map_err(mbtiles_error)?;
+            connection.close().map_err(|(_, e)| mbtiles_error(e))?;
+        }
+        Ok(())
+    }
+}
+
+// Ways of a GeoJSON result file
+fn read_ways(results_file: &str) -> Result<Vec<Way>, String> {
+    let content = std::fs::read_to_string(results_file)
+        .map_err(|e| format!("Unable to read {}: {}", results_file, e))?;
+    let collection: Value = serde_json::from_str(&content)
+        .map_err(|e| format!("Unable to parse {}: {}", results_file, e))?;
+    if !collection["crs"].is_null() {
+        return Err("The geometries of the result file must not be reprojected".to_string());
+    }
+    let features = collection["features"]
+        .as_array()
+        .ok_or("The result file is not a GeoJSON FeatureCollection of ways")?;
+    let mut ways = Vec::with_capacity(features.len());
+    for feature in features {
+        let properties = &feature["properties"];
+        let id = properties["way_id"]
+            .as_u64()
+            .ok_or("Missing way_id in the properties of a feature")?;
+        let field = |name: &str| {
+            properties[name]
+                .as_f64()
+                .ok_or_else(|| format!("Missing {} for way {}", name, id))
+        };
+        let coordinates: Vec<[f64; 2]> = feature["geometry"]["coordinates"]
+            .as_array()
+            .ok_or_else(|| format!("Missing geometry for way {}", id))?
+            .iter()
+            .filter_map(|position| Some([position[0].as_f64()?, position[1].as_f64()?]))
+            .collect();
+        let (distance, climb, descent) = (field("distance")?, field("climb")?, field("descent")?);
+        ways.push(Way {
+            id,
+            coordinates,
+            grade: average_grade(distance, climb, descent),
+            climb,
+            descent,
+            distance,
+        });
+    }
+    Ok(ways)
+}
+
+fn generate(results_file: &str, output: &str, min_zoom: u8, max_zoom: u8) -> Result<(), String> {
+    let ways = read_ways(results_file)?;
+    let mut sink = Sink::create(output)?;
+    let mut bounds = [180f64, 85.0511, -180f64, -85.0511];
+    for [lon, lat] in ways.iter().flat_map(|way| &way.coordinates) {
+        bounds = [
+            bounds[0].min(*lon),
+            bounds[1].min(*lat),
+            bounds[2].max(*lon),
+            bounds[3].max(*lat),
+        ];
+    }
+
+    let buffer = BUFFER / EXTENT as f64;
+    for zoom in min_zoom..=max_zoom {
+        let last_tile = (1u32 << zoom) - 1;
+        let mut tiles: BTreeMap<(u32, u32), Vec<TileFeature>> = BTreeMap::new();
+        for way in &ways {
+            let points: Vec<[f64; 2]> = way.coordinates.iter().map(|c| project(*c, zoom)).collect();
+            if points.len() < 2 {
+                continue;
+            }
+            let range = |axis: usize, f: fn(f64, f64) -> f64, init: f64| {
+                points
+                    .iter()
+                    .fold(init, |value, point| f(value, point[axis]))
+            };
+            let tile = |value: f64| (value.max(0.) as u32).min(last_tile);
+            let (x0, x1) = (
+                tile(range(0, f64::min, f64::INFINITY) - buffer),
+                tile(range(0, f64::max, f64::NEG_INFINITY) + buffer),
+            );
+            let (y0, y1) = (
+                tile(range(1, f64::min, f64::INFINITY) - buffer),
+                tile(range(1, f64::max, f64::NEG_INFINITY) + buffer),
+            );
+            for x in x0..=x1 {
+                for y in y0..=y1 {
+                    let (min, max) = (
+                        [x as f64 - buffer, y as f64 - buffer],
+                        [x as f64 + 1. + buffer, y as f64 + 1. + buffer],
+                    );
+                    let parts: Vec<Vec<[i32; 2]>> = clip_line(&points, min, max)
+                        .into_iter()
+                        .map(|part| {
+                            let mut line: Vec<[i32; 2]> = part
+                                .iter()
+                                .map(|[px, py]| {
+                                    [
+                                        ((px - x as f64) * EXTENT as f64).round() as i32,
+                                        ((py - y as f64) * EXTENT as f64).round() as i32,
+                                    ]
+                                })
+                                .collect();
+                            line.dedup();
+                            line
+                        })
+                        .filter(|line| line.len() >= 2)
+                        .collect();
+                    if !parts.is_empty() {
+                        tiles
+                            .entry((x, y))
+                            .or_default()
+                            .push(TileFeature { way, parts });
+                    }
+                }
+            }
+        }
+        log::info!(
+            target: "tiles",
+            fields:serde = json!({ "zoom": zoom, "tile_count": tiles.len() });
+            "Zoom level {}: {} tiles", zoom, tiles.len()
+        );
+        for ((x, y), features) in tiles {
+            sink.write(zoom, x, y, &encode_tile(&features))?;
+        }
+    }
+
+    let fields: serde_json::Map<String, Value> =
+        [("slope_class", "Number"), ("slope_label", "String")]
+            .into_iter()
+            .chain(NUMERIC_ATTRIBUTES.map(|name| (name, "Number")))
+            .map(|(name, kind)| (name.to_string(), json!(kind)))
+            .collect();
+    let vector_layers = json!({
+        "vector_layers": [{
+            "id": LAYER_NAME,
+            "fields": fields,
+            "minzoom": min_zoom,
+            "maxzoom": max_zoom,
+        }],
+    });
+    sink.finish(&[
+        ("name", "osm-ways-slope".to_string()),
+        ("format", "pbf".to_string()),
+        ("type", "overlay".to_string()),
+        ("minzoom", min_zoom.to_string()),
+        ("maxzoom", max_zoom.to_string()),
+        ("bounds", bounds.map(|value| value.to_string()).join(",")),
+        ("json", vector_layers.to_string()),
+    ])
+}
+
+/// Write the vector tiles of the ways of a GeoJSON result file, to a directory
+/// of z/x/y.pbf tiles, to a file per tile (with `{tile}` in the output path)
+/// or to an MBTiles file
+pub fn run(results_file: &str, output: &str, min_zoom: u8, max_zoom: u8) {
+    if let Err(e) = generate(results_file, output, min_zoom, max_zoom) {
+        log::error!(
+            target: "tiles",
+            fields:serde = json!({ "results_file": results_file, "output": output });
+            "{}", e
+        );
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::wire_format::WireType;
+    use protobuf::CodedInputStream;
+
+    enum Field {
+        Varint(u64),
+        Bytes(Vec<u8>),
+    }
+
+    // Fields (number and value) of an encoded message
+    fn decode(buf: &[u8]) -> Vec<(u32, Field)> {
+        let mut input = CodedInputStream::from_bytes(buf);
+        let mut fields = Vec::new();
+        while !input.eof().unwrap() {
+            let (number, wire_type) = input.read_tag_unpack().unwrap();
+            let value = match wire_type {
+                WireType::WireTypeVarint => Field::Varint(input.read_uint64().unwrap()),
+                WireType::WireTypeLengthDelimited => Field::Bytes(input.read_bytes().unwrap()),
+                wire_type => panic!("Unexpected wire type {:?}", wire_type),
+            };
+            fields.push((number, value));
+        }
+        fields
+    }
+
+    fn bytes(fields: &[(u32, Field)], number: u32) -> Vec<&[u8]> {
+        fields
+            .iter()
+            .filter_map(|(n, value)| match value {
+                Field::Bytes(bytes) if *n == number => Some(bytes.as_slice()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn varint(fields: &[(u32, Field)], number: u32) -> Option<u64> {
+        fields.iter().find_map(|(n, value)| match value {
+            Field::Varint(value) if *n == number => Some(*value),
+            _ => None,
+        })
+    }
+
+    // Packed unsigned integers, e.g. the commands of a geometry
+    fn packed(buf: &[u8]) -> Vec<u32> {
+        let mut input = CodedInputStream::from_bytes(buf);
+        let mut values = Vec::new();
+        while !input.eof().unwrap() {
+            values.push(input.read_raw_varint32().unwrap());
+        }
+        values
+    }
+
+    #[test]
+    fn zigzag_encoding() {
+        assert_eq!(
+            [0, -1, 1, -2, 2, 2047, -2048].map(zigzag),
+            [0, 1, 2, 3, 4, 4094, 4095]
+        );
+    }
+
+    #[test]
+    fn tile_geometry_commands() {
+        let way = Way {
+            id: 42,
+            coordinates: Vec::new(),
+            grade: 6.,
+            climb: 30.,
+            descent: 0.,
+            distance: 500.,
+        };
+        let feature = TileFeature {
+            way: &way,
+            parts: vec![vec![[0, 0], [10, 5], [5, -3]], vec![[100, 100], [90, 100]]],
+        };
+        let tile = decode(&encode_tile(&[feature]));
+        let layers = bytes(&tile, 3);
+        assert_eq!(layers.len(), 1);
+        let layer = decode(layers[0]);
+        assert_eq!(varint(&layer, 15), Some(2));
+        assert_eq!(bytes(&layer, 1), vec![LAYER_NAME.as_bytes()]);
+        assert_eq!(varint(&layer, 5), Some(EXTENT as u64));
+        let features = bytes(&layer, 2);
+        assert_eq!(features.len(), 1);
+        let feature = decode(features[0]);
+        assert_eq!(varint(&feature, 1), Some(42));
+        assert_eq!(varint(&feature, 3), Some(GEOMETRY_LINESTRING as u64));
+        // MoveTo(1) (0, 0), LineTo(2) (+10, +5) (-5, -8), then MoveTo(1) (+95, +103)
+        // and LineTo(1) (-10, 0), the coordinates being relative to the previous point
+        assert_eq!(
+            packed(bytes(&feature, 4)[0]),
+            vec![9, 0, 0, 18, 20, 10, 9, 15, 9, 190, 206, 10, 19, 0]
+        );
+    }
+
+    #[test]
+    fn lines_clipped_to_the_buffered_extent() {
+        let buffer = BUFFER / EXTENT as f64;
+        let (min, max) = ([-buffer, -buffer], [1. + buffer, 1. + buffer]);
+        // Crossing the whole tile
+        let parts = clip_line(&[[-0.5, 0.5], [1.5, 0.5]], min, max);
+        assert_eq!(parts.len(), 1);
+        let extent: Vec<[f64; 2]> = parts[0]
+            .iter()
+            .map(|[x, y]| [x * EXTENT as f64, y * EXTENT as f64])
+            .collect();
+        assert_eq!(
+            extent,
+            vec![[-BUFFER, 2048.], [EXTENT as f64 + BUFFER, 2048.]]
+        );
+        // Leaving the tile and coming back, in two parts
+        let parts = clip_line(
+            &[[0.5, 0.5], [0.5, 2.], [0.75, 2.], [0.75, 0.25]],
+            [0., 0.],
+            [1., 1.],
+        );
+        assert_eq!(parts.len(), 2);
+        for (part, expected) in parts
+            .iter()
+            .zip([vec![[0.5, 0.5], [0.5, 1.]], vec![[0.75, 1.], [0.75, 0.25]]])
+        {
+            assert_eq!(part.len(), expected.len());
+            for (point, expected) in part.iter().zip(expected) {
+                assert!((point[0] - expected[0]).abs() < 1e-9);
+                assert!((point[1] - expected[1]).abs() < 1e-9);
+            }
+        }
+    }
+}
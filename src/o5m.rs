@@ -0,0 +1,272 @@
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Tags, Way, WayId};
+use std::collections::BTreeMap;
+use std::io::Read;
+
+// Header dataset of the o5m files (0xe0, its length and "o5m2"), after the initial reset
+const HEADER: [u8; 6] = [0xe0, 0x04, b'o', b'5', b'm', b'2'];
+
+// Types of datasets
+const NODE: u8 = 0x10;
+const WAY: u8 = 0x11;
+const END_OF_FILE: u8 = 0xfe;
+const RESET: u8 = 0xff;
+
+// Size of the table of the recently used strings, and longest string pair stored in it
+const TABLE_SIZE: usize = 15000;
+const MAX_TABLE_ENTRY: usize = 250;
+
+/// Whether the file is an o5m file (e.g. written by osmconvert), from its header
+pub fn is_o5m(path: &str) -> bool {
+    let mut start = [0; 7];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut start))
+        .is_ok_and(|_| has_header(&start))
+}
+
+/// Whether the first bytes of a stream are the header of an o5m file
+pub fn has_header(start: &[u8]) -> bool {
+    start.first() == Some(&RESET) && start.get(1..7) == Some(&HEADER[..])
+}
+
+// Payload of a dataset, with the decoding of its numbers and strings
+struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Cursor<'_> {
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn unsigned(&mut self) -> Result<u64, String> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.data.get(self.position).ok_or("truncated number")?;
+            self.position += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("invalid number".to_string())
+    }
+
+    // Signed numbers have their sign in the lowest bit
+    fn signed(&mut self) -> Result<i64, String> {
+        let value = self.unsigned()?;
+        Ok(((value >> 1) as i64) ^ -((value & 1) as i64))
+    }
+
+    // Zero-terminated string
+    fn string(&mut self) -> Result<&[u8], String> {
+        let rest = &self.data[self.position.min(self.data.len())..];
+        let end = rest
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or("unterminated string")?;
+        self.position += end + 1;
+        Ok(&rest[..end])
+    }
+}
+
+// State of the decoding, reset by the reset datasets
+#[derive(Default)]
+struct Decoder {
+    id: i64,
+    timestamp: i64,
+    changeset: i64,
+    lon: i64,
+    lat: i64,
+    node_ref: i64,
+    // Recently used string pairs, the last one being referenced by 1
+    table: std::collections::VecDeque<Vec<u8>>,
+}
+
+impl Decoder {
+    fn store(&mut self, pair: &[u8]) {
+        if self.table.len() == TABLE_SIZE {
+            self.table.pop_front();
+        }
+        self.table.push_back(pair.to_vec());
+    }
+
+    // Pair of strings (the key and value of a tag, or the uid and name of a user),
+    // given inline or as a reference to the table
+    fn string_pair(&mut self, cursor: &mut Cursor) -> Result<(String, String), String> {
+        let pair = match cursor.unsigned()? {
+            0 => {
+                let start = cursor.position;
+                // (the uid of anonymous users, 0, being encoded as an empty first string)
+                let first = cursor.string()?.len();
+                let second = cursor.string()?.len();
+                let pair = &cursor.data[start..cursor.position];
+                if first + second <= MAX_TABLE_ENTRY {
+                    self.store(pair);
+                }
+                pair.to_vec()
+            }
+            reference => self
+                .table
+                .len()
+                .checked_sub(reference as usize)
+                .and_then(|index| self.table.get(index))
+                .ok_or("invalid string reference")?
+                .clone(),
+        };
+        let mut strings = pair.split(|byte| *byte == 0);
+        let first = String::from_utf8_lossy(strings.next().unwrap_or_default()).into_owned();
+        let second = String::from_utf8_lossy(strings.next().unwrap_or_default()).into_owned();
+        Ok((first, second))
+    }
+
+    // Id and version information of an object, returning its id
+    fn object_head(&mut self, cursor: &mut Cursor) -> Result<i64, String> {
+        self.id += cursor.signed()?;
+        let version = cursor.unsigned()?;
+        if version != 0 {
+            self.timestamp += cursor.signed()?;
+            if self.timestamp != 0 {
+                self.changeset += cursor.signed()?;
+                self.string_pair(cursor)?;
+            }
+        }
+        Ok(self.id)
+    }
+
+    fn tags(&mut self, cursor: &mut Cursor) -> Result<Tags, String> {
+        let mut tags = Tags::new();
+        while !cursor.is_empty() {
+            let (key, value) = self.string_pair(cursor)?;
+            tags.insert(key.into(), value.into());
+        }
+        Ok(tags)
+    }
+
+    // Node of a dataset, None if it has been deleted (in change files)
+    fn node(&mut self, cursor: &mut Cursor) -> Result<Option<Node>, String> {
+        let id = self.object_head(cursor)?;
+        if cursor.is_empty() {
+            return Ok(None);
+        }
+        self.lon += cursor.signed()?;
+        self.lat += cursor.signed()?;
+        Ok(Some(Node {
+            id: NodeId(id),
+            decimicro_lon: self.lon as i32,
+            decimicro_lat: self.lat as i32,
+            tags: self.tags(cursor)?,
+        }))
+    }
+
+    // Way of a dataset, None if it has been deleted (in change files)
+    fn way(&mut self, cursor: &mut Cursor) -> Result<Option<Way>, String> {
+        let id = self.object_head(cursor)?;
+        if cursor.is_empty() {
+            return Ok(None);
+        }
+        let refs_length = cursor.unsigned()? as usize;
+        let refs_end = cursor.position + refs_length;
+        let mut nodes = Vec::new();
+        while cursor.position < refs_end {
+            self.node_ref += cursor.signed()?;
+            nodes.push(NodeId(self.node_ref));
+        }
+        Ok(Some(Way {
+            id: WayId(id),
+            nodes,
+            tags: self.tags(cursor)?,
+        }))
+    }
+}
+
+// Call `f` with each node and way of the file (the relations are skipped)
+pub fn for_each_object<F: FnMut(OsmObj)>(path: &str, f: F) {
+    let file = std::fs::File::open(path)
+        .unwrap_or_else(|e| panic!("Unable to open OSM file {}: {}", path, e));
+    read_objects(std::io::BufReader::new(file), path, f);
+}
+
+// Next byte of the input, None at its end
+fn next_byte<R: Read>(input: &mut R) -> std::io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read_exact(&mut byte) {
+        Ok(()) => Ok(Some(byte[0])),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Length of a dataset, an unsigned number read from the input
+fn dataset_length<R: Read>(input: &mut R) -> Result<usize, String> {
+    let mut length = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = next_byte(input)
+            .map_err(|e| e.to_string())?
+            .ok_or("truncated dataset")?;
+        length |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(length);
+        }
+    }
+    Err("invalid dataset length".to_string())
+}
+
+/// Call `f` with each node and way of an o5m stream (the relations and
+/// the deleted objects being skipped), reading it dataset by dataset
+pub fn read_objects<R: Read, F: FnMut(OsmObj)>(mut input: R, name: &str, mut f: F) {
+    let mut decoder = Decoder::default();
+    let mut payload = Vec::new();
+    let mut count = 0;
+    let result = (|| {
+        while let Some(dataset) = next_byte(&mut input).map_err(|e| e.to_string())? {
+            count += 1;
+            match dataset {
+                END_OF_FILE => break,
+                RESET => decoder = Decoder::default(),
+                // The other single-byte datasets have no payload
+                0xf0..=0xfd => {}
+                _ => {
+                    let length = dataset_length(&mut input)?;
+                    payload.resize(length, 0);
+                    input
+                        .read_exact(&mut payload)
+                        .map_err(|_| "truncated dataset")?;
+                    let mut cursor = Cursor {
+                        data: &payload,
+                        position: 0,
+                    };
+                    match dataset {
+                        NODE => {
+                            if let Some(node) = decoder.node(&mut cursor)? {
+                                f(OsmObj::Node(node));
+                            }
+                        }
+                        WAY => {
+                            if let Some(way) = decoder.way(&mut cursor)? {
+                                f(OsmObj::Way(way));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok::<_, String>(())
+    })();
+    if let Err(e) = result {
+        panic!(
+            "Unable to read OSM file {}: {} (in dataset {})",
+            name, e, count
+        );
+    }
+}
+
+/// Read the ways validating the predicate and their nodes, as osmpbfreader's
+/// `get_objs_and_deps` does for PBF files (with two passes over the file)
+pub fn get_ways_and_deps<P>(path: &str, pred: P) -> BTreeMap<OsmId, OsmObj>
+where
+    P: FnMut(&OsmObj) -> bool,
+{
+    crate::stream::get_ways_and_deps_in_two_passes(|f| for_each_object(path, f), pred)
+}
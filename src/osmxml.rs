@@ -0,0 +1,286 @@
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Tags, Way, WayId};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+
+/// Whether the file is an OSM XML file (.osm, or .osm.bz2), from its extension
+pub fn is_xml(path: &str) -> bool {
+    let path = path.to_lowercase();
+    path.ends_with(".osm") || path.ends_with(".osm.bz2")
+}
+
+/// Element of an XML document (the comments being skipped)
+pub enum Element {
+    Start(String, Vec<(String, String)>),
+    Empty(String, Vec<(String, String)>),
+    End(String),
+}
+
+fn decode_entities(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_string();
+    }
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let decoded = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// Name and attributes of the body of a start (or empty) element tag
+fn parse_tag(body: &str) -> (String, Vec<(String, String)>) {
+    let name_end = body
+        .find(|c: char| c.is_whitespace() || c == '/')
+        .unwrap_or(body.len());
+    let mut attributes = Vec::new();
+    let mut rest = &body[name_end..];
+    while let Some(equal) = rest.find('=') {
+        let key = rest[..equal].trim().trim_start_matches('/').trim();
+        let after = rest[equal + 1..].trim_start();
+        let quote = match after.chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => break,
+        };
+        let value_end = match after[1..].find(quote) {
+            Some(end) => end + 1,
+            None => break,
+        };
+        attributes.push((key.to_string(), decode_entities(&after[1..value_end])));
+        rest = &after[value_end + 1..];
+    }
+    (body[..name_end].to_string(), attributes)
+}
+
+/// Minimal streaming reader of the elements of an XML document
+pub struct XmlReader<R: BufRead> {
+    input: R,
+    buf: Vec<u8>,
+    /// Text preceding the last element read (trimmed, with its entities decoded)
+    pub text: String,
+}
+
+impl<R: BufRead> XmlReader<R> {
+    pub fn new(input: R) -> XmlReader<R> {
+        XmlReader {
+            input,
+            buf: Vec::new(),
+            text: String::new(),
+        }
+    }
+
+    pub fn next_element(&mut self) -> std::io::Result<Option<Element>> {
+        self.text.clear();
+        loop {
+            // Read the text up to the next tag
+            self.buf.clear();
+            if self.input.read_until(b'<', &mut self.buf)? == 0 || self.buf.last() != Some(&b'<') {
+                return Ok(None);
+            }
+            let text = String::from_utf8_lossy(&self.buf[..self.buf.len() - 1]);
+            if !text.trim().is_empty() {
+                self.text.push_str(&decode_entities(&text));
+            }
+            // Read the tag, a '>' within quotes (or comments and CDATA sections) not ending it
+            self.buf.clear();
+            loop {
+                if self.input.read_until(b'>', &mut self.buf)? == 0 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "unterminated XML tag",
+                    ));
+                }
+                let complete = if self.buf.starts_with(b"!--") {
+                    self.buf.ends_with(b"-->")
+                } else if self.buf.starts_with(b"![CDATA[") {
+                    self.buf.ends_with(b"]]>")
+                } else {
+                    let mut quote = None;
+                    for byte in &self.buf {
+                        match (quote, *byte) {
+                            (None, b'"' | b'\'') => quote = Some(*byte),
+                            (Some(q), byte) if q == byte => quote = None,
+                            _ => {}
+                        }
+                    }
+                    quote.is_none()
+                };
+                if complete {
+                    break;
+                }
+            }
+            if let Some(cdata) = self.buf.strip_prefix(b"![CDATA[") {
+                self.text
+                    .push_str(&String::from_utf8_lossy(&cdata[..cdata.len() - 3]));
+                continue;
+            }
+            let body = String::from_utf8_lossy(&self.buf[..self.buf.len() - 1]);
+            let body = body.trim();
+            if body.starts_with('!') || body.starts_with('?') {
+                continue;
+            }
+            if self.text.trim().len() != self.text.len() {
+                self.text = self.text.trim().to_string();
+            }
+            return Ok(Some(match body.strip_prefix('/') {
+                Some(name) => Element::End(name.trim().to_string()),
+                None => match body.strip_suffix('/') {
+                    Some(body) => {
+                        let (name, attributes) = parse_tag(body);
+                        Element::Empty(name, attributes)
+                    }
+                    None => {
+                        let (name, attributes) = parse_tag(body);
+                        Element::Start(name, attributes)
+                    }
+                },
+            }));
+        }
+    }
+}
+
+/// Value of an attribute of an element
+pub fn attribute<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+// Whether the object is deleted (in the files of JOSM, or in diffs / history files)
+fn is_deleted(attributes: &[(String, String)]) -> bool {
+    attribute(attributes, "action") == Some("delete")
+        || attribute(attributes, "visible") == Some("false")
+}
+
+fn decimicro(value: Option<&str>) -> Option<i32> {
+    value?
+        .parse::<f64>()
+        .ok()
+        .map(|degrees| (degrees * 1e7).round() as i32)
+}
+
+/// Input of an XML file, decompressed for .gz and .bz2 files
+pub fn open(path: &str) -> std::io::Result<Box<dyn BufRead>> {
+    let file = std::fs::File::open(path)?;
+    Ok(if path.to_lowercase().ends_with(".gz") {
+        Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(file)))
+    } else if path.to_lowercase().ends_with(".bz2") {
+        Box::new(BufReader::new(bzip2::read::MultiBzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    })
+}
+
+// Call `f` with each node and way of the file (the relations are skipped)
+pub fn for_each_object<F: FnMut(OsmObj)>(path: &str, f: F) {
+    let input = open(path).unwrap_or_else(|e| panic!("Unable to open OSM file {}: {}", path, e));
+    read_objects(input, path, f);
+}
+
+/// Call `f` with each node and way of an OSM XML document (the relations
+/// and the deleted objects being skipped)
+pub fn read_objects<R: BufRead, F: FnMut(OsmObj)>(input: R, name: &str, mut f: F) {
+    let mut reader = XmlReader::new(input);
+    // Object being read, with its tags (and nodes, for a way)
+    let mut current: Option<(OsmObj, bool)> = None;
+    loop {
+        let element = reader
+            .next_element()
+            .unwrap_or_else(|e| panic!("Unable to read OSM file {}: {}", name, e));
+        let (name, attributes, empty) = match element {
+            None => break,
+            Some(Element::End(name)) => {
+                if name == "node" || name == "way" {
+                    if let Some((obj, false)) = current.take() {
+                        f(obj);
+                    }
+                }
+                continue;
+            }
+            Some(Element::Start(name, attributes)) => (name, attributes, false),
+            Some(Element::Empty(name, attributes)) => (name, attributes, true),
+        };
+        let id = attribute(&attributes, "id").and_then(|id| id.parse::<i64>().ok());
+        match (name.as_str(), id) {
+            ("node", Some(id)) => {
+                let node = Node {
+                    id: NodeId(id),
+                    tags: Tags::new(),
+                    decimicro_lat: decimicro(attribute(&attributes, "lat")).unwrap_or_default(),
+                    decimicro_lon: decimicro(attribute(&attributes, "lon")).unwrap_or_default(),
+                };
+                current = Some((OsmObj::Node(node), is_deleted(&attributes)));
+            }
+            ("way", Some(id)) => {
+                let way = Way {
+                    id: WayId(id),
+                    tags: Tags::new(),
+                    nodes: Vec::new(),
+                };
+                current = Some((OsmObj::Way(way), is_deleted(&attributes)));
+            }
+            ("tag", _) => {
+                let tag = attribute(&attributes, "k").zip(attribute(&attributes, "v"));
+                let tags = match &mut current {
+                    Some((OsmObj::Node(node), _)) => Some(&mut node.tags),
+                    Some((OsmObj::Way(way), _)) => Some(&mut way.tags),
+                    _ => None,
+                };
+                if let (Some(tags), Some((key, value))) = (tags, tag) {
+                    tags.insert(key.into(), value.into());
+                }
+            }
+            ("nd", _) => {
+                let node_ref = attribute(&attributes, "ref").and_then(|r| r.parse::<i64>().ok());
+                if let (Some((OsmObj::Way(way), _)), Some(node_ref)) = (&mut current, node_ref) {
+                    way.nodes.push(NodeId(node_ref));
+                }
+            }
+            _ => {}
+        }
+        // Objects without children
+        if empty && (name == "node" || name == "way") {
+            if let Some((obj, false)) = current.take() {
+                f(obj);
+            }
+        }
+    }
+}
+
+/// Read the ways validating the predicate and their nodes, as osmpbfreader's
+/// `get_objs_and_deps` does for PBF files (with two passes over the file)
+pub fn get_ways_and_deps<P>(path: &str, pred: P) -> BTreeMap<OsmId, OsmObj>
+where
+    P: FnMut(&OsmObj) -> bool,
+{
+    crate::stream::get_ways_and_deps_in_two_passes(|f| for_each_object(path, f), pred)
+}
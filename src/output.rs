@@ -0,0 +1,1053 @@
+use crate::compress::{Compression, FileSink};
+use crate::flatgeobuf::FlatGeobufWriter;
+use crate::kml::KmlWriter;
+use crate::postgis::PostgisWriter;
+use crate::reproject::Reprojection;
+use crate::WayInfo;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::io::{BufWriter, Write};
+
+/// Format of an output file
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // Map of way id -> metrics
+    Json,
+    // One JSON object per line and per way, with its way_id and metrics
+    #[value(name = "ndjson")]
+    NdJson,
+    // Totals over all the ways
+    Summary,
+    // FeatureCollection of the ways, with their metrics as properties
+    #[value(name = "geojson")]
+    GeoJson,
+    // One row per way, with the main metrics
+    Csv,
+    // Columns of the main metrics, typed
+    Parquet,
+    // GeoPackage layer of the ways, with their main metrics (requires GDAL)
+    #[value(name = "gpkg")]
+    GeoPackage,
+    // FlatGeobuf of the ways, with their main metrics and a spatial index
+    #[value(name = "fgb")]
+    FlatGeobuf,
+    // Table of a PostGIS database, the path being a postgis:// URL
+    #[value(name = "postgis")]
+    PostGis,
+    // Copy of the input OSM file, with the slope tags added to the computed ways
+    Pbf,
+    // Placemarks of the ways, colored by average grade, for Google Earth
+    Kml,
+    // Zipped KML
+    Kmz,
+}
+
+const FORMATS: [(&str, Format); 12] = [
+    ("json", Format::Json),
+    ("ndjson", Format::NdJson),
+    ("summary", Format::Summary),
+    ("geojson", Format::GeoJson),
+    ("csv", Format::Csv),
+    ("parquet", Format::Parquet),
+    ("gpkg", Format::GeoPackage),
+    ("fgb", Format::FlatGeobuf),
+    ("postgis", Format::PostGis),
+    ("pbf", Format::Pbf),
+    ("kml", Format::Kml),
+    ("kmz", Format::Kmz),
+];
+
+impl Format {
+    /// Whether the format is written as a stream, which can be compressed with
+    /// --compress or go to stdout (the other ones being compressed already,
+    /// needing to seek in their files, or not files)
+    pub fn is_stream(&self) -> bool {
+        !matches!(
+            self,
+            Format::GeoPackage | Format::FlatGeobuf | Format::PostGis | Format::Pbf | Format::Kmz
+        )
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Json | Format::Summary => "json",
+            Format::NdJson => "ndjson",
+            Format::GeoJson => "geojson",
+            Format::Csv => "csv",
+            Format::Parquet => "parquet",
+            Format::GeoPackage => "gpkg",
+            Format::FlatGeobuf => "fgb",
+            Format::PostGis => "sql",
+            Format::Pbf => "osm.pbf",
+            Format::Kml => "kml",
+            Format::Kmz => "kmz",
+        }
+    }
+}
+
+/// A computed way, as streamed to the writers
+pub struct Record<'a> {
+    pub way_id: i64,
+    pub info: &'a WayInfo,
+    // [lon, lat] coordinates of the nodes of the way
+    pub geometry: &'a [[f64; 2]],
+    // Value of the partition of the way, if any
+    pub partition: Option<&'a str>,
+}
+
+/// Segment of a computed way between two consecutive nodes, as streamed to the
+/// writers with --per-segment
+#[derive(serde::Serialize)]
+pub struct Segment {
+    pub way_id: i64,
+    // Position of the segment along the way, from 0
+    pub index: usize,
+    pub from_node: i64,
+    pub to_node: i64,
+    // Length in meters
+    pub length: f64,
+    // Elevation change (in meters) and grade (in %, signed), if both nodes have an elevation
+    pub elevation_delta: Option<f64>,
+    pub grade: Option<f64>,
+    #[serde(skip)]
+    pub geometry: [[f64; 2]; 2],
+}
+
+/// Formats which can be written segment by segment, with --per-segment
+pub const SEGMENT_FORMATS: [Format; 3] = [Format::NdJson, Format::Csv, Format::GeoJson];
+
+/// Serializer of the computed ways, which receives them one at a time
+pub trait OutputWriter {
+    fn write(&mut self, record: &Record);
+    /// Write a segment of a way instead of the whole way, with --per-segment (only
+    /// called for the SEGMENT_FORMATS)
+    fn write_segment(&mut self, _segment: &Segment) {
+        unreachable!("the output formats are checked against --per-segment")
+    }
+    /// Complete the output, once all the ways have been written
+    fn finish(self: Box<Self>);
+}
+
+/// Output file, from a `[FORMAT:]PATH` specification
+#[derive(Debug)]
+pub struct Output {
+    pub format: Format,
+    pub path: String,
+}
+
+impl Output {
+    /// Parse an output specification, the format being inferred from the
+    /// extension of the path when it isn't given
+    pub fn parse(spec: &str) -> Result<Output, String> {
+        if crate::postgis::is_url(spec) {
+            return Ok(Output {
+                format: Format::PostGis,
+                path: spec.to_string(),
+            });
+        }
+        if let Some((name, path)) = spec.split_once(':') {
+            if let Some((_, format)) = FORMATS.iter().find(|(format, _)| *format == name) {
+                return Ok(Output {
+                    format: *format,
+                    path: path.to_string(),
+                });
+            }
+        }
+        let extension = std::path::Path::new(spec)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("json") => Ok(Output {
+                format: Format::Json,
+                path: spec.to_string(),
+            }),
+            Some("ndjson") | Some("jsonl") => Ok(Output {
+                format: Format::NdJson,
+                path: spec.to_string(),
+            }),
+            Some("geojson") => Ok(Output {
+                format: Format::GeoJson,
+                path: spec.to_string(),
+            }),
+            Some("csv") => Ok(Output {
+                format: Format::Csv,
+                path: spec.to_string(),
+            }),
+            Some("parquet") => Ok(Output {
+                format: Format::Parquet,
+                path: spec.to_string(),
+            }),
+            Some("gpkg") => Ok(Output {
+                format: Format::GeoPackage,
+                path: spec.to_string(),
+            }),
+            Some("pbf") => Ok(Output {
+                format: Format::Pbf,
+                path: spec.to_string(),
+            }),
+            Some("kml") => Ok(Output {
+                format: Format::Kml,
+                path: spec.to_string(),
+            }),
+            Some("kmz") => Ok(Output {
+                format: Format::Kmz,
+                path: spec.to_string(),
+            }),
+            Some("fgb") => Ok(Output {
+                format: Format::FlatGeobuf,
+                path: spec.to_string(),
+            }),
+            _ => Err(format!(
+                "Unable to infer the output format of {}, use FORMAT:PATH with one of: {}",
+                spec,
+                FORMATS.map(|(name, _)| name).join(", ")
+            )),
+        }
+    }
+
+    /// Whether the output can be partitioned (a PostGIS table or a copy of the
+    /// input OSM file can't)
+    pub fn is_partitionable(&self) -> bool {
+        !matches!(self.format, Format::PostGis | Format::Pbf)
+    }
+
+    /// Writer of the output, as a Hive-style partitioned dataset if a partition
+    /// is given: the path is then a directory with a `<name>=<value>/part-0.<extension>`
+    /// file for each value of the partition, or the path of the file of each tile
+    /// when it contains the `{tile}` placeholder
+    pub fn writer<'a>(
+        &self,
+        partition: Option<&Partition>,
+        options: WriterOptions<'a>,
+    ) -> Box<dyn OutputWriter + 'a> {
+        if self.format == Format::Pbf {
+            return Box::new(PbfTagsWriter {
+                source: options.osm_path.to_string(),
+                path: self.path.clone(),
+                tags: Default::default(),
+            });
+        }
+        match partition.filter(|_| self.is_partitionable()) {
+            Some(partition) => Box::new(PartitionedWriter {
+                format: self.format,
+                path: self.path.clone(),
+                name: partition.name().to_string(),
+                options,
+                writers: BTreeMap::new(),
+                parts: BTreeMap::new(),
+                writes: 0,
+            }),
+            None => file_writer(self.format, &self.path, options),
+        }
+    }
+}
+
+/// Settings of the writers, shared by all the outputs
+#[derive(Clone, Copy)]
+pub struct WriterOptions<'a> {
+    pub reprojection: Option<&'a Reprojection>,
+    // Path of the input OSM file (after download)
+    pub osm_path: &'a str,
+    // Upper bounds (in %) of the grade classes of the KML styles
+    pub kml_breaks: &'a [f64],
+    pub compression: Option<Compression>,
+    // One record per segment of the ways, rather than per way
+    pub per_segment: bool,
+}
+
+fn file_writer<'a>(
+    format: Format,
+    path: &str,
+    options: WriterOptions<'a>,
+) -> Box<dyn OutputWriter + 'a> {
+    let reprojection = options.reprojection;
+    if format == Format::GeoPackage {
+        return gpkg_writer(path, reprojection);
+    }
+    if format == Format::FlatGeobuf {
+        return Box::new(FlatGeobufWriter::create(path, reprojection));
+    }
+    if format == Format::Kmz {
+        let writer = KmlWriter::create_kmz(path, options.kml_breaks)
+            .unwrap_or_else(|_| panic!("Unable to write output file {}", path));
+        return Box::new(writer);
+    }
+    if format == Format::PostGis {
+        let writer = PostgisWriter::create(path, reprojection).unwrap_or_else(|e| {
+            log::error!(target: "setup", fields:serde = json!({ "output": "postgis" }); "{}", e);
+            std::process::exit(1);
+        });
+        return Box::new(writer);
+    }
+    let (path, file) = FileSink::create(path, options.compression).unwrap_or_else(|e| {
+        log::error!(
+            target: "setup",
+            fields:serde = json!({ "output": path });
+            "Unable to write output file {}: {}", path, e
+        );
+        std::process::exit(1);
+    });
+    let mut out = FileOut { path, out: file };
+    match format {
+        Format::Json => Box::new(JsonWriter { out, count: 0 }),
+        Format::NdJson => Box::new(NdJsonWriter {
+            out,
+            flush_lines: options.compression.is_none(),
+        }),
+        Format::Summary => Box::new(SummaryWriter {
+            out,
+            totals: Default::default(),
+        }),
+        Format::GeoJson => Box::new(GeoJsonWriter {
+            out,
+            reprojection,
+            count: 0,
+        }),
+        Format::Csv => {
+            out.write(if options.per_segment {
+                CSV_SEGMENT_HEADER
+            } else {
+                CSV_HEADER
+            });
+            Box::new(CsvWriter { out })
+        }
+        Format::Parquet => {
+            let writer = parquet_writer(out.out)
+                .unwrap_or_else(|e| panic!("Unable to write output file {}: {}", out.path, e));
+            Box::new(ParquetOutput {
+                path: out.path,
+                writer,
+                columns: Default::default(),
+            })
+        }
+        Format::Kml => Box::new(KmlWriter::create(out.path, out.out, options.kml_breaks)),
+        Format::GeoPackage | Format::FlatGeobuf | Format::PostGis | Format::Pbf | Format::Kmz => {
+            unreachable!()
+        }
+    }
+}
+
+#[cfg(feature = "gdal")]
+fn gpkg_writer<'a>(
+    path: &str,
+    reprojection: Option<&'a Reprojection>,
+) -> Box<dyn OutputWriter + 'a> {
+    let writer = crate::gpkg::GpkgWriter::create(path, reprojection).unwrap_or_else(|e| {
+        log::error!(target: "setup", fields:serde = json!({ "output": path }); "{}", e);
+        std::process::exit(1);
+    });
+    Box::new(writer)
+}
+
+#[cfg(not(feature = "gdal"))]
+fn gpkg_writer<'a>(
+    path: &str,
+    _reprojection: Option<&'a Reprojection>,
+) -> Box<dyn OutputWriter + 'a> {
+    log::error!(
+        target: "setup",
+        fields:serde = json!({ "output": path });
+        "The GeoPackage output is not available when built without GDAL"
+    );
+    std::process::exit(1);
+}
+
+// Buffered (and possibly compressed) output file, panicking on write errors
+struct FileOut {
+    path: String,
+    out: FileSink,
+}
+
+impl FileOut {
+    fn write(&mut self, s: &str) {
+        self.out
+            .write_all(s.as_bytes())
+            .unwrap_or_else(|_| panic!("Unable to write output file {}", self.path));
+    }
+
+    fn flush(&mut self) {
+        self.out
+            .flush()
+            .unwrap_or_else(|_| panic!("Unable to write output file {}", self.path));
+    }
+
+    fn finish(self) {
+        let path = self.path;
+        self.out
+            .finish()
+            .unwrap_or_else(|e| panic!("Unable to write output file {}: {}", path, e));
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("Unable to serialize result to string")
+}
+
+// Map of way id -> metrics
+struct JsonWriter {
+    out: FileOut,
+    count: usize,
+}
+
+impl OutputWriter for JsonWriter {
+    fn write(&mut self, record: &Record) {
+        let separator = if self.count == 0 { "{" } else { "," };
+        self.count += 1;
+        self.out.write(&format!(
+            "{}\"{}\":{}",
+            separator,
+            record.way_id,
+            to_json(record.info)
+        ));
+    }
+
+    fn finish(mut self: Box<Self>) {
+        self.out.write(if self.count == 0 { "{}" } else { "}" });
+        self.out.finish();
+    }
+}
+
+// One line per way, flushed as soon as it is written so that the consumers of
+// the output (e.g. `jq` reading from a pipe) receive the ways as they are computed
+struct NdJsonWriter {
+    out: FileOut,
+    // Not when compressed, which would degrade the compression
+    flush_lines: bool,
+}
+
+impl OutputWriter for NdJsonWriter {
+    fn write(&mut self, record: &Record) {
+        let mut line = serde_json::to_value(record.info).expect("Unable to serialize result");
+        line["way_id"] = json!(record.way_id);
+        self.out.write(&to_json(&line));
+        self.out.write("\n");
+        if self.flush_lines {
+            self.out.flush();
+        }
+    }
+
+    fn write_segment(&mut self, segment: &Segment) {
+        self.out.write(&to_json(segment));
+        self.out.write("\n");
+        if self.flush_lines {
+            self.out.flush();
+        }
+    }
+
+    fn finish(self: Box<Self>) {
+        self.out.finish();
+    }
+}
+
+// Copy of the input OSM file, written once all the ways are computed with their
+// slope:climb and slope:descent (in meters) and slope:avg (average grade, in %) tags.
+// The copy is written next to the output path then renamed, so that the output can
+// replace the input file.
+struct PbfTagsWriter {
+    source: String,
+    path: String,
+    tags: fxhash::FxHashMap<i64, Vec<(String, String)>>,
+}
+
+impl OutputWriter for PbfTagsWriter {
+    fn write(&mut self, record: &Record) {
+        let way = record.info;
+        let grade = crate::summary::average_grade(way.distance, way.climb, way.descent);
+        self.tags.insert(
+            record.way_id,
+            vec![
+                ("slope:climb".to_string(), format!("{:.1}", way.climb)),
+                ("slope:descent".to_string(), format!("{:.1}", way.descent)),
+                ("slope:avg".to_string(), format!("{:.1}", grade)),
+            ],
+        );
+    }
+
+    fn finish(self: Box<Self>) {
+        let partial = format!("{}.partial", self.path);
+        let file = std::fs::File::create(&partial)
+            .unwrap_or_else(|_| panic!("Unable to write output file {}", partial));
+        let mut out = BufWriter::new(file);
+        crate::pbf::copy_with_way_tags(&self.source, &mut out, &self.tags);
+        out.flush()
+            .and_then(|_| std::fs::rename(&partial, &self.path))
+            .unwrap_or_else(|_| panic!("Unable to write output file {}", self.path));
+    }
+}
+
+const CSV_HEADER: &str =
+    "way_id,distance,climb_distance,descent_distance,climb,descent,climb_per_km,descent_per_km\n";
+const CSV_SEGMENT_HEADER: &str = "way_id,index,from_node,to_node,length,elevation_delta,grade\n";
+
+// One row per way, with the main metrics
+struct CsvWriter {
+    out: FileOut,
+}
+
+impl OutputWriter for CsvWriter {
+    fn write(&mut self, record: &Record) {
+        let way = record.info;
+        self.out.write(&format!(
+            "{},{},{},{},{},{},{},{}\n",
+            record.way_id,
+            way.distance,
+            way.climb_distance,
+            way.descent_distance,
+            way.climb,
+            way.descent,
+            way.climb_per_km,
+            way.descent_per_km
+        ));
+    }
+
+    fn write_segment(&mut self, segment: &Segment) {
+        let optional = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+        self.out.write(&format!(
+            "{},{},{},{},{},{},{}\n",
+            segment.way_id,
+            segment.index,
+            segment.from_node,
+            segment.to_node,
+            segment.length,
+            optional(segment.elevation_delta),
+            optional(segment.grade)
+        ));
+    }
+
+    fn finish(self: Box<Self>) {
+        self.out.finish();
+    }
+}
+
+// Columns of the Parquet output, in the order of the schema: the way id, the
+// required doubles, the nullable doubles, whether the way is closed and the
+// nullable ids of its endpoints
+const PARQUET_DOUBLES: [&str; 9] = [
+    "distance",
+    "climb_distance",
+    "descent_distance",
+    "climb",
+    "descent",
+    "climb_per_km",
+    "descent_per_km",
+    "avg_climb_grade",
+    "avg_descent_grade",
+];
+const PARQUET_OPTIONAL_DOUBLES: [&str; 6] = [
+    "climb_forward",
+    "descent_forward",
+    "climb_backward",
+    "descent_backward",
+    "max_grade",
+    "min_grade",
+];
+const PARQUET_OPTIONAL_INT64S: [&str; 2] = ["first_node", "last_node"];
+
+// Number of ways of a row group of the Parquet output
+const PARQUET_ROW_GROUP_SIZE: usize = 128 * 1024;
+
+type ParquetWriter = parquet::file::writer::SerializedFileWriter<FileSink>;
+
+fn parquet_writer(out: FileSink) -> parquet::errors::Result<ParquetWriter> {
+    let columns = std::iter::once("REQUIRED INT64 way_id".to_string())
+        .chain(
+            PARQUET_DOUBLES
+                .iter()
+                .map(|name| format!("REQUIRED DOUBLE {}", name)),
+        )
+        .chain(
+            PARQUET_OPTIONAL_DOUBLES
+                .iter()
+                .map(|name| format!("OPTIONAL DOUBLE {}", name)),
+        )
+        .chain(std::iter::once("REQUIRED BOOLEAN is_closed".to_string()))
+        .chain(
+            PARQUET_OPTIONAL_INT64S
+                .iter()
+                .map(|name| format!("OPTIONAL INT64 {}", name)),
+        )
+        .collect::<Vec<_>>();
+    let schema = parquet::schema::parser::parse_message_type(&format!(
+        "message ways {{ {}; }}",
+        columns.join("; ")
+    ))?;
+    let properties = parquet::file::properties::WriterProperties::builder()
+        .set_created_by(format!("osm-ways-slope {}", env!("CARGO_PKG_VERSION")))
+        .build();
+    ParquetWriter::new(out, schema.into(), properties.into())
+}
+
+// Values of the columns of the row group being buffered
+#[derive(Default)]
+struct ParquetColumns {
+    way_id: Vec<i64>,
+    doubles: [Vec<f64>; 9],
+    optional_doubles: [Vec<Option<f64>>; 6],
+    is_closed: Vec<bool>,
+    optional_int64s: [Vec<Option<i64>>; 2],
+}
+
+// Write the values of a nullable column, the definition level of the nulls being 0
+fn write_optional<T>(
+    column: &mut parquet::file::writer::SerializedColumnWriter,
+    values: &[Option<T::T>],
+) -> parquet::errors::Result<()>
+where
+    T: parquet::data_type::DataType,
+{
+    let present: Vec<T::T> = values.iter().flatten().cloned().collect();
+    let levels: Vec<i16> = values.iter().map(|value| value.is_some() as i16).collect();
+    column
+        .typed::<T>()
+        .write_batch(&present, Some(&levels), None)?;
+    Ok(())
+}
+
+impl ParquetColumns {
+    fn write(&self, writer: &mut ParquetWriter) -> parquet::errors::Result<()> {
+        use parquet::data_type::{BoolType, DoubleType, Int64Type};
+        let mut group = writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = group.next_column()? {
+            let doubles = 1 + self.doubles.len();
+            let optional_doubles = doubles + self.optional_doubles.len();
+            match index {
+                0 => column
+                    .typed::<Int64Type>()
+                    .write_batch(&self.way_id, None, None)?,
+                i if i < doubles => {
+                    column
+                        .typed::<DoubleType>()
+                        .write_batch(&self.doubles[i - 1], None, None)?
+                }
+                i if i < optional_doubles => {
+                    write_optional::<DoubleType>(&mut column, &self.optional_doubles[i - doubles])?;
+                    0
+                }
+                i if i == optional_doubles => {
+                    column
+                        .typed::<BoolType>()
+                        .write_batch(&self.is_closed, None, None)?
+                }
+                i => {
+                    let values = &self.optional_int64s[i - optional_doubles - 1];
+                    write_optional::<Int64Type>(&mut column, values)?;
+                    0
+                }
+            };
+            column.close()?;
+            index += 1;
+        }
+        group.close()?;
+        Ok(())
+    }
+}
+
+struct ParquetOutput {
+    path: String,
+    writer: ParquetWriter,
+    columns: ParquetColumns,
+}
+
+impl ParquetOutput {
+    fn flush_row_group(&mut self) {
+        if self.columns.way_id.is_empty() {
+            return;
+        }
+        let columns = std::mem::take(&mut self.columns);
+        columns
+            .write(&mut self.writer)
+            .unwrap_or_else(|e| panic!("Unable to write output file {}: {}", self.path, e));
+    }
+}
+
+impl OutputWriter for ParquetOutput {
+    fn write(&mut self, record: &Record) {
+        let way = record.info;
+        let columns = &mut self.columns;
+        columns.way_id.push(record.way_id);
+        let doubles = [
+            way.distance,
+            way.climb_distance,
+            way.descent_distance,
+            way.climb,
+            way.descent,
+            way.climb_per_km,
+            way.descent_per_km,
+            way.avg_climb_grade,
+            way.avg_descent_grade,
+        ];
+        for (column, value) in columns.doubles.iter_mut().zip(doubles) {
+            column.push(value);
+        }
+        let optional_doubles = [
+            way.climb_forward,
+            way.descent_forward,
+            way.climb_backward,
+            way.descent_backward,
+            way.max_grade,
+            way.min_grade,
+        ];
+        for (column, value) in columns.optional_doubles.iter_mut().zip(optional_doubles) {
+            column.push(value);
+        }
+        columns.is_closed.push(way.is_closed);
+        for (column, value) in columns
+            .optional_int64s
+            .iter_mut()
+            .zip([way.first_node, way.last_node])
+        {
+            column.push(value);
+        }
+        if columns.way_id.len() == PARQUET_ROW_GROUP_SIZE {
+            self.flush_row_group();
+        }
+    }
+
+    fn finish(mut self: Box<Self>) {
+        self.flush_row_group();
+        let path = self.path;
+        self.writer
+            .into_inner()
+            .map_err(|e| e.to_string())
+            .and_then(|sink| sink.finish().map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| panic!("Unable to write output file {}: {}", path, e));
+    }
+}
+
+// Totals over all the ways
+#[derive(Default, serde::Serialize)]
+struct Totals {
+    way_count: usize,
+    distance: f64,
+    climb_distance: f64,
+    descent_distance: f64,
+    climb: f64,
+    descent: f64,
+}
+
+struct SummaryWriter {
+    out: FileOut,
+    totals: Totals,
+}
+
+impl OutputWriter for SummaryWriter {
+    fn write(&mut self, record: &Record) {
+        let (totals, way) = (&mut self.totals, record.info);
+        totals.way_count += 1;
+        totals.distance += way.distance;
+        totals.climb_distance += way.climb_distance;
+        totals.descent_distance += way.descent_distance;
+        totals.climb += way.climb;
+        totals.descent += way.descent;
+    }
+
+    fn finish(mut self: Box<Self>) {
+        let json_str = to_json(&self.totals);
+        self.out.write(&json_str);
+        self.out.finish();
+    }
+}
+
+// FeatureCollection of LineString features, the metrics of the ways being the properties
+struct GeoJsonWriter<'a> {
+    out: FileOut,
+    reprojection: Option<&'a Reprojection>,
+    count: usize,
+}
+
+impl GeoJsonWriter<'_> {
+    fn write_feature(&mut self, mut feature: serde_json::Value) {
+        if self.reprojection.is_some() {
+            let mut collection = json!({ "features": [feature] });
+            crate::reproject::apply_to_output(self.reprojection, &mut collection, &self.out.path);
+            feature = collection["features"][0].take();
+        }
+        if self.count == 0 {
+            self.out.write("{\"type\":\"FeatureCollection\",");
+            if let Some(reprojection) = self.reprojection {
+                self.out
+                    .write(&format!("\"crs\":{},", to_json(&reprojection.crs_member())));
+            }
+            self.out.write("\"features\":[");
+        } else {
+            self.out.write(",");
+        }
+        self.count += 1;
+        self.out.write(&to_json(&feature));
+    }
+}
+
+impl OutputWriter for GeoJsonWriter<'_> {
+    fn write(&mut self, record: &Record) {
+        let mut properties = serde_json::to_value(record.info).expect("Unable to serialize result");
+        properties["way_id"] = json!(record.way_id);
+        self.write_feature(json!({
+            "type": "Feature",
+            "id": record.way_id,
+            "geometry": { "type": "LineString", "coordinates": record.geometry },
+            "properties": properties,
+        }));
+    }
+
+    fn write_segment(&mut self, segment: &Segment) {
+        self.write_feature(json!({
+            "type": "Feature",
+            "geometry": { "type": "LineString", "coordinates": segment.geometry },
+            "properties": segment,
+        }));
+    }
+
+    fn finish(mut self: Box<Self>) {
+        if self.count == 0 {
+            let mut collection = json!({ "type": "FeatureCollection", "features": [] });
+            crate::reproject::apply_to_output(self.reprojection, &mut collection, &self.out.path);
+            self.out.write(&to_json(&collection));
+        } else {
+            self.out.write("]}");
+        }
+        self.out.finish();
+    }
+}
+
+// Number of partition files open at once, the least recently written one being
+// closed to open another one (its following ways going to a new part file)
+const MAX_OPEN_PARTITIONS: usize = 64;
+
+// Files of the format for each value of the partition, created on its first way
+struct PartitionedWriter<'a> {
+    format: Format,
+    // Directory of the dataset, or path of the files of the tiles with {tile}
+    path: String,
+    name: String,
+    options: WriterOptions<'a>,
+    // Open writers, with the number of ways written when they were last written to
+    writers: BTreeMap<String, (Box<dyn OutputWriter + 'a>, u64)>,
+    // Number of part files created for each value
+    parts: BTreeMap<String, usize>,
+    writes: u64,
+}
+
+impl OutputWriter for PartitionedWriter<'_> {
+    fn write(&mut self, record: &Record) {
+        let value = record.partition.unwrap_or(HIVE_DEFAULT_PARTITION);
+        if !self.writers.contains_key(value) {
+            if self.writers.len() >= MAX_OPEN_PARTITIONS {
+                let least_recent = self
+                    .writers
+                    .iter()
+                    .min_by_key(|(_, (_, last_write))| *last_write)
+                    .map(|(value, _)| value.clone())
+                    .unwrap();
+                let (writer, _) = self.writers.remove(&least_recent).unwrap();
+                writer.finish();
+            }
+            let part = self.parts.entry(value.to_string()).or_default();
+            let path = part_path(&self.path, &self.name, value, *part, self.format);
+            *part += 1;
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)
+                    .unwrap_or_else(|_| panic!("Unable to create directory {}", dir.display()));
+            }
+            let writer = file_writer(self.format, &path.to_string_lossy(), self.options);
+            self.writers.insert(value.to_string(), (writer, 0));
+        }
+        self.writes += 1;
+        let (writer, last_write) = self.writers.get_mut(value).unwrap();
+        *last_write = self.writes;
+        writer.write(record);
+    }
+
+    fn finish(self: Box<Self>) {
+        for (writer, _) in self.writers.into_values() {
+            writer.finish();
+        }
+    }
+}
+
+// Path of a part file of a partition value: `<path>/<name>=<value>/part-<n>.<extension>`,
+// or the path with {tile} expanded to the tile (with `-<n>` appended to the name of
+// its file from the second part on)
+fn part_path(
+    path: &str,
+    name: &str,
+    value: &str,
+    part: usize,
+    format: Format,
+) -> std::path::PathBuf {
+    if !path.contains("{tile}") {
+        return std::path::Path::new(path)
+            .join(format!(
+                "{}={}",
+                escape_partition(name),
+                escape_partition(value)
+            ))
+            .join(format!("part-{}.{}", part, format.extension()));
+    }
+    let path = std::path::PathBuf::from(crate::template::expand_tile(path, value));
+    if part == 0 {
+        return path;
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, part, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, part),
+    };
+    path.with_file_name(file_name)
+}
+
+// Partition of the ways without a value (e.g. without the partition tag)
+const HIVE_DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+// Percent-encode the characters which can't appear in the name of a partition directory
+fn escape_partition(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '=' | '%' | ':' | '"' | '\'' | '*' | '?' | '#' | '\n' => {
+                format!("%{:02X}", c as u32)
+            }
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Partitioning of the outputs, by the value of a tag or by tile
+#[derive(Debug, Clone)]
+pub enum Partition {
+    Tag(String),
+    // Web Mercator tile (at the given zoom level) of the first node of the way
+    Tile(u8),
+}
+
+impl Partition {
+    /// Parse `tile:ZOOM` or a tag key
+    pub fn parse(spec: &str) -> Result<Partition, String> {
+        match spec.strip_prefix("tile:") {
+            Some(zoom) => match zoom.parse::<u8>() {
+                Ok(zoom) if zoom <= 24 => Ok(Partition::Tile(zoom)),
+                _ => Err(format!("Invalid tile zoom level {}", zoom)),
+            },
+            None => Ok(Partition::Tag(spec.trim_end_matches('=').to_string())),
+        }
+    }
+
+    /// Name of the partition column
+    pub fn name(&self) -> &str {
+        match self {
+            Partition::Tag(key) => key,
+            Partition::Tile(_) => "tile",
+        }
+    }
+
+    /// Value of the partition column for a way, given its tags and first (lon, lat)
+    pub fn value(&self, tags: &osmpbfreader::Tags, first: Option<(f64, f64)>) -> Option<String> {
+        match self {
+            Partition::Tag(key) => tags.get(key.as_str()).map(|value| value.to_string()),
+            Partition::Tile(zoom) => {
+                let (lon, lat) = first?;
+                let n = (1u32 << zoom) as f64;
+                let lat = lat.clamp(-85.0511, 85.0511).to_radians();
+                let x = ((lon + 180.) / 360. * n).floor().clamp(0., n - 1.);
+                let y = ((1. - lat.tan().asinh() / std::f64::consts::PI) / 2. * n)
+                    .floor()
+                    .clamp(0., n - 1.);
+                Some(format!("{}-{}-{}", zoom, x, y))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+
+    fn way(climb_forward: Option<f64>, max_grade: Option<f64>, last_node: Option<i64>) -> WayInfo {
+        serde_json::from_value(json!({
+            "distance": 1000.,
+            "climb_distance": 600.,
+            "descent_distance": 400.,
+            "climb": 30.,
+            "descent": 20.,
+            "climb_forward": climb_forward,
+            "max_grade": max_grade,
+            "first_node": 1,
+            "last_node": last_node,
+            "is_closed": true,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn parquet_round_trip_with_nulls() {
+        let path =
+            std::env::temp_dir().join(format!("osm-ways-slope-{}.parquet", std::process::id()));
+        let (path, sink) = FileSink::create(path.to_str().unwrap(), None).unwrap();
+        let mut output = Box::new(ParquetOutput {
+            path: path.clone(),
+            writer: parquet_writer(sink).unwrap(),
+            columns: Default::default(),
+        });
+        for (way_id, way) in [
+            (1, way(Some(30.), Some(8.5), Some(2))),
+            (2, way(None, None, None)),
+        ] {
+            output.write(&Record {
+                way_id,
+                info: &way,
+                geometry: &[],
+                partition: None,
+            });
+        }
+        output.finish();
+
+        let reader = SerializedFileReader::try_from(path.as_str()).unwrap();
+        let rows: Vec<BTreeMap<String, Field>> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                row.unwrap()
+                    .get_column_iter()
+                    .map(|(name, field)| (name.clone(), field.clone()))
+                    .collect()
+            })
+            .collect();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["way_id"], Field::Long(1));
+        assert_eq!(rows[0]["climb"], Field::Double(30.));
+        assert_eq!(rows[0]["climb_forward"], Field::Double(30.));
+        assert_eq!(rows[0]["max_grade"], Field::Double(8.5));
+        assert_eq!(rows[0]["last_node"], Field::Long(2));
+        assert_eq!(rows[0]["is_closed"], Field::Bool(true));
+        assert_eq!(rows[1]["way_id"], Field::Long(2));
+        assert_eq!(rows[1]["distance"], Field::Double(1000.));
+        assert_eq!(rows[1]["climb_forward"], Field::Null);
+        assert_eq!(rows[1]["max_grade"], Field::Null);
+        assert_eq!(rows[1]["first_node"], Field::Long(1));
+        assert_eq!(rows[1]["last_node"], Field::Null);
+    }
+
+    #[test]
+    fn part_paths_of_tiles() {
+        let path = |template, part| part_path(template, "tile", "8-133-90", part, Format::Json);
+        assert_eq!(
+            path("out", 0),
+            std::path::Path::new("out/tile=8-133-90/part-0.json")
+        );
+        assert_eq!(
+            path("out/{tile}.json", 0),
+            std::path::Path::new("out/8-133-90.json")
+        );
+        assert_eq!(
+            path("out/{tile}.json", 2),
+            std::path::Path::new("out/8-133-90-2.json")
+        );
+        assert_eq!(
+            path("out/{tile}", 1),
+            std::path::Path::new("out/8-133-90-1")
+        );
+    }
+}
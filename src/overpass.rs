@@ -0,0 +1,162 @@
+use serde_json::json;
+use std::io::BufRead;
+use std::path::Path;
+
+/// Default endpoint of the Overpass API
+pub const DEFAULT_URL: &str = "https://overpass-api.de/api/interpreter";
+
+// Timeout (in seconds) of the queries built from a bounding box
+const TIMEOUT: u32 = 180;
+
+// west,south,east,north bounding box
+fn parse_bbox(input: &str) -> Option<[f64; 4]> {
+    let values: Vec<f64> = input
+        .split(',')
+        .map(|value| value.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    match values[..] {
+        [west, south, east, north] if west < east && south < north => {
+            Some([west, south, east, north])
+        }
+        _ => None,
+    }
+}
+
+// Quoted Overpass QL string
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Tag selector of a filter, e.g. `["highway"="primary"]`
+pub fn tag_selector(key: &str, value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("[{}={}]", quote(key), quote(value)),
+        None => format!("[{}]", quote(key)),
+    }
+}
+
+/// Overpass QL query of the input: the query itself, read from a file if the input
+/// is a path, or the query of the ways of a `west,south,east,north` bounding box
+/// (matching any or all of the tag selectors) and of their nodes
+pub fn query(input: &str, selectors: &[String], all: bool) -> Result<String, String> {
+    let bbox = match parse_bbox(input) {
+        Some(bbox) => bbox,
+        None if Path::new(input).is_file() => {
+            return std::fs::read_to_string(input)
+                .map_err(|e| format!("Unable to read Overpass query {}: {}", input, e));
+        }
+        None => return Ok(input.to_string()),
+    };
+    let [west, south, east, north] = bbox;
+    let area = format!("({},{},{},{})", south, west, north, east);
+    let statements: Vec<String> = if selectors.is_empty() {
+        vec![format!("way{};", area)]
+    } else if all {
+        vec![format!("way{}{};", selectors.concat(), area)]
+    } else {
+        selectors
+            .iter()
+            .map(|selector| format!("way{}{};", selector, area))
+            .collect()
+    };
+    Ok(format!(
+        "[out:xml][timeout:{}];\n({});\n(._;>;);\nout;\n",
+        TIMEOUT,
+        statements.join(" ")
+    ))
+}
+
+// Text of the error reported by Overpass in its (HTML) response
+fn error_message(body: &str) -> Option<String> {
+    body.lines()
+        .find(|line| line.contains("Error</strong>"))
+        .map(|line| {
+            let mut text = String::new();
+            let mut in_tag = false;
+            for c in line.chars() {
+                match c {
+                    '<' => in_tag = true,
+                    '>' => in_tag = false,
+                    c if !in_tag => text.push(c),
+                    _ => {}
+                }
+            }
+            text.replace("&quot;", "\"").trim().to_string()
+        })
+}
+
+// Runtime error (e.g. timeout or memory exhaustion) reported in a remark of the data
+fn runtime_error(path: &Path) -> Option<String> {
+    let file = std::fs::File::open(path).ok()?;
+    std::io::BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .find(|line| line.contains("<remark>") && line.contains("error"))
+        .map(|line| {
+            line.replace("<remark>", "")
+                .replace("</remark>", "")
+                .trim()
+                .to_string()
+        })
+}
+
+/// Path of the OSM XML response of the query, posted to the endpoint,
+/// or of its cached copy (unless it is refreshed), the Overpass responses having
+/// no validators
+pub fn fetch(cache: &crate::fetch::Cache, url: &str, query: &str) -> Result<String, String> {
+    std::fs::create_dir_all(&cache.dir).map_err(|e| {
+        format!(
+            "Unable to create cache directory {}: {}",
+            cache.dir.display(),
+            e
+        )
+    })?;
+    let key = format!("{:016x}", fxhash::hash64(&(url, query)));
+    let path = cache.dir.join(format!("overpass-{}.osm", key));
+    if path.exists() && !cache.refresh {
+        log::info!(
+            target: "fetch",
+            fields:serde = json!({ "url": url, "path": path.display().to_string() });
+            "Using the cached response of the Overpass query"
+        );
+        return Ok(path.to_string_lossy().to_string());
+    }
+
+    let partial = cache.dir.join(format!("overpass-{}.part", key));
+    log::info!(
+        target: "fetch",
+        fields:serde = json!({ "url": url, "query": query });
+        "Running the Overpass query on {}", url
+    );
+    let failure = |status: u16, body: &str| match error_message(body) {
+        Some(message) => format!("The Overpass query failed: {}", message),
+        None => format!("The Overpass query failed (HTTP status {})", status),
+    };
+    let response = match ureq::post(url).send_form(&[("data", query)]) {
+        Ok(response) if response.status() == 200 => response,
+        Ok(response) | Err(ureq::Error::Status(_, response)) => {
+            let status = response.status();
+            return Err(failure(status, &response.into_string().unwrap_or_default()));
+        }
+        Err(e) => {
+            return Err(format!(
+                "Unable to query {}: {}",
+                url,
+                crate::fetch::request_error(e)
+            ))
+        }
+    };
+    std::fs::File::create(&partial)
+        .and_then(|mut file| std::io::copy(&mut response.into_reader(), &mut file))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&partial);
+            format!("Unable to query {}: {}", url, e)
+        })?;
+    if let Some(message) = runtime_error(&partial) {
+        let _ = std::fs::remove_file(&partial);
+        return Err(format!("The Overpass query failed: {}", message));
+    }
+    std::fs::rename(&partial, &path)
+        .map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+    Ok(path.to_string_lossy().to_string())
+}
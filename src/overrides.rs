@@ -0,0 +1,64 @@
+use fxhash::FxHashMap;
+
+// Node id -> elevation map of a JSON file ({"<node id>": <elevation>, ...})
+fn read_json(content: &str) -> Result<FxHashMap<i64, f64>, String> {
+    let map: std::collections::BTreeMap<String, f64> =
+        serde_json::from_str(content).map_err(|e| e.to_string())?;
+    map.into_iter()
+        .map(|(id, elevation)| {
+            id.parse()
+                .map(|id| (id, elevation))
+                .map_err(|_| format!("Invalid node id {}", id))
+        })
+        .collect()
+}
+
+// Node id -> elevation map of a CSV file of `node_id,elevation` records,
+// with an optional header line
+fn read_csv(content: &str) -> Result<FxHashMap<i64, f64>, String> {
+    let mut overrides = FxHashMap::default();
+    let lines = content
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty());
+    for (i, line) in lines {
+        let record = line.split_once(',').and_then(|(id, elevation)| {
+            Some((id.trim().parse().ok()?, elevation.trim().parse().ok()?))
+        });
+        match record {
+            Some((id, elevation)) => {
+                overrides.insert(id, elevation);
+            }
+            // Header line
+            None if i == 0 => continue,
+            None => return Err(format!("Invalid record on line {}: {}", i + 1, line)),
+        }
+    }
+    Ok(overrides)
+}
+
+/// Elevation (in meters) of the value of an ele tag, e.g. "1234", "1234.5" or "1234 m"
+/// (None for other units or invalid values)
+pub fn parse_ele_tag(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let value = value.strip_suffix('m').unwrap_or(value).trim_end();
+    value
+        .parse()
+        .ok()
+        .filter(|elevation: &f64| elevation.is_finite())
+}
+
+/// Read a file of node id -> elevation overrides, as JSON (`.json` extension) or CSV
+pub fn read(path: &str) -> Result<FxHashMap<i64, f64>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read elevation overrides {}: {}", path, e))?;
+    let is_json = path.to_lowercase().ends_with(".json");
+    if is_json {
+        read_json(&content)
+    } else {
+        read_csv(&content)
+    }
+    .map_err(|e| format!("Invalid elevation overrides {}: {}", path, e))
+}
@@ -0,0 +1,778 @@
+use osmpbfreader::fileformat::{Blob, BlobHeader};
+use osmpbfreader::osmformat::{self, HeaderBlock, PrimitiveBlock};
+use protobuf::Message;
+use std::io::Read;
+
+// Optional feature of the header block of files produced by `osmium add-locations-to-ways`
+const LOCATIONS_ON_WAYS: &str = "LocationsOnWays";
+
+// Field numbers of the (packed, delta coded) node locations in a way
+const WAY_LAT_FIELD: u32 = 9;
+const WAY_LON_FIELD: u32 = 10;
+
+fn blob_data(blob: &Blob) -> Result<Vec<u8>, String> {
+    if blob.has_raw() {
+        Ok(blob.get_raw().to_vec())
+    } else if blob.has_zlib_data() {
+        let mut data = Vec::with_capacity(blob.get_raw_size() as usize);
+        flate2::read::ZlibDecoder::new(blob.get_zlib_data())
+            .read_to_end(&mut data)
+            .map_err(|_| "Unable to decompress PBF blob".to_string())?;
+        Ok(data)
+    } else {
+        Err("Unsupported PBF blob compression".into())
+    }
+}
+
+/// Read the header block of a PBF file, or describe why it can't be read
+pub fn try_read_header(path: &str) -> Result<HeaderBlock, String> {
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| format!("Unable to open OSM file {}: {}", path, e))?;
+    let mut size = [0u8; 4];
+    file.read_exact(&mut size)
+        .map_err(|_| "Unable to read PBF blob header size".to_string())?;
+    let mut buf = vec![0u8; u32::from_be_bytes(size) as usize];
+    file.read_exact(&mut buf)
+        .map_err(|_| "Unable to read PBF blob header".to_string())?;
+    let header =
+        BlobHeader::parse_from_bytes(&buf).map_err(|_| "Invalid PBF blob header".to_string())?;
+    if header.get_field_type() != "OSMHeader" {
+        return Err(format!("The first blob of {} is not an OSMHeader", path));
+    }
+    let mut buf = vec![0u8; header.get_datasize() as usize];
+    file.read_exact(&mut buf)
+        .map_err(|_| "Unable to read PBF blob".to_string())?;
+    let blob = Blob::parse_from_bytes(&buf).map_err(|_| "Invalid PBF blob".to_string())?;
+    HeaderBlock::parse_from_bytes(&blob_data(&blob)?)
+        .map_err(|_| "Invalid PBF header block".to_string())
+}
+
+/// Read the header block of a PBF file
+pub fn read_header(path: &str) -> HeaderBlock {
+    try_read_header(path).unwrap_or_else(|e| {
+        log::error!(
+            target: "read",
+            fields:serde = serde_json::json!({ "osm_file": path });
+            "{}", e
+        );
+        std::process::exit(1);
+    })
+}
+
+/// Whether the ways of the file carry the locations of their nodes
+pub fn has_locations_on_ways(path: &str) -> bool {
+    read_header(path)
+        .get_optional_features()
+        .iter()
+        .any(|feature| feature == LOCATIONS_ON_WAYS)
+}
+
+// Decode a packed, delta coded, repeated sint64 field of a way
+fn unpack_delta_sint64(way: &osmpbfreader::osmformat::Way, field: u32) -> Vec<i64> {
+    let mut values = Vec::with_capacity(way.get_refs().len());
+    let mut current = 0i64;
+    if let Some(unknown) = way.get_unknown_fields().get(field) {
+        for bytes in &unknown.length_delimited {
+            let mut varint = 0u64;
+            let mut shift = 0;
+            for byte in bytes {
+                varint |= ((byte & 0x7f) as u64) << shift;
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    let value = ((varint >> 1) as i64) ^ -((varint & 1) as i64);
+                    current += value;
+                    values.push(current);
+                    varint = 0;
+                    shift = 0;
+                }
+            }
+        }
+    }
+    values
+}
+
+fn way_locations(way: &osmpbfreader::osmformat::Way, block: &PrimitiveBlock) -> Vec<(f64, f64)> {
+    let granularity = block.get_granularity() as i64;
+    let lats = unpack_delta_sint64(way, WAY_LAT_FIELD);
+    let lons = unpack_delta_sint64(way, WAY_LON_FIELD);
+    lons.iter()
+        .zip(lats.iter())
+        .map(|(lon, lat)| {
+            (
+                (block.get_lon_offset() + granularity * lon) as f64 * 1e-9,
+                (block.get_lat_offset() + granularity * lat) as f64 * 1e-9,
+            )
+        })
+        .collect()
+}
+
+/// Call `f` with every way validating the predicate and the (lon, lat)
+/// locations of its nodes, for files with the LocationsOnWays feature.
+/// Ways without locations for all of their nodes are skipped.
+pub fn for_each_way_with_locations<P, F>(path: &str, mut pred: P, mut f: F)
+where
+    P: FnMut(&osmpbfreader::OsmObj) -> bool,
+    F: FnMut(osmpbfreader::Way, Vec<(f64, f64)>),
+{
+    let file =
+        std::fs::File::open(path).unwrap_or_else(|_| panic!("Unable to open OSM file {}", path));
+    let mut pbf = osmpbfreader::OsmPbfReader::new(file);
+    for block in pbf.primitive_blocks() {
+        let block = block.expect("Unable to read PBF block");
+        for group in block.get_primitivegroup() {
+            let raw_ways = group.get_ways().iter();
+            for (raw_way, way) in raw_ways.zip(osmpbfreader::groups::ways(group, &block)) {
+                let obj = osmpbfreader::OsmObj::Way(way);
+                if !pred(&obj) {
+                    continue;
+                }
+                let way = match obj {
+                    osmpbfreader::OsmObj::Way(way) => way,
+                    _ => unreachable!(),
+                };
+                let locations = way_locations(raw_way, &block);
+                if locations.len() == way.nodes.len() {
+                    f(way, locations);
+                }
+            }
+        }
+    }
+}
+
+/// Call `f` with each node and way of a PBF stream, in a single pass (the
+/// relations being skipped). The node locations embedded in the ways of the
+/// files with the LocationsOnWays feature are given as nodes before their way.
+pub fn read_objects<R: Read, F: FnMut(osmpbfreader::OsmObj)>(input: R, mut f: F) {
+    let mut pbf = osmpbfreader::OsmPbfReader::new(input);
+    for block in pbf.primitive_blocks() {
+        let block = block.expect("Unable to read PBF block");
+        for group in block.get_primitivegroup() {
+            osmpbfreader::groups::simple_nodes(group, &block)
+                .chain(osmpbfreader::groups::dense_nodes(group, &block))
+                .for_each(|node| f(osmpbfreader::OsmObj::Node(node)));
+            let raw_ways = group.get_ways().iter();
+            for (raw_way, way) in raw_ways.zip(osmpbfreader::groups::ways(group, &block)) {
+                let locations = way_locations(raw_way, &block);
+                if locations.len() == way.nodes.len() {
+                    for (node_id, (lon, lat)) in way.nodes.iter().zip(locations) {
+                        f(osmpbfreader::OsmObj::Node(osmpbfreader::Node {
+                            id: *node_id,
+                            tags: osmpbfreader::Tags::new(),
+                            decimicro_lat: (lat * 1e7).round() as i32,
+                            decimicro_lon: (lon * 1e7).round() as i32,
+                        }));
+                    }
+                }
+                f(osmpbfreader::OsmObj::Way(way));
+            }
+        }
+    }
+}
+
+// Decode the primitive blocks of a file on all the cores, calling `consume`
+// with the result of `decode` for each of them, in the order of the file
+fn for_each_block_parallel<T, D, C>(path: &str, decode: D, mut consume: C)
+where
+    T: Send,
+    D: Fn(&PrimitiveBlock) -> T + Sync,
+    C: FnMut(T),
+{
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let file =
+        std::fs::File::open(path).unwrap_or_else(|_| panic!("Unable to open OSM file {}", path));
+    let mut pbf = osmpbfreader::OsmPbfReader::new(std::io::BufReader::new(file));
+    let mut blobs = pbf.blobs();
+    loop {
+        // The blobs are read in batches, which bounds the memory used
+        let batch: Vec<Blob> = blobs
+            .by_ref()
+            .take(threads * 4)
+            .map(|blob| blob.expect("Unable to read PBF blob"))
+            .collect();
+        if batch.is_empty() {
+            break;
+        }
+        let results: Vec<T> = std::thread::scope(|scope| {
+            let handles: Vec<_> = batch
+                .chunks(batch.len().div_ceil(threads))
+                .map(|blobs| {
+                    scope.spawn(|| {
+                        blobs
+                            .iter()
+                            .map(|blob| {
+                                let block = osmpbfreader::primitive_block_from_blob(blob)
+                                    .expect("Unable to read PBF block");
+                                decode(&block)
+                            })
+                            .collect::<Vec<T>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("PBF decoding thread panicked"))
+                .collect()
+        });
+        results.into_iter().for_each(&mut consume);
+    }
+}
+
+// (id, decimicro lon, decimicro lat) of the nodes of a block whose id is needed,
+// without decoding their tags
+fn needed_nodes<N: Fn(i64) -> bool>(block: &PrimitiveBlock, needed: N) -> Vec<(i64, i32, i32)> {
+    let granularity = block.get_granularity() as i64;
+    let decimicro = |offset: i64, value: i64| ((offset + granularity * value) / 100) as i32;
+    let mut nodes = Vec::new();
+    for group in block.get_primitivegroup() {
+        for node in group.get_nodes() {
+            if needed(node.get_id()) {
+                nodes.push((
+                    node.get_id(),
+                    decimicro(block.get_lon_offset(), node.get_lon()),
+                    decimicro(block.get_lat_offset(), node.get_lat()),
+                ));
+            }
+        }
+        let dense = group.get_dense();
+        let (mut id, mut lat, mut lon) = (0, 0, 0);
+        for ((d_id, d_lat), d_lon) in dense
+            .get_id()
+            .iter()
+            .zip(dense.get_lat())
+            .zip(dense.get_lon())
+        {
+            id += d_id;
+            lat += d_lat;
+            lon += d_lon;
+            if needed(id) {
+                nodes.push((
+                    id,
+                    decimicro(block.get_lon_offset(), lon),
+                    decimicro(block.get_lat_offset(), lat),
+                ));
+            }
+        }
+    }
+    nodes
+}
+
+/// Same result as `get_objs_and_deps` for a predicate on ways only (the matching
+/// ways and their nodes, without their tags), decoding the blocks on all the cores:
+/// a first pass reads the ways, a second one only the locations of their nodes
+pub fn get_ways_and_deps_parallel<P>(
+    path: &str,
+    mut pred: P,
+) -> std::collections::BTreeMap<osmpbfreader::OsmId, osmpbfreader::OsmObj>
+where
+    P: FnMut(&osmpbfreader::OsmObj) -> bool,
+{
+    let mut objs = std::collections::BTreeMap::new();
+    let mut needed = fxhash::FxHashSet::default();
+    for_each_way(path, &mut pred, |way| {
+        needed.extend(way.nodes.iter().map(|node_id| node_id.0));
+        let obj = osmpbfreader::OsmObj::Way(way);
+        objs.insert(obj.id(), obj);
+    });
+    for_each_needed_node(
+        path,
+        |id| needed.contains(&id),
+        |node| {
+            objs.insert(node.id.into(), osmpbfreader::OsmObj::Node(node));
+        },
+    );
+    objs
+}
+
+/// Call `f` with every way of a file validating the predicate, the blocks being
+/// decoded on all the cores (the ways not being kept)
+pub fn for_each_way<P, F>(path: &str, mut pred: P, mut f: F)
+where
+    P: FnMut(&osmpbfreader::OsmObj) -> bool,
+    F: FnMut(osmpbfreader::Way),
+{
+    for_each_block_parallel(
+        path,
+        |block| {
+            block
+                .get_primitivegroup()
+                .iter()
+                .flat_map(|group| osmpbfreader::groups::ways(group, block))
+                .collect::<Vec<_>>()
+        },
+        |ways| {
+            for way in ways {
+                let obj = osmpbfreader::OsmObj::Way(way);
+                if pred(&obj) {
+                    if let osmpbfreader::OsmObj::Way(way) = obj {
+                        f(way);
+                    }
+                }
+            }
+        },
+    );
+}
+
+/// Call `f` with every node of a file whose id is needed (without its tags), the
+/// blocks being decoded on all the cores
+pub fn for_each_needed_node<N, F>(path: &str, needed: N, mut f: F)
+where
+    N: Fn(i64) -> bool + Sync,
+    F: FnMut(osmpbfreader::Node),
+{
+    for_each_block_parallel(
+        path,
+        |block| needed_nodes(block, &needed),
+        |nodes| {
+            for (id, decimicro_lon, decimicro_lat) in nodes {
+                f(osmpbfreader::Node {
+                    id: osmpbfreader::NodeId(id),
+                    tags: osmpbfreader::Tags::new(),
+                    decimicro_lat,
+                    decimicro_lon,
+                });
+            }
+        },
+    );
+}
+
+/// Ways of a file validating the predicate, read again block by block, so that
+/// only the ways of a block are in memory at once
+pub struct WayStream<P> {
+    pbf: osmpbfreader::OsmPbfReader<std::io::BufReader<std::fs::File>>,
+    pred: P,
+    // Matching ways of the last block read
+    ways: std::vec::IntoIter<osmpbfreader::Way>,
+}
+
+pub fn stream_ways<P>(path: &str, pred: P) -> WayStream<P>
+where
+    P: FnMut(&osmpbfreader::OsmObj) -> bool,
+{
+    let file =
+        std::fs::File::open(path).unwrap_or_else(|_| panic!("Unable to open OSM file {}", path));
+    WayStream {
+        pbf: osmpbfreader::OsmPbfReader::new(std::io::BufReader::new(file)),
+        pred,
+        ways: Vec::new().into_iter(),
+    }
+}
+
+impl<P> Iterator for WayStream<P>
+where
+    P: FnMut(&osmpbfreader::OsmObj) -> bool,
+{
+    type Item = osmpbfreader::Way;
+
+    fn next(&mut self) -> Option<osmpbfreader::Way> {
+        loop {
+            if let Some(way) = self.ways.next() {
+                return Some(way);
+            }
+            let block = self
+                .pbf
+                .primitive_blocks()
+                .next()?
+                .expect("Unable to read PBF block");
+            let mut ways = Vec::new();
+            for group in block.get_primitivegroup() {
+                for way in osmpbfreader::groups::ways(group, &block) {
+                    let obj = osmpbfreader::OsmObj::Way(way);
+                    if (self.pred)(&obj) {
+                        if let osmpbfreader::OsmObj::Way(way) = obj {
+                            ways.push(way);
+                        }
+                    }
+                }
+            }
+            self.ways = ways.into_iter();
+        }
+    }
+}
+
+/// Parse an ISO 8601 UTC instant (e.g. 2020-01-01T00:00:00Z, or a date)
+/// into seconds since the Unix epoch
+pub fn parse_timestamp(s: &str) -> Result<i64, String> {
+    let invalid = || {
+        format!(
+            "Invalid timestamp {} (expected e.g. 2020-01-01T00:00:00Z)",
+            s
+        )
+    };
+    let (date, time) = match s.split_once('T') {
+        Some((date, time)) => (date, time.strip_suffix('Z').ok_or_else(invalid)?),
+        None => (s, "00:00:00"),
+    };
+    let fields = |part: &str, separator| -> Result<[i64; 3], String> {
+        let values = part
+            .split(separator)
+            .map(|value| value.parse().map_err(|_| invalid()))
+            .collect::<Result<Vec<i64>, String>>()?;
+        values.try_into().map_err(|_| invalid())
+    };
+    let ([year, month, day], [hour, minute, second]) = (fields(date, '-')?, fields(time, ':')?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..24).contains(&hour)
+        || !(0..60).contains(&minute)
+        || !(0..=60).contains(&second)
+    {
+        return Err(invalid());
+    }
+    // Days since the epoch of the civil date (H. Hinnant's days_from_civil)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    Ok(days * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+// Timestamp (in seconds) of an object, from its block's date granularity
+fn seconds(timestamp: i64, block: &PrimitiveBlock) -> i64 {
+    timestamp * block.get_date_granularity() as i64 / 1000
+}
+
+// Keep the version of an object if it is the last one seen so far (None for a deletion)
+fn keep_latest<T>(
+    versions: &mut fxhash::FxHashMap<i64, (i32, Option<T>)>,
+    id: i64,
+    version: i32,
+    object: Option<T>,
+) {
+    match versions.get(&id) {
+        Some((latest, _)) if *latest > version => {}
+        _ => {
+            versions.insert(id, (version, object));
+        }
+    }
+}
+
+// Id, version and decimicro (lon, lat) of a node version, without location if deleted
+type NodeVersion = (i64, i32, Option<(i32, i32)>);
+
+// Versions of the nodes of a block whose id is in the set, created at or before the instant
+fn needed_node_versions(
+    block: &PrimitiveBlock,
+    needed: &fxhash::FxHashSet<i64>,
+    at: i64,
+) -> Vec<NodeVersion> {
+    let granularity = block.get_granularity() as i64;
+    let decimicro = |offset: i64, value: i64| ((offset + granularity * value) / 100) as i32;
+    let location = |lon, lat| {
+        (
+            decimicro(block.get_lon_offset(), lon),
+            decimicro(block.get_lat_offset(), lat),
+        )
+    };
+    let mut nodes = Vec::new();
+    for group in block.get_primitivegroup() {
+        for node in group.get_nodes() {
+            let info = node.get_info();
+            if needed.contains(&node.get_id()) && seconds(info.get_timestamp(), block) <= at {
+                let visible = !info.has_visible() || info.get_visible();
+                nodes.push((
+                    node.get_id(),
+                    info.get_version(),
+                    visible.then(|| location(node.get_lon(), node.get_lat())),
+                ));
+            }
+        }
+        let dense = group.get_dense();
+        let info = dense.get_denseinfo();
+        let (mut id, mut lat, mut lon, mut timestamp) = (0, 0, 0, 0);
+        for (i, ((d_id, d_lat), d_lon)) in dense
+            .get_id()
+            .iter()
+            .zip(dense.get_lat())
+            .zip(dense.get_lon())
+            .enumerate()
+        {
+            id += d_id;
+            lat += d_lat;
+            lon += d_lon;
+            timestamp += info.get_timestamp().get(i).copied().unwrap_or(0);
+            if needed.contains(&id) && seconds(timestamp, block) <= at {
+                let visible = info.get_visible().get(i).copied().unwrap_or(true);
+                nodes.push((
+                    id,
+                    info.get_version().get(i).copied().unwrap_or(0),
+                    visible.then(|| location(lon, lat)),
+                ));
+            }
+        }
+    }
+    nodes
+}
+
+/// Same result as `get_ways_and_deps_parallel`, for a full-history file: the ways
+/// and nodes are those of the versions valid at the instant (in seconds since the
+/// epoch), i.e. the last versions created at or before it, unless they were deleted
+pub fn get_ways_and_deps_at<P>(
+    path: &str,
+    at: i64,
+    mut pred: P,
+) -> std::collections::BTreeMap<osmpbfreader::OsmId, osmpbfreader::OsmObj>
+where
+    P: FnMut(&osmpbfreader::OsmObj) -> bool,
+{
+    let mut ways = fxhash::FxHashMap::default();
+    for_each_block_parallel(
+        path,
+        |block| {
+            block
+                .get_primitivegroup()
+                .iter()
+                .flat_map(|group| {
+                    group
+                        .get_ways()
+                        .iter()
+                        .zip(osmpbfreader::groups::ways(group, block))
+                })
+                .filter(|(raw_way, _)| seconds(raw_way.get_info().get_timestamp(), block) <= at)
+                .map(|(raw_way, way)| {
+                    let info = raw_way.get_info();
+                    let visible = !info.has_visible() || info.get_visible();
+                    (raw_way.get_id(), info.get_version(), visible.then_some(way))
+                })
+                .collect::<Vec<_>>()
+        },
+        |versions| {
+            for (id, version, way) in versions {
+                keep_latest(&mut ways, id, version, way);
+            }
+        },
+    );
+
+    let mut objs = std::collections::BTreeMap::new();
+    let mut needed = fxhash::FxHashSet::default();
+    for (_, (_, way)) in ways {
+        if let Some(way) = way {
+            let obj = osmpbfreader::OsmObj::Way(way);
+            if pred(&obj) {
+                if let osmpbfreader::OsmObj::Way(way) = &obj {
+                    needed.extend(way.nodes.iter().map(|node_id| node_id.0));
+                }
+                objs.insert(obj.id(), obj);
+            }
+        }
+    }
+
+    let mut nodes = fxhash::FxHashMap::default();
+    for_each_block_parallel(
+        path,
+        |block| needed_node_versions(block, &needed, at),
+        |versions| {
+            for (id, version, location) in versions {
+                keep_latest(&mut nodes, id, version, location);
+            }
+        },
+    );
+    for (id, (_, location)) in nodes {
+        if let Some((decimicro_lon, decimicro_lat)) = location {
+            let node = osmpbfreader::Node {
+                id: osmpbfreader::NodeId(id),
+                tags: osmpbfreader::Tags::new(),
+                decimicro_lat,
+                decimicro_lon,
+            };
+            objs.insert(node.id.into(), osmpbfreader::OsmObj::Node(node));
+        }
+    }
+    objs
+}
+
+// Maximum number of entities in a written primitive block
+const WRITER_BLOCK_SIZE: usize = 8000;
+
+// String table of a primitive block being written
+struct StringTable {
+    ids: fxhash::FxHashMap<String, u32>,
+    strings: Vec<Vec<u8>>,
+}
+
+impl StringTable {
+    fn new() -> StringTable {
+        // The first string of the table is always the empty string
+        StringTable {
+            ids: Default::default(),
+            strings: vec![Vec::new()],
+        }
+    }
+
+    fn id(&mut self, s: &str) -> u32 {
+        if let Some(id) = self.ids.get(s) {
+            return *id;
+        }
+        let id = self.strings.len() as u32;
+        self.strings.push(s.as_bytes().to_vec());
+        self.ids.insert(s.to_string(), id);
+        id
+    }
+
+    fn into_block(self, group: osmformat::PrimitiveGroup) -> PrimitiveBlock {
+        let mut block = PrimitiveBlock::new();
+        block.mut_stringtable().set_s(self.strings.into());
+        block.set_granularity(100);
+        block.mut_primitivegroup().push(group);
+        block
+    }
+}
+
+/// Minimal PBF writer, with dense nodes and zlib compressed blobs
+pub struct PbfWriter<W: std::io::Write> {
+    out: W,
+}
+
+impl<W: std::io::Write> PbfWriter<W> {
+    pub fn new(out: W) -> PbfWriter<W> {
+        let mut header = HeaderBlock::new();
+        header
+            .mut_required_features()
+            .push("OsmSchema-V0.6".to_string());
+        header
+            .mut_required_features()
+            .push("DenseNodes".to_string());
+        PbfWriter::with_header(out, header)
+    }
+
+    /// Writer of a file with the given header block
+    pub fn with_header(out: W, mut header: HeaderBlock) -> PbfWriter<W> {
+        let mut writer = PbfWriter { out };
+        header.set_writingprogram(format!("osm-ways-slope {}", env!("CARGO_PKG_VERSION")));
+        writer.write_blob("OSMHeader", header.write_to_bytes().unwrap());
+        writer
+    }
+
+    fn write_blob(&mut self, kind: &str, data: Vec<u8>) {
+        use std::io::Write;
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&data).unwrap();
+        let mut blob = Blob::new();
+        blob.set_raw_size(data.len() as i32);
+        blob.set_zlib_data(encoder.finish().unwrap());
+        let blob = blob.write_to_bytes().unwrap();
+
+        let mut header = BlobHeader::new();
+        header.set_field_type(kind.to_string());
+        header.set_datasize(blob.len() as i32);
+        let header = header.write_to_bytes().unwrap();
+
+        self.out
+            .write_all(&(header.len() as u32).to_be_bytes())
+            .and_then(|_| self.out.write_all(&header))
+            .and_then(|_| self.out.write_all(&blob))
+            .expect("Unable to write PBF blob");
+    }
+
+    /// Write the nodes, as dense nodes
+    pub fn write_nodes(&mut self, nodes: &[osmpbfreader::Node]) {
+        for chunk in nodes.chunks(WRITER_BLOCK_SIZE) {
+            let mut strings = StringTable::new();
+            let mut dense = osmformat::DenseNodes::new();
+            let (mut id, mut lat, mut lon) = (0i64, 0i64, 0i64);
+            for node in chunk {
+                dense.mut_id().push(node.id.0 - id);
+                dense.mut_lat().push(node.decimicro_lat as i64 - lat);
+                dense.mut_lon().push(node.decimicro_lon as i64 - lon);
+                id = node.id.0;
+                lat = node.decimicro_lat as i64;
+                lon = node.decimicro_lon as i64;
+                for (key, value) in node.tags.iter() {
+                    let key = strings.id(key) as i32;
+                    let value = strings.id(value) as i32;
+                    dense.mut_keys_vals().extend([key, value]);
+                }
+                dense.mut_keys_vals().push(0);
+            }
+            let mut group = osmformat::PrimitiveGroup::new();
+            group.set_dense(dense);
+            let block = strings.into_block(group);
+            self.write_blob("OSMData", block.write_to_bytes().unwrap());
+        }
+    }
+
+    /// Write the ways
+    pub fn write_ways(&mut self, ways: &[osmpbfreader::Way]) {
+        for chunk in ways.chunks(WRITER_BLOCK_SIZE) {
+            let mut strings = StringTable::new();
+            let mut group = osmformat::PrimitiveGroup::new();
+            for way in chunk {
+                let mut raw_way = osmformat::Way::new();
+                raw_way.set_id(way.id.0);
+                for (key, value) in way.tags.iter() {
+                    raw_way.mut_keys().push(strings.id(key));
+                    raw_way.mut_vals().push(strings.id(value));
+                }
+                let mut previous = 0i64;
+                for node in &way.nodes {
+                    raw_way.mut_refs().push(node.0 - previous);
+                    previous = node.0;
+                }
+                group.mut_ways().push(raw_way);
+            }
+            let block = strings.into_block(group);
+            self.write_blob("OSMData", block.write_to_bytes().unwrap());
+        }
+    }
+}
+
+// Add (or replace) tags on the ways of a block which are in the map
+fn add_way_tags(
+    block: &PrimitiveBlock,
+    tags: &fxhash::FxHashMap<i64, Vec<(String, String)>>,
+) -> PrimitiveBlock {
+    let mut block = block.clone();
+    let mut strings: Vec<Vec<u8>> = block.take_stringtable().take_s().into_vec();
+    let mut ids: fxhash::FxHashMap<Vec<u8>, u32> = strings
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.clone(), i as u32))
+        .collect();
+    for group in block.mut_primitivegroup().iter_mut() {
+        for way in group.mut_ways().iter_mut() {
+            let way_tags = match tags.get(&way.get_id()) {
+                Some(way_tags) => way_tags,
+                None => continue,
+            };
+            for (key, value) in way_tags {
+                let mut id = |s: &str| {
+                    *ids.entry(s.as_bytes().to_vec()).or_insert_with(|| {
+                        strings.push(s.as_bytes().to_vec());
+                        (strings.len() - 1) as u32
+                    })
+                };
+                let (key_id, value_id) = (id(key), id(value));
+                let existing = way
+                    .get_keys()
+                    .iter()
+                    .position(|k| strings[*k as usize] == key.as_bytes());
+                match existing {
+                    Some(i) => way.mut_vals()[i] = value_id,
+                    None => {
+                        way.mut_keys().push(key_id);
+                        way.mut_vals().push(value_id);
+                    }
+                }
+            }
+        }
+    }
+    block.mut_stringtable().set_s(strings.into());
+    block
+}
+
+/// Copy a PBF file, adding (or replacing) the given tags on its ways
+/// while keeping everything else (nodes, relations, metadata) as is
+pub fn copy_with_way_tags<W: std::io::Write>(
+    path: &str,
+    out: W,
+    tags: &fxhash::FxHashMap<i64, Vec<(String, String)>>,
+) {
+    let mut writer = PbfWriter::with_header(out, read_header(path));
+    for_each_block_parallel(
+        path,
+        |block| add_way_tags(block, tags).write_to_bytes().unwrap(),
+        |data| writer.write_blob("OSMData", data),
+    );
+}
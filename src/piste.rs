@@ -0,0 +1,68 @@
+use crate::gradient;
+use serde::{Deserialize, Serialize};
+
+// Maximum sustained grade (in %) of each piste:difficulty value, in increasing order
+const DIFFICULTIES: [(&str, f64); 5] = [
+    ("novice", 12.),
+    ("easy", 25.),
+    ("intermediate", 40.),
+    ("advanced", 60.),
+    ("expert", f64::INFINITY),
+];
+
+/// Ski piste specific metrics of a way
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PisteInfo {
+    // Net grade between the first and last nodes with a known elevation (absolute, in %)
+    avg_grade: f64,
+    // Steepest grade sustained over the measuring window (in %)
+    piste_max_grade: f64,
+    estimated_difficulty: String,
+    tagged_difficulty: Option<String>,
+    // Whether the tagged difficulty doesn't match the estimated one
+    difficulty_mismatch: bool,
+}
+
+// Difficulty matching the given maximum sustained grade
+fn estimate_difficulty(max_grade: f64) -> &'static str {
+    DIFFICULTIES
+        .iter()
+        .find(|(_, max)| max_grade <= *max)
+        .map(|(difficulty, _)| *difficulty)
+        .unwrap_or("expert")
+}
+
+/// Compute the slope statistics of a piste and compare them with its difficulty tag
+pub fn compute(
+    tags: &osmpbfreader::Tags,
+    distances: &[f64],
+    elevations: &[Option<f64>],
+    window: f64,
+) -> PisteInfo {
+    let avg_grade = gradient::net_gradient(distances, elevations)
+        .unwrap_or(0.)
+        .abs()
+        * 100.;
+    let max_grade = gradient::max_sustained_gradient(distances, elevations, window) * 100.;
+    let estimated_difficulty = estimate_difficulty(max_grade);
+    let tagged_difficulty = tags.get("piste:difficulty").map(|value| value.to_string());
+    // Other values (freeride, extreme, ...) are not rated on steepness alone
+    let difficulty_mismatch = match tagged_difficulty.as_deref() {
+        Some(tagged)
+            if DIFFICULTIES
+                .iter()
+                .any(|(difficulty, _)| *difficulty == tagged) =>
+        {
+            tagged != estimated_difficulty
+        }
+        _ => false,
+    };
+
+    PisteInfo {
+        avg_grade,
+        piste_max_grade: max_grade,
+        estimated_difficulty: estimated_difficulty.to_string(),
+        tagged_difficulty,
+        difficulty_mismatch,
+    }
+}
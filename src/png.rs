@@ -0,0 +1,110 @@
+use std::io::Read;
+
+// PNG file signature
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+// Value of the Paeth predictor, the neighbour closest to a + b - c
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Width, height and (row by row) RGB pixels of an 8-bit RGB or RGBA,
+/// non-interlaced, PNG image (the alpha channel being ignored)
+pub fn decode_rgb(data: &[u8]) -> Result<(usize, usize, Vec<[u8; 3]>), String> {
+    if data.get(0..8) != Some(&SIGNATURE[..]) {
+        return Err("Not a PNG file".into());
+    }
+    let mut header = None;
+    let mut compressed = Vec::new();
+    let mut position = 8;
+    while position + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[position..position + 4].try_into().unwrap()) as usize;
+        let kind = &data[position + 4..position + 8];
+        let content = data
+            .get(position + 8..position + 8 + length)
+            .ok_or("Truncated PNG file")?;
+        match kind {
+            b"IHDR" if length >= 13 => {
+                let dimension =
+                    |i: usize| u32::from_be_bytes(content[i..i + 4].try_into().unwrap()) as usize;
+                // Width, height, bit depth, color type and interlace method
+                header = Some((
+                    dimension(0),
+                    dimension(4),
+                    content[8],
+                    content[9],
+                    content[12],
+                ));
+            }
+            b"IDAT" => compressed.extend_from_slice(content),
+            b"IEND" => break,
+            _ => {}
+        }
+        // Length, type, content and CRC
+        position += 12 + length;
+    }
+    let (width, height, bit_depth, color_type, interlace) = header.ok_or("Missing PNG header")?;
+    let channels = match (bit_depth, color_type, interlace) {
+        (8, 2, 0) => 3,
+        (8, 6, 0) => 4,
+        _ => {
+            return Err(format!(
+                "Unsupported PNG image (bit depth {}, color type {}, interlace {}): only \
+                 8-bit RGB(A) non-interlaced images are read",
+                bit_depth, color_type, interlace
+            ))
+        }
+    };
+
+    let mut raw = Vec::new();
+    flate2::read::ZlibDecoder::new(&compressed[..])
+        .read_to_end(&mut raw)
+        .map_err(|e| e.to_string())?;
+    let row_len = width * channels;
+    if raw.len() < height * (row_len + 1) {
+        return Err("Truncated PNG image data".into());
+    }
+    // Each row starts with the type of the filter applied to its bytes
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut previous = vec![0u8; row_len];
+    let mut row = vec![0u8; row_len];
+    for filtered in raw.chunks_exact(row_len + 1).take(height) {
+        let filter = filtered[0];
+        for i in 0..row_len {
+            let left = if i >= channels { row[i - channels] } else { 0 };
+            let up = previous[i];
+            let up_left = if i >= channels {
+                previous[i - channels]
+            } else {
+                0
+            };
+            let predictor = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                filter => return Err(format!("Invalid PNG filter {}", filter)),
+            };
+            row[i] = filtered[i + 1].wrapping_add(predictor);
+        }
+        pixels.extend(
+            row.chunks_exact(channels)
+                .map(|pixel| [pixel[0], pixel[1], pixel[2]]),
+        );
+        std::mem::swap(&mut previous, &mut row);
+    }
+    Ok((width, height, pixels))
+}
@@ -0,0 +1,258 @@
+use crate::output::{OutputWriter, Record};
+use crate::reproject::Reprojection;
+use serde_json::json;
+use std::io::Write;
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::thread::JoinHandle;
+
+// Table used when the connection URL has no `table` parameter
+const DEFAULT_TABLE: &str = "way_slopes";
+
+// Columns of the table, after way_id and before the geometry
+const COLUMNS: [(&str, &str); 8] = [
+    ("distance", "double precision"),
+    ("climb_distance", "double precision"),
+    ("descent_distance", "double precision"),
+    ("climb", "double precision"),
+    ("descent", "double precision"),
+    ("climb_per_km", "double precision"),
+    ("descent_per_km", "double precision"),
+    ("is_closed", "boolean"),
+];
+
+/// Whether an output specification is a PostGIS connection URL
+pub fn is_url(spec: &str) -> bool {
+    spec.starts_with("postgis://")
+}
+
+// Number of rows waiting to be sent to the database
+const ROW_QUEUE: usize = 4096;
+
+// Split a `postgis://…?table=…` URL into the PostgreSQL connection URL and the table name
+fn parse_url(url: &str) -> (String, String) {
+    let url = url.replacen("postgis://", "postgresql://", 1);
+    let (base, query) = match url.split_once('?') {
+        Some((base, query)) => (base.to_string(), query),
+        None => (url.clone(), ""),
+    };
+    let mut table = DEFAULT_TABLE.to_string();
+    let mut params = Vec::new();
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        match param.strip_prefix("table=") {
+            Some(name) => table = name.to_string(),
+            None => params.push(param),
+        }
+    }
+    if params.is_empty() {
+        (base, table)
+    } else {
+        (format!("{}?{}", base, params.join("&")), table)
+    }
+}
+
+// Quote a (possibly schema-qualified) table name
+fn quote_table(table: &str) -> String {
+    table
+        .split('.')
+        .map(|part| format!("\"{}\"", part.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// Message of a database error, with its cause (e.g. the error reported by the server)
+fn error_message(e: postgres::Error) -> String {
+    match std::error::Error::source(&e) {
+        Some(source) => format!("{}: {}", e, source),
+        None => e.to_string(),
+    }
+}
+
+// Connection to the database, with TLS if the server supports it (its certificate
+// being verified against the Mozilla root certificates), and the password of
+// PGPASSWORD if the URL has none
+fn connect(url: &str) -> Result<postgres::Client, String> {
+    let mut config: postgres::Config = url
+        .parse()
+        .map_err(|e| format!("Invalid PostGIS connection URL: {}", e))?;
+    if config.get_password().is_none() {
+        if let Ok(password) = std::env::var("PGPASSWORD") {
+            config.password(password);
+        }
+    }
+    let roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+    };
+    let tls = rustls::ClientConfig::builder_with_provider(
+        rustls::crypto::ring::default_provider().into(),
+    )
+    .with_safe_default_protocol_versions()
+    .map_err(|e| format!("Unable to connect to PostGIS: {}", e))?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    config
+        .connect(tokio_postgres_rustls::MakeRustlsConnect::new(tls))
+        .map_err(|e| format!("Unable to connect to PostGIS: {}", error_message(e)))
+}
+
+// Statements of the load of the rows in a transaction
+struct Load {
+    // Creating the table if needed, and the temporary table the rows are copied to
+    setup: String,
+    copy: String,
+    // Merging the copied rows into the table
+    merge: String,
+}
+
+// Load the rows received until the channel is closed, in a single transaction
+fn load(mut client: postgres::Client, load: Load, rows: Receiver<String>) -> Result<(), String> {
+    let mut transaction = client.transaction().map_err(error_message)?;
+    transaction
+        .batch_execute(&load.setup)
+        .map_err(error_message)?;
+    let mut copy = transaction.copy_in(&load.copy).map_err(error_message)?;
+    for row in rows {
+        copy.write_all(row.as_bytes()).map_err(|e| e.to_string())?;
+    }
+    copy.finish().map_err(error_message)?;
+    transaction
+        .batch_execute(&load.merge)
+        .map_err(error_message)?;
+    transaction.commit().map_err(error_message)
+}
+
+/// Table of a PostGIS database, created if needed, in which the ways are upserted
+/// (on way_id) in a single transaction: the rows are streamed with COPY in a
+/// temporary table, merged into the table once all the ways are written
+pub struct PostgisWriter<'a> {
+    table: String,
+    reprojection: Option<&'a Reprojection>,
+    // Prefix of the EWKT geometries
+    srid_prefix: String,
+    // Rows sent to the thread loading them, and this thread
+    rows: Option<SyncSender<String>>,
+    loader: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl<'a> PostgisWriter<'a> {
+    pub fn create(
+        url: &str,
+        reprojection: Option<&'a Reprojection>,
+    ) -> Result<PostgisWriter<'a>, String> {
+        let (connection, table) = parse_url(url);
+        let srid = match reprojection {
+            Some(reprojection) => reprojection.epsg_code().unwrap_or(0),
+            None => 4326,
+        };
+        let client = connect(&connection)?;
+
+        let quoted = quote_table(&table);
+        let names: Vec<&str> = std::iter::once("way_id")
+            .chain(COLUMNS.iter().map(|(name, _)| *name))
+            .chain(std::iter::once("geom"))
+            .collect();
+        let columns: Vec<String> = COLUMNS
+            .iter()
+            .map(|(name, column_type)| format!("{} {}", name, column_type))
+            .collect();
+        let updates: Vec<String> = names[1..]
+            .iter()
+            .map(|name| format!("{} = EXCLUDED.{}", name, name))
+            .collect();
+        let geometry_type = if srid > 0 {
+            format!("geometry(LineString, {})", srid)
+        } else {
+            "geometry(LineString)".to_string()
+        };
+        let statements = Load {
+            setup: format!(
+                "SET LOCAL client_min_messages = warning;\n\
+                 CREATE TABLE IF NOT EXISTS {table} (way_id bigint PRIMARY KEY, {columns}, geom {geometry_type});\n\
+                 CREATE TEMPORARY TABLE way_slopes_load (LIKE {table}) ON COMMIT DROP;",
+                table = quoted,
+                columns = columns.join(", "),
+                geometry_type = geometry_type,
+            ),
+            copy: format!(
+                "COPY way_slopes_load ({}) FROM STDIN",
+                names.join(", ")
+            ),
+            merge: format!(
+                "INSERT INTO {table} ({names}) SELECT {names} FROM way_slopes_load \
+                 ON CONFLICT (way_id) DO UPDATE SET {updates};",
+                table = quoted,
+                names = names.join(", "),
+                updates = updates.join(", ")
+            ),
+        };
+        let (rows, received) = std::sync::mpsc::sync_channel(ROW_QUEUE);
+        let loader = std::thread::spawn(move || load(client, statements, received));
+        Ok(PostgisWriter {
+            table,
+            reprojection,
+            srid_prefix: if srid > 0 {
+                format!("SRID={};", srid)
+            } else {
+                String::new()
+            },
+            rows: Some(rows),
+            loader: Some(loader),
+        })
+    }
+
+    // Wait for the end of the load, reporting its error if it failed
+    fn finish_load(&mut self) {
+        // Closing the channel ends the copy, and commits the transaction
+        self.rows = None;
+        let result = self.loader.take().map_or(Ok(()), |loader| {
+            loader
+                .join()
+                .unwrap_or_else(|_| Err("The load of the rows failed".to_string()))
+        });
+        if let Err(e) = result {
+            log::error!(
+                target: "output",
+                fields:serde = json!({ "table": self.table });
+                "Unable to load the ways into PostGIS: {}", e
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+impl OutputWriter for PostgisWriter<'_> {
+    fn write(&mut self, record: &Record) {
+        let coordinates =
+            crate::reproject::output_coordinates(self.reprojection, record.geometry, &self.table);
+        let points: Vec<String> = coordinates
+            .iter()
+            .map(|[x, y]| format!("{} {}", x, y))
+            .collect();
+        let way = record.info;
+        let row = format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}LINESTRING({})\n",
+            record.way_id,
+            way.distance,
+            way.climb_distance,
+            way.descent_distance,
+            way.climb,
+            way.descent,
+            way.climb_per_km,
+            way.descent_per_km,
+            if way.is_closed { "t" } else { "f" },
+            self.srid_prefix,
+            points.join(",")
+        );
+        // The loader having stopped on an error, it is reported
+        let sent = self
+            .rows
+            .as_ref()
+            .is_some_and(|rows| rows.send(row).is_ok());
+        if !sent {
+            self.finish_load();
+        }
+    }
+
+    fn finish(mut self: Box<Self>) {
+        self.finish_load();
+    }
+}
@@ -0,0 +1,252 @@
+use crate::elevation::Dem;
+use crate::reproject::Reprojection;
+use crate::{car, cycling, mtb, piste, rail, truck, waterway, wheelchair, winter};
+use crate::{Args, Filter, WayInfo};
+use clap::ValueEnum;
+use osm_ways_slope::gradient::ClimbDescent;
+
+/// Set of defaults and additional metrics tailored to a kind of network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Profile {
+    // Roads (highway=*) with the base metrics only
+    Default,
+    // Railway lines (railway=*), with gradients in per-mille and ruling gradient checks
+    Rail,
+    // Rivers and streams, with downstream direction, drop per km and steep reaches
+    Waterway,
+    // Downhill ski pistes, with slope statistics compared against piste:difficulty
+    Piste,
+    // Mountain bike trails, with an estimated mtb:scale:uphill value
+    Mtb,
+    // Footways, crossings and sidewalks, evaluated against wheelchair running slope thresholds
+    Wheelchair,
+    // Roads, with the sections exceeding heavy vehicle grade thresholds
+    Truck,
+    // Roads, with the travel time penalty of motor vehicles caused by climbs
+    Car,
+    // Roads, with the travel time and energy of a cyclist
+    Cycling,
+    // Roads, scored for winter-maintenance priority
+    Winter,
+}
+
+impl Profile {
+    /// Filters used when none are given on the command line
+    pub fn default_filters(self) -> Vec<Filter> {
+        match self {
+            Profile::Default
+            | Profile::Truck
+            | Profile::Car
+            | Profile::Cycling
+            | Profile::Winter => {
+                vec![Filter::Key("highway".to_string())]
+            }
+            Profile::Rail => vec![Filter::Key("railway".to_string())],
+            Profile::Waterway => vec![
+                Filter::KeyValue("waterway".to_string(), "river".to_string()),
+                Filter::KeyValue("waterway".to_string(), "stream".to_string()),
+            ],
+            Profile::Piste => vec![Filter::KeyValue(
+                "piste:type".to_string(),
+                "downhill".to_string(),
+            )],
+            Profile::Mtb => vec![
+                Filter::Key("mtb:scale".to_string()),
+                Filter::KeyValue("highway".to_string(), "path".to_string()),
+            ],
+            Profile::Wheelchair => vec![
+                Filter::KeyValue("highway".to_string(), "footway".to_string()),
+                Filter::KeyValue("highway".to_string(), "pedestrian".to_string()),
+                Filter::Key("footway".to_string()),
+                Filter::Key("crossing".to_string()),
+                Filter::Key("sidewalk".to_string()),
+            ],
+        }
+    }
+
+    // Options (by their argument id) only used with the profile
+    fn options(self) -> &'static [&'static str] {
+        match self {
+            Profile::Default => &[],
+            Profile::Rail => &["ruling_gradient"],
+            Profile::Waterway => &["steep_reach"],
+            Profile::Piste => &["piste_window"],
+            Profile::Mtb => &["mtb_window"],
+            Profile::Wheelchair => &["wheelchair_max_slope", "wheelchair_limit_slope"],
+            Profile::Truck => &["truck_thresholds", "truck_min_length", "truck_layer"],
+            Profile::Car => &["vehicle_mass", "vehicle_power"],
+            Profile::Cycling => &[
+                "cycling_mass",
+                "cycling_rolling_resistance",
+                "cycling_cda",
+                "cycling_power",
+                "cycling_max_speed",
+            ],
+            Profile::Winter => &["winter_ranking", "winter_layer"],
+        }
+    }
+
+    /// Check that no option of another profile is given on the command line, as
+    /// it would be ignored
+    pub fn check_options(self, matches: &clap::ArgMatches) -> Result<(), String> {
+        for profile in Profile::value_variants().iter().filter(|&&p| p != self) {
+            for id in profile.options() {
+                if matches.value_source(id) == Some(clap::parser::ValueSource::CommandLine) {
+                    return Err(format!(
+                        "--{} is only used with --profile {}",
+                        id.replace('_', "-"),
+                        profile.to_possible_value().unwrap().get_name()
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Way whose metrics of the profile are computed
+pub struct ProfileWay<'a> {
+    pub id: i64,
+    pub tags: &'a osmpbfreader::Tags,
+    pub coordinates: &'a [[f64; 2]],
+    pub distances: &'a [f64],
+    pub elevations: &'a [Option<f64>],
+    pub totals: &'a ClimbDescent,
+}
+
+/// Additional metrics of the profile of a run, and the features of its optional
+/// layers, collected over the ways
+pub struct ProfileMetrics<'a> {
+    args: &'a Args,
+    // Read for the terrain slope of the winter profile
+    dem: Option<&'a Dem>,
+    vehicle: car::Vehicle,
+    cyclist: cycling::Cyclist,
+    truck_features: Vec<serde_json::Value>,
+    winter_ways: Vec<winter::RankedWay>,
+}
+
+impl<'a> ProfileMetrics<'a> {
+    pub fn new(args: &'a Args, dem: Option<&'a Dem>) -> ProfileMetrics<'a> {
+        ProfileMetrics {
+            args,
+            dem,
+            vehicle: car::Vehicle {
+                mass: args.vehicle_mass,
+                power: args.vehicle_power * 1000.,
+            },
+            cyclist: cycling::Cyclist {
+                mass: args.cycling_mass,
+                rolling_resistance: args.cycling_rolling_resistance,
+                drag_area: args.cycling_cda,
+                power: args.cycling_power,
+                max_speed: args.cycling_max_speed / 3.6,
+            },
+            truck_features: Vec::new(),
+            winter_ways: Vec::new(),
+        }
+    }
+
+    /// Set the metrics of the profile on the info of a way
+    pub fn compute(&mut self, way: &ProfileWay, info: &mut WayInfo) {
+        let args = self.args;
+        let (distances, elevations) = (way.distances, way.elevations);
+        match args.profile {
+            Profile::Default => {}
+            Profile::Rail => {
+                info.rail = Some(rail::compute(distances, elevations, args.ruling_gradient))
+            }
+            Profile::Waterway => {
+                info.waterway = Some(waterway::compute(distances, elevations, args.steep_reach))
+            }
+            Profile::Piste => {
+                info.piste = Some(piste::compute(
+                    way.tags,
+                    distances,
+                    elevations,
+                    args.piste_window,
+                ))
+            }
+            Profile::Mtb => {
+                info.mtb = Some(mtb::compute(
+                    way.tags,
+                    distances,
+                    elevations,
+                    way.totals,
+                    args.mtb_window,
+                ))
+            }
+            Profile::Wheelchair => {
+                info.wheelchair = Some(wheelchair::compute(
+                    way.tags,
+                    distances,
+                    elevations,
+                    args.wheelchair_max_slope,
+                    args.wheelchair_limit_slope,
+                ))
+            }
+            Profile::Truck => info.truck = Some(self.truck(way)),
+            Profile::Car => {
+                info.car = Some(car::compute(way.tags, distances, elevations, &self.vehicle))
+            }
+            Profile::Cycling => {
+                info.cycling = Some(cycling::compute(distances, elevations, &self.cyclist))
+            }
+            Profile::Winter => info.winter = Some(self.winter(way)),
+        }
+    }
+
+    // Sections exceeding the truck thresholds, kept for --truck-layer
+    fn truck(&mut self, way: &ProfileWay) -> truck::TruckInfo {
+        let info = truck::compute(
+            way.distances,
+            way.elevations,
+            &self.args.truck_thresholds,
+            self.args.truck_min_length,
+        );
+        if self.args.truck_layer.is_some() {
+            self.truck_features.extend(truck::layer_features(
+                way.id,
+                &info,
+                way.coordinates,
+                way.distances,
+            ));
+        }
+        info
+    }
+
+    // Winter-maintenance score, the way being kept for --winter-ranking and
+    // --winter-layer
+    fn winter(&mut self, way: &ProfileWay) -> winter::WinterInfo {
+        let info = winter::compute(
+            way.tags,
+            way.coordinates,
+            way.distances,
+            way.elevations,
+            self.dem.expect("The winter profile requires a DEM"),
+        );
+        if self.args.winter_ranking.is_some() || self.args.winter_layer.is_some() {
+            self.winter_ways.push(winter::RankedWay {
+                way_id: way.id,
+                name: way.tags.get("name").map(|name| name.to_string()),
+                highway: way.tags.get("highway").map(|highway| highway.to_string()),
+                info: info.clone(),
+                coordinates: way.coordinates.to_vec(),
+            });
+        }
+        info
+    }
+
+    /// Write the optional layers of the profile
+    pub fn write_layers(mut self, reprojection: Option<&Reprojection>) {
+        if let Some(truck_layer) = &self.args.truck_layer {
+            truck::write_layer(truck_layer, self.truck_features, reprojection);
+        }
+        if let Some(winter_ranking) = &self.args.winter_ranking {
+            winter::write_ranking(winter_ranking, &mut self.winter_ways);
+        }
+        if let Some(winter_layer) = &self.args.winter_layer {
+            winter::write_layer(winter_layer, &mut self.winter_ways, reprojection);
+        }
+    }
+}
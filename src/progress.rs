@@ -0,0 +1,62 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn init(quiet: bool) {
+    QUIET.store(quiet, Ordering::SeqCst);
+}
+
+/// Progress of a phase of the run, drawn on a line of stderr once started: the
+/// number of items done and their rate, with a bar and the remaining time when
+/// the total is known. Nothing is drawn with --quiet, with JSON logs, or when
+/// stderr isn't a terminal.
+pub struct Progress {
+    label: &'static str,
+    bar: ProgressBar,
+}
+
+impl Progress {
+    pub fn new(label: &'static str) -> Progress {
+        Progress {
+            label,
+            bar: ProgressBar::hidden(),
+        }
+    }
+
+    /// Start drawing the line, counting the items from now on
+    pub fn start(&self, total: Option<u64>) {
+        if QUIET.load(Ordering::SeqCst) || crate::logger::is_json() {
+            return;
+        }
+        let template = match total {
+            Some(total) => {
+                self.bar.set_length(total);
+                "{prefix}: [{bar:30}] {percent}% {pos}/{len} ({rate}, ETA {eta})"
+            }
+            None => "{prefix}: {pos} ({rate}, {elapsed})",
+        };
+        self.bar.set_style(
+            ProgressStyle::with_template(template)
+                .expect("Invalid progress template")
+                .with_key("rate", |state: &ProgressState, w: &mut dyn Write| {
+                    write!(w, "{:.0}/s", state.per_sec()).ok();
+                })
+                .progress_chars("# "),
+        );
+        self.bar.set_prefix(self.label);
+        self.bar.reset();
+        // Hidden when stderr isn't a terminal
+        self.bar.set_draw_target(ProgressDrawTarget::stderr());
+    }
+
+    pub fn inc(&self, count: u64) {
+        self.bar.inc(count);
+    }
+
+    /// Stop drawing and erase the line, for the messages following the phase
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
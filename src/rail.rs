@@ -0,0 +1,40 @@
+use crate::gradient;
+use serde::{Deserialize, Serialize};
+
+/// Portion of a way steeper than the ruling gradient
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SteepSection {
+    // Distance from the start of the way, in meters
+    start: f64,
+    end: f64,
+    gradient_permille: f64,
+}
+
+/// Railway specific metrics of a way
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RailInfo {
+    // Net gradient between the first and last nodes with a known elevation
+    gradient_permille: f64,
+    // Gradient of the steepest segment (signed, in the direction of the way)
+    max_gradient_permille: f64,
+    steep_sections: Vec<SteepSection>,
+}
+
+/// Compute the gradients of a way given the cumulative distances (in meters)
+/// and the elevations of its nodes
+pub fn compute(distances: &[f64], elevations: &[Option<f64>], ruling_gradient: f64) -> RailInfo {
+    let steep_sections = gradient::steep_sections(distances, elevations, ruling_gradient)
+        .into_iter()
+        .map(|section| SteepSection {
+            start: section.start,
+            end: section.end,
+            gradient_permille: section.gradient,
+        })
+        .collect();
+
+    RailInfo {
+        gradient_permille: gradient::net_gradient(distances, elevations).unwrap_or(0.) * 1000.,
+        max_gradient_permille: gradient::max_segment_gradient(distances, elevations),
+        steep_sections,
+    }
+}
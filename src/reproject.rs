@@ -0,0 +1,232 @@
+#[cfg(feature = "gdal")]
+use gdal::spatial_ref::{CoordTransform, SpatialRef};
+use serde_json::{json, Value};
+#[cfg(not(feature = "gdal"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Whether the clamping of the latitudes to the limits of Web Mercator was logged
+#[cfg(not(feature = "gdal"))]
+static CLAMPED: AtomicBool = AtomicBool::new(false);
+
+/// Reprojection of the geometries of the GeoJSON outputs (or of the locations read
+/// on a projected DEM) from WGS84 to another CRS, or back (only to and from Web
+/// Mercator when built without the `gdal` feature)
+pub struct Reprojection {
+    #[cfg(feature = "gdal")]
+    transform: CoordTransform,
+    // From the CRS to WGS84
+    #[cfg(not(feature = "gdal"))]
+    inverse: bool,
+    crs: String,
+}
+
+// Collect the positions ([x, y, ...] arrays) of GeoJSON coordinates
+fn positions<'a>(coordinates: &'a mut Value, result: &mut Vec<&'a mut Vec<Value>>) {
+    if let Value::Array(values) = coordinates {
+        if values.first().is_some_and(Value::is_number) {
+            result.push(values);
+        } else {
+            for value in values {
+                positions(value, result);
+            }
+        }
+    }
+}
+
+impl Reprojection {
+    // WGS84 and the given CRS
+    #[cfg(feature = "gdal")]
+    fn spatial_refs(crs: &str) -> Result<(SpatialRef, SpatialRef), String> {
+        let wgs84 = SpatialRef::from_epsg(4326).map_err(|e| e.to_string())?;
+        let other =
+            SpatialRef::from_definition(crs).map_err(|e| format!("Invalid CRS {}: {}", crs, e))?;
+        // Keep the (lon, lat) / (x, y) order whatever the order of the axes of the CRS
+        wgs84.set_axis_mapping_strategy(
+            gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER,
+        );
+        other.set_axis_mapping_strategy(
+            gdal_sys::OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER,
+        );
+        Ok((wgs84, other))
+    }
+
+    /// Reprojection to the given CRS, e.g. "EPSG:3857"
+    #[cfg(feature = "gdal")]
+    pub fn new(crs: &str) -> Result<Reprojection, String> {
+        let (wgs84, target) = Reprojection::spatial_refs(crs)?;
+        let transform = CoordTransform::new(&wgs84, &target).map_err(|e| e.to_string())?;
+        Ok(Reprojection {
+            transform,
+            crs: crs.to_string(),
+        })
+    }
+
+    /// Reprojection from the given CRS to WGS84
+    #[cfg(feature = "gdal")]
+    pub fn inverse(crs: &str) -> Result<Reprojection, String> {
+        let (wgs84, source) = Reprojection::spatial_refs(crs)?;
+        let transform = CoordTransform::new(&source, &wgs84).map_err(|e| e.to_string())?;
+        Ok(Reprojection {
+            transform,
+            crs: "EPSG:4326".to_string(),
+        })
+    }
+
+    #[cfg(not(feature = "gdal"))]
+    fn web_mercator(crs: &str, inverse: bool) -> Result<Reprojection, String> {
+        match crs.to_uppercase().as_str() {
+            "EPSG:3857" | "EPSG:900913" => Ok(Reprojection {
+                inverse,
+                crs: if inverse { "EPSG:4326" } else { crs }.to_string(),
+            }),
+            _ => Err(format!(
+                "Unsupported CRS {} (only EPSG:3857 is available without GDAL)",
+                crs
+            )),
+        }
+    }
+
+    #[cfg(not(feature = "gdal"))]
+    pub fn new(crs: &str) -> Result<Reprojection, String> {
+        Reprojection::web_mercator(crs, false)
+    }
+
+    #[cfg(not(feature = "gdal"))]
+    pub fn inverse(crs: &str) -> Result<Reprojection, String> {
+        Reprojection::web_mercator(crs, true)
+    }
+
+    #[cfg(feature = "gdal")]
+    fn transform_coords(&self, xs: &mut [f64], ys: &mut [f64]) -> Result<(), String> {
+        let mut zs = vec![0.; xs.len()];
+        self.transform
+            .transform_coords(xs, ys, &mut zs)
+            .map_err(|e| e.to_string())
+    }
+
+    // Spherical Web Mercator, the latitudes beyond its limits being clamped to them
+    #[cfg(not(feature = "gdal"))]
+    fn transform_coords(&self, xs: &mut [f64], ys: &mut [f64]) -> Result<(), String> {
+        const RADIUS: f64 = 6_378_137.;
+        const MAX_LATITUDE: f64 = 85.06;
+        if !self.inverse
+            && ys.iter().any(|y| y.abs() > MAX_LATITUDE)
+            && !CLAMPED.swap(true, Ordering::Relaxed)
+        {
+            log::warn!(
+                target: "setup",
+                fields:serde = json!({ "crs": self.crs, "max_latitude": MAX_LATITUDE });
+                "Some points are beyond the latitudes of Web Mercator, their latitude is clamped to ±{}°", MAX_LATITUDE
+            );
+        }
+        for (x, y) in xs.iter_mut().zip(ys.iter_mut()) {
+            if self.inverse {
+                *x = (*x / RADIUS).to_degrees();
+                *y = (2. * (*y / RADIUS).exp().atan() - std::f64::consts::FRAC_PI_2).to_degrees();
+                continue;
+            }
+            *x = x.to_radians() * RADIUS;
+            *y = (std::f64::consts::FRAC_PI_4
+                + y.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians() / 2.)
+                .tan()
+                .ln()
+                * RADIUS;
+        }
+        Ok(())
+    }
+
+    /// Definition of the target CRS
+    pub fn crs(&self) -> &str {
+        &self.crs
+    }
+
+    /// EPSG code of the target CRS, when it is given as "EPSG:<code>"
+    pub fn epsg_code(&self) -> Option<i32> {
+        self.crs.to_uppercase().strip_prefix("EPSG:")?.parse().ok()
+    }
+
+    /// Reproject [lon, lat] coordinates
+    pub fn reproject(&self, coordinates: &[[f64; 2]]) -> Result<Vec<[f64; 2]>, String> {
+        let mut xs: Vec<f64> = coordinates.iter().map(|[x, _]| *x).collect();
+        let mut ys: Vec<f64> = coordinates.iter().map(|[_, y]| *y).collect();
+        self.transform_coords(&mut xs, &mut ys)?;
+        Ok(xs.into_iter().zip(ys).map(|(x, y)| [x, y]).collect())
+    }
+
+    /// Reproject the geometries of a GeoJSON FeatureCollection, and name its CRS
+    /// (with the "crs" member of the 2008 GeoJSON specification)
+    pub fn apply(&self, collection: &mut Value) -> Result<(), String> {
+        let features = collection["features"].as_array_mut();
+        for feature in features.into_iter().flatten() {
+            let mut geometry_positions = Vec::new();
+            positions(
+                &mut feature["geometry"]["coordinates"],
+                &mut geometry_positions,
+            );
+            let coordinate = |position: &Vec<Value>, index: usize| {
+                position
+                    .get(index)
+                    .and_then(Value::as_f64)
+                    .ok_or_else(|| format!("Invalid position {:?}", position))
+            };
+            let mut xs = geometry_positions
+                .iter()
+                .map(|p| coordinate(p, 0))
+                .collect::<Result<Vec<f64>, String>>()?;
+            let mut ys = geometry_positions
+                .iter()
+                .map(|p| coordinate(p, 1))
+                .collect::<Result<Vec<f64>, String>>()?;
+            self.transform_coords(&mut xs, &mut ys)?;
+            for (position, (x, y)) in geometry_positions.into_iter().zip(xs.into_iter().zip(ys)) {
+                position[0] = json!(x);
+                position[1] = json!(y);
+            }
+        }
+        collection["crs"] = self.crs_member();
+        Ok(())
+    }
+
+    /// "crs" member naming the CRS, as in the 2008 GeoJSON specification
+    pub fn crs_member(&self) -> Value {
+        json!({
+            "type": "name",
+            "properties": { "name": self.crs },
+        })
+    }
+}
+
+/// Coordinates of an output, reprojected if a reprojection is given, the run
+/// being aborted when they can't be
+pub fn output_coordinates(
+    reprojection: Option<&Reprojection>,
+    coordinates: &[[f64; 2]],
+    output: &str,
+) -> Vec<[f64; 2]> {
+    match reprojection {
+        Some(reprojection) => reprojection.reproject(coordinates).unwrap_or_else(|e| {
+            log::error!(
+                target: "output",
+                fields:serde = json!({ "output": output, "crs": reprojection.crs });
+                "Unable to reproject the geometries of {}: {}", output, e
+            );
+            std::process::exit(1);
+        }),
+        None => coordinates.to_vec(),
+    }
+}
+
+/// Reproject the geometries of a GeoJSON FeatureCollection of an output if a
+/// reprojection is given, the run being aborted when they can't be
+pub fn apply_to_output(reprojection: Option<&Reprojection>, collection: &mut Value, output: &str) {
+    if let Some(reprojection) = reprojection {
+        reprojection.apply(collection).unwrap_or_else(|e| {
+            log::error!(
+                target: "output",
+                fields:serde = json!({ "output": output, "crs": reprojection.crs });
+                "Unable to reproject the geometries of {}: {}", output, e
+            );
+            std::process::exit(1);
+        });
+    }
+}
@@ -0,0 +1,85 @@
+// Sampling interval used by --approx
+pub const APPROX_SAMPLE_EVERY: usize = 5;
+
+/// Whether the node at this position of a way of `count` nodes is sampled on the DEM,
+/// when sampling every `every` nodes (the endpoints are always sampled)
+pub fn is_sampled(position: usize, count: usize, every: usize) -> bool {
+    position.is_multiple_of(every) || position + 1 == count
+}
+
+/// Fill the elevation of the nodes which were not sampled by a linear interpolation
+/// (on the distance along the way) between the closest sampled nodes with an elevation.
+/// The nodes before the first or after the last of them are left without elevation.
+pub fn interpolate(
+    distances: &[f64],
+    elevations: &mut [Option<f64>],
+    sampled: impl Fn(usize) -> bool,
+) {
+    let mut previous: Option<(f64, f64)> = None;
+    let mut gap_start = None;
+    for i in 0..elevations.len() {
+        if !sampled(i) {
+            gap_start.get_or_insert(i);
+            continue;
+        }
+        let elevation = match elevations[i] {
+            Some(elevation) => elevation,
+            None => continue,
+        };
+        if let (Some(start), Some((d0, e0))) = (gap_start, previous) {
+            let span = distances[i] - d0;
+            for j in start..i {
+                if !sampled(j) {
+                    let t = if span > 0. {
+                        (distances[j] - d0) / span
+                    } else {
+                        0.
+                    };
+                    elevations[j] = Some(e0 + (elevation - e0) * t);
+                }
+            }
+        }
+        previous = Some((distances[i], elevation));
+        gap_start = None;
+    }
+}
+
+/// Elevation profile of a way with points inserted along its segments longer than
+/// `spacing` (in meters), evenly spaced on each segment, given the (lon, lat)
+/// coordinates, cumulative distances and elevations of its nodes. The elevations
+/// of the inserted points are looked up at once with `sample`, from their locations.
+pub fn densify(
+    coordinates: &[[f64; 2]],
+    distances: &[f64],
+    elevations: &[Option<f64>],
+    spacing: f64,
+    sample: impl FnOnce(&[(f64, f64)]) -> Vec<Option<f64>>,
+) -> (Vec<f64>, Vec<Option<f64>>) {
+    let mut dense_distances = Vec::with_capacity(distances.len());
+    let mut dense_elevations = Vec::with_capacity(elevations.len());
+    // Locations of the inserted points, and their positions in the profile
+    let mut points = Vec::new();
+    let mut positions = Vec::new();
+    for i in 0..coordinates.len() {
+        if i > 0 {
+            let length = distances[i] - distances[i - 1];
+            let parts = (length / spacing).ceil() as usize;
+            let ([lon_a, lat_a], [lon_b, lat_b]) = (coordinates[i - 1], coordinates[i]);
+            for part in 1..parts {
+                let t = part as f64 / parts as f64;
+                points.push((lon_a + (lon_b - lon_a) * t, lat_a + (lat_b - lat_a) * t));
+                positions.push(dense_distances.len());
+                dense_distances.push(distances[i - 1] + length * t);
+                dense_elevations.push(None);
+            }
+        }
+        dense_distances.push(distances[i]);
+        dense_elevations.push(elevations[i]);
+    }
+    if !points.is_empty() {
+        for (position, elevation) in positions.into_iter().zip(sample(&points)) {
+            dense_elevations[position] = elevation;
+        }
+    }
+    (dense_distances, dense_elevations)
+}
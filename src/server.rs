@@ -0,0 +1,193 @@
+use crate::elevation::{Dem, ElevationProvider};
+use crate::{geodesy, gradient};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+// Maximum size of a request body
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+// Time after which a stalled client is disconnected, so that it doesn't block the
+// requests queued behind it
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Decode a Google encoded polyline into [lon, lat] coordinates
+fn decode_polyline(polyline: &str, precision: u32) -> Result<Vec<[f64; 2]>, String> {
+    let factor = 10f64.powi(precision as i32);
+    let mut values = Vec::new();
+    let (mut value, mut shift) = (0i64, 0);
+    for byte in polyline.trim().bytes() {
+        if !(63..127).contains(&byte) {
+            return Err("Invalid character in the encoded polyline".into());
+        }
+        if shift >= 64 {
+            return Err("Invalid value in the encoded polyline".into());
+        }
+        let chunk = (byte - 63) as i64;
+        value |= (chunk & 0x1f) << shift;
+        shift += 5;
+        if chunk & 0x20 == 0 {
+            values.push(if value & 1 == 1 {
+                !(value >> 1)
+            } else {
+                value >> 1
+            });
+            value = 0;
+            shift = 0;
+        }
+    }
+    if shift != 0 || values.len() % 2 != 0 {
+        return Err("Truncated encoded polyline".into());
+    }
+    let (mut lat, mut lon) = (0, 0);
+    Ok(values
+        .chunks_exact(2)
+        .map(|pair| {
+            lat += pair[0];
+            lon += pair[1];
+            [lon as f64 / factor, lat as f64 / factor]
+        })
+        .collect())
+}
+
+// Coordinates of the posted geometry: a GeoJSON LineString (or a Feature of it),
+// a {"polyline": "...", "precision": 5} object or an encoded polyline
+fn coordinates(body: &str) -> Result<Vec<[f64; 2]>, String> {
+    let value: Value = match serde_json::from_str(body) {
+        Ok(value) => value,
+        Err(_) => return decode_polyline(body, 5),
+    };
+    if let Some(polyline) = value["polyline"].as_str() {
+        let precision = value["precision"].as_u64().unwrap_or(5) as u32;
+        return decode_polyline(polyline, precision);
+    }
+    let geometry = match value["type"].as_str() {
+        Some("Feature") => &value["geometry"],
+        _ => &value,
+    };
+    if geometry["type"] != "LineString" {
+        return Err("Expected a GeoJSON LineString or an encoded polyline".into());
+    }
+    geometry["coordinates"]
+        .as_array()
+        .ok_or("Missing LineString coordinates")?
+        .iter()
+        .map(
+            |position| match (position[0].as_f64(), position[1].as_f64()) {
+                (Some(lon), Some(lat)) => Ok([lon, lat]),
+                _ => Err("Invalid LineString position".to_string()),
+            },
+        )
+        .collect()
+}
+
+/// Elevation profile and slope metrics of a line
+pub fn profile(coordinates: &[[f64; 2]], dem: &dyn ElevationProvider) -> Value {
+    let mut distances = Vec::with_capacity(coordinates.len());
+    geodesy::cumulative_distances(coordinates, &mut distances);
+    let elevations: Vec<Option<f64>> = coordinates
+        .iter()
+        .map(|[lon, lat]| dem.elevation(*lon, *lat))
+        .collect();
+    let totals = gradient::ClimbDescent::compute(&distances, &elevations);
+    json!({
+        "distance": distances.last().copied().unwrap_or(0.),
+        "climb_distance": totals.climb_distance,
+        "descent_distance": totals.descent_distance,
+        "climb": totals.climb,
+        "descent": totals.descent,
+        "net_grade": gradient::net_gradient(&distances, &elevations).map(|gradient| gradient * 100.),
+        "max_grade": gradient::max_segment_gradient(&distances, &elevations) / 10.,
+        "profile": distances
+            .iter()
+            .zip(&elevations)
+            .map(|(distance, elevation)| json!([distance, elevation]))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &Value) -> std::io::Result<()> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn handle(stream: &mut TcpStream, dem: &dyn ElevationProvider) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    if path.split('?').next() != Some("/profile") {
+        return respond(stream, "404 Not Found", &json!({ "error": "Not found" }));
+    }
+    if method != "POST" {
+        return respond(
+            stream,
+            "405 Method Not Allowed",
+            &json!({ "error": "Use POST" }),
+        );
+    }
+    if content_length > MAX_BODY_SIZE {
+        return respond(
+            stream,
+            "413 Payload Too Large",
+            &json!({ "error": "Request body too large" }),
+        );
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    match coordinates(&String::from_utf8_lossy(&body)) {
+        Ok(coordinates) => respond(stream, "200 OK", &profile(&coordinates, dem)),
+        Err(e) => respond(stream, "400 Bad Request", &json!({ "error": e })),
+    }
+}
+
+/// Serve the elevation profile of the posted geometries against the DEM
+/// (`POST /profile`), the requests being handled one at a time (a client sending
+/// or reading nothing for 30 s being disconnected)
+pub fn run(elevation_file: &str, listen: &str) {
+    let dem = Dem::open(elevation_file).unwrap_or_else(|e| {
+        log::error!(target: "setup", fields:serde = serde_json::json!({ "elevation_file": elevation_file }); "{}", e);
+        std::process::exit(1);
+    });
+    let listener = TcpListener::bind(listen).unwrap_or_else(|e| {
+        log::error!(
+            target: "serve",
+            fields:serde = json!({ "listen": listen });
+            "Unable to listen on {}: {}", listen, e
+        );
+        std::process::exit(1);
+    });
+    log::info!(
+        target: "serve",
+        fields:serde = json!({ "listen": listen });
+        "Listening on http://{}", listen
+    );
+    for stream in listener.incoming() {
+        let result = stream.and_then(|mut stream| handle(&mut stream, &dem));
+        if let Err(e) = result {
+            log::warn!(target: "serve", fields:serde = json!({}); "Request failed: {}", e);
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Handle SIGINT / SIGTERM by requesting the computation to stop,
+/// a second signal aborting immediately
+pub fn install() {
+    ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        eprintln!("Interrupted, writing the partial results (interrupt again to abort)");
+    })
+    .expect("Unable to install the signal handler");
+}
+
+/// Whether a signal was received
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,116 @@
+//! Smoothing of the elevations along a way, to filter out the DEM noise
+
+/// Elevations averaged over the nodes within `window` / 2 meters
+/// along the way (the nodes without elevation being left as is)
+pub fn moving_average(
+    distances: &[f64],
+    elevations: &[Option<f64>],
+    window: f64,
+) -> Vec<Option<f64>> {
+    smooth(distances, elevations, window, |points| {
+        points.iter().map(|(_, e)| e).sum::<f64>() / points.len() as f64
+    })
+}
+
+/// Elevations of a Savitzky-Golay filter: the value at each node of the quadratic
+/// fitted (by least squares) to the nodes within `window` / 2 meters along the way,
+/// which keeps the summits and valleys flattened by a moving average. The nodes
+/// without elevation are left as is.
+pub fn savitzky_golay(
+    distances: &[f64],
+    elevations: &[Option<f64>],
+    window: f64,
+) -> Vec<Option<f64>> {
+    smooth(distances, elevations, window, |points| {
+        // Normal equations of the fit of e = a + b x + c x^2, x being the distance
+        // from the node over the half window, the elevations being taken relative to
+        // the first node (so that a constant profile is kept exactly)
+        let base = points[0].1;
+        let mut s = [0.; 5];
+        let mut t = [0.; 3];
+        for (x, e) in points {
+            let mut power = 1.;
+            for (k, sum) in s.iter_mut().enumerate() {
+                *sum += power;
+                if k < 3 {
+                    t[k] += power * (e - base);
+                }
+                power *= x;
+            }
+        }
+        let det3 = |m: [[f64; 3]; 3]| {
+            m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+                - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+                + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+        };
+        let det = det3([[s[0], s[1], s[2]], [s[1], s[2], s[3]], [s[2], s[3], s[4]]]);
+        if points.len() >= 3 && det.abs() > 1e-9 {
+            // Intercept of the fit, by Cramer's rule
+            return base + det3([[t[0], s[1], s[2]], [t[1], s[2], s[3]], [t[2], s[3], s[4]]]) / det;
+        }
+        // Line fitted to too few nodes for a quadratic (e.g. at the ends of a way)
+        let det = s[0] * s[2] - s[1] * s[1];
+        if det.abs() > 1e-9 {
+            base + (t[0] * s[2] - t[1] * s[1]) / det
+        } else {
+            base + t[0] / s[0]
+        }
+    })
+}
+
+// Elevation of each node with an elevation given by `f` from the (x, elevation) of the
+// nodes with an elevation around it, x being their distance from it over the half window
+fn smooth<F: Fn(&[(f64, f64)]) -> f64>(
+    distances: &[f64],
+    elevations: &[Option<f64>],
+    window: f64,
+    f: F,
+) -> Vec<Option<f64>> {
+    let half = window / 2.;
+    let mut points = Vec::new();
+    // First node within the window of the current one
+    let mut first = 0;
+    let mut smoothed = Vec::with_capacity(elevations.len());
+    for i in 0..elevations.len() {
+        if elevations[i].is_none() || half <= 0. {
+            smoothed.push(elevations[i]);
+            continue;
+        }
+        while distances[i] - distances[first] > half {
+            first += 1;
+        }
+        points.clear();
+        points.extend(
+            (first..elevations.len())
+                .take_while(|j| distances[*j] - distances[i] <= half)
+                .filter_map(|j| elevations[j].map(|e| ((distances[j] - distances[i]) / half, e))),
+        );
+        smoothed.push(Some(f(&points)));
+    }
+    smoothed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_average_over_the_window() {
+        let distances = [0., 10., 20., 30.];
+        let elevations = [Some(0.), Some(3.), Some(6.), None];
+        assert_eq!(
+            moving_average(&distances, &elevations, 20.),
+            vec![Some(1.5), Some(3.), Some(4.5), None]
+        );
+    }
+
+    #[test]
+    fn savitzky_golay_keeps_a_quadratic_profile() {
+        let distances = [0., 10., 20., 30., 40.];
+        let elevations: Vec<Option<f64>> = distances.iter().map(|d| Some(d * d / 100.)).collect();
+        let smoothed = savitzky_golay(&distances, &elevations, 40.);
+        for (smoothed, elevation) in smoothed.iter().zip(&elevations) {
+            assert!((smoothed.unwrap() - elevation.unwrap()).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use crate::elevation::Sample;
+use fxhash::{FxHashMap, FxHashSet};
+
+/// Location and elevation sample of a node
+#[derive(Debug, Clone, Copy)]
+pub struct NodeInfo {
+    pub lon: f64,
+    pub lat: f64,
+    pub sample: Sample,
+}
+
+/// Storage of the nodes used by the matched ways, indexed by node id
+pub trait NodeStore {
+    fn insert(&mut self, id: i64, node: NodeInfo);
+    fn get(&self, id: i64) -> Option<NodeInfo>;
+}
+
+/// Floating point type used to store a value in a node store
+pub trait Precision: Copy {
+    fn from_f64(value: f64) -> Self;
+    fn to_f64(self) -> f64;
+}
+
+impl Precision for f64 {
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+impl Precision for f32 {
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+const STATUS_VALUE: u8 = 0;
+const STATUS_NODATA: u8 = 1;
+const STATUS_OUT_OF_EXTENT: u8 = 2;
+
+#[derive(Clone, Copy)]
+struct StoredNode<C, E> {
+    lon: C,
+    lat: C,
+    elevation: E,
+    status: u8,
+}
+
+/// In-memory node store, with coordinates stored as C and elevations as E
+#[derive(Default)]
+pub struct HashNodeStore<C, E> {
+    map: FxHashMap<i64, StoredNode<C, E>>,
+}
+
+impl<C: Precision, E: Precision> NodeStore for HashNodeStore<C, E> {
+    fn insert(&mut self, id: i64, node: NodeInfo) {
+        let (elevation, status) = match node.sample {
+            Sample::Value(elevation) => (elevation, STATUS_VALUE),
+            Sample::NoData(elevation) => (elevation, STATUS_NODATA),
+            Sample::OutOfExtent => (0., STATUS_OUT_OF_EXTENT),
+        };
+        self.map.insert(
+            id,
+            StoredNode {
+                lon: C::from_f64(node.lon),
+                lat: C::from_f64(node.lat),
+                elevation: E::from_f64(elevation),
+                status,
+            },
+        );
+    }
+
+    fn get(&self, id: i64) -> Option<NodeInfo> {
+        self.map.get(&id).map(|node| NodeInfo {
+            lon: node.lon.to_f64(),
+            lat: node.lat.to_f64(),
+            sample: match node.status {
+                STATUS_VALUE => Sample::Value(node.elevation.to_f64()),
+                STATUS_NODATA => Sample::NoData(node.elevation.to_f64()),
+                _ => Sample::OutOfExtent,
+            },
+        })
+    }
+}
+
+impl<C: Precision, E: Precision> HashNodeStore<C, E> {
+    // Approximate memory used by the nodes of the map
+    fn size(&self) -> usize {
+        self.map.capacity() * (std::mem::size_of::<(i64, StoredNode<C, E>)>() + 1)
+    }
+}
+
+/// Create the node store matching the requested precisions, spilled to a node
+/// cache file once it is larger than the threshold (in bytes), if any
+pub fn new_node_store(
+    f32_coordinates: bool,
+    f32_elevations: bool,
+    cache: Option<(&str, usize)>,
+) -> Box<dyn NodeStore> {
+    fn store<C: Precision + 'static, E: Precision + 'static>(
+        cache: Option<(&str, usize)>,
+    ) -> Box<dyn NodeStore> {
+        match cache {
+            Some((path, threshold)) => Box::new(SpillingNodeStore::<C, E> {
+                memory: HashNodeStore {
+                    map: FxHashMap::default(),
+                },
+                cache: None,
+                path: path.to_string(),
+                threshold,
+            }),
+            None => Box::new(HashNodeStore::<C, E> {
+                map: FxHashMap::default(),
+            }),
+        }
+    }
+    match (f32_coordinates, f32_elevations) {
+        (false, false) => store::<f64, f64>(cache),
+        (false, true) => store::<f64, f32>(cache),
+        (true, false) => store::<f32, f64>(cache),
+        (true, true) => store::<f32, f32>(cache),
+    }
+}
+
+// Size of a record of the flat nodes file: lon and lat as decimicro degrees (i32),
+// elevation (f32) and status (u32)
+const FLAT_RECORD_SIZE: usize = 16;
+// Status of a record that was never written (the file is zero-filled)
+const FLAT_STATUS_EMPTY: u32 = 0;
+// The file grows by at least this number of records at a time
+const FLAT_GROWTH: usize = 1 << 20;
+
+/// osm2pgsql-style node store: a dense array on disk, indexed by node id
+/// and memory-mapped, for extracts where hash maps of node ids use too much RAM
+pub struct FlatNodeStore {
+    file: std::fs::File,
+    mmap: memmap2::MmapMut,
+    capacity: usize,
+    // Nodes with a negative id (e.g. created in JOSM), which can't index the file
+    negative: FxHashMap<i64, NodeInfo>,
+}
+
+impl FlatNodeStore {
+    pub fn create(path: &str) -> FlatNodeStore {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap_or_else(|_| panic!("Unable to create flat nodes file {}", path));
+        file.set_len((FLAT_GROWTH * FLAT_RECORD_SIZE) as u64)
+            .expect("Unable to resize flat nodes file");
+        let mmap =
+            unsafe { memmap2::MmapMut::map_mut(&file) }.expect("Unable to map flat nodes file");
+        FlatNodeStore {
+            file,
+            mmap,
+            capacity: FLAT_GROWTH,
+            negative: FxHashMap::default(),
+        }
+    }
+
+    fn grow(&mut self, id: usize) {
+        let capacity = (id + 1).max(self.capacity * 2).div_ceil(FLAT_GROWTH) * FLAT_GROWTH;
+        self.mmap.flush().expect("Unable to flush flat nodes file");
+        self.file
+            .set_len((capacity * FLAT_RECORD_SIZE) as u64)
+            .expect("Unable to resize flat nodes file");
+        self.mmap = unsafe { memmap2::MmapMut::map_mut(&self.file) }
+            .expect("Unable to map flat nodes file");
+        self.capacity = capacity;
+    }
+}
+
+impl NodeStore for FlatNodeStore {
+    fn insert(&mut self, id: i64, node: NodeInfo) {
+        let Ok(index) = usize::try_from(id) else {
+            self.negative.insert(id, node);
+            return;
+        };
+        if index >= self.capacity {
+            self.grow(index);
+        }
+        let (elevation, status) = match node.sample {
+            Sample::Value(elevation) => (elevation, STATUS_VALUE),
+            Sample::NoData(elevation) => (elevation, STATUS_NODATA),
+            Sample::OutOfExtent => (0., STATUS_OUT_OF_EXTENT),
+        };
+        let offset = index * FLAT_RECORD_SIZE;
+        let record = &mut self.mmap[offset..offset + FLAT_RECORD_SIZE];
+        record[0..4].copy_from_slice(&((node.lon * 1e7).round() as i32).to_le_bytes());
+        record[4..8].copy_from_slice(&((node.lat * 1e7).round() as i32).to_le_bytes());
+        record[8..12].copy_from_slice(&(elevation as f32).to_le_bytes());
+        record[12..16].copy_from_slice(&(status as u32 + 1).to_le_bytes());
+    }
+
+    fn get(&self, id: i64) -> Option<NodeInfo> {
+        let Ok(index) = usize::try_from(id) else {
+            return self.negative.get(&id).copied();
+        };
+        if index >= self.capacity {
+            return None;
+        }
+        let offset = index * FLAT_RECORD_SIZE;
+        let record = &self.mmap[offset..offset + FLAT_RECORD_SIZE];
+        let field = |start: usize| -> [u8; 4] { record[start..start + 4].try_into().unwrap() };
+        let status = u32::from_le_bytes(field(12));
+        if status == FLAT_STATUS_EMPTY {
+            return None;
+        }
+        let elevation = f32::from_le_bytes(field(8)) as f64;
+        Some(NodeInfo {
+            lon: i32::from_le_bytes(field(0)) as f64 * 1e-7,
+            lat: i32::from_le_bytes(field(4)) as f64 * 1e-7,
+            sample: match (status - 1) as u8 {
+                STATUS_VALUE => Sample::Value(elevation),
+                STATUS_NODATA => Sample::NoData(elevation),
+                _ => Sample::OutOfExtent,
+            },
+        })
+    }
+}
+
+/// In-memory node store whose nodes are moved to a flat nodes file (the node cache)
+/// once it uses more memory than a threshold, all the nodes being stored in the
+/// file from then on
+pub struct SpillingNodeStore<C, E> {
+    memory: HashNodeStore<C, E>,
+    cache: Option<FlatNodeStore>,
+    path: String,
+    // Size (in bytes) of the in-memory store above which it is spilled
+    threshold: usize,
+}
+
+impl<C: Precision, E: Precision> NodeStore for SpillingNodeStore<C, E> {
+    fn insert(&mut self, id: i64, node: NodeInfo) {
+        if let Some(cache) = &mut self.cache {
+            return cache.insert(id, node);
+        }
+        self.memory.insert(id, node);
+        if self.memory.size() > self.threshold {
+            log::info!(
+                target: "read",
+                fields:serde = serde_json::json!({
+                    "node_cache": self.path,
+                    "node_count": self.memory.map.len(),
+                });
+                "Moving the {} nodes read so far to the node cache {}",
+                self.memory.map.len(),
+                self.path,
+            );
+            let mut cache = FlatNodeStore::create(&self.path);
+            for id in self.memory.map.keys() {
+                cache.insert(*id, self.memory.get(*id).unwrap());
+            }
+            self.memory = HashNodeStore {
+                map: FxHashMap::default(),
+            };
+            self.cache = Some(cache);
+        }
+    }
+
+    fn get(&self, id: i64) -> Option<NodeInfo> {
+        match &self.cache {
+            Some(cache) => cache.get(id),
+            None => self.memory.get(id),
+        }
+    }
+}
+
+// Number of node ids (as a power of 2) covered by each page of a NodeIdSet
+const ID_PAGE_BITS: u32 = 16;
+
+/// Set of node ids stored as a bitmap indexed by id, allocated by pages of 65536 ids
+/// as they are used, so that its size doesn't depend on the number of references
+/// to the nodes (the negative ids being kept in a hash set)
+#[derive(Default)]
+pub struct NodeIdSet {
+    pages: Vec<Option<Box<[u64]>>>,
+    negative: FxHashSet<i64>,
+    len: usize,
+}
+
+impl NodeIdSet {
+    /// Add a node id, returning whether it wasn't in the set
+    pub fn insert(&mut self, id: i64) -> bool {
+        let Ok(id) = u64::try_from(id) else {
+            let inserted = self.negative.insert(id);
+            self.len += inserted as usize;
+            return inserted;
+        };
+        let page = (id >> ID_PAGE_BITS) as usize;
+        if page >= self.pages.len() {
+            self.pages.resize(page + 1, None);
+        }
+        let words = self.pages[page]
+            .get_or_insert_with(|| vec![0; 1 << (ID_PAGE_BITS - 6)].into_boxed_slice());
+        let bit = id & ((1 << ID_PAGE_BITS) - 1);
+        let word = &mut words[(bit >> 6) as usize];
+        let mask = 1 << (bit & 63);
+        let inserted = *word & mask == 0;
+        *word |= mask;
+        self.len += inserted as usize;
+        inserted
+    }
+
+    pub fn contains(&self, id: i64) -> bool {
+        let Ok(id) = u64::try_from(id) else {
+            return self.negative.contains(&id);
+        };
+        let bit = id & ((1 << ID_PAGE_BITS) - 1);
+        self.pages
+            .get((id >> ID_PAGE_BITS) as usize)
+            .and_then(Option::as_ref)
+            .is_some_and(|words| words[(bit >> 6) as usize] & (1 << (bit & 63)) != 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl FromIterator<i64> for NodeIdSet {
+    fn from_iter<I: IntoIterator<Item = i64>>(iter: I) -> Self {
+        let mut set = NodeIdSet::default();
+        for id in iter {
+            set.insert(id);
+        }
+        set
+    }
+}
@@ -0,0 +1,94 @@
+use osmpbfreader::{Node, OsmId, OsmObj, Tags};
+use std::collections::BTreeMap;
+use std::io::BufRead;
+
+/// Path standing for stdin as the OSM input, and for stdout as an output
+pub const STANDARD_STREAM: &str = "-";
+
+pub fn is_standard_stream(path: &str) -> bool {
+    path == STANDARD_STREAM
+}
+
+/// Read the ways validating the predicate and their nodes from a file read twice
+/// by `for_each_object`, which calls its argument on each object of the file: the
+/// ways in the first pass, and the nodes of these ways in the second one
+pub fn get_ways_and_deps_in_two_passes<R, P>(
+    mut for_each_object: R,
+    mut pred: P,
+) -> BTreeMap<OsmId, OsmObj>
+where
+    R: FnMut(&mut dyn FnMut(OsmObj)),
+    P: FnMut(&OsmObj) -> bool,
+{
+    let mut objs = BTreeMap::new();
+    let mut needed = fxhash::FxHashSet::default();
+    for_each_object(&mut |obj| {
+        if let OsmObj::Way(way) = &obj {
+            if pred(&obj) {
+                needed.extend(way.nodes.iter().map(|node_id| node_id.0));
+                objs.insert(obj.id(), obj);
+            }
+        }
+    });
+    for_each_object(&mut |obj| {
+        if let OsmObj::Node(node) = &obj {
+            if needed.contains(&node.id.0) {
+                objs.insert(obj.id(), obj);
+            }
+        }
+    });
+    objs
+}
+
+/// Read the ways validating the predicate and their nodes from stdin, in a single
+/// pass as it can't be rewound: the format (PBF, o5m or OSM XML) is detected from
+/// the first bytes, and the locations of all the nodes are kept until the end of
+/// the stream, the ways coming after them
+pub fn get_ways_and_deps<P>(mut pred: P) -> BTreeMap<OsmId, OsmObj>
+where
+    P: FnMut(&OsmObj) -> bool,
+{
+    let mut input = std::io::BufReader::with_capacity(1 << 16, std::io::stdin().lock());
+    let start = input
+        .fill_buf()
+        .unwrap_or_else(|e| panic!("Unable to read OSM data from stdin: {}", e))
+        .to_vec();
+    let mut locations = fxhash::FxHashMap::default();
+    let mut ways = Vec::new();
+    let consume = |obj: OsmObj| match obj {
+        OsmObj::Node(node) => {
+            locations.insert(node.id, (node.decimicro_lon, node.decimicro_lat));
+        }
+        OsmObj::Way(_) if pred(&obj) => ways.push(obj),
+        _ => {}
+    };
+    // PBF streams start with the (big-endian) size of their first blob header
+    let is_xml = start
+        .iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .is_some_and(|byte| *byte == b'<');
+    if crate::o5m::has_header(&start) {
+        crate::o5m::read_objects(input, "stdin", consume);
+    } else if is_xml {
+        crate::osmxml::read_objects(input, "stdin", consume);
+    } else {
+        crate::pbf::read_objects(input, consume);
+    }
+
+    let mut objs = BTreeMap::new();
+    for way in ways {
+        for node_id in &way.way().unwrap().nodes {
+            if let Some((decimicro_lon, decimicro_lat)) = locations.get(node_id) {
+                let node = Node {
+                    id: *node_id,
+                    tags: Tags::new(),
+                    decimicro_lat: *decimicro_lat,
+                    decimicro_lon: *decimicro_lon,
+                };
+                objs.insert(OsmId::Node(*node_id), OsmObj::Node(node));
+            }
+        }
+        objs.insert(way.id(), way);
+    }
+    objs
+}
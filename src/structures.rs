@@ -0,0 +1,34 @@
+// Tags of the structures on which the DEM doesn't reflect the elevation of a railway
+pub const RAIL_STRUCTURES: [&str; 4] = ["bridge", "tunnel", "cutting", "embankment"];
+
+// Tags of the structures flattened with --flatten-structures, on which the DEM
+// reflects the valley floor or the hilltop instead of the way
+pub const STRUCTURES: [&str; 2] = ["bridge", "tunnel"];
+
+/// Whether the way is tagged with one of the given structure keys (e.g. bridge=yes)
+pub fn is_structure(tags: &osmpbfreader::Tags, keys: &[&str]) -> bool {
+    keys.iter()
+        .any(|key| matches!(tags.get(*key), Some(value) if value.as_str() != "no"))
+}
+
+/// Replace the elevation of the inner nodes of a way by a linear interpolation
+/// (on the distance along the way) between its first and last nodes.
+/// Returns false if the elevation of one of the endpoints is unknown.
+pub fn interpolate_between_endpoints(distances: &[f64], elevations: &mut [Option<f64>]) -> bool {
+    let n = elevations.len();
+    if n < 3 {
+        return false;
+    }
+    let (start, end) = match (elevations[0], elevations[n - 1]) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return false,
+    };
+    let total = distances[n - 1];
+    if total <= 0. {
+        return false;
+    }
+    for i in 1..n - 1 {
+        elevations[i] = Some(start + (end - start) * distances[i] / total);
+    }
+    true
+}
@@ -0,0 +1,150 @@
+use serde_json::{json, Value};
+use std::io::IsTerminal;
+
+/// Upper bounds (in %) of the classes of the grade distribution, the last class being unbounded
+pub const GRADE_CLASSES: [f64; 4] = [2., 5., 8., 12.];
+
+// ANSI escape sequences
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+// Colors of the grade classes, from the flattest to the steepest
+const CLASS_COLORS: [&str; 5] = ["\x1b[32m", "\x1b[92m", "\x1b[33m", "\x1b[91m", "\x1b[31m"];
+
+/// Headline numbers of a run
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub way_count: usize,
+    // Total length of the ways (in meters)
+    pub distance: f64,
+    pub climb: f64,
+    pub descent: f64,
+    // Length of the ways (in meters) in each grade class, the grade of a way
+    // being its climb and descent over its length
+    pub grade_distances: [f64; GRADE_CLASSES.len() + 1],
+}
+
+/// Whether to colorize the output written to the given stream,
+/// which must be a terminal (and NO_COLOR must be unset)
+pub fn use_color(no_color: bool, stream: &impl IsTerminal) -> bool {
+    !no_color && std::env::var_os("NO_COLOR").is_none() && stream.is_terminal()
+}
+
+/// Average grade (in %) of a way, as its climb and descent over its length
+pub fn average_grade(distance: f64, climb: f64, descent: f64) -> f64 {
+    if distance > 0. {
+        (climb + descent) / distance * 100.
+    } else {
+        0.
+    }
+}
+
+/// Index of the class of an average grade, from the flattest (0), given the
+/// increasing upper bounds of the classes
+pub fn grade_class(grade: f64, bounds: &[f64]) -> usize {
+    bounds
+        .iter()
+        .position(|bound| grade < *bound)
+        .unwrap_or(bounds.len())
+}
+
+/// Label of a grade class, e.g. "5-8%"
+pub fn class_label(class: usize, bounds: &[f64]) -> String {
+    match (class.checked_sub(1).map(|i| bounds[i]), bounds.get(class)) {
+        (None, Some(upper)) => format!("< {}%", upper),
+        (Some(lower), Some(upper)) => format!("{}-{}%", lower, upper),
+        (Some(lower), None) => format!(">= {}%", lower),
+        (None, None) => unreachable!(),
+    }
+}
+
+impl Summary {
+    pub fn add(&mut self, distance: f64, climb: f64, descent: f64) {
+        self.way_count += 1;
+        self.distance += distance;
+        self.climb += climb;
+        self.descent += descent;
+        let class = grade_class(average_grade(distance, climb, descent), &GRADE_CLASSES);
+        self.grade_distances[class] += distance;
+    }
+
+    /// Summary of a result file, as written by a run
+    pub fn from_results(results: &Value) -> Result<Summary, String> {
+        let ways = results
+            .as_object()
+            .ok_or("The results are not a JSON object of ways")?;
+        let mut summary = Summary::default();
+        for (way_id, way) in ways {
+            let field = |name: &str| {
+                way[name]
+                    .as_f64()
+                    .ok_or_else(|| format!("Missing {} for way {}", name, way_id))
+            };
+            summary.add(field("distance")?, field("climb")?, field("descent")?);
+        }
+        Ok(summary)
+    }
+
+    /// Compact table of the headline numbers, with ANSI colors if `color` is set
+    pub fn render(&self, color: bool) -> String {
+        let style = |code: &'static str| if color { code } else { "" };
+        let (bold, dim, reset) = (style(BOLD), style(DIM), style(RESET));
+        let mut lines = vec![
+            format!("{}{:<18}{:>12}{}", bold, "Ways", self.way_count, reset),
+            format!(
+                "{}{:<18}{:>12.1}{} km",
+                bold,
+                "Length",
+                self.distance / 1000.,
+                reset
+            ),
+            format!(
+                "{}{:<18}{:>12.0}{} m",
+                bold, "Total ascent", self.climb, reset
+            ),
+            format!(
+                "{}{:<18}{:>12.0}{} m",
+                bold, "Total descent", self.descent, reset
+            ),
+            format!("{}Grade distribution (share of the length){}", dim, reset),
+        ];
+        for (class, distance) in self.grade_distances.iter().enumerate() {
+            let label = class_label(class, &GRADE_CLASSES);
+            let share = if self.distance > 0. {
+                distance / self.distance
+            } else {
+                0.
+            };
+            lines.push(format!(
+                "  {:<16}{}{:<20}{}{:>6.1}%",
+                label,
+                style(CLASS_COLORS[class]),
+                "#".repeat((share * 20.).round() as usize),
+                reset,
+                share * 100.
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Print the summary of a result file on stdout
+pub fn run(results_file: &str, no_color: bool) {
+    let summary = std::fs::read_to_string(results_file)
+        .map_err(|e| format!("Unable to read {}: {}", results_file, e))
+        .and_then(|content| {
+            serde_json::from_str(&content)
+                .map_err(|e| format!("Unable to parse {}: {}", results_file, e))
+        })
+        .and_then(|results| Summary::from_results(&results));
+    match summary {
+        Ok(summary) => println!(
+            "{}",
+            summary.render(use_color(no_color, &std::io::stdout()))
+        ),
+        Err(e) => {
+            log::error!(target: "stats", fields:serde = json!({ "path": results_file }); "{}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Values of the placeholders that can be used in the output paths
+#[derive(Clone)]
+pub struct Placeholders {
+    // Name of the OSM file, without its extensions
+    pub region: String,
+    // Date of the run, as YYYY-MM-DD
+    pub date: String,
+    // Short hash of the filters, to tell apart the outputs of different filters
+    pub filter_hash: String,
+    // Value of {tile} in the outputs written per tile: the placeholder itself, left
+    // for the writers of the tiles to expand (the paths of the other outputs can't use it)
+    pub tile: Option<String>,
+}
+
+// Convert a number of days since 1970-01-01 to a (year, month, day) date
+// (http://howardhinnant.github.io/date_algorithms.html#civil_from_days)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Current (UTC) date as YYYY-MM-DD
+pub fn today() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before the UNIX epoch")
+        .as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Name of the OSM file without its directory and extensions,
+/// e.g. "monaco-latest" for "/data/monaco-latest.osm.pbf"
+pub fn region(osm_file: &str) -> String {
+    let name = std::path::Path::new(osm_file)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match name.split_once('.') {
+        Some((stem, _)) => stem.to_string(),
+        None => name,
+    }
+}
+
+/// Expand the `{region}`, `{date}`, `{filter_hash}` and `{tile}` placeholders of a path
+pub fn expand(template: &str, placeholders: &Placeholders) -> Result<String, String> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        result.push_str(&rest[..start]);
+        let value = match &rest[start + 1..end] {
+            "region" => &placeholders.region,
+            "date" => &placeholders.date,
+            "filter_hash" => &placeholders.filter_hash,
+            "tile" => placeholders.tile.as_ref().ok_or_else(|| {
+                format!(
+                    "The {{tile}} placeholder of {} can only be used by the outputs partitioned by tile (--partition-by tile:ZOOM)",
+                    template
+                )
+            })?,
+            name => return Err(format!("Unknown placeholder {{{}}} in {}", name, template)),
+        };
+        result.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Expand the `{tile}` placeholder of the path of an output written per tile,
+/// the tile being given as ZOOM-X-Y
+pub fn expand_tile(template: &str, tile: &str) -> String {
+    template.replace("{tile}", tile)
+}
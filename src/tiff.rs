@@ -0,0 +1,738 @@
+use crate::blockcache::BlockCache;
+use fxhash::FxHashMap;
+use std::io::Read;
+use std::rc::Rc;
+
+// TIFF tags used to read single-band GeoTIFF DEMs
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const PLANAR_CONFIGURATION: u16 = 284;
+const PREDICTOR: u16 = 317;
+const TILE_WIDTH: u16 = 322;
+const TILE_LENGTH: u16 = 323;
+const TILE_OFFSETS: u16 = 324;
+const TILE_BYTE_COUNTS: u16 = 325;
+const SAMPLE_FORMAT: u16 = 339;
+const MODEL_PIXEL_SCALE: u16 = 33550;
+const MODEL_TIEPOINT: u16 = 33922;
+const MODEL_TRANSFORMATION: u16 = 34264;
+const GEO_KEY_DIRECTORY: u16 = 34735;
+const GDAL_NODATA: u16 = 42113;
+
+// GeoKeys
+const GT_MODEL_TYPE: u16 = 1024;
+const GT_RASTER_TYPE: u16 = 1025;
+const GEOGRAPHIC_TYPE: u16 = 2048;
+const PROJECTED_CS_TYPE: u16 = 3072;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+const MODEL_TYPE_PROJECTED: u16 = 1;
+
+const TAGS: [u16; 21] = [
+    IMAGE_WIDTH,
+    IMAGE_LENGTH,
+    BITS_PER_SAMPLE,
+    COMPRESSION,
+    PHOTOMETRIC_INTERPRETATION,
+    STRIP_OFFSETS,
+    SAMPLES_PER_PIXEL,
+    ROWS_PER_STRIP,
+    STRIP_BYTE_COUNTS,
+    PLANAR_CONFIGURATION,
+    PREDICTOR,
+    TILE_WIDTH,
+    TILE_LENGTH,
+    TILE_OFFSETS,
+    TILE_BYTE_COUNTS,
+    SAMPLE_FORMAT,
+    MODEL_PIXEL_SCALE,
+    MODEL_TIEPOINT,
+    MODEL_TRANSFORMATION,
+    GEO_KEY_DIRECTORY,
+    GDAL_NODATA,
+];
+
+#[derive(Debug, Clone, Copy)]
+enum SampleFormat {
+    Unsigned,
+    Signed,
+    Float,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Compression {
+    None,
+    Lzw,
+    Deflate,
+    PackBits,
+}
+
+/// Single-band GeoTIFF raster (the first band of multi-band rasters),
+/// read without GDAL from a memory-mapped file
+pub struct GeoTiff {
+    mmap: memmap2::Mmap,
+    little_endian: bool,
+    pub width: usize,
+    pub height: usize,
+    // Geo transform, as in GDAL (origin x, pixel width, row rotation,
+    // origin y, column rotation, pixel height)
+    pub transform: [f64; 6],
+    pub nodata: Option<f64>,
+    // EPSG code of the CRS, from the GeoKeys
+    pub epsg: Option<u16>,
+    // Whether the CRS is projected (rather than geographic)
+    pub projected: bool,
+    // Size of the strips / tiles
+    chunk_width: usize,
+    chunk_height: usize,
+    chunk_offsets: Vec<u64>,
+    chunk_byte_counts: Vec<u64>,
+    // Number of samples in a pixel of a chunk (1 for planar rasters)
+    chunk_samples: usize,
+    bytes_per_sample: usize,
+    sample_format: SampleFormat,
+    compression: Compression,
+    predictor: u16,
+    cache: BlockCache,
+}
+
+// Values of an IFD entry
+struct Entry {
+    field_type: u16,
+    count: u64,
+    // Offset of the values in the file
+    offset: u64,
+}
+
+impl GeoTiff {
+    pub fn open(path: &str) -> Result<GeoTiff, String> {
+        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let mmap = unsafe { memmap2::Mmap::map(&file) }.map_err(|e| e.to_string())?;
+        let little_endian = match mmap.get(0..2) {
+            Some(b"II") => true,
+            Some(b"MM") => false,
+            _ => return Err("Not a TIFF file".into()),
+        };
+        let mut tiff = GeoTiff {
+            mmap,
+            little_endian,
+            width: 0,
+            height: 0,
+            transform: [0., 1., 0., 0., 0., 1.],
+            nodata: None,
+            epsg: None,
+            projected: false,
+            chunk_width: 0,
+            chunk_height: 0,
+            chunk_offsets: Vec::new(),
+            chunk_byte_counts: Vec::new(),
+            chunk_samples: 1,
+            bytes_per_sample: 0,
+            sample_format: SampleFormat::Unsigned,
+            compression: Compression::None,
+            predictor: 1,
+            cache: BlockCache::new(),
+        };
+        tiff.read_ifd()?;
+        Ok(tiff)
+    }
+
+    /// SRTM .hgt tile: a square grid of big-endian i16 values (-32768 for the voids)
+    /// whose outer pixels are centred on the edges of the 1° tile named after its
+    /// south-west corner (e.g. N46E006.hgt), read as an uncompressed raster
+    pub fn open_hgt(path: &str) -> Result<GeoTiff, String> {
+        let name = std::path::Path::new(path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("")
+            .to_ascii_uppercase();
+        let corner = |sign: char, digits: &str| -> Option<f64> {
+            let value: f64 = digits.parse().ok()?;
+            Some(if "SW".contains(sign) { -value } else { value })
+        };
+        let mut chars = name.chars();
+        let (lat, lon) = match (chars.next(), name.get(1..3), chars.nth(2), name.get(4..7)) {
+            (Some(ns @ ('N' | 'S')), Some(lat), Some(ew @ ('E' | 'W')), Some(lon)) => {
+                (corner(ns, lat), corner(ew, lon))
+            }
+            _ => (None, None),
+        };
+        let (lat, lon) = lat
+            .zip(lon)
+            .ok_or("The name of an SRTM tile must give its corner (e.g. N46E006.hgt)")?;
+
+        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let mmap = unsafe { memmap2::Mmap::map(&file) }.map_err(|e| e.to_string())?;
+        let size = ((mmap.len() / 2) as f64).sqrt() as usize;
+        if size < 2 || size * size * 2 != mmap.len() {
+            return Err("Invalid SRTM tile size".into());
+        }
+        let resolution = 1. / (size - 1) as f64;
+        // Strips of 16 rows
+        let rows: Vec<usize> = (0..size).step_by(16).collect();
+        Ok(GeoTiff {
+            mmap,
+            little_endian: false,
+            width: size,
+            height: size,
+            transform: [
+                lon - resolution / 2.,
+                resolution,
+                0.,
+                lat + 1. + resolution / 2.,
+                0.,
+                -resolution,
+            ],
+            nodata: Some(-32768.),
+            epsg: Some(4326),
+            projected: false,
+            chunk_width: size,
+            chunk_height: 16,
+            chunk_offsets: rows.iter().map(|row| (row * size * 2) as u64).collect(),
+            chunk_byte_counts: rows
+                .iter()
+                .map(|row| ((size - row).min(16) * size * 2) as u64)
+                .collect(),
+            chunk_samples: 1,
+            bytes_per_sample: 2,
+            sample_format: SampleFormat::Signed,
+            compression: Compression::None,
+            predictor: 1,
+            cache: BlockCache::new(),
+        })
+    }
+
+    fn bytes(&self, offset: u64, len: usize) -> Result<&[u8], String> {
+        let start = offset as usize;
+        self.mmap
+            .get(start..start + len)
+            .ok_or_else(|| "Truncated TIFF file".to_string())
+    }
+
+    fn uint(&self, offset: u64, len: usize) -> Result<u64, String> {
+        let bytes = self.bytes(offset, len)?;
+        let fold = |value: u64, byte: &u8| value << 8 | *byte as u64;
+        Ok(if self.little_endian {
+            bytes.iter().rev().fold(0, fold)
+        } else {
+            bytes.iter().fold(0, fold)
+        })
+    }
+
+    // Values of an entry, as f64
+    fn values(&self, entry: &Entry) -> Result<Vec<f64>, String> {
+        (0..entry.count)
+            .map(|i| {
+                Ok(match entry.field_type {
+                    1 | 2 | 7 => self.uint(entry.offset + i, 1)? as f64,
+                    6 => self.uint(entry.offset + i, 1)? as u8 as i8 as f64,
+                    3 => self.uint(entry.offset + i * 2, 2)? as f64,
+                    8 => self.uint(entry.offset + i * 2, 2)? as u16 as i16 as f64,
+                    4 | 13 => self.uint(entry.offset + i * 4, 4)? as f64,
+                    9 => self.uint(entry.offset + i * 4, 4)? as u32 as i32 as f64,
+                    16 | 18 => self.uint(entry.offset + i * 8, 8)? as f64,
+                    17 => self.uint(entry.offset + i * 8, 8)? as i64 as f64,
+                    11 => f32::from_bits(self.uint(entry.offset + i * 4, 4)? as u32) as f64,
+                    12 => f64::from_bits(self.uint(entry.offset + i * 8, 8)?),
+                    5 | 10 => {
+                        let (numerator, denominator) = (
+                            self.uint(entry.offset + i * 8, 4)?,
+                            self.uint(entry.offset + i * 8 + 4, 4)?,
+                        );
+                        if entry.field_type == 5 {
+                            numerator as f64 / denominator as f64
+                        } else {
+                            numerator as u32 as i32 as f64 / denominator as u32 as i32 as f64
+                        }
+                    }
+                    field_type => {
+                        return Err(format!("Unsupported TIFF field type {}", field_type))
+                    }
+                })
+            })
+            .collect()
+    }
+
+    // Read the first IFD (the full resolution image)
+    fn read_ifd(&mut self) -> Result<(), String> {
+        let big_tiff = match self.uint(2, 2)? {
+            42 => false,
+            43 => true,
+            _ => return Err("Not a TIFF file".into()),
+        };
+        let (ifd_offset, count_size, entry_size, value_size) = if big_tiff {
+            (self.uint(8, 8)?, 8, 20, 8)
+        } else {
+            (self.uint(4, 4)?, 2, 12, 4)
+        };
+        let entry_count = self.uint(ifd_offset, count_size)?;
+        let mut entries = FxHashMap::default();
+        for i in 0..entry_count {
+            let start = ifd_offset + count_size as u64 + i * entry_size;
+            let tag = self.uint(start, 2)? as u16;
+            let field_type = self.uint(start + 2, 2)? as u16;
+            let count = self.uint(start + 4, value_size)?;
+            let type_size: u64 = match field_type {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 | 11 | 13 => 4,
+                _ => 8,
+            };
+            // The values are stored in the entry itself when they fit
+            let value_start = start + 4 + value_size as u64;
+            let offset = if type_size * count <= value_size as u64 {
+                value_start
+            } else {
+                self.uint(value_start, value_size)?
+            };
+            entries.insert(
+                tag,
+                Entry {
+                    field_type,
+                    count,
+                    offset,
+                },
+            );
+        }
+
+        // Values of the tags used, decoded before the fields are set
+        let decoded: FxHashMap<u16, Vec<f64>> = entries
+            .iter()
+            .filter(|(tag, _)| TAGS.contains(tag))
+            .map(|(tag, entry)| Ok((*tag, self.values(entry)?)))
+            .collect::<Result<_, String>>()?;
+        let values =
+            |tag: u16| -> Result<Option<Vec<f64>>, String> { Ok(decoded.get(&tag).cloned()) };
+        let value = |tag: u16| -> Result<Option<f64>, String> {
+            Ok(values(tag)?.and_then(|values| values.first().copied()))
+        };
+        let required = |tag: u16| -> Result<f64, String> {
+            value(tag)?.ok_or_else(|| format!("Missing TIFF tag {}", tag))
+        };
+
+        self.width = required(IMAGE_WIDTH)? as usize;
+        self.height = required(IMAGE_LENGTH)? as usize;
+        let samples_per_pixel = value(SAMPLES_PER_PIXEL)?.unwrap_or(1.) as usize;
+        let planar = value(PLANAR_CONFIGURATION)?.unwrap_or(1.) == 2.;
+        self.chunk_samples = if planar { 1 } else { samples_per_pixel };
+        let bits = value(BITS_PER_SAMPLE)?.unwrap_or(1.) as usize;
+        if ![8, 16, 32, 64].contains(&bits) {
+            return Err(format!("Unsupported TIFF bits per sample {}", bits));
+        }
+        self.bytes_per_sample = bits / 8;
+        self.sample_format = match value(SAMPLE_FORMAT)?.unwrap_or(1.) as u16 {
+            1 => SampleFormat::Unsigned,
+            2 => SampleFormat::Signed,
+            3 => SampleFormat::Float,
+            format => return Err(format!("Unsupported TIFF sample format {}", format)),
+        };
+        self.compression = match value(COMPRESSION)?.unwrap_or(1.) as u16 {
+            1 => Compression::None,
+            5 => Compression::Lzw,
+            8 | 32946 => Compression::Deflate,
+            32773 => Compression::PackBits,
+            compression => {
+                return Err(format!(
+                    "Unsupported TIFF compression {} (supported: none, LZW, deflate, PackBits)",
+                    compression
+                ))
+            }
+        };
+        self.predictor = value(PREDICTOR)?.unwrap_or(1.) as u16;
+        if value(PHOTOMETRIC_INTERPRETATION)? == Some(6.) {
+            return Err("Unsupported YCbCr TIFF".into());
+        }
+
+        let (offsets, byte_counts) = match value(TILE_WIDTH)? {
+            Some(tile_width) => {
+                self.chunk_width = tile_width as usize;
+                self.chunk_height = required(TILE_LENGTH)? as usize;
+                (values(TILE_OFFSETS)?, values(TILE_BYTE_COUNTS)?)
+            }
+            None => {
+                self.chunk_width = self.width;
+                self.chunk_height = value(ROWS_PER_STRIP)?
+                    .map_or(self.height, |rows| (rows as usize).min(self.height));
+                (values(STRIP_OFFSETS)?, values(STRIP_BYTE_COUNTS)?)
+            }
+        };
+        let to_u64 = |values: Vec<f64>| values.into_iter().map(|v| v as u64).collect();
+        self.chunk_offsets = offsets
+            .map(to_u64)
+            .ok_or("Missing TIFF strip / tile offsets")?;
+        self.chunk_byte_counts = byte_counts
+            .map(to_u64)
+            .ok_or("Missing TIFF strip / tile byte counts")?;
+
+        self.nodata = values(GDAL_NODATA)?.and_then(|text| {
+            let text: String = text.iter().map(|byte| *byte as u8 as char).collect();
+            text.trim_matches(char::from(0)).trim().parse().ok()
+        });
+
+        // GeoKeys: header (version, revision, minor revision, count) then
+        // (key, location, count, value) entries
+        let mut pixel_is_point = false;
+        if let Some(keys) = values(GEO_KEY_DIRECTORY)? {
+            for key in keys.chunks_exact(4).skip(1) {
+                // Only the keys whose value is stored in the directory itself are used
+                if key[1] != 0. {
+                    continue;
+                }
+                match key[0] as u16 {
+                    GT_RASTER_TYPE => pixel_is_point = key[3] as u16 == RASTER_PIXEL_IS_POINT,
+                    GT_MODEL_TYPE => self.projected = key[3] as u16 == MODEL_TYPE_PROJECTED,
+                    GEOGRAPHIC_TYPE | PROJECTED_CS_TYPE if key[3] < 32767. => {
+                        self.epsg = Some(key[3] as u16)
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        self.transform = match (
+            values(MODEL_TRANSFORMATION)?,
+            values(MODEL_TIEPOINT)?,
+            values(MODEL_PIXEL_SCALE)?,
+        ) {
+            (Some(m), _, _) if m.len() >= 8 => [m[3], m[0], m[1], m[7], m[4], m[5]],
+            (_, Some(tiepoint), Some(scale)) if tiepoint.len() >= 6 && scale.len() >= 2 => [
+                tiepoint[3] - tiepoint[0] * scale[0],
+                scale[0],
+                0.,
+                tiepoint[4] + tiepoint[1] * scale[1],
+                0.,
+                -scale[1],
+            ],
+            _ => return Err("Missing GeoTIFF georeferencing".into()),
+        };
+        // As GDAL does, the coordinates of PixelIsPoint rasters are those of the pixel centres
+        if pixel_is_point {
+            self.transform[0] -= (self.transform[1] + self.transform[2]) / 2.;
+            self.transform[3] -= (self.transform[4] + self.transform[5]) / 2.;
+        }
+        Ok(())
+    }
+
+    // Decoded values of the first band of a strip / tile
+    fn chunk(&self, index: usize) -> Result<Rc<Vec<f64>>, String> {
+        self.cache.get(index, || self.decode_chunk(index))
+    }
+
+    fn decode_chunk(&self, index: usize) -> Result<Vec<f64>, String> {
+        let offset = *self.chunk_offsets.get(index).ok_or("Missing TIFF chunk")?;
+        let byte_count = *self
+            .chunk_byte_counts
+            .get(index)
+            .ok_or("Missing TIFF chunk")?;
+        let raw = self.bytes(offset, byte_count as usize)?;
+        let mut data = match self.compression {
+            Compression::None => raw.to_vec(),
+            Compression::Deflate => {
+                let mut data = Vec::new();
+                flate2::read::ZlibDecoder::new(raw)
+                    .read_to_end(&mut data)
+                    .map_err(|e| e.to_string())?;
+                data
+            }
+            Compression::Lzw => lzw_decode(raw),
+            Compression::PackBits => packbits_decode(raw),
+        };
+
+        let row_samples = self.chunk_width * self.chunk_samples;
+        let row_len = row_samples * self.bytes_per_sample;
+        match self.predictor {
+            1 => {}
+            // Horizontal differencing
+            2 => {
+                for row in data.chunks_exact_mut(row_len) {
+                    for i in self.chunk_samples..row_samples {
+                        let previous = self.decode_uint(row, i - self.chunk_samples);
+                        let current = self.decode_uint(row, i);
+                        self.encode_uint(row, i, current.wrapping_add(previous));
+                    }
+                }
+            }
+            // Floating point: byte-wise differencing, then the bytes of the
+            // samples are stored from the most significant one
+            3 => {
+                for row in data.chunks_exact_mut(row_len) {
+                    for i in self.chunk_samples..row_len {
+                        row[i] = row[i].wrapping_add(row[i - self.chunk_samples]);
+                    }
+                    let shuffled = row.to_vec();
+                    for sample in 0..row_samples {
+                        for byte in 0..self.bytes_per_sample {
+                            let value = shuffled[byte * row_samples + sample];
+                            let position = if self.little_endian {
+                                self.bytes_per_sample - 1 - byte
+                            } else {
+                                byte
+                            };
+                            row[sample * self.bytes_per_sample + position] = value;
+                        }
+                    }
+                }
+            }
+            predictor => return Err(format!("Unsupported TIFF predictor {}", predictor)),
+        }
+
+        Ok(
+            (0..data.len() / (self.bytes_per_sample * self.chunk_samples))
+                .map(|i| self.decode(&data, i * self.chunk_samples))
+                .collect(),
+        )
+    }
+
+    fn decode_uint(&self, data: &[u8], i: usize) -> u64 {
+        let bytes = &data[i * self.bytes_per_sample..(i + 1) * self.bytes_per_sample];
+        let fold = |value: u64, byte: &u8| value << 8 | *byte as u64;
+        if self.little_endian {
+            bytes.iter().rev().fold(0, fold)
+        } else {
+            bytes.iter().fold(0, fold)
+        }
+    }
+
+    fn encode_uint(&self, data: &mut [u8], i: usize, value: u64) {
+        let bytes = &mut data[i * self.bytes_per_sample..(i + 1) * self.bytes_per_sample];
+        let len = bytes.len();
+        for (j, byte) in bytes.iter_mut().enumerate() {
+            let shift = if self.little_endian { j } else { len - 1 - j };
+            *byte = (value >> (8 * shift)) as u8;
+        }
+    }
+
+    // Value of the i-th sample of decoded data
+    fn decode(&self, data: &[u8], i: usize) -> f64 {
+        let value = self.decode_uint(data, i);
+        match (self.sample_format, self.bytes_per_sample) {
+            (SampleFormat::Float, 4) => f32::from_bits(value as u32) as f64,
+            (SampleFormat::Float, 8) => f64::from_bits(value),
+            (SampleFormat::Signed, 1) => value as u8 as i8 as f64,
+            (SampleFormat::Signed, 2) => value as u16 as i16 as f64,
+            (SampleFormat::Signed, 4) => value as u32 as i32 as f64,
+            (SampleFormat::Signed, _) => value as i64 as f64,
+            _ => value as f64,
+        }
+    }
+
+    /// Size of the strips / tiles
+    pub fn chunk_size(&self) -> (usize, usize) {
+        (self.chunk_width, self.chunk_height)
+    }
+
+    /// Values of the pixels of a window (row by row), which must be inside the raster
+    pub fn read(
+        &self,
+        (x, y): (usize, usize),
+        (width, height): (usize, usize),
+    ) -> Result<Vec<f64>, String> {
+        let chunks_across = self.width.div_ceil(self.chunk_width);
+        let mut values = Vec::with_capacity(width * height);
+        for row in y..y + height {
+            for col in x..x + width {
+                let index = (row / self.chunk_height) * chunks_across + col / self.chunk_width;
+                let chunk = self.chunk(index)?;
+                let i = (row % self.chunk_height) * self.chunk_width + col % self.chunk_width;
+                values.push(*chunk.get(i).ok_or("Truncated TIFF chunk")?);
+            }
+        }
+        Ok(values)
+    }
+}
+
+// Decode PackBits data: runs of literal bytes, or of a repeated byte
+fn packbits_decode(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let header = data[i] as i8;
+        i += 1;
+        match header {
+            0..=127 => {
+                let end = (i + header as usize + 1).min(data.len());
+                output.extend_from_slice(&data[i..end]);
+                i = end;
+            }
+            // No-op
+            -128 => {}
+            _ => {
+                if let Some(byte) = data.get(i) {
+                    output.extend(std::iter::repeat_n(*byte, (1 - header as isize) as usize));
+                }
+                i += 1;
+            }
+        }
+    }
+    output
+}
+
+// Decode TIFF LZW data (MSB-first codes, with the early change of the code width)
+fn lzw_decode(data: &[u8]) -> Vec<u8> {
+    const CLEAR: usize = 256;
+    const END: usize = 257;
+    let mut output = Vec::new();
+    let mut table: Vec<Vec<u8>> = (0..=255u8).map(|byte| vec![byte]).collect();
+    table.push(Vec::new());
+    table.push(Vec::new());
+    let mut width = 9;
+    let mut previous: Option<usize> = None;
+    let (mut buffer, mut bits, mut position) = (0u32, 0, 0);
+    loop {
+        while bits < width {
+            let byte = match data.get(position) {
+                Some(byte) => *byte,
+                None => return output,
+            };
+            buffer = buffer << 8 | byte as u32;
+            bits += 8;
+            position += 1;
+        }
+        let code = ((buffer >> (bits - width)) & ((1 << width) - 1)) as usize;
+        bits -= width;
+        match code {
+            CLEAR => {
+                table.truncate(258);
+                width = 9;
+                previous = None;
+                continue;
+            }
+            END => return output,
+            _ => {}
+        }
+        let entry = match previous {
+            _ if code < table.len() => table[code].clone(),
+            // Code of the entry being added
+            Some(previous) => {
+                let mut entry = table[previous].clone();
+                entry.push(table[previous][0]);
+                entry
+            }
+            None => return output,
+        };
+        output.extend_from_slice(&entry);
+        if let Some(previous) = previous {
+            let mut new_entry = table[previous].clone();
+            new_entry.push(entry[0]);
+            table.push(new_entry);
+        }
+        previous = Some(code);
+        width = match table.len() + 1 {
+            len if len >= 2048 => 12,
+            len if len >= 1024 => 11,
+            len if len >= 512 => 10,
+            _ => 9,
+        };
+    }
+}
+
+/// Write a single-band, uncompressed f32 GeoTIFF in WGS84 (EPSG:4326) or in a projected
+/// CRS given by its EPSG code, with an optional nodata value
+pub fn write_f32(
+    path: &std::path::Path,
+    (width, height): (usize, usize),
+    transform: [f64; 6],
+    data: &[f32],
+    nodata: Option<f64>,
+    epsg: u16,
+) -> std::io::Result<()> {
+    // Geographic WGS84, or a projected CRS
+    let (model_type, crs_key) = match epsg {
+        4326 => (2, GEOGRAPHIC_TYPE),
+        _ => (MODEL_TYPE_PROJECTED, PROJECTED_CS_TYPE),
+    };
+    // (tag, type, values) of the IFD entries, sorted by tag
+    let short = |values: &[u16]| (3u16, values.iter().flat_map(|v| v.to_le_bytes()).collect());
+    let long = |value: u32| (4u16, value.to_le_bytes().to_vec());
+    let double = |values: &[f64]| (12u16, values.iter().flat_map(|v| v.to_le_bytes()).collect());
+    let ascii = |text: String| (2u16, text.bytes().chain([0]).collect());
+    let strip_offset = 8u32;
+    let mut entries: Vec<(u16, (u16, Vec<u8>))> = vec![
+        (IMAGE_WIDTH, long(width as u32)),
+        (IMAGE_LENGTH, long(height as u32)),
+        (BITS_PER_SAMPLE, short(&[32])),
+        (COMPRESSION, short(&[1])),
+        (PHOTOMETRIC_INTERPRETATION, short(&[1])),
+        (STRIP_OFFSETS, long(strip_offset)),
+        (SAMPLES_PER_PIXEL, short(&[1])),
+        (ROWS_PER_STRIP, long(height as u32)),
+        (STRIP_BYTE_COUNTS, long((data.len() * 4) as u32)),
+        (PLANAR_CONFIGURATION, short(&[1])),
+        (SAMPLE_FORMAT, short(&[3])),
+        (
+            MODEL_PIXEL_SCALE,
+            double(&[transform[1], -transform[5], 0.]),
+        ),
+        (
+            MODEL_TIEPOINT,
+            double(&[0., 0., 0., transform[0], transform[3], 0.]),
+        ),
+        (
+            GEO_KEY_DIRECTORY,
+            short(&[
+                1,
+                1,
+                0,
+                3,
+                GT_MODEL_TYPE,
+                0,
+                1,
+                model_type,
+                GT_RASTER_TYPE,
+                0,
+                1,
+                1,
+                crs_key,
+                0,
+                1,
+                epsg,
+            ]),
+        ),
+    ];
+    if let Some(nodata) = nodata {
+        entries.push((GDAL_NODATA, ascii(nodata.to_string())));
+    }
+
+    let mut file = Vec::new();
+    file.extend_from_slice(b"II");
+    file.extend_from_slice(&42u16.to_le_bytes());
+    let ifd_offset = strip_offset as usize + data.len() * 4;
+    file.extend_from_slice(&(ifd_offset as u32).to_le_bytes());
+    file.extend(data.iter().flat_map(|v| v.to_le_bytes()));
+
+    // Values which don't fit in the entries are written after the IFD
+    let mut extra = Vec::new();
+    let extra_offset = ifd_offset + 2 + entries.len() * 12 + 4;
+    file.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (tag, (field_type, values)) in &entries {
+        let type_size = if *field_type == 2 {
+            1
+        } else if *field_type == 3 {
+            2
+        } else if *field_type == 4 {
+            4
+        } else {
+            8
+        };
+        file.extend_from_slice(&tag.to_le_bytes());
+        file.extend_from_slice(&field_type.to_le_bytes());
+        file.extend_from_slice(&((values.len() / type_size) as u32).to_le_bytes());
+        if values.len() <= 4 {
+            let mut value = values.clone();
+            value.resize(4, 0);
+            file.extend_from_slice(&value);
+        } else {
+            file.extend_from_slice(&((extra_offset + extra.len()) as u32).to_le_bytes());
+            extra.extend_from_slice(values);
+        }
+    }
+    file.extend_from_slice(&0u32.to_le_bytes());
+    file.extend_from_slice(&extra);
+    std::fs::write(path, file)
+}
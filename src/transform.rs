@@ -0,0 +1,138 @@
+use crate::expression::Expr;
+use osmpbfreader::Tags;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Tag derived from the values of another tag
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DerivedTag {
+    // Key of the source tag
+    from: String,
+    // Value of the derived tag for each value of the source tag
+    values: BTreeMap<String, String>,
+    // Value of the derived tag when the source tag is missing or has another value
+    default: Option<String>,
+}
+
+/// Tag transformations applied to the ways before filtering them
+/// and user-defined metrics, read from a TOML file:
+///
+/// ```toml
+/// # Tags copied to the output
+/// output_tags = ["highway", "surface_class"]
+///
+/// # Replace values of a tag
+/// [replace.highway]
+/// living_street = "residential"
+///
+/// # Derive a tag from the values of another one
+/// [derive.surface_class]
+/// from = "surface"
+/// values = { asphalt = "paved", concrete = "paved", gravel = "unpaved" }
+/// default = "unknown"
+///
+/// # Fields computed from the metrics of each way
+/// [metrics]
+/// effort = "climb * 10 + distance / 1000"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Transform {
+    #[serde(default)]
+    output_tags: Vec<String>,
+    #[serde(default)]
+    replace: BTreeMap<String, BTreeMap<String, String>>,
+    #[serde(default)]
+    derive: BTreeMap<String, DerivedTag>,
+    #[serde(default, rename = "metrics")]
+    metric_expressions: BTreeMap<String, String>,
+    // Parsed metric expressions
+    #[serde(skip)]
+    metrics: Vec<(String, Expr)>,
+}
+
+impl Transform {
+    /// Read a transform file, whose metrics can't have the names of the `reserved` fields
+    pub fn read(path: &str, reserved: &[&str]) -> Result<Transform, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|_| format!("Unable to read transform file {}", path))?;
+        let mut transform: Transform = toml::from_str(&content)
+            .map_err(|e| format!("Invalid transform file {}: {}", path, e))?;
+        transform.metrics = transform
+            .metric_expressions
+            .iter()
+            .map(|(name, expression)| {
+                if reserved.contains(&name.as_str()) {
+                    return Err(format!(
+                        "The metric {} has the name of a field of the ways",
+                        name
+                    ));
+                }
+                Expr::parse(expression)
+                    .map(|expr| (name.to_string(), expr))
+                    .map_err(|e| format!("Invalid expression for metric {}: {}", name, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(transform)
+    }
+
+    /// Apply the replacements, then derive the new tags from the replaced values
+    pub fn apply(&self, tags: &mut Tags) {
+        for (key, replacements) in &self.replace {
+            let replacement = tags
+                .get(key.as_str())
+                .and_then(|value| replacements.get(value.as_str()));
+            if let Some(replacement) = replacement {
+                tags.insert(key.into(), replacement.into());
+            }
+        }
+        for (key, derived) in &self.derive {
+            let value = tags
+                .get(derived.from.as_str())
+                .and_then(|value| derived.values.get(value.as_str()))
+                .or(derived.default.as_ref());
+            if let Some(value) = value {
+                tags.insert(key.into(), value.into());
+            }
+        }
+    }
+
+    /// Copy of the tags of an object transformed, to be matched against the filters
+    pub fn transformed(&self, tags: &Tags) -> Tags {
+        let mut tags = tags.clone();
+        self.apply(&mut tags);
+        tags
+    }
+
+    /// Tags of the way to copy to the output, if any were requested
+    pub fn output_tags(&self, tags: &Tags) -> Option<BTreeMap<String, String>> {
+        if self.output_tags.is_empty() {
+            return None;
+        }
+        Some(
+            self.output_tags
+                .iter()
+                .filter_map(|key| {
+                    tags.get(key.as_str())
+                        .map(|value| (key.to_string(), value.to_string()))
+                })
+                .collect(),
+        )
+    }
+
+    /// Values of the user-defined metrics, computed from the (serialized) result of a way
+    pub fn metrics<T: serde::Serialize>(&self, result: &T) -> BTreeMap<String, Option<f64>> {
+        if self.metrics.is_empty() {
+            return BTreeMap::new();
+        }
+        let result = serde_json::to_value(result).expect("Unable to serialize result");
+        let fields = result
+            .as_object()
+            .expect("The result of a way is not an object");
+        self.metrics
+            .iter()
+            .map(|(name, expr)| (name.to_string(), expr.eval(fields)))
+            .collect()
+    }
+}
@@ -0,0 +1,100 @@
+use crate::geometry;
+use crate::gradient;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Sub-section of a way whose grade exceeds a threshold over more than the minimum length
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exceedance {
+    // Grade threshold (in %) that is exceeded
+    threshold: f64,
+    // Distance from the start of the way, in meters
+    start: f64,
+    end: f64,
+    // Net grade over the section (in %, signed, in the direction of the way)
+    grade: f64,
+}
+
+/// Heavy vehicle specific metrics of a way
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TruckInfo {
+    // Highest threshold exceeded by the way, if any
+    max_exceeded_threshold: Option<f64>,
+    exceedances: Vec<Exceedance>,
+}
+
+/// Find the sections of a way exceeding each of the grade thresholds (in %)
+/// over more than `min_length` meters
+pub fn compute(
+    distances: &[f64],
+    elevations: &[Option<f64>],
+    thresholds: &[f64],
+    min_length: f64,
+) -> TruckInfo {
+    let exceedances: Vec<Exceedance> = thresholds
+        .iter()
+        .flat_map(|threshold| {
+            gradient::steep_sections(distances, elevations, threshold * 10.)
+                .into_iter()
+                .filter(|section| section.end - section.start >= min_length)
+                .map(|section| Exceedance {
+                    threshold: *threshold,
+                    start: section.start,
+                    end: section.end,
+                    grade: section.gradient / 10.,
+                })
+        })
+        .collect();
+    let max_exceeded_threshold = exceedances
+        .iter()
+        .map(|exceedance| exceedance.threshold)
+        .reduce(f64::max);
+
+    TruckInfo {
+        max_exceeded_threshold,
+        exceedances,
+    }
+}
+
+/// Build the features of the restriction layer for the exceedances of a way
+pub fn layer_features(
+    way_id: i64,
+    info: &TruckInfo,
+    coordinates: &[[f64; 2]],
+    distances: &[f64],
+) -> Vec<serde_json::Value> {
+    info.exceedances
+        .iter()
+        .map(|exceedance| {
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": geometry::sub_line(coordinates, distances, exceedance.start, exceedance.end),
+                },
+                "properties": {
+                    "way_id": way_id,
+                    "threshold": exceedance.threshold,
+                    "grade": exceedance.grade,
+                    "length": exceedance.end - exceedance.start,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Write the restriction layer as a GeoJSON file
+pub fn write_layer(
+    path: &str,
+    features: Vec<serde_json::Value>,
+    reprojection: Option<&crate::reproject::Reprojection>,
+) {
+    let mut collection = json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+    crate::reproject::apply_to_output(reprojection, &mut collection, path);
+    let json_str =
+        serde_json::to_string(&collection).expect("Unable to serialize restriction layer");
+    std::fs::write(path, json_str).expect("Unable to write restriction layer file");
+}
@@ -0,0 +1,85 @@
+use crate::osmxml::{attribute, Element, XmlReader};
+use crate::WayInfo;
+use std::collections::BTreeMap;
+
+/// Changes of an OsmChange diff (.osc, .osc.gz or .osc.bz2), and the results of
+/// the previous run, so that only the ways affected by the changes are recomputed
+pub struct Update {
+    // Ways and nodes created, modified or deleted by the diff
+    changed_ways: fxhash::FxHashSet<i64>,
+    changed_nodes: fxhash::FxHashSet<i64>,
+    // way_id -> metrics of the previous JSON output
+    previous: BTreeMap<i64, WayInfo>,
+}
+
+impl Update {
+    pub fn read(previous_file: &str, diff_file: &str) -> Result<Update, String> {
+        let content = std::fs::read_to_string(previous_file)
+            .map_err(|e| format!("Unable to read {}: {}", previous_file, e))?;
+        let previous = serde_json::from_str(&content).map_err(|e| {
+            format!(
+                "Unable to parse {} (which must be a JSON output): {}",
+                previous_file, e
+            )
+        })?;
+
+        let input = crate::osmxml::open(diff_file)
+            .map_err(|e| format!("Unable to open {}: {}", diff_file, e))?;
+        let mut reader = XmlReader::new(input);
+        let mut changed_ways = fxhash::FxHashSet::default();
+        let mut changed_nodes = fxhash::FxHashSet::default();
+        while let Some(element) = reader
+            .next_element()
+            .map_err(|e| format!("Unable to read {}: {}", diff_file, e))?
+        {
+            if let Element::Start(name, attributes) | Element::Empty(name, attributes) = element {
+                let id = attribute(&attributes, "id").and_then(|id| id.parse::<i64>().ok());
+                match (name.as_str(), id) {
+                    ("node", Some(id)) => {
+                        changed_nodes.insert(id);
+                    }
+                    ("way", Some(id)) => {
+                        changed_ways.insert(id);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(Update {
+            changed_ways,
+            changed_nodes,
+            previous,
+        })
+    }
+
+    pub fn changed_way_count(&self) -> usize {
+        self.changed_ways.len()
+    }
+
+    pub fn changed_node_count(&self) -> usize {
+        self.changed_nodes.len()
+    }
+
+    /// Whether a way has to be recomputed: it was changed by the diff, or some
+    /// of its nodes were (e.g. moved)
+    pub fn affects(&self, way: &osmpbfreader::Way) -> bool {
+        self.changed_ways.contains(&way.id.0)
+            || way
+                .nodes
+                .iter()
+                .any(|node_id| self.changed_nodes.contains(&node_id.0))
+    }
+
+    /// Ways of the previous output which are kept as is: those neither changed
+    /// by the diff nor recomputed (the deleted ways, and those which no longer
+    /// match the filters, being dropped)
+    pub fn into_unchanged(
+        self,
+        recomputed: &fxhash::FxHashSet<i64>,
+    ) -> impl Iterator<Item = (i64, WayInfo)> + '_ {
+        let changed_ways = self.changed_ways;
+        self.previous.into_iter().filter(move |(way_id, _)| {
+            !changed_ways.contains(way_id) && !recomputed.contains(way_id)
+        })
+    }
+}
@@ -0,0 +1,155 @@
+use serde_json::Value;
+use std::fmt;
+
+/// Numeric tolerances used when comparing against a reference output
+pub struct Tolerance {
+    pub absolute: f64,
+    pub relative: f64,
+}
+
+/// Difference between the output and the reference for a way
+pub struct Discrepancy {
+    way_id: String,
+    // Path of the differing field, e.g. "climb" or "steep_sections[0].end"
+    field: String,
+    expected: Option<Value>,
+    actual: Option<Value>,
+}
+
+impl Discrepancy {
+    pub fn way_id(&self) -> &str {
+        &self.way_id
+    }
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |value: &Option<Value>| match value {
+            Some(value) => value.to_string(),
+            None => "missing".to_string(),
+        };
+        if self.field.is_empty() {
+            if self.actual.is_none() {
+                write!(f, "way {}: missing from the output", self.way_id)
+            } else {
+                write!(f, "way {}: not in the reference file", self.way_id)
+            }
+        } else {
+            write!(
+                f,
+                "way {}: {} expected {}, got {}",
+                self.way_id,
+                self.field,
+                show(&self.expected),
+                show(&self.actual)
+            )
+        }
+    }
+}
+
+fn numbers_match(expected: f64, actual: f64, tolerance: &Tolerance) -> bool {
+    let difference = (expected - actual).abs();
+    difference <= tolerance.absolute || difference <= tolerance.relative * expected.abs()
+}
+
+// Compare two values recursively, pushing the path of every differing field
+fn compare_values(
+    path: &str,
+    expected: &Value,
+    actual: &Value,
+    tolerance: &Tolerance,
+    differences: &mut Vec<(String, Option<Value>, Option<Value>)>,
+) {
+    match (expected, actual) {
+        (Value::Number(e), Value::Number(a)) => {
+            if !numbers_match(e.as_f64().unwrap(), a.as_f64().unwrap(), tolerance) {
+                differences.push((
+                    path.to_string(),
+                    Some(expected.clone()),
+                    Some(actual.clone()),
+                ));
+            }
+        }
+        (Value::Array(e), Value::Array(a)) if e.len() == a.len() => {
+            for (i, (e, a)) in e.iter().zip(a).enumerate() {
+                compare_values(&format!("{}[{}]", path, i), e, a, tolerance, differences);
+            }
+        }
+        (Value::Object(e), Value::Object(a)) => {
+            let field = |key: &str| {
+                if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", path, key)
+                }
+            };
+            for (key, e_value) in e {
+                let field = field(key);
+                match a.get(key) {
+                    Some(a_value) => {
+                        compare_values(&field, e_value, a_value, tolerance, differences)
+                    }
+                    None => differences.push((field, Some(e_value.clone()), None)),
+                }
+            }
+            for (key, a_value) in a {
+                if !e.contains_key(key) {
+                    differences.push((field(key), None, Some(a_value.clone())));
+                }
+            }
+        }
+        _ => {
+            if expected != actual {
+                differences.push((
+                    path.to_string(),
+                    Some(expected.clone()),
+                    Some(actual.clone()),
+                ));
+            }
+        }
+    }
+}
+
+/// Compare the output (a map of way id -> result) against the reference file
+pub fn compare(actual: &Value, reference_path: &str, tolerance: &Tolerance) -> Vec<Discrepancy> {
+    let reference = std::fs::read_to_string(reference_path)
+        .unwrap_or_else(|_| panic!("Unable to read reference file {}", reference_path));
+    let expected: Value = serde_json::from_str(&reference)
+        .unwrap_or_else(|_| panic!("Invalid reference file {}", reference_path));
+    let (expected, actual) = match (expected.as_object(), actual.as_object()) {
+        (Some(expected), Some(actual)) => (expected, actual),
+        _ => panic!(
+            "The reference file {} is not a map of way ids",
+            reference_path
+        ),
+    };
+
+    let mut way_ids: Vec<&String> = expected.keys().chain(actual.keys()).collect();
+    way_ids.sort_by_key(|way_id| (way_id.parse::<i64>().unwrap_or(i64::MAX), *way_id));
+    way_ids.dedup();
+
+    let mut discrepancies = Vec::new();
+    for way_id in way_ids {
+        match (expected.get(way_id), actual.get(way_id)) {
+            (Some(e), Some(a)) => {
+                let mut differences = Vec::new();
+                compare_values("", e, a, tolerance, &mut differences);
+                discrepancies.extend(differences.into_iter().map(|(field, expected, actual)| {
+                    Discrepancy {
+                        way_id: way_id.to_string(),
+                        field,
+                        expected,
+                        actual,
+                    }
+                }));
+            }
+            (e, a) => discrepancies.push(Discrepancy {
+                way_id: way_id.to_string(),
+                field: String::new(),
+                expected: e.cloned(),
+                actual: a.cloned(),
+            }),
+        }
+    }
+    discrepancies
+}
@@ -0,0 +1,73 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Reason why the result of a way was computed with a fallback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    // A node of the way is missing from the OSM file, it was skipped
+    MissingNode,
+    // A node of the way was sampled on a nodata pixel
+    NoData,
+    // A node of the way is outside of the raster extent
+    OutOfExtent,
+    // The way is a structure (bridge, tunnel, ...) whose elevation was interpolated
+    StructureInterpolation,
+}
+
+#[derive(Debug, Serialize)]
+struct WayWarning<'a> {
+    way_id: i64,
+    reason: Reason,
+    nodes: &'a [i64],
+}
+
+/// Collects the ways that triggered a fallback, grouped by way and reason
+#[derive(Debug, Default)]
+pub struct WarningLog {
+    entries: BTreeMap<(i64, Reason), Vec<i64>>,
+}
+
+impl WarningLog {
+    pub fn add(&mut self, way_id: i64, reason: Reason, node_id: i64) {
+        let nodes = self.entries.entry((way_id, reason)).or_default();
+        if !nodes.contains(&node_id) {
+            nodes.push(node_id);
+        }
+    }
+
+    /// Record a warning concerning the way as a whole
+    pub fn add_way(&mut self, way_id: i64, reason: Reason) {
+        self.entries.entry((way_id, reason)).or_default();
+    }
+
+    /// Number of distinct ways with at least one warning
+    pub fn way_count(&self) -> usize {
+        let mut way_ids: Vec<i64> = self.entries.keys().map(|(way_id, _)| *way_id).collect();
+        way_ids.dedup();
+        way_ids.len()
+    }
+
+    /// Warnings as (way id, reason, node ids)
+    pub fn iter(&self) -> impl Iterator<Item = (i64, Reason, &[i64])> {
+        self.entries
+            .iter()
+            .map(|((way_id, reason), nodes)| (*way_id, *reason, nodes.as_slice()))
+    }
+
+    /// Write all the warnings as a JSON array to the given path
+    pub fn write(&self, path: &str) {
+        let warnings: Vec<WayWarning> = self
+            .entries
+            .iter()
+            .map(|((way_id, reason), nodes)| WayWarning {
+                way_id: *way_id,
+                reason: *reason,
+                nodes,
+            })
+            .collect();
+        let json_str =
+            serde_json::to_string(&warnings).expect("Unable to serialize warnings to string");
+        std::fs::write(path, json_str).expect("Unable to write warnings file");
+    }
+}
@@ -0,0 +1,55 @@
+use crate::gradient;
+use serde::{Deserialize, Serialize};
+
+/// Portion of a waterway whose drop exceeds the steep reach threshold
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SteepReach {
+    // Distance from the start of the way, in meters
+    start: f64,
+    end: f64,
+    drop_per_km: f64,
+}
+
+/// Waterway specific metrics of a way
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WaterwayInfo {
+    // Whether the water flows from the first to the last node of the way,
+    // as inferred from the elevation profile (None if it is flat or unknown)
+    flows_forward: Option<bool>,
+    // Average drop (in meters per kilometer) in the downstream direction
+    drop_per_km: f64,
+    steep_reaches: Vec<SteepReach>,
+}
+
+/// Compute the stream gradients of a way given the cumulative distances
+/// (in meters) and the elevations of its nodes
+pub fn compute(distances: &[f64], elevations: &[Option<f64>], steep_reach: f64) -> WaterwayInfo {
+    // m/km and per-mille are the same unit
+    let net_gradient = gradient::net_gradient(distances, elevations).unwrap_or(0.) * 1000.;
+    let flows_forward = if net_gradient == 0. {
+        None
+    } else {
+        Some(net_gradient < 0.)
+    };
+    // Only the drops in the downstream direction are steep reaches
+    let downstream_sign = if flows_forward == Some(false) {
+        1.
+    } else {
+        -1.
+    };
+    let steep_reaches = gradient::steep_sections(distances, elevations, steep_reach)
+        .into_iter()
+        .filter(|section| section.gradient.signum() == downstream_sign)
+        .map(|section| SteepReach {
+            start: section.start,
+            end: section.end,
+            drop_per_km: section.gradient.abs(),
+        })
+        .collect();
+
+    WaterwayInfo {
+        flows_forward,
+        drop_per_km: net_gradient.abs(),
+        steep_reaches,
+    }
+}
@@ -0,0 +1,76 @@
+use crate::gradient;
+use serde::{Deserialize, Serialize};
+
+/// Compliance of a way with the running slope thresholds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Classification {
+    // No segment steeper than the maximum running slope
+    Pass,
+    // Some segments steeper than the maximum running slope, none steeper than the limit
+    Limited,
+    // Some segments steeper than the limit
+    Fail,
+}
+
+/// Wheelchair accessibility metrics of a way
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WheelchairInfo {
+    // Running slope of the steepest segment (absolute, in %)
+    max_running_slope: f64,
+    classification: Classification,
+    // Length (in meters) of the sections steeper than the maximum running slope
+    non_compliant_length: f64,
+    // Length (in meters) of the sections steeper than the limit
+    over_limit_length: f64,
+    // Review priority of the way: 1 for crossings, 2 for sidewalks, 3 for footways, 4 otherwise
+    priority: u8,
+}
+
+fn priority(tags: &osmpbfreader::Tags) -> u8 {
+    if tags.contains_key("crossing") || tags.contains("footway", "crossing") {
+        1
+    } else if tags.contains_key("sidewalk") || tags.contains("footway", "sidewalk") {
+        2
+    } else if tags.contains("highway", "footway") || tags.contains_key("footway") {
+        3
+    } else {
+        4
+    }
+}
+
+// Total length of the sections steeper than the threshold (in %), in either direction
+fn length_above(distances: &[f64], elevations: &[Option<f64>], threshold: f64) -> f64 {
+    gradient::steep_sections(distances, elevations, threshold * 10.)
+        .iter()
+        .map(|section| section.end - section.start)
+        .sum()
+}
+
+/// Evaluate a way against the maximum running slope and limit thresholds (in %)
+pub fn compute(
+    tags: &osmpbfreader::Tags,
+    distances: &[f64],
+    elevations: &[Option<f64>],
+    max_slope: f64,
+    limit_slope: f64,
+) -> WheelchairInfo {
+    let max_running_slope = gradient::max_segment_gradient(distances, elevations).abs() / 10.;
+    let non_compliant_length = length_above(distances, elevations, max_slope);
+    let over_limit_length = length_above(distances, elevations, limit_slope);
+    let classification = if over_limit_length > 0. {
+        Classification::Fail
+    } else if non_compliant_length > 0. {
+        Classification::Limited
+    } else {
+        Classification::Pass
+    };
+
+    WheelchairInfo {
+        max_running_slope,
+        classification,
+        non_compliant_length,
+        over_limit_length,
+        priority: priority(tags),
+    }
+}
@@ -0,0 +1,164 @@
+use crate::elevation::Dem;
+use crate::gradient;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+// Weights of the components of the priority score
+const STEEPNESS_WEIGHT: f64 = 0.5;
+const NORTHNESS_WEIGHT: f64 = 0.2;
+const CLASS_WEIGHT: f64 = 0.3;
+// Grade (in %) from which the steepness component is maximal
+const MAX_STEEPNESS: f64 = 12.;
+// Terrain slope (in degrees) below which the aspect of the terrain is not considered
+const FLAT_TERRAIN: f64 = 2.;
+// Length (in meters) over which the grade of a road is measured
+const GRADE_WINDOW: f64 = 50.;
+
+// Importance of each road class for winter service
+const ROAD_CLASSES: [(&str, f64); 10] = [
+    ("motorway", 1.),
+    ("trunk", 1.),
+    ("primary", 0.9),
+    ("secondary", 0.8),
+    ("tertiary", 0.6),
+    ("unclassified", 0.4),
+    ("residential", 0.4),
+    ("living_street", 0.2),
+    ("service", 0.2),
+    ("track", 0.1),
+];
+const DEFAULT_CLASS: f64 = 0.3;
+
+// Stroke colors of the styled layer, from the lowest to the highest priority
+const COLORS: [&str; 4] = ["#2c7bb6", "#abd9e9", "#fdae61", "#d7191c"];
+
+/// Winter-maintenance metrics of a way
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WinterInfo {
+    // Gritting / plowing priority, from 0 to 100
+    winter_score: f64,
+    // Steepest grade sustained over 50 m (in %)
+    winter_max_grade: f64,
+    // Mean cosine of the aspect of the terrain crossed by the way
+    // (1 when it only crosses north-facing slopes)
+    northness: f64,
+}
+
+/// Score a way for winter-maintenance priority from its steepness,
+/// the aspect of the terrain it crosses and its road class
+pub fn compute(
+    tags: &osmpbfreader::Tags,
+    coordinates: &[[f64; 2]],
+    distances: &[f64],
+    elevations: &[Option<f64>],
+    dem: &Dem,
+) -> WinterInfo {
+    let max_grade = gradient::max_sustained_gradient(distances, elevations, GRADE_WINDOW) * 100.;
+    let northness = if coordinates.is_empty() {
+        0.
+    } else {
+        coordinates
+            .iter()
+            .filter_map(|[lon, lat]| dem.terrain(*lon, *lat))
+            .filter(|(slope, _)| *slope >= FLAT_TERRAIN)
+            .map(|(_, aspect)| aspect.to_radians().cos())
+            .sum::<f64>()
+            / coordinates.len() as f64
+    };
+    let class = tags
+        .get("highway")
+        .and_then(|highway| ROAD_CLASSES.iter().find(|(c, _)| *c == highway.as_str()))
+        .map(|(_, weight)| *weight)
+        .unwrap_or(DEFAULT_CLASS);
+
+    let winter_score = 100.
+        * (STEEPNESS_WEIGHT * (max_grade / MAX_STEEPNESS).min(1.)
+            + NORTHNESS_WEIGHT * northness.max(0.)
+            + CLASS_WEIGHT * class);
+
+    WinterInfo {
+        winter_score,
+        winter_max_grade: max_grade,
+        northness,
+    }
+}
+
+/// Scored way, kept for the ranked list and the styled layer
+pub struct RankedWay {
+    pub way_id: i64,
+    pub name: Option<String>,
+    pub highway: Option<String>,
+    pub info: WinterInfo,
+    pub coordinates: Vec<[f64; 2]>,
+}
+
+fn sort_by_score(ways: &mut [RankedWay]) {
+    ways.sort_by(|a, b| b.info.winter_score.total_cmp(&a.info.winter_score));
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Write the ways ranked by decreasing priority as a CSV file
+pub fn write_ranking(path: &str, ways: &mut [RankedWay]) {
+    sort_by_score(ways);
+    let mut csv =
+        String::from("rank,way_id,name,highway,winter_score,winter_max_grade,northness\n");
+    for (rank, way) in ways.iter().enumerate() {
+        csv.push_str(&format!(
+            "{},{},{},{},{:.2},{:.2},{:.3}\n",
+            rank + 1,
+            way.way_id,
+            csv_field(way.name.as_deref().unwrap_or("")),
+            csv_field(way.highway.as_deref().unwrap_or("")),
+            way.info.winter_score,
+            way.info.winter_max_grade,
+            way.info.northness,
+        ));
+    }
+    std::fs::write(path, csv).expect("Unable to write winter ranking file");
+}
+
+/// Write the ways as a GeoJSON layer styled (simplestyle-spec) by priority
+pub fn write_layer(
+    path: &str,
+    ways: &mut [RankedWay],
+    reprojection: Option<&crate::reproject::Reprojection>,
+) {
+    sort_by_score(ways);
+    let features: Vec<serde_json::Value> = ways
+        .iter()
+        .enumerate()
+        .map(|(rank, way)| {
+            let class = ((way.info.winter_score / 25.) as usize).min(COLORS.len() - 1);
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": way.coordinates,
+                },
+                "properties": {
+                    "way_id": way.way_id,
+                    "name": way.name,
+                    "highway": way.highway,
+                    "rank": rank + 1,
+                    "winter_score": way.info.winter_score,
+                    "stroke": COLORS[class],
+                    "stroke-width": 1 + class,
+                },
+            })
+        })
+        .collect();
+    let mut collection = json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+    crate::reproject::apply_to_output(reprojection, &mut collection, path);
+    let json_str = serde_json::to_string(&collection).expect("Unable to serialize winter layer");
+    std::fs::write(path, json_str).expect("Unable to write winter layer file");
+}
@@ -0,0 +1,94 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const BIN: &str = env!("CARGO_BIN_EXE_osm-ways-slope");
+
+// Empty directory, in the temporary directory, for the files of a test
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("osm-ways-slope-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("Unable to create the test directory");
+    dir
+}
+
+fn run(args: &[&Path]) {
+    let output = Command::new(BIN)
+        .args(args)
+        .output()
+        .expect("Unable to run osm-ways-slope");
+    assert!(
+        output.status.success(),
+        "osm-ways-slope {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn climb_and_descent_of_the_fixture() {
+    let dir = test_dir("fixture");
+    run(&[Path::new("gen-fixture"), &dir]);
+    let output_file = dir.join("output.json");
+    run(&[
+        &dir.join("fixture.osm.pbf"),
+        &dir.join("fixture.tif"),
+        &output_file,
+    ]);
+
+    let content = std::fs::read_to_string(&output_file).expect("Unable to read the output");
+    let result: serde_json::Value = serde_json::from_str(&content).expect("Invalid output");
+    // (way id, climb, descent) on the plane rising by 10 m per pixel towards the east
+    for (id, climb, descent) in [
+        ("1", 200., 0.),
+        ("2", 0., 200.),
+        ("3", 0., 0.),
+        ("4", 200., 200.),
+    ] {
+        let way = &result[id];
+        assert!(
+            (way["climb"].as_f64().unwrap() - climb).abs() < 1e-6,
+            "climb of way {}: {}",
+            id,
+            way["climb"]
+        );
+        assert!(
+            (way["descent"].as_f64().unwrap() - descent).abs() < 1e-6,
+            "descent of way {}: {}",
+            id,
+            way["descent"]
+        );
+    }
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn fixture_matches_its_reference_file() {
+    let dir = test_dir("verify");
+    run(&[Path::new("gen-fixture"), &dir]);
+    run(&[
+        &dir.join("fixture.osm.pbf"),
+        &dir.join("fixture.tif"),
+        &dir.join("output.json"),
+        Path::new("--verify"),
+        &dir.join("expected.json"),
+    ]);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn options_of_another_profile_are_rejected() {
+    let dir = test_dir("profile");
+    run(&[Path::new("gen-fixture"), &dir]);
+    let output = Command::new(BIN)
+        .arg(dir.join("fixture.osm.pbf"))
+        .arg(dir.join("fixture.tif"))
+        .arg(dir.join("output.json"))
+        .args(["--profile", "truck", "--ruling-gradient", "30"])
+        .output()
+        .expect("Unable to run osm-ways-slope");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("--ruling-gradient is only used with --profile rail"));
+    assert!(!dir.join("output.json").exists());
+    std::fs::remove_dir_all(&dir).ok();
+}